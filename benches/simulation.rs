@@ -0,0 +1,113 @@
+use cgmath::{Deg, InnerSpace, Vector3};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use cube::{
+    game_state::{GameState, InputState, Instance, ModelWithInstances, Shader, NO_TINT},
+    physics::{Aabb, Physics},
+    rotor::Rotor,
+    tick_rate,
+    transform::Transform,
+};
+use std::time::Instant;
+
+const NUM_INSTANCES: usize = 10_000;
+
+fn make_instances(count: usize) -> Vec<Instance> {
+    (0..count)
+        .map(|i| {
+            let position = Vector3::new((i % 100) as f32, 0.0, (i / 100) as f32);
+            let rotation =
+                Rotor::from_axis_angle(Vector3::new(1.0, 1.0, 0.0).normalize(), Deg(i as f32));
+            Instance {
+                transform: Transform::new(position, rotation, 0.5),
+                shader: Shader::Texture,
+                tint: NO_TINT,
+                layer: 0,
+            }
+        })
+        .collect()
+}
+
+fn game_state_with_instances(count: usize) -> GameState {
+    let mut game_state = GameState::new(16.0 / 9.0);
+    game_state
+        .instanced_entities
+        .push(ModelWithInstances { id: 5, instances: make_instances(count) });
+    game_state
+}
+
+fn physics_update(c: &mut Criterion) {
+    let delta_t = tick_rate::tick_secs();
+    c.bench_function("Physics::update", |b| {
+        let mut physics = Physics::new();
+        b.iter(|| {
+            physics.apply_force(black_box(Vector3::new(1.0, 0.0, 0.0)));
+            black_box(physics.update(delta_t, 10.0))
+        })
+    });
+}
+
+// Boxes resting on the floor, whose contacts cancel their gravity. After the first half second
+// they are asleep, which is what most boxes in a level are on most ticks.
+fn physics_update_resting(c: &mut Criterion) {
+    let delta_t = tick_rate::tick_secs();
+    c.bench_function("Physics::update (500 resting bodies)", |b| {
+        let mut bodies: Vec<Physics> = (0..500)
+            .map(|_| {
+                let mut physics = Physics::new();
+                physics.gravity = Vector3::new(0.0, 0.0, 0.0);
+                physics.can_sleep = true;
+                physics
+            })
+            .collect();
+        b.iter(|| {
+            for physics in &mut bodies {
+                physics.reset_forces();
+                black_box(physics.update(delta_t, 10.0));
+            }
+        })
+    });
+}
+
+fn collision_bounding_box(c: &mut Criterion) {
+    let vertices: Vec<Vector3<f32>> =
+        make_instances(1000).iter().map(|instance| instance.transform.position).collect();
+    c.bench_function("Aabb::from_points (1k vertices)", |b| {
+        b.iter(|| Aabb::from_points(black_box(&vertices)))
+    });
+}
+
+fn instances_to_raw(c: &mut Criterion) {
+    let instances = make_instances(NUM_INSTANCES);
+    c.bench_function("Instance::to_raw (10k instances)", |b| {
+        b.iter(|| black_box(&instances).iter().map(Instance::to_raw).collect::<Vec<_>>())
+    });
+}
+
+fn game_state_tick(c: &mut Criterion) {
+    let mut input = InputState::new();
+    input.forward = true;
+    input.mouse_x = 3;
+    c.bench_function("GameState::update (10k instances)", |b| {
+        let mut game_state = game_state_with_instances(NUM_INSTANCES);
+        let mut tick_instant = Instant::now();
+        b.iter(|| {
+            tick_instant += tick_rate::time_per_tick();
+            game_state.update(black_box(&input), tick_instant);
+        })
+    });
+    // The game thread clones the whole state every tick to hand a snapshot to the render thread.
+    c.bench_function("GameState snapshot clone (10k instances)", |b| {
+        let game_state = game_state_with_instances(NUM_INSTANCES);
+        b.iter_batched(|| (), |_| black_box(game_state.clone()), BatchSize::SmallInput)
+    });
+}
+
+criterion_group!(
+    benches,
+    physics_update,
+    physics_update_resting,
+    collision_bounding_box,
+    instances_to_raw,
+    game_state_tick
+);
+criterion_main!(benches);
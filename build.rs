@@ -1,4 +1,6 @@
 use std::env;
+use std::fmt::Write;
+use std::path::Path;
 use fs_extra::{copy_items, dir::CopyOptions};
 use anyhow::Result;
 
@@ -11,7 +13,31 @@ fn main() -> Result<()> {
     copy_options.overwrite = true;
     let mut paths_to_copy = Vec::new();
     paths_to_copy.push("assets/");
-    let _ = copy_items(&paths_to_copy, out_dir, &copy_options);
+    let _ = copy_items(&paths_to_copy, &out_dir, &copy_options);
+
+    if env::var_os("CARGO_FEATURE_EMBED_ASSETS").is_some() {
+        write_embedded_assets(&out_dir)?;
+    }
+
+    Ok(())
+}
+
+// Writes embedded_assets.rs, a table of every file in assets/ by the name resources loads it
+// with, for resources.rs to include.
+fn write_embedded_assets(out_dir: &str) -> Result<()> {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR")?;
+    let assets_dir = Path::new(&manifest_dir).join("assets");
+    let mut table = String::from("pub static EMBEDDED_ASSETS: &[(&str, &[u8])] = &[\n");
+    for entry in glob::glob(&format!("{}/**/*", assets_dir.display()))? {
+        let path = entry?;
+        if !path.is_file() {
+            continue;
+        }
+        let name = path.strip_prefix(&assets_dir)?.to_string_lossy().replace('\\', "/");
+        writeln!(table, "    ({:?}, include_bytes!({:?})),", name, path.display().to_string())?;
+    }
+    table += "];\n";
+    std::fs::write(Path::new(out_dir).join("embedded_assets.rs"), table)?;
 
     Ok(())
-}
\ No newline at end of file
+}
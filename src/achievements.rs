@@ -0,0 +1,171 @@
+/* Achievements: lifetime play statistics kept on disk, and the achievements they unlock. */
+use crate::events::GameEvent;
+
+use anyhow::{bail, Context};
+use std::collections::BTreeSet;
+
+pub const ACHIEVEMENTS_FILE: &str = "achievements.txt";
+
+/// Counts over every game played.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LifetimeStats {
+    pub pushes: u64,
+    pub undos: u64,
+    pub levels_solved: u64,
+    // Tiles walked onto. Teleporting doesn't count.
+    pub tiles_walked: u64,
+}
+impl LifetimeStats {
+    // The stats by the names the file uses.
+    fn fields(&mut self) -> [(&'static str, &mut u64); 4] {
+        [
+            ("pushes", &mut self.pushes),
+            ("undos", &mut self.undos),
+            ("levels_solved", &mut self.levels_solved),
+            ("tiles_walked", &mut self.tiles_walked),
+        ]
+    }
+}
+
+pub struct Achievement {
+    // Names it in the file, and its name's string key is `achievement.<id>`.
+    pub id: &'static str,
+    pub name: &'static str,
+    // Whether the event, with the stats already counting it, earns the achievement.
+    earned: fn(&LifetimeStats, &GameEvent) -> bool,
+}
+
+/// Every achievement, checked in this order.
+pub static ACHIEVEMENTS: [Achievement; 8] = [
+    Achievement {
+        id: "first_push",
+        name: "Heave: push a box",
+        earned: |stats, _| stats.pushes >= 1,
+    },
+    Achievement {
+        id: "thousand_pushes",
+        name: "Heavy lifter: 1000 pushes",
+        earned: |stats, _| stats.pushes >= 1000,
+    },
+    Achievement {
+        id: "hundred_undos",
+        name: "Second thoughts: 100 undos",
+        earned: |stats, _| stats.undos >= 100,
+    },
+    Achievement {
+        id: "first_solve",
+        name: "Solved: finish a level",
+        earned: |stats, _| stats.levels_solved >= 1,
+    },
+    Achievement {
+        id: "ten_solves",
+        name: "Puzzler: finish 10 levels",
+        earned: |stats, _| stats.levels_solved >= 10,
+    },
+    Achievement {
+        id: "fifty_solves",
+        name: "Warehouse keeper: finish 50 levels",
+        earned: |stats, _| stats.levels_solved >= 50,
+    },
+    Achievement {
+        id: "marathon",
+        name: "Marathon: walk 10000 tiles",
+        earned: |stats, _| stats.tiles_walked >= 10000,
+    },
+    Achievement {
+        id: "speedrun",
+        name: "Speedrunner: finish a level in under 10 seconds",
+        earned: |_, event| match event {
+            GameEvent::LevelCompleted { stats, .. } => stats.ticks < 1000,
+            _ => false,
+        },
+    },
+];
+
+/// The lifetime stats and the achievements unlocked so far.
+///
+/// The file format is one entry per line: `stat <name> <count>` or `unlocked <achievement id>`.
+/// Unknown stats and achievements are dropped, so renaming one forgets it rather than failing.
+#[derive(Default)]
+pub struct Achievements {
+    pub stats: LifetimeStats,
+    unlocked: BTreeSet<&'static str>,
+}
+impl Achievements {
+    pub fn new() -> Self {
+        Achievements::default()
+    }
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut achievements = Achievements::new();
+        for (line_number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            achievements
+                .parse_line(line)
+                .with_context(|| format!("achievements line {}", line_number + 1))?;
+        }
+        Ok(achievements)
+    }
+    fn parse_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields[..] {
+            ["stat", name, count] => {
+                let count = count.parse()?;
+                if let Some((_, stat)) = self.stats.fields().into_iter().find(|(n, _)| *n == name) {
+                    *stat = count;
+                }
+            }
+            ["unlocked", id] => {
+                if let Some(achievement) = ACHIEVEMENTS.iter().find(|a| a.id == id) {
+                    self.unlocked.insert(achievement.id);
+                }
+            }
+            _ => bail!("expected stat <name> <count> or unlocked <id>"),
+        }
+        Ok(())
+    }
+    /// A missing file means nothing played yet.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                Self::parse(&text).with_context(|| format!("parsing achievements {}", path))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Achievements::new()),
+            Err(e) => Err(e).with_context(|| format!("reading achievements {}", path)),
+        }
+    }
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let mut stats = self.stats;
+        let mut text: String = stats
+            .fields()
+            .iter()
+            .map(|(name, count)| format!("stat {} {}\n", name, count))
+            .collect();
+        for id in &self.unlocked {
+            text += &format!("unlocked {}\n", id);
+        }
+        std::fs::write(path, text).with_context(|| format!("writing achievements to {}", path))
+    }
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.unlocked.contains(id)
+    }
+    /// Counts `event` into the stats. Returns the achievements it unlocked.
+    pub fn record(&mut self, event: &GameEvent) -> Vec<&'static Achievement> {
+        match event {
+            GameEvent::BoxPushed { .. } => self.stats.pushes += 1,
+            GameEvent::MoveUndone => self.stats.undos += 1,
+            GameEvent::LevelCompleted { .. } => self.stats.levels_solved += 1,
+            GameEvent::PlayerMoved { .. } => self.stats.tiles_walked += 1,
+            _ => {}
+        }
+        let mut unlocked = Vec::new();
+        for achievement in &ACHIEVEMENTS {
+            if !self.is_unlocked(achievement.id) && (achievement.earned)(&self.stats, event) {
+                self.unlocked.insert(achievement.id);
+                unlocked.push(achievement);
+            }
+        }
+        unlocked
+    }
+}
@@ -0,0 +1,126 @@
+/* Animation: keyframed transform clips that move entity instances, sampled from the game tick. */
+use crate::{game_state::Instance, transform::Transform};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    // Holds the previous keyframe, then jumps.
+    Step,
+}
+impl Easing {
+    fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Playback {
+    // Stops on the last keyframe.
+    Once,
+    Loop,
+    // Plays forwards, then backwards, and repeats.
+    PingPong,
+}
+
+#[derive(Clone, Copy)]
+pub struct Keyframe {
+    // Ticks since the start of the clip.
+    pub tick: u32,
+    pub transform: Transform,
+    // How to ease from the previous keyframe into this one.
+    pub easing: Easing,
+}
+
+#[derive(Clone)]
+pub struct AnimationClip {
+    // Sorted by tick; the first keyframe is at tick 0.
+    keyframes: Vec<Keyframe>,
+    playback: Playback,
+}
+impl AnimationClip {
+    /// `keyframes` must not be empty. They are sorted by tick, and shifted so the first one is at
+    /// tick 0.
+    pub fn new(mut keyframes: Vec<Keyframe>, playback: Playback) -> Self {
+        assert!(!keyframes.is_empty(), "an animation clip needs at least one keyframe");
+        keyframes.sort_by_key(|keyframe| keyframe.tick);
+        let first = keyframes[0].tick;
+        for keyframe in &mut keyframes {
+            keyframe.tick -= first;
+        }
+        AnimationClip { keyframes, playback }
+    }
+    pub fn duration_ticks(&self) -> u32 {
+        self.keyframes.last().unwrap().tick
+    }
+    /// Whether a clip started at tick 0 has reached its end by `tick`. Looping clips never finish.
+    pub fn is_finished(&self, tick: u32) -> bool {
+        self.playback == Playback::Once && tick >= self.duration_ticks()
+    }
+    /// Where the clip has the instance `tick` ticks after it started.
+    pub fn sample(&self, tick: u32) -> Transform {
+        let duration = self.duration_ticks();
+        let tick = if duration == 0 {
+            0
+        } else {
+            match self.playback {
+                Playback::Once => tick.min(duration),
+                Playback::Loop => tick % duration,
+                Playback::PingPong => {
+                    let phase = tick % (2 * duration);
+                    if phase <= duration {
+                        phase
+                    } else {
+                        2 * duration - phase
+                    }
+                }
+            }
+        };
+        let next = self.keyframes.partition_point(|keyframe| keyframe.tick <= tick);
+        if next == 0 || next == self.keyframes.len() {
+            return self.keyframes[next.saturating_sub(1)].transform;
+        }
+        let (from, to) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        let t = to.easing.apply((tick - from.tick) as f32 / (to.tick - from.tick) as f32);
+        from.transform.lerp(&to.transform, t)
+    }
+}
+
+// Which instance an animation drives: `instance` within GameState::instanced_entities[`entity`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstanceRef {
+    pub entity: usize,
+    pub instance: usize,
+}
+
+#[derive(Clone)]
+pub struct EntityAnimation {
+    pub target: InstanceRef,
+    pub clip: AnimationClip,
+    pub start_tick: isize,
+}
+impl EntityAnimation {
+    fn local_tick(&self, tick: isize) -> u32 {
+        (tick - self.start_tick).max(0) as u32
+    }
+    pub fn apply(&self, tick: isize, instance: &mut Instance) {
+        instance.transform = self.clip.sample(self.local_tick(tick));
+    }
+    pub fn is_finished(&self, tick: isize) -> bool {
+        self.clip.is_finished(self.local_tick(tick))
+    }
+}
@@ -0,0 +1,1411 @@
+/* App: which screen is showing - the menus, the game or the pause menu - and the UI for each. */
+use crate::{
+    achievements::{Achievements, ACHIEVEMENTS_FILE},
+    camera::Camera,
+    debug_lines::DebugLines,
+    events::GameEvent,
+    free_camera::{FlyInput, FreeCamera},
+    game_state::{GameState, InputState},
+    headless::HEADLESS_ASPECT_RATIO,
+    jobs::JobPool,
+    level::{Level, LevelStats},
+    log_println,
+    minimap::MinimapGrid,
+    movement::MovementMode,
+    net::{HostEvent, RaceEvent, RaceStart, Session, SharedState},
+    records::{Records, RECORDS_FILE},
+    replay::{self, Replay},
+    resources,
+    rollback::Rollback,
+    save::{SaveGame, SAVE_FILE},
+    settings::{Control, KeyBindings, Setting, Settings, SETTINGS_FILE},
+    strings::{Language, StringTable, LANG_EXTENSION},
+    text_field::{TextField, TextKey},
+    tick_rate,
+    toasts::Toasts,
+    ui::UiLayer,
+};
+
+use cgmath::EuclideanSpace;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const PACK_DIR: &str = "levels";
+const PACK_EXTENSION: &str = "xsb";
+const TITLE_COLOR: [f32; 4] = [1.0, 0.85, 0.45, 1.0];
+const ITEM_COLOR: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+const SELECTED_ITEM_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const SELECTION_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.15];
+// Drawn over the game behind the menus, so the menu text stays readable.
+const MENU_BACKDROP_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+const PAUSE_BACKDROP_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+const DEBUG_TEXT_COLOR: [f32; 4] = [1.0, 1.0, 0.3, 1.0];
+// Mouse counts per tick at which acceleration 1 doubles how far the camera turns.
+const MOUSE_ACCELERATION_SPEED: f32 = 20.0;
+// The longest a level's name can be typed.
+const LEVEL_NAME_MAX_CHARS: usize = 32;
+// The game speeds `cycle_time_scale` steps through: slow motion, normal and fast-forward.
+const TIME_SCALES: [f32; 3] = [0.25, 1.0, 4.0];
+// The range `set_time_scale` keeps to.
+const MIN_TIME_SCALE: f32 = 0.1;
+const MAX_TIME_SCALE: f32 = 10.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AppState {
+    Menu,
+    Playing,
+    // The game is frozen behind the pause menu.
+    Paused,
+    // TODO: there is no level editor yet, this is a placeholder screen with only a name field.
+    Editor,
+}
+
+// A screen of the menus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Page {
+    Main,
+    Packs,
+    // The levels of the pack at this index in App::packs.
+    Levels(usize),
+    Settings,
+    // The replay files found when the page opened, in App::replay_files.
+    Replays,
+    Paused,
+    // Shown once the last level of the pack at this index in App::packs is solved.
+    PackComplete(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Action {
+    Continue,
+    Open(Page),
+    Play { pack: usize, level: usize },
+    Adjust(Setting),
+    Bind(Control),
+    Editor,
+    Resume,
+    Restart,
+    // Saves the fastest run at the current level as a replay file.
+    ExportReplay,
+    // Checks the replay at this index in App::replay_files, then plays its level with it as the
+    // ghost.
+    ImportReplay(usize),
+    MainMenu,
+    Quit,
+}
+
+struct MenuItem {
+    label: String,
+    action: Action,
+}
+
+// A pack's strings are in `<pack>.<language code>.lang` beside it, with the keys `name`,
+// `level.<n>.name` and `level.<n>.hint`, counting levels from 1. Its English strings fill in for
+// any the language's table is missing.
+struct Pack {
+    file: String,
+    levels: Vec<Level>,
+    strings: StringTable,
+}
+impl Pack {
+    // The file name without its extension.
+    fn path_stem(&self) -> &str {
+        self.file.strip_suffix(&format!(".{}", PACK_EXTENSION)).unwrap_or(&self.file)
+    }
+    // The name the pack gives itself, or its file name without its directory or extension. A
+    // dropped pack's path has Windows separators.
+    fn name(&self) -> &str {
+        let stem = self.path_stem();
+        self.strings.get("name", stem.rsplit(['/', '\\']).next().unwrap_or(stem))
+    }
+    fn level_name(&self, index: usize) -> Option<&str> {
+        let name = self.strings.get(&format!("level.{}.name", index + 1), "");
+        (!name.is_empty()).then_some(name)
+    }
+    fn level_hint(&self, index: usize) -> Option<&str> {
+        let hint = self.strings.get(&format!("level.{}.hint", index + 1), "");
+        (!hint.is_empty()).then_some(hint)
+    }
+    fn load_strings(&mut self, language: Language) {
+        let mut strings = StringTable::new();
+        for code in [Language::English.code(), language.code()] {
+            let file_name = format!("{}.{}.{}", self.path_stem(), code, LANG_EXTENSION);
+            match StringTable::load(&file_name) {
+                Ok(table) => strings.extend(table),
+                Err(e) => log_println!("Skipping strings {}: {:?}", file_name, e),
+            }
+        }
+        self.strings = strings;
+    }
+}
+
+// A race against another game: both play the same level, each showing the other's player as a
+// ghost, simulated here from their input.
+struct Race {
+    // Ticks played here since the race started.
+    tick: u32,
+    opponent: Rollback,
+    // Whether the result, and the games drifting apart, have been announced.
+    decided: bool,
+    desync_reported: bool,
+}
+
+/// What the render thread needs for one frame.
+pub struct Frame {
+    pub game_state: GameState,
+    // The player's camera, or the free camera while it is in use.
+    pub camera: Camera,
+    pub ui: UiLayer,
+    // Shown in a corner of the screen while playing, if the minimap is on.
+    pub minimap: Option<MinimapGrid>,
+    pub settings: Settings,
+    // Drawn over the scene, like the grid overlay. Empty unless a debug view is on.
+    pub debug_lines: DebugLines,
+    // When the game thread made the frame, for measuring how long it takes to reach the screen.
+    pub made_at: Instant,
+    // How fast the game's time passes, relative to real time: 0.0 while it isn't being played.
+    pub time_scale: f32,
+}
+
+/// Owns the game and everything around it: the menus, records, settings and save.
+///
+/// The game only ticks while it is being played. The menus are driven by the same `InputState`:
+/// forward and backward or the mouse wheel move the selection, `confirm` picks the selected item
+/// and `back` leaves the current screen.
+pub struct App {
+    state: AppState,
+    game_state: GameState,
+    packs: Vec<Pack>,
+    records: Records,
+    achievements: Achievements,
+    settings: Settings,
+    save: Option<SaveGame>,
+    // The menu pages open, the current one last, each with the index of its selected item.
+    pages: Vec<(Page, usize)>,
+    // Last tick's input, to move the selection once per key press rather than every tick.
+    previous_input: InputState,
+    // Set from the pause menu, and passed on to the game as input on its next tick.
+    restart_requested: bool,
+    // The control waiting for a key press to bind to it.
+    binding: Option<Control>,
+    // The name of the level in the editor, typed in while it's focused.
+    level_name: TextField,
+    // Mouse movement left over after scaling by the sensitivity, carried to the next tick.
+    mouse_remainder: (f32, f32),
+    // The scaled mouse movement of the last tick, after smoothing.
+    mouse_smoothed: (f32, f32),
+    // The debug fly camera, while it has taken over from the player's.
+    free_camera: Option<FreeCamera>,
+    // Whether the level's grid and tile coordinates are drawn over it.
+    grid_overlay: bool,
+    // Whether quaternion-turned copies of turned instances are drawn beside them.
+    rotation_check: bool,
+    // Messages about what just happened, like a new record, drawn over every screen.
+    toasts: Toasts,
+    // The UI's strings in the chosen language, and that language. None until they first load.
+    strings: StringTable,
+    language: Option<Language>,
+    // Index in `packs` of the pack being played, once a level has started.
+    playing_pack: Option<usize>,
+    // The co-op game being hosted or joined, if any. A joining game doesn't simulate: it shows
+    // the host's levels and sends its player's steps.
+    session: Option<Session>,
+    race: Option<Race>,
+    replay_files: Vec<String>,
+    // Game seconds per real second while playing alone, for slow motion and fast-forward.
+    time_scale: f32,
+    quit_requested: bool,
+}
+impl App {
+    pub fn new(game_state: GameState) -> Self {
+        let files = resources::list_files(PACK_DIR, PACK_EXTENSION).unwrap_or_else(|e| {
+            log_println!("Failed to list level packs: {:?}", e);
+            Vec::new()
+        });
+        // Packs are loaded in parallel on the job pool.
+        let jobs: Vec<_> = files
+            .into_iter()
+            .map(|file| {
+                let job = JobPool::global().spawn({
+                    let file = file.clone();
+                    move || Level::load_pack(&file)
+                });
+                (file, job)
+            })
+            .collect();
+        let packs = jobs
+            .into_iter()
+            .filter_map(|(file, job)| match job.wait().and_then(|loaded| loaded) {
+                Ok(levels) if !levels.is_empty() => {
+                    Some(Pack { file, levels, strings: StringTable::new() })
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    log_println!("Skipping level pack {}: {:?}", file, e);
+                    None
+                }
+            })
+            .collect();
+        let records = Records::load(RECORDS_FILE).unwrap_or_else(|e| {
+            log_println!("Starting without records: {:?}", e);
+            Records::new()
+        });
+        let achievements = Achievements::load(ACHIEVEMENTS_FILE).unwrap_or_else(|e| {
+            log_println!("Starting without lifetime stats: {:?}", e);
+            Achievements::new()
+        });
+        let settings = Settings::load(SETTINGS_FILE).unwrap_or_else(|e| {
+            log_println!("Starting with default settings: {:?}", e);
+            Settings::new()
+        });
+        let save = SaveGame::load(SAVE_FILE).unwrap_or_else(|e| {
+            log_println!("Ignoring save: {:?}", e);
+            None
+        });
+        let mut app = App {
+            state: AppState::Menu,
+            game_state,
+            packs,
+            records,
+            achievements,
+            settings,
+            save,
+            pages: vec![(Page::Main, 0)],
+            previous_input: InputState::new(),
+            restart_requested: false,
+            binding: None,
+            level_name: TextField::new("", LEVEL_NAME_MAX_CHARS),
+            mouse_remainder: (0.0, 0.0),
+            mouse_smoothed: (0.0, 0.0),
+            free_camera: None,
+            grid_overlay: false,
+            rotation_check: false,
+            toasts: Toasts::new(),
+            strings: StringTable::new(),
+            language: None,
+            playing_pack: None,
+            session: None,
+            race: None,
+            replay_files: Vec::new(),
+            time_scale: 1.0,
+            quit_requested: false,
+        };
+        app.apply_settings();
+        app
+    }
+    pub fn state(&self) -> AppState {
+        self.state
+    }
+    pub fn game_state_mut(&mut self) -> &mut GameState {
+        &mut self.game_state
+    }
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+    /// Whether the next key pressed should be passed to `bind_key` rather than handled as input.
+    pub fn is_binding_key(&self) -> bool {
+        self.binding.is_some()
+    }
+    /// Binds `key` to the control picked in the settings menu. A reserved key cancels instead.
+    pub fn bind_key(&mut self, key: u16) {
+        let Some(control) = self.binding.take() else {
+            return;
+        };
+        if !KeyBindings::RESERVED.iter().any(|reserved| reserved.0 == key) {
+            self.settings.bindings.bind(control, key);
+            self.settings_changed();
+        }
+    }
+    /// Whether a text field is focused, so that characters should be passed to `type_char` and
+    /// keys not mapped to controls.
+    pub fn is_entering_text(&self) -> bool {
+        self.state == AppState::Editor && self.level_name.is_focused()
+    }
+    /// Types a character, as delivered by WM_CHAR or the IME, into the focused text field.
+    pub fn type_char(&mut self, c: char) {
+        if self.is_entering_text() {
+            self.level_name.type_char(c);
+        }
+    }
+    /// Passes an editing key to the focused text field.
+    pub fn press_text_key(&mut self, key: TextKey) {
+        if self.is_entering_text() {
+            self.level_name.press(key);
+        }
+    }
+    /// Shows or hides the level's grid lines and tile coordinates.
+    pub fn toggle_grid_overlay(&mut self) {
+        self.grid_overlay = !self.grid_overlay;
+    }
+    /// Shows or hides a quaternion-turned copy of every turned instance, to check the shader's
+    /// rotors against.
+    pub fn toggle_rotation_check(&mut self) {
+        self.rotation_check = !self.rotation_check;
+    }
+    /// Switches the player between physics movement and tile-by-tile steps. Not while joined to
+    /// someone else's game, which moves the player for us, or racing, where both games have to
+    /// play by the same rules.
+    pub fn toggle_movement_mode(&mut self) {
+        if self.state != AppState::Playing || !matches!(self.session, None | Some(Session::Host(_)))
+        {
+            return;
+        }
+        let mode = self.game_state.get_movement_mode().toggled();
+        self.game_state.set_movement_mode(mode);
+        match mode {
+            MovementMode::Physics => self.toast("toast.physics_movement", "Physics movement"),
+            MovementMode::Step => self.toast("toast.step_movement", "Step movement"),
+        }
+    }
+    /// How fast the game's time passes against real time. The chosen time scale only applies while
+    /// playing alone: the menus keep to real time, and so do sessions, which the other game paces.
+    pub fn time_scale(&self) -> f32 {
+        if self.state == AppState::Playing && self.session.is_none() {
+            self.time_scale
+        } else {
+            1.0
+        }
+    }
+    /// The real time between ticks at the current time scale. Ticks always move the game on by the
+    /// same time, so scaling it doesn't change where the simulation goes.
+    pub fn real_time_per_tick(&self) -> Duration {
+        tick_rate::time_per_tick().div_f32(self.time_scale())
+    }
+    pub fn set_time_scale(&mut self, scale: f32) {
+        self.time_scale = scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+    /// Steps to the next of slow motion, normal speed and fast-forward. Not in a session, which
+    /// plays in real time.
+    pub fn cycle_time_scale(&mut self) {
+        if self.state != AppState::Playing || self.session.is_some() {
+            return;
+        }
+        let next = TIME_SCALES.into_iter().find(|&scale| scale > self.time_scale);
+        self.time_scale = next.unwrap_or(TIME_SCALES[0]);
+        let text = self.strings.format("toast.time_scale", "Game speed {0}x", &[&self.time_scale]);
+        self.toasts.push(text);
+    }
+    /// Opens a file dropped on the window: a level pack starts at its first level, and a replay
+    /// plays its level with it as the ghost. Not while joined to someone else's game, which picks
+    /// the levels for us.
+    pub fn open_file(&mut self, path: &str) {
+        if self.joined() || self.binding.is_some() {
+            return;
+        }
+        let extension = std::path::Path::new(path).extension().and_then(|e| e.to_str());
+        match extension {
+            Some(e) if e.eq_ignore_ascii_case(PACK_EXTENSION) => self.open_pack(path),
+            Some(e) if e.eq_ignore_ascii_case(replay::REPLAY_EXTENSION) => self.import_replay(path),
+            _ => {
+                log_println!("Ignoring dropped file {}", path);
+                self.toast("toast.file_unknown", "That isn't a level pack or a replay");
+            }
+        }
+    }
+    /// Hosts or joins a co-op game.
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+    /// Whether Quit was picked since the last call.
+    pub fn take_quit_request(&mut self) -> bool {
+        std::mem::take(&mut self.quit_requested)
+    }
+    /// Advances by one game tick. Returns the input the game was updated with, if it was updated.
+    pub fn update(
+        &mut self,
+        input: &InputState,
+        fly: &FlyInput,
+        step_time: Instant,
+    ) -> Option<InputState> {
+        let previous = std::mem::replace(&mut self.previous_input, input.clone());
+        // The toasts and the fly camera go by real time, whatever the time scale.
+        let real_delta_t = tick_rate::tick_secs() / self.time_scale();
+        self.toasts.update(real_delta_t);
+        self.receive_session(step_time);
+        let updated = match self.state {
+            AppState::Playing if input.back => {
+                self.write_achievements();
+                self.state = AppState::Paused;
+                self.pages = vec![(Page::Paused, 0)];
+                None
+            }
+            AppState::Playing if self.joined() => {
+                self.push_steps(input, &previous);
+                let (mouse_x, mouse_y) = self.scale_mouse(input);
+                let input =
+                    InputState { mouse_x, mouse_y, wheel: input.wheel, ..InputState::new() };
+                self.game_state.update_replica(&input, step_time);
+                None
+            }
+            AppState::Playing => {
+                if fly.toggle {
+                    self.free_camera = match self.free_camera {
+                        Some(_) => None,
+                        None => Some(FreeCamera::new(self.game_state.get_camera())),
+                    };
+                }
+                let (mouse_x, mouse_y) = self.scale_mouse(input);
+                let mut input = if let Some(free_camera) = &mut self.free_camera {
+                    // The wheel steps the speed too.
+                    let fly = FlyInput { speed_steps: fly.speed_steps + input.wheel, ..*fly };
+                    free_camera.update(&fly, mouse_x, mouse_y, real_delta_t);
+                    // The player stands still while the camera is away.
+                    InputState::new()
+                } else {
+                    InputState { mouse_x, mouse_y, confirm: false, back: false, ..input.clone() }
+                };
+                input.restart |= std::mem::take(&mut self.restart_requested);
+                self.game_state.update(&input, step_time);
+                self.handle_game_events();
+                Some(input)
+            }
+            AppState::Editor => {
+                // Enter and Escape go to the name while it's focused, so these only come once it
+                // isn't.
+                if input.back {
+                    self.state = AppState::Menu;
+                } else if input.confirm {
+                    self.level_name.focus();
+                }
+                None
+            }
+            AppState::Menu | AppState::Paused => {
+                self.update_menu(input, &previous);
+                None
+            }
+        };
+        if let (Some(input), Some(race)) = (&updated, &mut self.race) {
+            race.tick += 1;
+            if let Some(Session::Race(peer)) = &mut self.session {
+                peer.push_input(input, self.game_state.simulation_checksum());
+            }
+        }
+        self.update_race();
+        self.send_session();
+        updated
+    }
+    /// The game as it stands, with the UI drawn over it for a `width` by `height` window.
+    pub fn frame(&self, width: f32, height: f32) -> Frame {
+        let camera = self.free_camera.map_or(self.game_state.get_camera(), |free| free.camera());
+        let mut debug_lines = DebugLines::new();
+        if self.grid_overlay && self.state == AppState::Playing {
+            debug_lines
+                .level_grid(self.game_state.get_level(), self.game_state.get_level_placement());
+        }
+        if self.rotation_check && self.state == AppState::Playing {
+            debug_lines.rotation_check(&self.game_state.instanced_entities, &camera);
+        }
+        let minimap = (self.settings.minimap && self.state == AppState::Playing).then(|| {
+            MinimapGrid::new(self.game_state.get_level(), self.game_state.get_player_tile())
+        });
+        Frame {
+            game_state: self.game_state.clone(),
+            camera,
+            ui: self.draw_ui(&camera, width, height),
+            minimap,
+            settings: self.settings,
+            debug_lines,
+            made_at: Instant::now(),
+            time_scale: if self.state == AppState::Playing { self.time_scale() } else { 0.0 },
+        }
+    }
+    // Mouse movement scaled by the sensitivity and acceleration, flipped if Y is inverted, and
+    // smoothed. Fractions of a count carry over to the next tick, so low sensitivities still turn
+    // smoothly. This happens before the game sees the input, so replays turn the same whatever
+    // the settings of whoever watches them.
+    fn scale_mouse(&mut self, input: &InputState) -> (i32, i32) {
+        let (dx, dy) = (input.mouse_x as f32, input.mouse_y as f32);
+        let speed = (dx * dx + dy * dy).sqrt();
+        let acceleration =
+            1.0 + self.settings.mouse_acceleration * speed / MOUSE_ACCELERATION_SPEED;
+        let gain = self.settings.mouse_sensitivity * acceleration;
+        let invert = if self.settings.invert_y { -1.0 } else { 1.0 };
+        let keep = self.settings.mouse_smoothing;
+        let smoothed = &mut self.mouse_smoothed;
+        smoothed.0 = smoothed.0 * keep + dx * gain * (1.0 - keep);
+        smoothed.1 = smoothed.1 * keep + dy * gain * invert * (1.0 - keep);
+        let x = smoothed.0 + self.mouse_remainder.0;
+        let y = smoothed.1 + self.mouse_remainder.1;
+        self.mouse_remainder = (x.fract(), y.fract());
+        (x.trunc() as i32, y.trunc() as i32)
+    }
+    // Applies the settings the game reads directly. The render thread applies the rest from each
+    // Frame.
+    fn apply_settings(&mut self) {
+        self.game_state.change_camera_fov(self.settings.fov);
+        self.game_state.set_fov_kick(self.settings.fov_kick);
+        self.game_state.set_camera_stiffness(self.settings.camera_stiffness);
+        self.game_state.set_camera_shake(self.settings.camera_shake);
+        self.game_state.set_day_night(self.settings.day_night);
+        if self.language != Some(self.settings.language) {
+            self.load_strings();
+        }
+    }
+    fn load_strings(&mut self) {
+        let language = self.settings.language;
+        self.strings = StringTable::load_language(language);
+        for pack in &mut self.packs {
+            pack.load_strings(language);
+        }
+        self.language = Some(language);
+    }
+    // Shows a toast with the text for `key`, or `english`.
+    fn toast(&mut self, key: &str, english: &str) {
+        let text = self.strings.get(key, english).to_string();
+        self.toasts.push(text);
+    }
+    fn joined(&self) -> bool {
+        matches!(self.session, Some(Session::Client(_)))
+    }
+    // Hears from the other game of a co-op session.
+    fn receive_session(&mut self, now: Instant) {
+        match &mut self.session {
+            Some(Session::Host(host)) => {
+                let events = host.receive(now).unwrap_or_else(|e| {
+                    log_println!("Co-op: {:?}", e);
+                    Vec::new()
+                });
+                for event in events {
+                    self.handle_host_event(event);
+                }
+            }
+            Some(Session::Race(peer)) => {
+                let events = peer.receive(now).unwrap_or_else(|e| {
+                    log_println!("Race: {:?}", e);
+                    Vec::new()
+                });
+                for event in events {
+                    self.handle_race_event(event);
+                }
+            }
+            Some(Session::Client(client)) => {
+                let received = client.receive(now);
+                let timed_out = client.timed_out(now);
+                match received {
+                    Ok(Some(state)) => self.show_host_state(&state),
+                    Ok(None) => {}
+                    Err(e) => log_println!("Co-op: {:?}", e),
+                }
+                if timed_out {
+                    log_println!("Co-op: the host stopped answering");
+                    self.leave_session("toast.host_lost", "Lost the connection to the host");
+                }
+            }
+            None => {}
+        }
+    }
+    fn handle_host_event(&mut self, event: HostEvent) {
+        match event {
+            HostEvent::Joined(address) => {
+                log_println!("Co-op: {} joined", address);
+                self.game_state.add_partner();
+                self.toast("toast.partner_joined", "A second player joined");
+            }
+            HostEvent::Left => {
+                log_println!("Co-op: the second player left");
+                self.game_state.remove_partner();
+                self.toast("toast.partner_left", "The second player left");
+            }
+            // Like the host's own input, steps are dropped while the game isn't being played.
+            HostEvent::Step(direction) => {
+                if self.state == AppState::Playing {
+                    self.game_state.step_partner(direction);
+                }
+            }
+        }
+    }
+    // Sends the other game of a co-op session what it needs: the host's state every tick, even
+    // in the menus so the joining game knows the host is still there, or the joining player's
+    // steps.
+    fn send_session(&mut self) {
+        let result = match &mut self.session {
+            Some(Session::Host(host)) => {
+                let pack = self.playing_pack.map_or("", |pack| self.packs[pack].file.as_str());
+                let tick = self.game_state.get_tick() as u32;
+                host.send_state(tick, self.game_state.shared_state(pack))
+            }
+            Some(Session::Client(client)) => client.send(),
+            Some(Session::Race(peer)) => peer.send(),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            log_println!("Co-op: {:?}", e);
+        }
+    }
+    fn handle_race_event(&mut self, event: RaceEvent) {
+        match event {
+            RaceEvent::Joined(address) => {
+                log_println!("Race: {} joined", address);
+                self.toast("toast.opponent_joined", "An opponent joined. Start a level to race");
+            }
+            RaceEvent::Left => {
+                log_println!("Race: the other game stopped answering");
+                self.end_race();
+                self.toast("toast.opponent_left", "Your opponent left");
+                if let Some(Session::Race(peer)) = &self.session {
+                    if !peer.is_hosting() {
+                        self.session = None;
+                    }
+                }
+            }
+            RaceEvent::Start(start) => self.join_race(&start),
+            RaceEvent::Input { tick, input, checksum } => {
+                if let Some(race) = &mut self.race {
+                    race.opponent.receive(tick, input, checksum);
+                }
+            }
+        }
+    }
+    // Whether picking a level starts a race: while hosting one, with an opponent there.
+    fn hosting_race(&self) -> bool {
+        matches!(&self.session, Some(Session::Race(peer)) if peer.is_hosting() && peer.has_peer())
+    }
+    // Starts racing on level `level` of `levels`. Both games start from a fresh game rather than
+    // from wherever they were, so each can simulate the other exactly from its input.
+    fn begin_race(&mut self, levels: Vec<Level>, level: usize) {
+        let mut start = GameState::new(HEADLESS_ASPECT_RATIO);
+        start.play_pack(levels, level);
+        let aspect = self.game_state.get_camera().aspect();
+        self.game_state = start.clone();
+        self.game_state.change_camera_aspect(aspect);
+        self.apply_settings();
+        self.race = Some(Race {
+            tick: 0,
+            opponent: Rollback::new(start, Instant::now()),
+            decided: false,
+            desync_reported: false,
+        });
+    }
+    // Starts the race the host picked, on the joining side.
+    fn join_race(&mut self, start: &RaceStart) {
+        let index = start.level_index as usize;
+        let found = self.packs.iter().position(|pack| {
+            pack.file == start.pack
+                && pack.levels.get(index).is_some_and(|level| level.id == start.level_id)
+        });
+        let Some(pack) = found else {
+            log_println!("Race: no level {} in {} like the host's", index + 1, start.pack);
+            self.end_race();
+            self.toast("toast.race_level_missing", "The host picked a level you don't have");
+            return;
+        };
+        self.begin_race(self.packs[pack].levels.clone(), index);
+        self.playing_pack = Some(pack);
+        self.toast("toast.race_started", "Race started");
+        self.toast_level_intro(pack, index);
+        self.resume();
+    }
+    fn end_race(&mut self) {
+        self.race = None;
+        self.game_state.show_opponent(None);
+    }
+    // Moves the opponent's game on and shows them while they are on the same level. Announces
+    // the result once it can't change: when both have finished the race's level, or when one has
+    // and the other has played longer without.
+    fn update_race(&mut self) {
+        let Some(race) = &mut self.race else {
+            return;
+        };
+        race.opponent.advance();
+        let opponent = race.opponent.state();
+        let same_level = opponent.get_level().id == self.game_state.get_level().id;
+        let position = same_level.then(|| opponent.get_player_position().to_vec());
+        self.game_state.show_opponent(position);
+        if race.opponent.desynced() && !race.desync_reported {
+            race.desync_reported = true;
+            self.toast("toast.race_desync", "The race went out of sync");
+        }
+        let Some(race) = &mut self.race else {
+            return;
+        };
+        let mine = self.game_state.get_completed_levels().first().copied();
+        let theirs = race.opponent.confirmed_state().get_completed_levels().first().copied();
+        let won = match (mine, theirs) {
+            (Some(mine), Some(theirs)) => Some(mine.ticks.cmp(&theirs.ticks)),
+            (Some(mine), None) if race.opponent.confirmed_tick() > mine.ticks => {
+                Some(std::cmp::Ordering::Less)
+            }
+            (None, Some(theirs)) if race.tick > theirs.ticks => Some(std::cmp::Ordering::Greater),
+            _ => None,
+        };
+        let Some(order) = won.filter(|_| !race.decided) else {
+            return;
+        };
+        race.decided = true;
+        let text = match (order, mine, theirs) {
+            (std::cmp::Ordering::Less, Some(stats), _) => {
+                let stats = self.format_stats(&stats);
+                self.strings.format("toast.race_won", "You won the race: {0}", &[&stats])
+            }
+            (std::cmp::Ordering::Greater, _, Some(stats)) => {
+                let stats = self.format_stats(&stats);
+                self.strings.format("toast.race_lost", "Your opponent won the race: {0}", &[&stats])
+            }
+            _ => self.strings.get("toast.race_tie", "The race is a tie").to_string(),
+        };
+        self.toasts.push(text);
+    }
+    // On the joining side, each press of a movement key is a step, relative to where the camera
+    // faces.
+    fn push_steps(&mut self, input: &InputState, previous: &InputState) {
+        let Some(Session::Client(client)) = &mut self.session else {
+            return;
+        };
+        let facing = self.game_state.get_facing();
+        let presses = [
+            (input.forward && !previous.forward, facing),
+            (input.backward && !previous.backward, facing.opposite()),
+            (input.right && !previous.right, facing.clockwise()),
+            (input.left && !previous.left, facing.clockwise().opposite()),
+        ];
+        for (_, direction) in presses.into_iter().filter(|(pressed, _)| *pressed) {
+            client.push_step(direction);
+        }
+    }
+    // Shows the host's state on the joining side, first starting the host's level if it isn't
+    // the one showing. Until the host starts a level, this game waits in the menus.
+    fn show_host_state(&mut self, state: &SharedState) {
+        if state.pack.is_empty() {
+            return;
+        }
+        let index = state.level_index as usize;
+        let showing = self.game_state.get_level_index() == index
+            && self.game_state.get_level().id == state.level_id;
+        if !showing || self.playing_pack.is_none() {
+            let found = self.packs.iter().position(|pack| {
+                pack.file == state.pack
+                    && pack.levels.get(index).is_some_and(|level| level.id == state.level_id)
+            });
+            let Some(pack) = found else {
+                log_println!("Co-op: no level {} in {} like the host's", index + 1, state.pack);
+                self.leave_session(
+                    "toast.host_level_missing",
+                    "The host is playing a level you don't have",
+                );
+                return;
+            };
+            self.game_state.play_pack(self.packs[pack].levels.clone(), index);
+            self.playing_pack = Some(pack);
+            self.toast_level_intro(pack, index);
+        }
+        self.game_state.apply_shared_state(state);
+        if self.state == AppState::Menu {
+            self.resume();
+        }
+    }
+    // Ends a co-op session from the joining side, back to the main menu.
+    fn leave_session(&mut self, key: &str, english: &str) {
+        self.session = None;
+        self.game_state.remove_partner();
+        self.toast(key, english);
+        self.state = AppState::Menu;
+        self.pages = vec![(Page::Main, 0)];
+    }
+    fn settings_changed(&mut self) {
+        self.apply_settings();
+        if let Err(e) = self.settings.save(SETTINGS_FILE) {
+            log_println!("Failed to save settings: {:?}", e);
+            self.toast("toast.settings_failed", "Failed to save settings");
+        }
+    }
+    fn handle_game_events(&mut self) {
+        let events = self.game_state.get_events().iter();
+        let unlocked = events.flat_map(|event| self.achievements.record(event)).collect::<Vec<_>>();
+        for achievement in &unlocked {
+            log_println!("Achievement unlocked: {}", achievement.id);
+            let key = format!("achievement.{}", achievement.id);
+            let name = self.strings.get(&key, achievement.name).to_string();
+            let text =
+                self.strings.format("toast.achievement", "Achievement unlocked: {0}", &[&name]);
+            self.toasts.push(text);
+        }
+        if !unlocked.is_empty() {
+            self.write_achievements();
+        }
+        let completed = self.game_state.get_events().iter().filter_map(|event| match *event {
+            GameEvent::LevelCompleted { level, stats } => Some((level, stats)),
+            _ => None,
+        });
+        for (level, stats) in completed.collect::<Vec<_>>() {
+            let stats_text = self.format_stats(&stats);
+            let text =
+                self.strings.format("toast.level_complete", "Level complete: {0}", &[&stats_text]);
+            self.toasts.push(text);
+            self.update_records(level, stats);
+            self.write_achievements();
+            // Continue from the next level, or replay the last one if the pack is done.
+            let index = self.game_state.get_level_index();
+            let next = (index + 1).min(self.game_state.get_level_count().saturating_sub(1));
+            if let Some(save) = &mut self.save {
+                save.level = next;
+                save.checkpoint = None;
+            }
+            self.write_save();
+            let last_level = index + 1 >= self.game_state.get_level_count();
+            if let (true, None, Some(pack)) = (last_level, &self.session, self.playing_pack) {
+                self.state = AppState::Menu;
+                self.pages = vec![(Page::Main, 0), (Page::PackComplete(pack), 0)];
+            }
+        }
+        let reached =
+            self.game_state.get_events().iter().any(|event| *event == GameEvent::CheckpointReached);
+        if reached && self.session.is_none() {
+            if let Some(save) = &mut self.save {
+                save.checkpoint = Some(self.game_state.get_checkpoint().clone());
+            }
+            self.write_save();
+        }
+    }
+    // Records a finished level, reporting how it compares to the previous best.
+    fn update_records(&mut self, level: u64, stats: LevelStats) {
+        if let Some(best) = self.records.get(level) {
+            log_println!(
+                "Level {:016x} records: {} moves, {} pushes, {} ticks",
+                level,
+                best.moves,
+                best.pushes,
+                best.ticks
+            );
+        }
+        if self.records.submit(level, stats) {
+            log_println!("New record for level {:016x}!", level);
+            self.toast("toast.new_record", "New record!");
+            if let Err(e) = self.records.save(RECORDS_FILE) {
+                log_println!("Failed to save records: {:?}", e);
+                self.toast("toast.records_failed", "Failed to save records");
+            }
+        }
+    }
+    // The lifetime stats go to disk when a level ends, an achievement unlocks or the game pauses,
+    // rather than on every step.
+    fn write_achievements(&mut self) {
+        if let Err(e) = self.achievements.save(ACHIEVEMENTS_FILE) {
+            log_println!("Failed to save achievements: {:?}", e);
+            self.toast("toast.achievements_failed", "Failed to save achievements");
+        }
+    }
+    fn write_save(&mut self) {
+        if let Some(save) = &self.save {
+            if let Err(e) = save.save(SAVE_FILE) {
+                log_println!("Failed to save: {:?}", e);
+                self.toast("toast.save_failed", "Failed to save progress");
+            }
+        }
+    }
+    fn start_level(&mut self, pack: usize, level: usize) {
+        let joined_race = matches!(&self.session, Some(Session::Race(peer)) if !peer.is_hosting());
+        if joined_race && self.race.is_some() {
+            self.toast("toast.host_picks_level", "The host picks the race's level");
+            return;
+        }
+        let pack_levels = self.packs[pack].levels.clone();
+        let level = level.min(pack_levels.len() - 1);
+        if self.hosting_race() {
+            if let Some(Session::Race(peer)) = &mut self.session {
+                peer.start(RaceStart {
+                    id: 0,
+                    pack: self.packs[pack].file.clone(),
+                    level_index: level as u32,
+                    level_id: pack_levels[level].id,
+                });
+            }
+            self.begin_race(pack_levels, level);
+        } else {
+            self.game_state.play_pack(pack_levels, level);
+        }
+        self.playing_pack = Some(pack);
+        self.save = Some(SaveGame { pack: self.packs[pack].file.clone(), level, checkpoint: None });
+        self.write_save();
+        self.toast_level_intro(pack, level);
+        self.resume();
+    }
+    // Shows the name and hint the pack gives the level, if it has them.
+    fn toast_level_intro(&mut self, pack: usize, level: usize) {
+        let pack = &self.packs[pack];
+        for text in [pack.level_name(level), pack.level_hint(level)].into_iter().flatten() {
+            self.toasts.push(text);
+        }
+    }
+    fn resume(&mut self) {
+        self.state = AppState::Playing;
+        self.pages.clear();
+    }
+    // The pack and level the save continues from, if its pack is still around.
+    fn saved_level(&self) -> Option<(usize, usize)> {
+        let save = self.save.as_ref()?;
+        let pack = self.packs.iter().position(|pack| pack.file == save.pack)?;
+        Some((pack, save.level))
+    }
+    fn update_menu(&mut self, input: &InputState, previous: &InputState) {
+        let Some(&(page, selected)) = self.pages.last() else {
+            return;
+        };
+        let items = self.menu_items(page);
+        let count = items.len().max(1);
+        let mut selected = selected.min(count - 1);
+        if input.forward && !previous.forward {
+            selected = (selected + count - 1) % count;
+        }
+        if input.backward && !previous.backward {
+            selected = (selected + 1) % count;
+        }
+        // Unlike the keys, the wheel stops at the ends of the list.
+        selected = (selected as i32 - input.wheel).clamp(0, count as i32 - 1) as usize;
+        if let Some(current) = self.pages.last_mut() {
+            current.1 = selected;
+        }
+        // Left and right step the selected setting down and up.
+        if let Some(&MenuItem { action: Action::Adjust(setting), .. }) = items.get(selected) {
+            let steps =
+                (input.right && !previous.right) as i32 - (input.left && !previous.left) as i32;
+            if steps != 0 {
+                self.settings.adjust(setting, steps);
+                self.settings_changed();
+            }
+        }
+        if input.back {
+            self.go_back();
+        } else if input.confirm {
+            if let Some(item) = items.get(selected) {
+                self.activate(item.action);
+            }
+        }
+    }
+    // Leaves the current page. Leaving the pause menu resumes the game, and the main menu has
+    // nowhere to go back to.
+    fn go_back(&mut self) {
+        if self.pages.len() > 1 {
+            self.pages.pop();
+        } else if self.state == AppState::Paused {
+            self.resume();
+        }
+    }
+    fn activate(&mut self, action: Action) {
+        match action {
+            Action::Continue => {
+                if let Some((pack, level)) = self.saved_level() {
+                    let checkpoint = self.save.as_ref().and_then(|save| save.checkpoint.clone());
+                    self.start_level(pack, level);
+                    // Races and co-op games start levels afresh.
+                    if let (Some(checkpoint), None) = (checkpoint, &self.session) {
+                        self.game_state.resume_from_checkpoint(checkpoint.clone());
+                        if let Some(save) = &mut self.save {
+                            save.checkpoint = Some(checkpoint);
+                        }
+                        self.write_save();
+                    }
+                }
+            }
+            Action::Open(Page::Replays) => {
+                self.replay_files = replay::list_files().unwrap_or_else(|e| {
+                    log_println!("Failed to list replays: {:?}", e);
+                    Vec::new()
+                });
+                self.pages.push((Page::Replays, 0));
+            }
+            Action::Open(page) => self.pages.push((page, 0)),
+            Action::Play { pack, level } => self.start_level(pack, level),
+            Action::Adjust(setting) => {
+                self.settings.adjust(setting, 1);
+                self.settings_changed();
+            }
+            Action::Bind(control) => self.binding = Some(control),
+            Action::Editor => {
+                self.state = AppState::Editor;
+                self.level_name.focus();
+            }
+            Action::Resume => self.resume(),
+            Action::Restart => {
+                self.restart_requested = true;
+                self.resume();
+            }
+            Action::ExportReplay => self.export_replay(),
+            Action::ImportReplay(index) => {
+                let path = self.replay_files[index].clone();
+                self.import_replay(&path);
+            }
+            Action::MainMenu if self.joined() => {
+                log_println!("Co-op: left the host's game");
+                self.leave_session("toast.left_host", "Left the host's game");
+            }
+            Action::MainMenu => {
+                self.state = AppState::Menu;
+                self.pages = vec![(Page::Main, 0)];
+            }
+            Action::Quit => self.quit_requested = true,
+        }
+    }
+    fn export_replay(&mut self) {
+        let Some(run) = self.game_state.get_best_run() else {
+            return;
+        };
+        let exported = Replay::record(&run).and_then(|replay| replay.export());
+        match exported {
+            Ok(path) => {
+                log_println!("Saved replay {}", path);
+                let text =
+                    self.strings.format("toast.replay_saved", "Replay saved to {0}", &[&path]);
+                self.toasts.push(text);
+            }
+            Err(e) => {
+                log_println!("Failed to export replay: {:?}", e);
+                self.toast("toast.replay_save_failed", "Replay couldn't be saved");
+            }
+        }
+    }
+    // Adds the pack at `path` to the packs, or reloads it if it already is one, and plays its first
+    // level.
+    fn open_pack(&mut self, path: &str) {
+        let levels = match Level::load_pack_path(path) {
+            Ok(levels) if !levels.is_empty() => levels,
+            Ok(_) => {
+                log_println!("Level pack {} has no levels", path);
+                self.toast("toast.pack_unreadable", "That level pack can't be read");
+                return;
+            }
+            Err(e) => {
+                log_println!("Failed to open level pack: {:?}", e);
+                self.toast("toast.pack_unreadable", "That level pack can't be read");
+                return;
+            }
+        };
+        let pack = match self.packs.iter().position(|pack| pack.file == path) {
+            Some(pack) => {
+                self.packs[pack].levels = levels;
+                pack
+            }
+            None => {
+                let mut pack = Pack { file: path.to_string(), levels, strings: StringTable::new() };
+                pack.load_strings(self.settings.language);
+                self.packs.push(pack);
+                self.packs.len() - 1
+            }
+        };
+        self.start_level(pack, 0);
+    }
+    fn import_replay(&mut self, path: &str) {
+        let replay = match Replay::load(path) {
+            Ok(replay) => replay,
+            Err(e) => {
+                log_println!("Failed to import replay: {:?}", e);
+                self.toast("toast.replay_unreadable", "That replay can't be read");
+                return;
+            }
+        };
+        let found = self.packs.iter().enumerate().find_map(|(pack, contents)| {
+            let level = contents.levels.iter().position(|level| level.id == replay.level_id);
+            level.map(|level| (pack, level))
+        });
+        let Some((pack, level)) = found else {
+            log_println!("No level {:016x} for replay {}", replay.level_id, path);
+            self.toast("toast.replay_level_missing", "You don't have the replay's level");
+            return;
+        };
+        let level_data = &self.packs[pack].levels[level];
+        if let Err(e) = replay.verify(level_data) {
+            log_println!("Replay {} doesn't check out: {:?}", path, e);
+            self.toast("toast.replay_invalid", "The replay doesn't solve its level");
+            return;
+        }
+        let run = Arc::new(replay.best_run(level_data));
+        self.game_state.set_best_run(replay.level_id, run);
+        self.start_level(pack, level);
+        let stats = self.format_stats(&replay.stats);
+        let text =
+            self.strings.format("toast.replay_verified", "Replay checks out: {0}", &[&stats]);
+        self.toasts.push(text);
+    }
+    fn format_stats(&self, stats: &LevelStats) -> String {
+        let secs = format!("{:.2}", stats.ticks as f32 * tick_rate::tick_secs());
+        self.strings.format(
+            "stats",
+            "{0} moves {1} pushes {2}s",
+            &[&stats.moves, &stats.pushes, &secs],
+        )
+    }
+    fn page_title(&self, page: Page) -> String {
+        let strings = &self.strings;
+        match page {
+            Page::Main => strings.get("title.main", "Cube").to_string(),
+            Page::Packs => strings.get("title.packs", "Level packs").to_string(),
+            Page::Levels(pack) => self.packs[pack].name().to_string(),
+            Page::Settings => strings.get("title.settings", "Settings").to_string(),
+            Page::Replays => strings.get("title.replays", "Replays").to_string(),
+            Page::Paused => strings.get("title.paused", "Paused").to_string(),
+            Page::PackComplete(_) => {
+                strings.get("title.pack_complete", "Pack complete").to_string()
+            }
+        }
+    }
+    // Lines shown between the page's title and its items.
+    fn page_text(&self, page: Page) -> Vec<String> {
+        let Page::PackComplete(pack) = page else {
+            return Vec::new();
+        };
+        let strings = &self.strings;
+        let completed = self.game_state.get_completed_levels();
+        let total = self.format_stats(&completed.iter().copied().sum());
+        let mut lines =
+            vec![strings.format("pack.total", "{0} levels: {1}", &[&completed.len(), &total])];
+        // The pack's records add up to a best only once every level has one.
+        let levels = &self.packs[pack].levels;
+        let records: Option<LevelStats> =
+            levels.iter().map(|level| self.records.get(level.id)).sum();
+        if let Some(records) = records {
+            let best = self.format_stats(&records);
+            lines.push(strings.format("pack.records", "Records: {0}", &[&best]));
+        }
+        lines
+    }
+    fn menu_items(&self, page: Page) -> Vec<MenuItem> {
+        let strings = &self.strings;
+        let item = |key: &str, english: &str, action| MenuItem {
+            label: strings.get(key, english).to_string(),
+            action,
+        };
+        match page {
+            Page::Main => {
+                let mut items = Vec::new();
+                if let Some((pack, level)) = self.saved_level() {
+                    let (name, number) = (self.packs[pack].name(), level + 1);
+                    let label = strings.format(
+                        "menu.continue",
+                        "Continue: {0} level {1}",
+                        &[&name, &number],
+                    );
+                    items.push(MenuItem { label, action: Action::Continue });
+                }
+                items.push(item("menu.play", "Play", Action::Open(Page::Packs)));
+                items.push(item("menu.replays", "Replays", Action::Open(Page::Replays)));
+                items.push(item("menu.settings", "Settings", Action::Open(Page::Settings)));
+                // The editor is only a stub so far, so players don't get offered it.
+                if cfg!(debug_assertions) {
+                    items.push(item("menu.editor", "Level editor", Action::Editor));
+                }
+                items.push(item("menu.quit", "Quit", Action::Quit));
+                items
+            }
+            Page::Packs => (self.packs.iter().enumerate())
+                .map(|(i, pack)| MenuItem {
+                    label: strings.format(
+                        "menu.pack",
+                        "{0} ({1} levels)",
+                        &[&pack.name(), &pack.levels.len()],
+                    ),
+                    action: Action::Open(Page::Levels(i)),
+                })
+                .collect(),
+            Page::Levels(pack) => (self.packs[pack].levels.iter().enumerate())
+                .map(|(i, level)| {
+                    let record = match self.records.get(level.id) {
+                        Some(best) => self.format_stats(&best),
+                        None => strings.get("menu.unsolved", "unsolved").to_string(),
+                    };
+                    let name = match self.packs[pack].level_name(i) {
+                        Some(name) => name.to_string(),
+                        None => strings.format("menu.level", "Level {0}", &[&(i + 1)]),
+                    };
+                    MenuItem {
+                        label: format!("{}: {}", name, record),
+                        action: Action::Play { pack, level: i },
+                    }
+                })
+                .collect(),
+            Page::Replays => (self.replay_files.iter().enumerate())
+                .map(|(i, path)| {
+                    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+                    let name = name.strip_suffix(&format!(".{}", replay::REPLAY_EXTENSION));
+                    MenuItem {
+                        label: name.unwrap_or(path).to_string(),
+                        action: Action::ImportReplay(i),
+                    }
+                })
+                .collect(),
+            Page::Settings => Setting::all()
+                .map(|setting| {
+                    let name = strings.get(&format!("setting.{}", setting.key()), setting.label());
+                    match setting {
+                        Setting::Key(control) => {
+                            let value = if self.binding == Some(control) {
+                                strings.get("menu.press_key", "press a key").to_string()
+                            } else {
+                                self.settings.value_text(setting, strings)
+                            };
+                            let label = format!("{}: {}", name, value);
+                            MenuItem { label, action: Action::Bind(control) }
+                        }
+                        _ => {
+                            let label =
+                                format!("{}: {}", name, self.settings.value_text(setting, strings));
+                            MenuItem { label, action: Action::Adjust(setting) }
+                        }
+                    }
+                })
+                .collect(),
+            Page::Paused => {
+                let mut items = vec![item("menu.resume", "Resume", Action::Resume)];
+                // Only the host can restart a co-op level.
+                if !self.joined() {
+                    items.push(item("menu.restart", "Restart level", Action::Restart));
+                }
+                if self.game_state.get_best_run().is_some() {
+                    let export = item("menu.export_replay", "Export replay", Action::ExportReplay);
+                    items.push(export);
+                }
+                items.push(item("menu.settings", "Settings", Action::Open(Page::Settings)));
+                items.push(item("menu.main_menu", "Main menu", Action::MainMenu));
+                items
+            }
+            Page::PackComplete(pack) => vec![
+                item("menu.play_again", "Play again", Action::Play { pack, level: 0 }),
+                item("menu.levels", "Level select", Action::Open(Page::Levels(pack))),
+                item("menu.main_menu", "Main menu", Action::MainMenu),
+            ],
+        }
+    }
+    fn draw_ui(&self, camera: &Camera, width: f32, height: f32) -> UiLayer {
+        let mut ui = UiLayer::new();
+        let scale = UiLayer::scale_for(height);
+        match self.state {
+            AppState::Playing => {
+                if let Some(free_camera) = &self.free_camera {
+                    let speed = format!("{:.1}", free_camera.speed);
+                    let text = self.strings.format(
+                        "hud.free_camera",
+                        "Free camera, speed {0}. F2 to return",
+                        &[&speed],
+                    );
+                    let margin = UiLayer::text_height(scale);
+                    ui.text(margin, margin, scale, DEBUG_TEXT_COLOR, &text);
+                }
+                if self.grid_overlay {
+                    self.draw_tile_coordinates(&mut ui, camera, width, height, scale);
+                }
+            }
+            AppState::Editor => {
+                ui.rect(0.0, 0.0, width, height, MENU_BACKDROP_COLOR);
+                let line_height = UiLayer::text_height(scale) * 2.0;
+                let y = height * 0.25;
+                let title = self.strings.get("title.editor", "Level editor");
+                text_centered(&mut ui, width / 2.0, y, scale * 2, TITLE_COLOR, title);
+                let name = if self.level_name.is_focused() {
+                    format!("{}_", self.level_name.text())
+                } else {
+                    self.level_name.text().to_string()
+                };
+                let name = self.strings.format("editor.level_name", "Level name: {0}", &[&name]);
+                let y_name = y + 2.0 * line_height;
+                text_centered(&mut ui, width / 2.0, y_name, scale, SELECTED_ITEM_COLOR, &name);
+                let hint = if self.level_name.is_focused() {
+                    self.strings.get("editor.name_hint", "Enter to keep the name, Esc to undo.")
+                } else {
+                    self.strings.get("editor.rename_hint", "Enter to rename the level.")
+                };
+                text_centered(&mut ui, width / 2.0, y_name + line_height, scale, ITEM_COLOR, hint);
+                let note =
+                    self.strings.get("editor.note", "Not available yet. Press Esc to go back.");
+                text_centered(&mut ui, width / 2.0, y + 4.0 * line_height, scale, ITEM_COLOR, note);
+            }
+            AppState::Menu | AppState::Paused => {
+                let backdrop = if self.state == AppState::Paused {
+                    PAUSE_BACKDROP_COLOR
+                } else {
+                    MENU_BACKDROP_COLOR
+                };
+                ui.rect(0.0, 0.0, width, height, backdrop);
+                if let Some(&(page, selected)) = self.pages.last() {
+                    self.draw_menu(&mut ui, page, selected, width, height, scale);
+                }
+            }
+        }
+        self.toasts.draw(&mut ui, width, scale);
+        ui
+    }
+    // Labels each of the level's tiles with its coordinates, centered where the middle of its
+    // floor shows on screen.
+    fn draw_tile_coordinates(
+        &self,
+        ui: &mut UiLayer,
+        camera: &Camera,
+        width: f32,
+        height: f32,
+        scale: u32,
+    ) {
+        let level = self.game_state.get_level();
+        let placement = self.game_state.get_level_placement();
+        let view_proj = camera.build_view_projection_matrix();
+        let scale = (scale / 2).max(1);
+        for z in 0..level.depth {
+            for x in 0..level.width {
+                let clip = view_proj * placement.tile_center(x, z).extend(1.0);
+                if clip.w <= 0.0 {
+                    continue;
+                }
+                let (ndc_x, ndc_y) = (clip.x / clip.w, clip.y / clip.w);
+                if ndc_x.abs() > 1.0 || ndc_y.abs() > 1.0 {
+                    continue;
+                }
+                let screen_x = (ndc_x + 1.0) / 2.0 * width;
+                let screen_y = (1.0 - ndc_y) / 2.0 * height - UiLayer::text_height(scale) / 2.0;
+                let label = format!("{},{}", x, z);
+                text_centered(ui, screen_x, screen_y, scale, DEBUG_TEXT_COLOR, &label);
+            }
+        }
+    }
+    fn draw_menu(
+        &self,
+        ui: &mut UiLayer,
+        page: Page,
+        selected: usize,
+        width: f32,
+        height: f32,
+        scale: u32,
+    ) {
+        let center_x = width / 2.0;
+        let line_height = UiLayer::text_height(scale) * 2.0;
+        let mut y = height * 0.25;
+        text_centered(ui, center_x, y, scale * 2, TITLE_COLOR, &self.page_title(page));
+        y += 3.0 * line_height;
+        let text = self.page_text(page);
+        for line in &text {
+            text_centered(ui, center_x, y, scale, SELECTED_ITEM_COLOR, line);
+            y += line_height;
+        }
+        if !text.is_empty() {
+            y += line_height;
+        }
+        let items = self.menu_items(page);
+        if items.is_empty() {
+            let empty = self.strings.get("menu.empty", "Nothing here");
+            text_centered(ui, center_x, y, scale, ITEM_COLOR, empty);
+        }
+        // Long lists scroll to keep the selected item on screen.
+        let visible = (((height - y) / line_height) as usize).saturating_sub(1).max(1);
+        let first = selected.saturating_sub(visible - 1).min(items.len().saturating_sub(visible));
+        for (i, item) in items.iter().enumerate().skip(first).take(visible) {
+            let color = if i == selected {
+                let highlight_width = UiLayer::text_width(&item.label, scale) + line_height;
+                let pad = (line_height - UiLayer::text_height(scale)) / 2.0;
+                ui.rect(
+                    center_x - highlight_width / 2.0,
+                    y - pad,
+                    highlight_width,
+                    line_height,
+                    SELECTION_COLOR,
+                );
+                SELECTED_ITEM_COLOR
+            } else {
+                ITEM_COLOR
+            };
+            text_centered(ui, center_x, y, scale, color, &item.label);
+            y += line_height;
+        }
+    }
+}
+
+fn text_centered(ui: &mut UiLayer, center_x: f32, y: f32, scale: u32, color: [f32; 4], text: &str) {
+    let x = center_x - UiLayer::text_width(text, scale) / 2.0;
+    ui.text(x.round(), y.round(), scale, color, text);
+}
@@ -0,0 +1,130 @@
+/* BugReport: an F10-triggered zip bundle of everything needed to reproduce a problem. */
+use crate::{
+    achievements::ACHIEVEMENTS_FILE,
+    crash,
+    determinism::StateHasher,
+    game_state::{GameState, InputState},
+    gpu_state::WebGPUState,
+    headless::InputScript,
+    records::RECORDS_FILE,
+    save::SAVE_FILE,
+    settings::SETTINGS_FILE,
+    tick_rate,
+};
+
+use anyhow::Context;
+use std::collections::VecDeque;
+use std::io::{Cursor, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INPUT_HISTORY_SECS: f32 = 30.0;
+// The files the game keeps between runs, added to the bundle under their own names when they
+// exist, so a report carries the player's progress and settings along with what they did.
+const SAVED_FILES: [&str; 4] = [SAVE_FILE, RECORDS_FILE, ACHIEVEMENTS_FILE, SETTINGS_FILE];
+
+/// A rolling window of the most recent ticks of input, owned by the game thread and read by the
+/// render thread when a report is requested.
+pub struct InputHistory {
+    ticks: VecDeque<InputState>,
+    capacity: usize,
+}
+impl InputHistory {
+    pub fn new() -> Self {
+        let capacity = (INPUT_HISTORY_SECS / tick_rate::tick_secs()) as usize;
+        InputHistory { ticks: VecDeque::with_capacity(capacity), capacity }
+    }
+    pub fn push_tick(&mut self, input: &InputState) {
+        if self.ticks.len() == self.capacity {
+            self.ticks.pop_front();
+        }
+        self.ticks.push_back(input.clone());
+    }
+    pub fn to_script(&self) -> InputScript {
+        let mut script = InputScript::new();
+        for input in &self.ticks {
+            script.push_tick(input);
+        }
+        script
+    }
+}
+impl Default for InputHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn describe_game_state(game_state: &GameState) -> String {
+    let mut hasher = StateHasher::new();
+    game_state.hash_into(&mut hasher);
+    let position = game_state.get_player_position();
+    let camera = game_state.get_camera();
+    format!(
+        "tick: {}\nstate hash: {:016x}\nplayer position: ({}, {}, {})\ncamera eye: ({}, {}, {})\n\
+         camera direction: ({}, {}, {})\n",
+        game_state.get_tick(),
+        hasher.finish(),
+        position.x,
+        position.y,
+        position.z,
+        camera.eye.x,
+        camera.eye.y,
+        camera.eye.z,
+        camera.direction.x,
+        camera.direction.y,
+        camera.direction.z,
+    )
+}
+
+/// Writes `bug-report-<unix time>.zip` to the working directory and returns its path. The bundle
+/// holds the latest game state, the last 30 seconds of input as a replayable input script, the
+/// adapter info, the save, records, achievements and settings files, the recent log, and a
+/// screenshot.
+pub fn write_bug_report(
+    gpu_state: &mut WebGPUState,
+    game_state: Option<&GameState>,
+    input_history: &InputHistory,
+) -> anyhow::Result<String> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = format!("bug-report-{}.zip", timestamp);
+    let mut zip = zip::ZipWriter::new(
+        std::fs::File::create(&path).with_context(|| format!("creating {}", path))?,
+    );
+    let options = zip::write::FileOptions::default();
+
+    if let Some(game_state) = game_state {
+        zip.start_file("state.txt", options)?;
+        zip.write_all(describe_game_state(game_state).as_bytes())?;
+    }
+    zip.start_file("input.txt", options)?;
+    zip.write_all(input_history.to_script().to_text().as_bytes())?;
+    zip.start_file("adapter.txt", options)?;
+    zip.write_all(format!("{:#?}\n", gpu_state.adapter_info()).as_bytes())?;
+    for file in SAVED_FILES {
+        // A file that was never written, like the save before finishing a level, is just missing.
+        if let Ok(contents) = std::fs::read(file) {
+            zip.start_file(file, options)?;
+            zip.write_all(&contents)?;
+        }
+    }
+    zip.start_file("log.txt", options)?;
+    for line in crash::recent_log(usize::MAX) {
+        writeln!(zip, "{}", line)?;
+    }
+
+    let (width, height) = gpu_state.surface_size();
+    let screenshot = gpu_state.read_region(0, 0, width, height)?;
+    let image = image::RgbaImage::from_raw(
+        width,
+        height,
+        screenshot.colors.iter().flatten().copied().collect(),
+    )
+    .context("screenshot has the wrong number of pixels")?;
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(image)
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    zip.start_file("screenshot.png", options)?;
+    zip.write_all(&png)?;
+
+    zip.finish()?;
+    Ok(path)
+}
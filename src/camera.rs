@@ -1,4 +1,16 @@
-use cgmath::{Deg, Matrix4, Point3, Vector3};
+use cgmath::{num_traits::abs, Deg, EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Vector3};
+
+use crate::{noise::perlin1, rotor::Rotor, transform::Transform};
+
+// Vertical fields of view the camera accepts, in degrees. Much wider distorts badly at the edges.
+pub const FOVY_RANGE: (f32, f32) = (30.0, 110.0);
+// Trauma lost per second.
+const TRAUMA_DECAY: f32 = 1.5;
+// Camera shake at full trauma: the largest rotation about each axis, and the largest offset.
+const SHAKE_MAX_DEGREES: f32 = 3.0;
+const SHAKE_MAX_OFFSET: f32 = 0.08;
+// How fast the shake wanders, in noise lattice points per second.
+const SHAKE_FREQUENCY: f32 = 15.0;
 
 #[derive(Clone, Copy)]
 pub struct Camera {
@@ -10,6 +22,11 @@ pub struct Camera {
     fovy: f32,
     znear: f32,
     zfar: f32,
+    // Drives the camera shake, from 0 (still) to 1. The shake grows with its square, so small
+    // knocks barely register while big ones stack up.
+    trauma: f32,
+    // Seconds the shake has run for, to sample its noise at.
+    shake_time: f32,
 }
 
 impl Camera {
@@ -22,17 +39,119 @@ impl Camera {
         znear: f32,
         zfar: f32,
     ) -> Self {
-        Camera { eye, velocity: (0.0, 0.0, 0.0).into(), direction, up, aspect, fovy, znear, zfar }
+        Camera {
+            eye,
+            velocity: (0.0, 0.0, 0.0).into(),
+            direction,
+            up,
+            aspect,
+            fovy,
+            znear,
+            zfar,
+            trauma: 0.0,
+            shake_time: 0.0,
+        }
     }
     pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
-        let view = cgmath::Matrix4::look_to_rh(self.eye, self.direction, self.up);
+        let view = self.shake_matrix() * self.transform().inverse().to_matrix();
+
+        return self.build_projection_matrix() * view;
+    }
+    /// Where the camera is and how it is turned: it looks down its -z axis, with +y up.
+    pub fn transform(&self) -> Transform {
+        let back = -self.direction.normalize();
+        let right = self.up.cross(back).normalize();
+        let up = back.cross(right);
+        let rotation = Rotor::from_matrix(Matrix3::from_cols(right, up, back));
+        Transform::new(self.eye.to_vec(), rotation, 1.0)
+    }
+    pub fn build_projection_matrix(&self) -> Matrix4<f32> {
         let proj = cgmath::perspective(Deg(self.fovy), self.aspect, self.znear, self.zfar);
 
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+        return OPENGL_TO_WGPU_MATRIX * proj;
+    }
+    pub fn aspect(&self) -> f32 {
+        self.aspect
     }
     pub fn set_aspect(&mut self, aspect: f32) {
         self.aspect = aspect;
     }
+    /// Clamped to FOVY_RANGE.
+    pub fn set_fovy(&mut self, fovy: f32) {
+        self.fovy = fovy.clamp(FOVY_RANGE.0, FOVY_RANGE.1);
+    }
+    pub fn fovy(&self) -> f32 {
+        self.fovy
+    }
+    pub fn up(&self) -> Vector3<f32> {
+        self.up
+    }
+    /// Turns the view by `yaw` about the vertical, then by `pitch` up or down.
+    pub fn turn(&mut self, yaw: Deg<f32>, pitch: Deg<f32>) {
+        let lateral_rot = Rotor::from_axis_angle(Vector3::unit_y(), yaw);
+        let vertical_rot = Rotor::from_axis_angle(
+            Vector3::normalize([self.direction.z, 0.0, -self.direction.x].into()),
+            pitch,
+        );
+        // Prevent the camera from getting too close to a vertical pole, while still allowing for
+        // lateral movement.
+        const POLAR_THRESHOLD: f32 = 0.001;
+        let new_vertical = Vector3::normalize(vertical_rot.rotate_vector(self.direction));
+        if abs(Vector3::dot(new_vertical, Vector3::unit_y())) > 1.0 - POLAR_THRESHOLD {
+            self.direction = Vector3::normalize(lateral_rot.rotate_vector(self.direction));
+        } else {
+            self.direction = Vector3::normalize(lateral_rot.rotate_vector(new_vertical));
+        }
+    }
+    /// Adds to the camera shake, up to full trauma.
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+    /// Advances the shake by `delta_t` seconds, letting the trauma wear off.
+    pub fn update_shake(&mut self, delta_t: f32) {
+        self.trauma = (self.trauma - TRAUMA_DECAY * delta_t).max(0.0);
+        self.shake_time = if self.trauma > 0.0 { self.shake_time + delta_t } else { 0.0 };
+    }
+    // View-space rotation and offset of the shake, each axis following its own noise signal.
+    fn shake_matrix(&self) -> Matrix4<f32> {
+        let shake = self.trauma * self.trauma;
+        let noise = |seed| shake * perlin1(self.shake_time * SHAKE_FREQUENCY, seed);
+        Matrix4::from_translation(SHAKE_MAX_OFFSET * Vector3::new(noise(3), noise(4), noise(5)))
+            * Matrix4::from_angle_x(Deg(SHAKE_MAX_DEGREES * noise(0)))
+            * Matrix4::from_angle_y(Deg(SHAKE_MAX_DEGREES * noise(1)))
+            * Matrix4::from_angle_z(Deg(SHAKE_MAX_DEGREES * noise(2)))
+    }
+}
+
+/// Eases the camera after sudden jumps in what it follows, such as collision corrections, while
+/// following smooth motion exactly. The camera's offset from the followed point is a critically
+/// damped spring pulled back to zero.
+#[derive(Clone, Copy)]
+pub struct SpringFollow {
+    // The spring's angular frequency, per second. Higher settles faster.
+    pub stiffness: f32,
+    offset: Vector3<f32>,
+    offset_velocity: Vector3<f32>,
+}
+impl SpringFollow {
+    pub fn new(stiffness: f32) -> Self {
+        SpringFollow {
+            stiffness,
+            offset: (0.0, 0.0, 0.0).into(),
+            offset_velocity: (0.0, 0.0, 0.0).into(),
+        }
+    }
+    /// Leaves the camera behind by `jump`, how far the followed point moved other than smoothly,
+    /// then lets the spring settle for `delta_t` seconds. Returns the camera's offset.
+    pub fn update(&mut self, jump: Vector3<f32>, delta_t: f32) -> Vector3<f32> {
+        self.offset -= jump;
+        // Implicit Euler, which stays stable however stiff the spring is.
+        let w = self.stiffness;
+        let det = 1.0 + 2.0 * w * delta_t + w * w * delta_t * delta_t;
+        self.offset_velocity = (self.offset_velocity - w * w * delta_t * self.offset) / det;
+        self.offset += self.offset_velocity * delta_t;
+        self.offset
+    }
 }
 
 #[rustfmt::skip]
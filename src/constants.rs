@@ -1,11 +1,7 @@
-use std::{cell::LazyCell, time::Duration};
-
-const GAME_TICKS_PER_SECOND: f32 = 100.0;
-pub const TIME_PER_GAME_TICK: LazyCell<Duration> =
-    LazyCell::new(|| Duration::from_secs_f32(1.0 / GAME_TICKS_PER_SECOND));
-const MAX_RENDER_FPS: f32 = 100.0;
-pub const MIN_TIME_PER_RENDER_FRAME: LazyCell<Duration> =
-    LazyCell::new(|| Duration::from_secs_f32(1.0 / MAX_RENDER_FPS));
+// The tick rate until --tick-rate picks another, see tick_rate.rs.
+pub const DEFAULT_GAME_TICKS_PER_SECOND: u32 = 100;
+// The frame rate cap until the player picks another.
+pub const DEFAULT_MAX_RENDER_FPS: f32 = 100.0;
 
 pub const PLAYER_FORCE: f32 = 6.0;
 pub const GRAVITY: f32 = -9.0;
@@ -0,0 +1,85 @@
+/* Crash: a panic hook that leaves a crash log behind, for builds without a console. */
+use crate::jobs;
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use windows::{
+    core::{s, PCSTR},
+    Win32::UI::WindowsAndMessaging::{MessageBoxA, MB_ICONERROR, MB_OK},
+};
+
+// How many of the most recent log lines a crash log holds.
+const RECENT_LOG_CAPACITY: usize = 200;
+
+static RECENT_LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Prints like `debug_println!` in debug builds, and in every build keeps the line for the crash
+/// log.
+#[macro_export]
+macro_rules! log_println {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        debug_print::debug_println!("{}", line);
+        $crate::crash::remember(line);
+    }};
+}
+
+pub fn remember(line: String) {
+    let Ok(mut log) = RECENT_LOG.lock() else {
+        return;
+    };
+    if log.len() == RECENT_LOG_CAPACITY {
+        log.pop_front();
+    }
+    log.push_back(line);
+}
+
+/// The last `count` lines logged, oldest first.
+pub fn recent_log(count: usize) -> Vec<String> {
+    let Ok(log) = RECENT_LOG.lock() else {
+        return Vec::new();
+    };
+    log.iter().skip(log.len().saturating_sub(count)).cloned().collect()
+}
+
+/// On a panic in any thread, writes `crash-<unix time>.txt` to the working directory with the
+/// panic message, a backtrace and the recent log lines, tells the player where it is, and exits.
+/// The game's threads can't carry on without each other, so there's nothing to gain by staying up.
+/// The job pool's threads are the exception: their panics are caught and only logged here.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let thread = std::thread::current();
+        if jobs::is_job_thread(&thread) {
+            crate::log_println!("thread '{}' {}", thread.name().unwrap_or("<unnamed>"), info);
+            return;
+        }
+        let mut report = String::new();
+        let _ = writeln!(report, "thread '{}' {}", thread.name().unwrap_or("<unnamed>"), info);
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        let _ = writeln!(report, "\nbacktrace:\n{}", backtrace);
+        let _ = writeln!(report, "recent log:");
+        // try_lock, in case the panic came from inside `remember`.
+        if let Ok(log) = RECENT_LOG.try_lock() {
+            for line in log.iter() {
+                let _ = writeln!(report, "{}", line);
+            }
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs());
+        let path = format!("crash-{}.txt", timestamp);
+        let message = match std::fs::write(&path, report) {
+            Ok(()) => format!("The game crashed. The crash log is in {}.\0", path),
+            Err(e) => format!("The game crashed, and writing the crash log failed: {}\0", e),
+        };
+        unsafe {
+            MessageBoxA(None, PCSTR(message.as_ptr()), s!("Crash"), MB_OK | MB_ICONERROR);
+        }
+        std::process::exit(1);
+    }));
+}
@@ -0,0 +1,418 @@
+/* Culling: GPU frustum culling of model instances, feeding indirect draws in the scene pass.
+ * Every model's instances share one set of buffers, rewritten whenever the instances change. */
+use std::mem;
+
+use cgmath::{InnerSpace, Matrix4, Vector4};
+
+use crate::{
+    camera::Camera,
+    gpu_state::{create_compute_pipeline, storage_buffer_layout_entry, BindGroupData, InstanceRaw},
+    model::{Mesh, Model},
+    uploader::Uploader,
+};
+
+const WORKGROUP_SIZE: u32 = 64;
+const INDIRECT_ARGS_SIZE: u64 = mem::size_of::<wgpu::util::DrawIndexedIndirect>() as u64;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FrustumUniform {
+    planes: [[f32; 4]; 6],
+}
+impl FrustumUniform {
+    // Gribb-Hartmann plane extraction, for wgpu's [0, 1] clip-space depth range.
+    fn from_camera(camera: &Camera) -> Self {
+        let m: Matrix4<f32> = camera.build_view_projection_matrix();
+        let row = |i: usize| Vector4::new(m.x[i], m.y[i], m.z[i], m.w[i]);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ]
+        .map(|plane| (plane / plane.truncate().magnitude()).into());
+        FrustumUniform { planes }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    instance_count: u32,
+    batch_count: u32,
+    _padding: [u32; 2],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BatchParams {
+    first_instance: u32,
+    instance_count: u32,
+    bounding_radius: f32,
+    first_command: u32,
+}
+
+/// Radius of a sphere around the model's origin that contains every vertex, before scaling.
+pub fn bounding_radius(model: &Model) -> f32 {
+    model
+        .meshes
+        .iter()
+        .flat_map(|mesh| &mesh.raw_vertices)
+        .map(|vertex| cgmath::Vector3::from(vertex.position).magnitude())
+        .fold(0.0, f32::max)
+}
+
+/// Instances of one set of meshes for this frame, in batches that are drawn separately.
+pub struct CullInput<'a> {
+    pub meshes: &'a [Mesh],
+    pub bounding_radius: f32,
+    pub batches: Vec<&'a [InstanceRaw]>,
+}
+
+/// Where an input's batches landed in the culler's shared buffers. Each mesh's draw commands are
+/// contiguous, one per batch in batch order, so a run of batches can be drawn with one
+/// multi-draw.
+#[derive(Clone, Debug, Default)]
+pub struct ModelDraws {
+    first_command: u32,
+    num_batches: u32,
+    first_instances: Vec<u32>,
+}
+impl ModelDraws {
+    /// Offset in `InstanceCuller::commands` of the command drawing `mesh_index` for `batch_index`.
+    pub fn command_offset(&self, mesh_index: usize, batch_index: usize) -> wgpu::BufferAddress {
+        let command = self.first_command as usize + mesh_index * self.num_batches as usize;
+        (command + batch_index) as u64 * INDIRECT_ARGS_SIZE
+    }
+    /// Index in `InstanceCuller::instances` of the batch's first visible instance.
+    pub fn first_instance(&self, batch_index: usize) -> u32 {
+        self.first_instances[batch_index]
+    }
+}
+
+// Buffers shared by every model, grown as levels need more room and never shrunk.
+struct SharedBuffers {
+    instance_capacity: usize,
+    batch_capacity: usize,
+    command_capacity: usize,
+    bind_group: wgpu::BindGroup,
+    params: wgpu::Buffer,
+    batches: wgpu::Buffer,
+    // Every instance, before culling.
+    input: wgpu::Buffer,
+    output: wgpu::Buffer,
+    // The commands as written, with no instances, copied over `commands` before each culling pass.
+    command_template: wgpu::Buffer,
+    commands: wgpu::Buffer,
+    count_sources: wgpu::Buffer,
+}
+impl SharedBuffers {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        instance_capacity: usize,
+        batch_capacity: usize,
+        command_capacity: usize,
+    ) -> Self {
+        let buffer = |label, size: usize, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        use wgpu::BufferUsages as Usages;
+        let params = buffer(
+            "Cull Params Buffer",
+            mem::size_of::<CullParams>(),
+            Usages::UNIFORM | Usages::COPY_DST,
+        );
+        let batches = buffer(
+            "Cull Batch Buffer",
+            batch_capacity * mem::size_of::<BatchParams>(),
+            Usages::STORAGE | Usages::COPY_DST,
+        );
+        let input = buffer(
+            "Cull Input Instance Buffer",
+            instance_capacity * mem::size_of::<InstanceRaw>(),
+            Usages::STORAGE | Usages::COPY_DST,
+        );
+        let output = buffer(
+            "Culled Instance Buffer",
+            instance_capacity * mem::size_of::<InstanceRaw>(),
+            Usages::STORAGE | Usages::VERTEX,
+        );
+        let command_template = buffer(
+            "Cull Command Template Buffer",
+            command_capacity * INDIRECT_ARGS_SIZE as usize,
+            Usages::COPY_SRC | Usages::COPY_DST,
+        );
+        let commands = buffer(
+            "Cull Indirect Args Buffer",
+            command_capacity * INDIRECT_ARGS_SIZE as usize,
+            Usages::STORAGE | Usages::INDIRECT | Usages::COPY_DST,
+        );
+        let count_sources = buffer(
+            "Cull Count Source Buffer",
+            command_capacity * mem::size_of::<u32>(),
+            Usages::STORAGE | Usages::COPY_DST,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cull Instances Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: batches.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: input.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: output.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: commands.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: count_sources.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: params.as_entire_binding() },
+            ],
+        });
+        SharedBuffers {
+            instance_capacity,
+            batch_capacity,
+            command_capacity,
+            bind_group,
+            params,
+            batches,
+            input,
+            output,
+            command_template,
+            commands,
+            count_sources,
+        }
+    }
+}
+
+pub struct InstanceCuller {
+    frustum: BindGroupData<FrustumUniform>,
+    instances_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    copy_counts_pipeline: wgpu::ComputePipeline,
+    buffers: SharedBuffers,
+    // What the last `write_instances` put in the buffers.
+    instance_count: u32,
+    command_count: u32,
+    // Workgroups per dispatch dimension. Culling wraps onto more rows past this.
+    max_workgroups: u32,
+    // Whether commands can start at an instance other than 0. Without it, every command starts at
+    // 0 and each batch's instances have to be bound at their own offset.
+    first_instance_supported: bool,
+    multi_draw_supported: bool,
+}
+impl InstanceCuller {
+    // Enough for the built-in levels without growing.
+    const INITIAL_INSTANCES: usize = 4096;
+    const INITIAL_BATCHES: usize = 64;
+
+    pub fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+        let frustum = BindGroupData::<FrustumUniform>::new(
+            FrustumUniform::from_camera(camera),
+            device,
+            "Frustum",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::COMPUTE,
+        );
+        let instances_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage_buffer_layout_entry(0, wgpu::ShaderStages::COMPUTE, true),
+                storage_buffer_layout_entry(1, wgpu::ShaderStages::COMPUTE, true),
+                storage_buffer_layout_entry(2, wgpu::ShaderStages::COMPUTE, false),
+                storage_buffer_layout_entry(3, wgpu::ShaderStages::COMPUTE, false),
+                storage_buffer_layout_entry(4, wgpu::ShaderStages::COMPUTE, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("cull_instances_bind_group_layout"),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&frustum.layout, &instances_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = || wgpu::ShaderModuleDescriptor {
+            label: Some("Culling Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("culling.wgsl").into()),
+        };
+        let pipeline =
+            create_compute_pipeline("Cull Instances", device, &layout, shader(), "cs_cull");
+        let copy_counts_pipeline = create_compute_pipeline(
+            "Copy Cull Counts",
+            device,
+            &layout,
+            shader(),
+            "cs_copy_counts",
+        );
+        let buffers = SharedBuffers::new(
+            device,
+            &instances_layout,
+            Self::INITIAL_INSTANCES,
+            Self::INITIAL_BATCHES,
+            Self::INITIAL_BATCHES,
+        );
+        let features = device.features();
+        let first_instance_supported = features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE);
+        InstanceCuller {
+            frustum,
+            instances_layout,
+            pipeline,
+            copy_counts_pipeline,
+            buffers,
+            instance_count: 0,
+            command_count: 0,
+            max_workgroups: device.limits().max_compute_workgroups_per_dimension,
+            first_instance_supported,
+            // Merged draws rely on each command finding its own instances.
+            multi_draw_supported: first_instance_supported
+                && features.contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+        }
+    }
+    /// The optional device features culling makes use of, if the adapter has them.
+    pub fn wanted_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+        adapter.features()
+            & (wgpu::Features::INDIRECT_FIRST_INSTANCE | wgpu::Features::MULTI_DRAW_INDIRECT)
+    }
+    pub fn update_frustum(&mut self, uploader: &mut Uploader, camera: &Camera) {
+        self.frustum.uniform = FrustumUniform::from_camera(camera);
+        uploader.upload(&self.frustum.buffer, 0, &[self.frustum.uniform]);
+    }
+    /// Lays every input's instances out in the shared buffers, one after another, and builds the
+    /// draw commands for them. Returns where each input's batches ended up, in `inputs` order.
+    pub fn write_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        inputs: &[CullInput],
+    ) -> Vec<ModelDraws> {
+        let mut instances = Vec::new();
+        let mut batches = Vec::new();
+        let mut commands = Vec::new();
+        let mut count_sources = Vec::new();
+        let mut draws = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let first_command = commands.len() as u32;
+            let num_batches = input.batches.len() as u32;
+            let mut first_instances = Vec::with_capacity(input.batches.len());
+            for (batch_index, raw) in input.batches.iter().enumerate() {
+                first_instances.push(instances.len() as u32);
+                batches.push(BatchParams {
+                    first_instance: instances.len() as u32,
+                    instance_count: raw.len() as u32,
+                    bounding_radius: input.bounding_radius,
+                    first_command: first_command + batch_index as u32,
+                });
+                instances.extend_from_slice(raw);
+            }
+            // Every mesh of a batch draws the instances counted for its first mesh.
+            for mesh in input.meshes {
+                for (batch_index, &first_instance) in first_instances.iter().enumerate() {
+                    commands.push(wgpu::util::DrawIndexedIndirect {
+                        vertex_count: mesh.num_elements,
+                        instance_count: 0,
+                        base_index: 0,
+                        vertex_offset: 0,
+                        base_instance: if self.first_instance_supported {
+                            first_instance
+                        } else {
+                            0
+                        },
+                    });
+                    count_sources.push(first_command + batch_index as u32);
+                }
+            }
+            draws.push(ModelDraws { first_command, num_batches, first_instances });
+        }
+
+        if instances.len() > self.buffers.instance_capacity
+            || batches.len() > self.buffers.batch_capacity
+            || commands.len() > self.buffers.command_capacity
+        {
+            self.buffers = SharedBuffers::new(
+                device,
+                &self.instances_layout,
+                instances.len().max(self.buffers.instance_capacity).next_power_of_two(),
+                batches.len().max(self.buffers.batch_capacity).next_power_of_two(),
+                commands.len().max(self.buffers.command_capacity).next_power_of_two(),
+            );
+        }
+        let command_bytes =
+            commands.iter().flat_map(|command| command.as_bytes().to_vec()).collect::<Vec<u8>>();
+        queue.write_buffer(&self.buffers.input, 0, bytemuck::cast_slice(&instances));
+        queue.write_buffer(&self.buffers.batches, 0, bytemuck::cast_slice(&batches));
+        queue.write_buffer(&self.buffers.command_template, 0, &command_bytes);
+        queue.write_buffer(&self.buffers.count_sources, 0, bytemuck::cast_slice(&count_sources));
+        let params = CullParams {
+            instance_count: instances.len() as u32,
+            batch_count: batches.len() as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.buffers.params, 0, bytemuck::cast_slice(&[params]));
+        self.instance_count = instances.len() as u32;
+        self.command_count = commands.len() as u32;
+
+        draws
+    }
+    /// The visible instances of every batch, after `encode`.
+    pub fn instances(&self) -> &wgpu::Buffer {
+        &self.buffers.output
+    }
+    /// The draw commands, with visible instance counts filled in by `encode`.
+    pub fn commands(&self) -> &wgpu::Buffer {
+        &self.buffers.commands
+    }
+    /// Whether the commands find their batch's instances on their own, so `instances` can be
+    /// bound once for every draw. Otherwise, bind `batch_instances` before each batch's draws.
+    pub fn first_instance_supported(&self) -> bool {
+        self.first_instance_supported
+    }
+    /// Whether consecutive commands can be drawn with `multi_draw_indexed_indirect`.
+    pub fn multi_draw_supported(&self) -> bool {
+        self.multi_draw_supported
+    }
+    /// The instances of the batch starting at `first_instance`, for binding when commands can't
+    /// find them on their own.
+    pub fn batch_instances(&self, first_instance: u32) -> wgpu::BufferSlice<'_> {
+        let offset = first_instance as u64 * mem::size_of::<InstanceRaw>() as u64;
+        self.buffers.output.slice(offset..)
+    }
+    /// Culls every batch's instances against the current frustum and writes the visible counts
+    /// into their draw commands.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.instance_count == 0 {
+            return;
+        }
+        // Every frame's counts start from zero.
+        encoder.copy_buffer_to_buffer(
+            &self.buffers.command_template,
+            0,
+            &self.buffers.commands,
+            0,
+            self.command_count as u64 * INDIRECT_ARGS_SIZE,
+        );
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, &self.frustum.bind_group, &[]);
+        pass.set_bind_group(1, &self.buffers.bind_group, &[]);
+        pass.set_pipeline(&self.pipeline);
+        // Cost scales with the total number of instances, however they're split into batches.
+        let workgroups = self.instance_count.div_ceil(WORKGROUP_SIZE);
+        let columns = workgroups.min(self.max_workgroups);
+        pass.dispatch_workgroups(columns, workgroups.div_ceil(columns), 1);
+        pass.set_pipeline(&self.copy_counts_pipeline);
+        pass.dispatch_workgroups(self.command_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
@@ -0,0 +1,110 @@
+// Frustum culling for instanced models. Each invocation tests one instance's bounding sphere and,
+// if it is visible, appends it to its batch's part of the compacted output buffer that the scene
+// pass draws from.
+struct Frustum {
+    // xyz is the inward-facing normal, w the distance; a point p is inside when dot(xyz, p) + w >= 0.
+    planes: array<vec4<f32>, 6>,
+}
+@group(0) @binding(0)
+var<uniform> frustum: Frustum;
+
+struct CullParams {
+    instance_count: u32,
+    batch_count: u32,
+}
+@group(1) @binding(5)
+var<uniform> params: CullParams;
+
+// A run of instances in the shared buffers that are drawn together.
+struct Batch {
+    // Index in instances_in of the batch's first instance. Its visible instances are written to
+    // instances_out from the same index.
+    first_instance: u32,
+    instance_count: u32,
+    // Radius of a sphere around the model's origin that contains every vertex, before scaling.
+    bounding_radius: f32,
+    // The command whose instance_count counts the batch's visible instances: its first mesh's.
+    first_command: u32,
+}
+@group(1) @binding(0)
+var<storage, read> batches: array<Batch>;
+// Instances are InstanceRaw laid out as plain floats: position (3), scale (1), rotation (4),
+// shader (1), tint (4). A struct with a vec3 would be padded differently from the Rust side.
+// gpu_state.rs asserts that InstanceRaw is laid out to match.
+@group(1) @binding(1)
+var<storage, read> instances_in: array<f32>;
+@group(1) @binding(2)
+var<storage, read_write> instances_out: array<f32>;
+
+// wgpu::util::DrawIndexedIndirect.
+struct DrawCommand {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+@group(1) @binding(3)
+var<storage, read_write> commands: array<DrawCommand>;
+// For each command, the command whose instance count it copies once culling is done.
+@group(1) @binding(4)
+var<storage, read> count_sources: array<u32>;
+
+const InstanceStride = 14u;
+
+// The batch holding instance `index`: the last one starting at or before it. Batches are in
+// instance order.
+fn find_batch(index: u32) -> Batch {
+    var low = 0u;
+    var high = params.batch_count;
+    while high - low > 1u {
+        let middle = (low + high) / 2u;
+        if batches[middle].first_instance <= index {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    return batches[low];
+}
+
+// One invocation per instance, over every batch. Workgroups wrap onto more rows when there are
+// too many for one.
+@compute @workgroup_size(64)
+fn cs_cull(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let index = id.y * num_workgroups.x * 64u + id.x;
+    if index >= params.instance_count {
+        return;
+    }
+    let batch = find_batch(index);
+    let base = index * InstanceStride;
+    let center = vec3<f32>(instances_in[base], instances_in[base + 1u], instances_in[base + 2u]);
+    let radius = instances_in[base + 3u] * batch.bounding_radius;
+    for (var i = 0; i < 6; i++) {
+        let plane = frustum.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            return;
+        }
+    }
+    let slot = atomicAdd(&commands[batch.first_command].instance_count, 1u);
+    let out_base = (batch.first_instance + slot) * InstanceStride;
+    for (var i = 0u; i < InstanceStride; i++) {
+        instances_out[out_base + i] = instances_in[base + i];
+    }
+}
+
+// Gives a batch's other meshes the visible count of its first mesh, after cs_cull.
+@compute @workgroup_size(64)
+fn cs_copy_counts(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= arrayLength(&count_sources) {
+        return;
+    }
+    let source = count_sources[index];
+    if source != index {
+        atomicStore(&commands[index].instance_count, atomicLoad(&commands[source].instance_count));
+    }
+}
@@ -0,0 +1,235 @@
+/* DebugLines: line segments drawn over the scene to help with debugging, and the renderer that
+ * draws them. */
+use std::mem;
+
+use cgmath::{InnerSpace, Rotation, Vector3};
+
+use crate::{
+    camera::Camera,
+    game_state::ModelWithInstances,
+    gpu_state::BindGroupData,
+    level::{Level, LevelPlacement},
+    post_process::HDR_FORMAT,
+    texture,
+    transform::Transform,
+    uploader::Uploader,
+};
+
+// Lifted off the floor so the lines aren't lost in it, or in the fixtures sunk into it.
+const GRID_HEIGHT: f32 = 0.06;
+const GRID_COLOR: [f32; 4] = [0.2, 1.0, 1.0, 1.0];
+// Every fifth line, to make counting tiles easier.
+const GRID_MAJOR_COLOR: [f32; 4] = [1.0, 0.4, 1.0, 1.0];
+const ROTATION_CHECK_COLOR: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+// How far to the side of an instance its rotation check is drawn, in multiples of its scale.
+// Models span -1 to 1, so this leaves a gap half their size.
+const ROTATION_CHECK_OFFSET: f32 = 2.5;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DebugVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+}
+
+/// Segments in world space, two vertices each.
+#[derive(Clone, Debug, Default)]
+pub struct DebugLines {
+    vertices: Vec<DebugVertex>,
+}
+impl DebugLines {
+    pub fn new() -> Self {
+        DebugLines::default()
+    }
+    pub fn vertices(&self) -> &[DebugVertex] {
+        &self.vertices
+    }
+    pub fn line(&mut self, from: Vector3<f32>, to: Vector3<f32>, color: [f32; 4]) {
+        self.vertices.push(DebugVertex { position: from.into(), color });
+        self.vertices.push(DebugVertex { position: to.into(), color });
+    }
+    /// The lines between `level`'s tiles, just above its floor.
+    pub fn level_grid(&mut self, level: &Level, placement: LevelPlacement) {
+        let corner =
+            |x: i32, z: i32| placement.origin + Vector3::new(x as f32, GRID_HEIGHT, z as f32);
+        let color = |i: i32| if i % 5 == 0 { GRID_MAJOR_COLOR } else { GRID_COLOR };
+        for x in 0..=level.width {
+            self.line(corner(x, 0), corner(x, level.depth), color(x));
+        }
+        for z in 0..=level.depth {
+            self.line(corner(0, z), corner(level.width, z), color(z));
+        }
+    }
+    /// Next to every turned instance, as seen from `camera`, the -1 to 1 cube its model spans,
+    /// turned by cgmath's quaternion for the instance's rotor rather than by the rotor. The shader
+    /// turns the instance itself by the rotor, so the two sides should match.
+    pub fn rotation_check(&mut self, entities: &[ModelWithInstances], camera: &Camera) {
+        let right = camera.direction.cross(camera.up()).normalize();
+        let instances = entities.iter().flat_map(|entity| &entity.instances);
+        for instance in instances.filter(|instance| instance.transform.rotation.s.abs() < 1.0) {
+            let Transform { position, rotation, scale } = instance.transform;
+            let quat = rotation.to_quat();
+            let center = position + ROTATION_CHECK_OFFSET * scale * right;
+            let corner = |i: usize| {
+                let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                center + quat.rotate_vector(scale * Vector3::new(sign(1), sign(2), sign(4)))
+            };
+            // Corners one bit apart share an edge.
+            for i in 0..8 {
+                for bit in [1, 2, 4].into_iter().filter(|bit| i & bit == 0) {
+                    self.line(corner(i), corner(i | bit), ROTATION_CHECK_COLOR);
+                }
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugCameraUniform {
+    view_proj: [[f32; 4]; 4],
+}
+impl DebugCameraUniform {
+    fn from_camera(camera: &Camera) -> Self {
+        DebugCameraUniform { view_proj: camera.build_view_projection_matrix().into() }
+    }
+}
+
+fn describe_debug_vb() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: mem::size_of::<DebugVertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: mem::offset_of!(DebugVertex, position) as wgpu::BufferAddress,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::offset_of!(DebugVertex, color) as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
+pub struct DebugLineRenderer {
+    camera_group: BindGroupData<DebugCameraUniform>,
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    // Capacity of `buffer` in vertices; it grows as needed.
+    capacity: usize,
+    count: u32,
+}
+impl DebugLineRenderer {
+    pub fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+        let camera_group = BindGroupData::<DebugCameraUniform>::new(
+            DebugCameraUniform::from_camera(camera),
+            device,
+            "Debug Line Camera",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::VERTEX,
+        );
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Line Pipeline Layout"),
+            bind_group_layouts: &[&camera_group.layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Debug Line Shaders"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("debug_lines.wgsl").into()),
+        });
+        // Not built with create_render_pipeline: it draws lines, and like particles it is depth
+        // tested against the scene without writing depth.
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Lines"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_line",
+                buffers: &[describe_debug_vb()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_line",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        const INITIAL_CAPACITY: usize = 256;
+        let buffer = Self::create_buffer(device, INITIAL_CAPACITY);
+        DebugLineRenderer { camera_group, pipeline, buffer, capacity: INITIAL_CAPACITY, count: 0 }
+    }
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Line Vertex Buffer"),
+            size: (capacity * mem::size_of::<DebugVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn update_camera(&mut self, uploader: &mut Uploader, camera: &Camera) {
+        self.camera_group.uniform = DebugCameraUniform::from_camera(camera);
+        uploader.upload(&self.camera_group.buffer, 0, &[self.camera_group.uniform]);
+    }
+    pub fn update_lines(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lines: &DebugLines) {
+        let vertices = lines.vertices();
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(vertices));
+        self.count = vertices.len() as u32;
+    }
+    /// Draws the lines over `hdr_view`, depth tested against the scene's `depth_view`.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        if self.count == 0 {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug Line Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: hdr_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.camera_group.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.draw(0..self.count, 0..1);
+    }
+}
@@ -0,0 +1,141 @@
+/* Determinism: per-tick state hashes of a replayed input trace, checked against a golden file. */
+use crate::{
+    game_state::GameState,
+    headless::{InputScript, HEADLESS_ASPECT_RATIO},
+    tick_rate,
+};
+
+use anyhow::{bail, Context};
+use std::time::Instant;
+
+// FNV-1a. std's DefaultHasher isn't guaranteed to be stable between Rust releases, which would
+// invalidate every golden file on a toolchain bump.
+pub struct StateHasher {
+    state: u64,
+}
+impl StateHasher {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    pub fn new() -> Self {
+        StateHasher { state: Self::OFFSET_BASIS }
+    }
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(Self::PRIME);
+        }
+    }
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+    // Hashes the exact bit pattern, so -0.0 and 0.0 (or two different NaNs) count as different
+    // states. That is what we want when looking for simulation drift.
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_bytes(&value.to_bits().to_le_bytes());
+    }
+    pub fn write_f32s(&mut self, values: &[f32]) {
+        for value in values {
+            self.write_f32(*value);
+        }
+    }
+    pub fn finish(&self) -> u64 {
+        self.state
+    }
+}
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Replays `script` from a fresh `GameState`, returning the state hash after every tick.
+pub fn hash_series(script: &InputScript) -> Vec<u64> {
+    let mut game_state = GameState::new(HEADLESS_ASPECT_RATIO);
+    let mut tick_instant = Instant::now();
+    let mut hashes = Vec::with_capacity(script.total_ticks() as usize);
+    for step in &script.steps {
+        for _ in 0..step.ticks {
+            tick_instant += tick_rate::time_per_tick();
+            game_state.update(&step.input, tick_instant);
+            let mut hasher = StateHasher::new();
+            game_state.hash_into(&mut hasher);
+            hashes.push(hasher.finish());
+        }
+    }
+    hashes
+}
+
+// Golden files hold one hex hash per line, in tick order.
+pub fn write_golden(path: &str, hashes: &[u64]) -> anyhow::Result<()> {
+    let text: String = hashes.iter().map(|hash| format!("{:016x}\n", hash)).collect();
+    std::fs::write(path, text).with_context(|| format!("writing golden hashes to {}", path))?;
+    Ok(())
+}
+
+pub fn read_golden(path: &str) -> anyhow::Result<Vec<u64>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading golden hashes from {}", path))?;
+    text.lines()
+        .map(|line| u64::from_str_radix(line.trim(), 16).map_err(anyhow::Error::from))
+        .collect()
+}
+
+/// Returns the first tick (1-based) where `actual` diverges from `golden`, if any.
+pub fn first_divergence(golden: &[u64], actual: &[u64]) -> Option<usize> {
+    let mismatch = golden.iter().zip(actual).position(|(g, a)| g != a);
+    match mismatch {
+        Some(i) => Some(i + 1),
+        None if golden.len() != actual.len() => Some(golden.len().min(actual.len()) + 1),
+        None => None,
+    }
+}
+
+/// Entry point for `cube --determinism record|check <input script> <golden file>`.
+pub fn run_from_args(args: &[String]) -> anyhow::Result<()> {
+    let [mode, script_path, golden_path, ..] = args else {
+        bail!("usage: cube --determinism record|check <input script> <golden file>");
+    };
+    let text = std::fs::read_to_string(script_path)
+        .with_context(|| format!("reading input script {}", script_path))?;
+    let hashes = hash_series(&InputScript::parse(&text)?);
+    match mode.as_str() {
+        "record" => {
+            write_golden(golden_path, &hashes)?;
+            println!("Recorded {} tick hashes to {}", hashes.len(), golden_path);
+        }
+        "check" => {
+            let golden = read_golden(golden_path)?;
+            if let Some(tick) = first_divergence(&golden, &hashes) {
+                bail!("simulation diverged from {} at tick {}", golden_path, tick);
+            }
+            println!("All {} tick hashes match {}", hashes.len(), golden_path);
+        }
+        _ => bail!("unknown determinism mode '{}', expected record or check", mode),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The golden hashes come from the float build. Fixed point steps bodies to other positions.
+    #[test]
+    #[cfg(not(feature = "fixed-point"))]
+    fn recorded_walk_matches_golden_hashes() {
+        const SCRIPT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/determinism/walk.script");
+        const GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/determinism/walk.golden");
+        let text = std::fs::read_to_string(SCRIPT).unwrap();
+        let hashes = hash_series(&InputScript::parse(&text).unwrap());
+        let golden = read_golden(GOLDEN).unwrap();
+        assert_eq!(first_divergence(&golden, &hashes), None);
+    }
+
+    #[test]
+    fn first_divergence_is_one_based() {
+        assert_eq!(first_divergence(&[1, 2, 3], &[1, 2, 3]), None);
+        assert_eq!(first_divergence(&[1, 2, 3], &[1, 5, 3]), Some(2));
+        assert_eq!(first_divergence(&[1, 2, 3], &[1, 2]), Some(3));
+    }
+}
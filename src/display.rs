@@ -0,0 +1,193 @@
+/* Display: what Windows can tell us about the monitor the window is on and whether the window
+ * can be seen on it, and where the window sat on its monitor last session. */
+use std::{ffi::CStr, mem};
+
+use anyhow::{bail, Context};
+use windows::{
+    core::PCSTR,
+    Win32::{
+        Foundation::{HWND, POINT, RECT},
+        Graphics::Gdi::{
+            EnumDisplaySettingsA, GetMonitorInfoA, MonitorFromPoint, MonitorFromRect,
+            MonitorFromWindow, DEVMODEA, ENUM_CURRENT_SETTINGS, HMONITOR, MONITORINFO,
+            MONITORINFOEXA, MONITOR_DEFAULTTONEAREST, MONITOR_DEFAULTTONULL,
+            MONITOR_DEFAULTTOPRIMARY,
+        },
+        UI::WindowsAndMessaging::{
+            GetForegroundWindow, GetWindow, GetWindowRect, IsIconic, GW_HWNDPREV,
+        },
+    },
+};
+
+pub const WINDOW_FILE: &str = "window.txt";
+
+// The share of its monitor's work area a window starts out with, when there is no placement to
+// restore.
+const DEFAULT_WINDOW_SCALE: i32 = 4;
+const DEFAULT_WINDOW_SHARE: i32 = 3;
+
+/// Where the window was and whether it covered its monitor, saved on exit to put it back there on
+/// the next launch.
+///
+/// As a line: 1 for fullscreen or 0, the windowed rectangle's left, top, right and bottom, then
+/// the monitor's device name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WindowPlacement {
+    // The device name Windows gives the monitor, like \\.\DISPLAY1.
+    pub monitor: String,
+    // Where the window is when not fullscreen, in screen coordinates.
+    pub windowed: RECT,
+    pub fullscreen: bool,
+}
+impl WindowPlacement {
+    /// Fullscreen on the primary monitor, going windowed to the middle of it.
+    pub fn primary() -> Option<Self> {
+        let monitor = unsafe { MonitorFromPoint(POINT { x: 0, y: 0 }, MONITOR_DEFAULTTOPRIMARY) };
+        let info = monitor_info(monitor)?;
+        let work = info.monitorInfo.rcWork;
+        let (width, height) = (work.right - work.left, work.bottom - work.top);
+        let (window_width, window_height) = (
+            width * DEFAULT_WINDOW_SHARE / DEFAULT_WINDOW_SCALE,
+            height * DEFAULT_WINDOW_SHARE / DEFAULT_WINDOW_SCALE,
+        );
+        let left = work.left + (width - window_width) / 2;
+        let top = work.top + (height - window_height) / 2;
+        Some(WindowPlacement {
+            monitor: device_name(&info),
+            windowed: RECT { left, top, right: left + window_width, bottom: top + window_height },
+            fullscreen: true,
+        })
+    }
+    /// The placement of `window`, which is fullscreen if it has a `windowed` rectangle to go back
+    /// to.
+    pub fn of(window: HWND, windowed: Option<RECT>) -> Option<Self> {
+        let fullscreen = windowed.is_some();
+        let windowed = match windowed {
+            Some(rect) => rect,
+            None => {
+                let mut rect = RECT::default();
+                unsafe { GetWindowRect(window, &mut rect) }.ok()?;
+                rect
+            }
+        };
+        let monitor = unsafe { MonitorFromRect(&windowed, MONITOR_DEFAULTTONEAREST) };
+        let monitor = device_name(&monitor_info(monitor)?);
+        Some(WindowPlacement { monitor, windowed, fullscreen })
+    }
+    /// Whether the windowed rectangle is still on the monitor it was saved on. A monitor that has
+    /// been unplugged or moved leaves nowhere to put the window back.
+    pub fn is_on_its_monitor(&self) -> bool {
+        let monitor = unsafe { MonitorFromRect(&self.windowed, MONITOR_DEFAULTTONULL) };
+        !monitor.is_invalid()
+            && monitor_info(monitor).is_some_and(|info| device_name(&info) == self.monitor)
+    }
+    /// The rectangle the window takes up: the whole of its monitor when fullscreen.
+    pub fn rect(&self) -> RECT {
+        if !self.fullscreen {
+            return self.windowed;
+        }
+        let monitor = unsafe { MonitorFromRect(&self.windowed, MONITOR_DEFAULTTONEAREST) };
+        monitor_info(monitor).map_or(self.windowed, |info| info.monitorInfo.rcMonitor)
+    }
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let line = text.lines().next().unwrap_or("").trim();
+        let fields = line.splitn(6, ' ').collect::<Vec<_>>();
+        let [fullscreen, left, top, right, bottom, monitor] = fields[..] else {
+            bail!("expected fullscreen, a rectangle and a monitor, found {:?}", line);
+        };
+        Ok(WindowPlacement {
+            monitor: monitor.to_string(),
+            windowed: RECT {
+                left: left.parse()?,
+                top: top.parse()?,
+                right: right.parse()?,
+                bottom: bottom.parse()?,
+            },
+            fullscreen: fullscreen == "1",
+        })
+    }
+    /// A missing file means there is no placement to restore.
+    pub fn load(path: &str) -> anyhow::Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text)
+                .map(Some)
+                .with_context(|| format!("parsing window placement {}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading window placement {}", path)),
+        }
+    }
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let r = self.windowed;
+        let text = format!(
+            "{} {} {} {} {} {}\n",
+            self.fullscreen as u8, r.left, r.top, r.right, r.bottom, self.monitor
+        );
+        std::fs::write(path, text).with_context(|| format!("writing window placement to {}", path))
+    }
+}
+
+fn monitor_info(monitor: HMONITOR) -> Option<MONITORINFOEXA> {
+    let mut info = MONITORINFOEXA::default();
+    info.monitorInfo.cbSize = mem::size_of::<MONITORINFOEXA>() as u32;
+    let info_ptr = &mut info as *mut MONITORINFOEXA as *mut MONITORINFO;
+    unsafe { GetMonitorInfoA(monitor, info_ptr) }.as_bool().then_some(info)
+}
+
+fn device_name(info: &MONITORINFOEXA) -> String {
+    CStr::from_bytes_until_nul(&info.szDevice)
+        .map_or(String::new(), |name| name.to_string_lossy().into_owned())
+}
+
+/// The area of the monitor showing most of `window`, in screen coordinates.
+pub fn monitor_rect(window: HWND) -> Option<RECT> {
+    let monitor = unsafe { MonitorFromWindow(window, MONITOR_DEFAULTTONEAREST) };
+    monitor_info(monitor).map(|info| info.monitorInfo.rcMonitor)
+}
+
+/// Whether no one can see `window`: it is minimized, or the window in front of it covers all of
+/// it. A window partly in front, or several covering it between them, doesn't count.
+pub fn is_out_of_sight(window: HWND) -> bool {
+    if unsafe { IsIconic(window) }.as_bool() {
+        return true;
+    }
+    let foreground = unsafe { GetForegroundWindow() };
+    if foreground.0 == 0 || foreground == window || !is_in_front(foreground, window) {
+        return false;
+    }
+    let (mut ours, mut theirs) = (RECT::default(), RECT::default());
+    if unsafe { GetWindowRect(window, &mut ours) }.is_err()
+        || unsafe { GetWindowRect(foreground, &mut theirs) }.is_err()
+    {
+        return false;
+    }
+    theirs.left <= ours.left
+        && theirs.top <= ours.top
+        && theirs.right >= ours.right
+        && theirs.bottom >= ours.bottom
+}
+
+// Whether `other` is above `window` in the z-order. The desktop can be the foreground window while
+// being behind everything.
+fn is_in_front(other: HWND, window: HWND) -> bool {
+    let mut above = unsafe { GetWindow(window, GW_HWNDPREV) };
+    while above.0 != 0 {
+        if above == other {
+            return true;
+        }
+        above = unsafe { GetWindow(above, GW_HWNDPREV) };
+    }
+    false
+}
+
+/// The refresh rate of the monitor showing most of `window`, in hertz, if Windows reports one.
+pub fn refresh_rate(window: HWND) -> Option<f32> {
+    let monitor = unsafe { MonitorFromWindow(window, MONITOR_DEFAULTTONEAREST) };
+    let info = monitor_info(monitor)?;
+    let mut mode = DEVMODEA { dmSize: mem::size_of::<DEVMODEA>() as u16, ..Default::default() };
+    let device = PCSTR(info.szDevice.as_ptr());
+    if !unsafe { EnumDisplaySettingsA(device, ENUM_CURRENT_SETTINGS, &mut mode) }.as_bool() {
+        return None;
+    }
+    // 0 and 1 stand for the hardware's default rate, which isn't reported.
+    (mode.dmDisplayFrequency > 1).then_some(mode.dmDisplayFrequency as f32)
+}
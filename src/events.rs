@@ -0,0 +1,115 @@
+/* Events: gameplay events raised during a game tick, for anything that reacts to the simulation. */
+use crate::{determinism::StateHasher, level::LevelStats};
+
+// Something that can set off a trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Body {
+    Player,
+    // The second player in a co-op game.
+    Partner,
+    // Index of a pushable box.
+    Box(usize),
+}
+impl Body {
+    fn hash_into(&self, hasher: &mut StateHasher) {
+        match self {
+            Body::Player => hasher.write_u64(u64::MAX),
+            Body::Partner => hasher.write_u64(u64::MAX - 1),
+            Body::Box(index) => hasher.write_u64(*index as u64),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    TriggerEntered { trigger: u32, body: Body },
+    TriggerExited { trigger: u32, body: Body },
+    // `door` is the door's id, shared with the plates that open it.
+    DoorOpened { door: u32 },
+    DoorClosed { door: u32 },
+    // The player walked from one tile onto the next.
+    PlayerMoved { from: (i32, i32), to: (i32, i32) },
+    // The player pushed box `index`.
+    BoxPushed { index: usize },
+    // The player's last push was taken back.
+    MoveUndone,
+    // Every box came to rest on a goal. `level` is the level's id.
+    LevelCompleted { level: u64, stats: LevelStats },
+    // The player fell out of the world and was put back at the last checkpoint.
+    PlayerRespawned,
+    // The player walked into a checkpoint, which is now where they go back to.
+    CheckpointReached,
+}
+
+/// The events raised on the current tick, in the order they happened.
+///
+/// The bus is cleared at the start of every tick, so a `GameState` snapshot carries exactly the
+/// events of the tick that produced it.
+#[derive(Clone)]
+pub struct EventBus {
+    events: Vec<GameEvent>,
+}
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus { events: Vec::new() }
+    }
+    pub fn publish(&mut self, event: GameEvent) {
+        self.events.push(event);
+    }
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &GameEvent> {
+        self.events.iter()
+    }
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.events.len() as u64);
+        for event in &self.events {
+            match event {
+                GameEvent::TriggerEntered { trigger, body } => {
+                    hasher.write_u64(0);
+                    hasher.write_u64(*trigger as u64);
+                    body.hash_into(hasher);
+                }
+                GameEvent::TriggerExited { trigger, body } => {
+                    hasher.write_u64(1);
+                    hasher.write_u64(*trigger as u64);
+                    body.hash_into(hasher);
+                }
+                GameEvent::DoorOpened { door } => {
+                    hasher.write_u64(2);
+                    hasher.write_u64(*door as u64);
+                }
+                GameEvent::DoorClosed { door } => {
+                    hasher.write_u64(3);
+                    hasher.write_u64(*door as u64);
+                }
+                GameEvent::PlayerMoved { from, to } => {
+                    hasher.write_u64(5);
+                    for value in [from.0, from.1, to.0, to.1] {
+                        hasher.write_u64(value as u64);
+                    }
+                }
+                GameEvent::BoxPushed { index } => {
+                    hasher.write_u64(6);
+                    hasher.write_u64(*index as u64);
+                }
+                GameEvent::MoveUndone => hasher.write_u64(7),
+                GameEvent::PlayerRespawned => hasher.write_u64(8),
+                GameEvent::CheckpointReached => hasher.write_u64(9),
+                GameEvent::LevelCompleted { level, stats } => {
+                    hasher.write_u64(4);
+                    hasher.write_u64(*level);
+                    hasher.write_u64(stats.moves as u64);
+                    hasher.write_u64(stats.pushes as u64);
+                    hasher.write_u64(stats.ticks as u64);
+                }
+            }
+        }
+    }
+}
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,124 @@
+/* Fixed: 48.16 fixed-point numbers, for simulation math that comes out the same on every
+ * machine. */
+use cgmath::{BaseFloat, Vector3};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use crate::rotor::scalar;
+
+/// A number with 16 bits after the binary point, held in an i64. Every operation is integer math,
+/// so it doesn't depend on how a machine rounds floats. Products round towards negative infinity,
+/// quotients towards zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fixed(i64);
+impl Fixed {
+    pub const FRACTION_BITS: u32 = 16;
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << Self::FRACTION_BITS);
+
+    pub fn from_bits(bits: i64) -> Self {
+        Fixed(bits)
+    }
+    pub fn to_bits(self) -> i64 {
+        self.0
+    }
+    /// The nearest fixed-point number to `value`. An f32 or f64 times a power of two is exact, so
+    /// only the final rounding happens, and that rounds the same everywhere.
+    pub fn from_float<S: BaseFloat>(value: S) -> Self {
+        let scale = (1u64 << Self::FRACTION_BITS) as f64;
+        Fixed((value.to_f64().unwrap() * scale).round() as i64)
+    }
+    pub fn to_float<S: BaseFloat>(self) -> S {
+        scalar(self.0 as f64 / (1u64 << Self::FRACTION_BITS) as f64)
+    }
+    /// 0 for negative numbers.
+    pub fn sqrt(self) -> Self {
+        if self.0 <= 0 {
+            return Fixed::ZERO;
+        }
+        // sqrt(bits / 2^16) * 2^16 = sqrt(bits * 2^16).
+        Fixed(((self.0 as u128) << Self::FRACTION_BITS).isqrt() as i64)
+    }
+}
+impl Add for Fixed {
+    type Output = Fixed;
+    fn add(self, other: Fixed) -> Fixed {
+        Fixed(self.0 + other.0)
+    }
+}
+impl Sub for Fixed {
+    type Output = Fixed;
+    fn sub(self, other: Fixed) -> Fixed {
+        Fixed(self.0 - other.0)
+    }
+}
+impl Neg for Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed(-self.0)
+    }
+}
+impl Mul for Fixed {
+    type Output = Fixed;
+    fn mul(self, other: Fixed) -> Fixed {
+        Fixed(((self.0 as i128 * other.0 as i128) >> Self::FRACTION_BITS) as i64)
+    }
+}
+/// Panics when dividing by zero.
+impl Div for Fixed {
+    type Output = Fixed;
+    fn div(self, other: Fixed) -> Fixed {
+        Fixed((((self.0 as i128) << Self::FRACTION_BITS) / other.0 as i128) as i64)
+    }
+}
+
+/// A vector of fixed-point numbers, with the operations the physics step needs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FixedVector3 {
+    pub x: Fixed,
+    pub y: Fixed,
+    pub z: Fixed,
+}
+impl FixedVector3 {
+    pub fn from_vector<S: BaseFloat>(v: Vector3<S>) -> Self {
+        FixedVector3 {
+            x: Fixed::from_float(v.x),
+            y: Fixed::from_float(v.y),
+            z: Fixed::from_float(v.z),
+        }
+    }
+    pub fn to_vector<S: BaseFloat>(self) -> Vector3<S> {
+        Vector3::new(self.x.to_float(), self.y.to_float(), self.z.to_float())
+    }
+    pub fn dot(self, other: FixedVector3) -> Fixed {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+    pub fn magnitude(self) -> Fixed {
+        self.dot(self).sqrt()
+    }
+    /// This vector stretched to `length`. A zero vector has no direction, so it stays zero.
+    pub fn normalize_to(self, length: Fixed) -> Self {
+        let magnitude = self.magnitude();
+        if magnitude == Fixed::ZERO {
+            return FixedVector3::default();
+        }
+        self * (length / magnitude)
+    }
+}
+impl Add for FixedVector3 {
+    type Output = FixedVector3;
+    fn add(self, other: FixedVector3) -> FixedVector3 {
+        FixedVector3 { x: self.x + other.x, y: self.y + other.y, z: self.z + other.z }
+    }
+}
+impl Sub for FixedVector3 {
+    type Output = FixedVector3;
+    fn sub(self, other: FixedVector3) -> FixedVector3 {
+        FixedVector3 { x: self.x - other.x, y: self.y - other.y, z: self.z - other.z }
+    }
+}
+impl Mul<Fixed> for FixedVector3 {
+    type Output = FixedVector3;
+    fn mul(self, k: Fixed) -> FixedVector3 {
+        FixedVector3 { x: self.x * k, y: self.y * k, z: self.z * k }
+    }
+}
@@ -0,0 +1,163 @@
+/* Frame times: how long frames and ticks take, kept as histograms and, for frames, as a graph of
+ * the latest ones, and hitches, the frames and ticks far slower than they should be, logged
+ * along with what was logged just before them. */
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::{crash, log_println};
+
+// Upper edges of the histogram's buckets, in milliseconds. Times past the last go in one more.
+const BUCKET_EDGES_MS: [f32; 9] = [2.0, 4.0, 8.0, 12.0, 17.0, 25.0, 34.0, 50.0, 100.0];
+/// How many of the latest frame times the graph shows.
+pub const GRAPH_FRAMES: usize = 120;
+// A frame is a hitch past this long, or past twice as long as the frame rate cap asks for if that
+// is longer, so a low cap isn't all hitches.
+const FRAME_HITCH_TIME: Duration = Duration::from_millis(50);
+// How many log lines from before a hitch are logged with it.
+const HITCH_LOG_LINES: usize = 5;
+// Hitches this close after a logged one are counted but not logged, so a machine that can't keep
+// up doesn't fill the log.
+const HITCH_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Counts of times in each of a fixed set of buckets, from under 2 ms to over 100 ms.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    counts: [u64; BUCKET_EDGES_MS.len() + 1],
+}
+impl Histogram {
+    pub fn record(&mut self, time: Duration) {
+        let ms = time.as_secs_f32() * 1000.0;
+        let bucket = BUCKET_EDGES_MS.iter().position(|&edge| ms < edge);
+        self.counts[bucket.unwrap_or(BUCKET_EDGES_MS.len())] += 1;
+    }
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+    /// The bucket the time a `fraction` of the way up the recorded ones falls in, like "<17 ms"
+    /// for a median of 16 ms. None with nothing recorded.
+    pub fn percentile(&self, fraction: f32) -> Option<String> {
+        let target = (self.count() as f32 * fraction).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        let bucket = self.counts.iter().position(|&count| {
+            seen += count;
+            seen >= target
+        })?;
+        Some(bucket_label(bucket))
+    }
+}
+impl fmt::Display for Histogram {
+    // Each bucket with anything in it, like "<17 ms: 480, <25 ms: 3".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let buckets = self.counts.iter().enumerate().filter(|(_, &count)| count > 0);
+        for (i, (bucket, count)) in buckets.enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            write!(f, "{}{}: {}", separator, bucket_label(bucket), count)?;
+        }
+        Ok(())
+    }
+}
+
+fn bucket_label(bucket: usize) -> String {
+    match BUCKET_EDGES_MS.get(bucket) {
+        Some(edge) => format!("<{} ms", edge),
+        None => format!(">{} ms", BUCKET_EDGES_MS[BUCKET_EDGES_MS.len() - 1]),
+    }
+}
+
+/// Frame and tick times from the render and game threads, for the stats HUD and the log.
+///
+/// A hitch is logged with the few lines logged before it, which often say what caused it, like a
+/// level loading or a shader being rebuilt.
+pub struct FrameTimes {
+    pub frames: Histogram,
+    pub ticks: Histogram,
+    // The latest frame times, oldest first.
+    recent_frames: VecDeque<Duration>,
+    // The time past which the latest frame was a hitch.
+    frame_hitch_time: Duration,
+    hitches: u64,
+    last_logged_hitch: Option<Instant>,
+    // Set while nothing is drawn, so the gap isn't counted as a frame.
+    skip_frame: bool,
+}
+impl FrameTimes {
+    pub fn new() -> Self {
+        FrameTimes {
+            frames: Histogram::default(),
+            ticks: Histogram::default(),
+            recent_frames: VecDeque::with_capacity(GRAPH_FRAMES),
+            frame_hitch_time: FRAME_HITCH_TIME,
+            hitches: 0,
+            last_logged_hitch: None,
+            skip_frame: false,
+        }
+    }
+    /// Counts a frame `time` after the one before, drawn with the frame rate capped to one per
+    /// `min_frame_time`, if at all.
+    pub fn record_frame(&mut self, time: Duration, min_frame_time: Option<Duration>) {
+        if std::mem::take(&mut self.skip_frame) {
+            return;
+        }
+        self.frames.record(time);
+        if self.recent_frames.len() == GRAPH_FRAMES {
+            self.recent_frames.pop_front();
+        }
+        self.recent_frames.push_back(time);
+        self.frame_hitch_time =
+            min_frame_time.map_or(FRAME_HITCH_TIME, |min| (min * 2).max(FRAME_HITCH_TIME));
+        if time > self.frame_hitch_time {
+            self.hitch("frame", time);
+        }
+    }
+    /// Leaves the next frame uncounted, for when nothing has been drawn for a while.
+    pub fn skip_frame(&mut self) {
+        self.skip_frame = true;
+    }
+    /// Counts a tick whose update took `time`, a hitch if that is longer than the `budget` of
+    /// real time between ticks, so the game falls behind.
+    pub fn record_tick(&mut self, time: Duration, budget: Duration) {
+        self.ticks.record(time);
+        if time > budget {
+            self.hitch("tick", time);
+        }
+    }
+    /// The latest frame times, oldest first.
+    pub fn recent_frames(&self) -> impl Iterator<Item = Duration> + '_ {
+        self.recent_frames.iter().copied()
+    }
+    /// The time past which a frame counts as a hitch, at the latest frame rate cap.
+    pub fn frame_hitch_time(&self) -> Duration {
+        self.frame_hitch_time
+    }
+    pub fn hitches(&self) -> u64 {
+        self.hitches
+    }
+    /// Logs both histograms.
+    pub fn log_summary(&self) {
+        log_println!("Frame times: {}", self.frames);
+        log_println!("Tick times: {}", self.ticks);
+    }
+    fn hitch(&mut self, what: &str, time: Duration) {
+        self.hitches += 1;
+        let now = Instant::now();
+        if self.last_logged_hitch.is_some_and(|last| now - last < HITCH_LOG_INTERVAL) {
+            return;
+        }
+        self.last_logged_hitch = Some(now);
+        let mut message = format!("Hitch: {} took {:.1} ms", what, time.as_secs_f32() * 1000.0);
+        let before = crash::recent_log(HITCH_LOG_LINES);
+        if !before.is_empty() {
+            message += ", after:";
+            message.extend(before.iter().map(|line| format!("\n  {}", line)));
+        }
+        log_println!("{}", message);
+    }
+}
+impl Default for FrameTimes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,70 @@
+/* Free camera: a debug fly camera that leaves the player behind while the game keeps running. */
+use cgmath::{Deg, InnerSpace, Vector3};
+
+use crate::camera::Camera;
+
+const DEFAULT_SPEED: f32 = 5.0;
+const SPEED_RANGE: (f32, f32) = (0.5, 100.0);
+// Each speed step multiplies or divides the speed by this.
+const SPEED_STEP: f32 = 1.25;
+// Degrees turned per unit of mouse movement, like the player's camera.
+const ROTATION_MOVEMENT_DEG: f32 = 0.1;
+
+/// Keys for the free camera. These never reach the game, so they aren't part of `InputState`.
+#[derive(Clone, Copy, Default)]
+pub struct FlyInput {
+    // Switch between the free camera and the player's.
+    pub toggle: bool,
+    pub forward: bool,
+    pub backward: bool,
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    // Speed steps up (positive) or down since the last tick.
+    pub speed_steps: i32,
+}
+impl FlyInput {
+    pub fn new() -> Self {
+        FlyInput::default()
+    }
+    pub fn post_update_reset(&mut self) {
+        self.toggle = false;
+        self.speed_steps = 0;
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FreeCamera {
+    camera: Camera,
+    // Units per second.
+    pub speed: f32,
+}
+impl FreeCamera {
+    /// Starts where `camera` is, looking the same way.
+    pub fn new(camera: Camera) -> Self {
+        FreeCamera { camera, speed: DEFAULT_SPEED }
+    }
+    pub fn camera(&self) -> Camera {
+        self.camera
+    }
+    /// Flies along the view direction and turns with the mouse. Up and down are along world Y.
+    pub fn update(&mut self, input: &FlyInput, mouse_x: i32, mouse_y: i32, delta_t: f32) {
+        self.speed =
+            (self.speed * SPEED_STEP.powi(input.speed_steps)).clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+        self.camera.update_shake(delta_t);
+        self.camera.turn(
+            Deg(-ROTATION_MOVEMENT_DEG * mouse_x as f32),
+            Deg(ROTATION_MOVEMENT_DEG * mouse_y as f32),
+        );
+        let forward = self.camera.direction.normalize();
+        let right = forward.cross(Vector3::unit_y()).normalize();
+        let axis = |positive: bool, negative: bool| positive as i32 as f32 - negative as i32 as f32;
+        let direction = forward * axis(input.forward, input.backward)
+            + right * axis(input.right, input.left)
+            + Vector3::unit_y() * axis(input.up, input.down);
+        if direction.magnitude2() > 0.0 {
+            self.camera.eye += direction.normalize() * self.speed * delta_t;
+        }
+    }
+}
@@ -0,0 +1,16 @@
+// Shared vertex stage for fullscreen passes. Prepended to the shader modules that use it.
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Draws a single triangle that covers the whole screen: (-1, -1), (3, -1), (-1, 3).
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    let x = f32((index << 1u) & 2u);
+    let y = f32(index & 2u);
+    var out: FullscreenOutput;
+    out.clip_position = vec4<f32>(x * 2.0 - 1.0, y * 2.0 - 1.0, 0.0, 1.0);
+    out.uv = vec2<f32>(x, 1.0 - y);
+    return out;
+}
@@ -1,13 +1,37 @@
-use std::time::Instant;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+    time::Instant,
+};
 
-use cgmath::{num_traits::abs, InnerSpace, Vector3, Zero};
+use cgmath::{EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
 
 use crate::{
-    camera::Camera,
-    constants::{GRAVITY, PLAYER_FORCE, TIME_PER_GAME_TICK},
+    animation::{AnimationClip, Easing, EntityAnimation, InstanceRef, Keyframe, Playback},
+    camera::{Camera, SpringFollow, FOVY_RANGE},
+    constants::{GRAVITY, PLAYER_FORCE},
+    determinism::StateHasher,
+    events::{Body, EventBus, GameEvent},
+    ghost::{BestRun, Ghost},
     gpu_state::InstanceRaw,
-    physics::{Collision, Physics},
+    headless::InputScript,
+    input::{Button, ButtonPhase},
+    joints::{Anchor, Contraption, Joint, JointKind},
+    level::{Direction, Level, LevelPlacement, LevelStats, Move, Tile},
+    log_println,
+    movement::{MovementMode, StepTween},
+    net::SharedState,
+    particles::{ParticleEffect, ParticleSystem},
+    physics::{Aabb, Collision, GravityZone, Physics},
+    platforms::Platform,
+    rng::Rng,
     rotor::Rotor,
+    save::Checkpoint,
+    scene::{Environment, SkyColors},
+    scripting::{LevelScript, ScriptCommand, ScriptEvent},
+    tick_rate,
+    transform::Transform,
+    triggers::{TriggerVolume, Triggers},
 };
 
 #[derive(Clone)]
@@ -16,13 +40,145 @@ pub struct ModelWithInstances {
     pub instances: Vec<Instance>,
 }
 
+/// Unit cubes that stay put for the whole level, like the level's walls. The renderer merges them
+/// into one mesh per shader when the level loads, rather than drawing each as an instance. A new
+/// level gets a new `Arc`, so comparing pointers tells whether they changed.
+#[derive(Clone, Default)]
+pub struct StaticGeometry {
+    pub instances: Vec<Instance>,
+}
+
 #[derive(Clone)]
 struct Player {
     camera: Camera,
+    // Smooths the eye over collision corrections and landings.
+    eye_follow: SpringFollow,
     physics: Physics,
 }
 
+// The second player in a co-op game. On the hosting side they step tile by tile as the joining
+// player asks. On the joining side they stand in for the host, wherever the host says they are.
+#[derive(Clone)]
+struct Partner {
+    tile: (i32, i32),
+    // Index of the partner's instance within instanced_entities[LEVEL_ENTITY].
+    instance: usize,
+}
+
+// A box held with the interact key, to push along the grid. `direction` is the way it is pushed:
+// from the player's tile to the box's, when it was grabbed.
+#[derive(Clone, Copy)]
+struct Grab {
+    index: usize,
+    direction: Direction,
+}
+
 const CAMERA_PHYSICS_OFFSET: f32 = 0.4;
+const CAMERA_STIFFNESS: f32 = 20.0;
+// Distance from the player's center of mass down to their feet.
+const PLAYER_FEET_OFFSET: f32 = 0.5;
+// The player's extent for overlap tests against triggers.
+const PLAYER_HALF_EXTENTS: Vector3<f32> = Vector3::new(0.125, PLAYER_FEET_OFFSET, 0.125);
+// Downward speed above which touching the floor kicks up dust.
+const LANDING_DUST_SPEED: f32 = 2.0;
+// Camera trauma per unit of landing speed beyond LANDING_DUST_SPEED.
+const LANDING_TRAUMA_PER_SPEED: f32 = 0.1;
+const DOOR_TRAUMA: f32 = 0.3;
+const LEVEL_COMPLETE_TRAUMA: f32 = 0.4;
+const LEVEL_SCRIPT: &str = "levels/sandbox.rhai";
+// Played in order, moving on to the next level once every box is on a goal.
+const LEVEL_PACK: &str = "levels/sandbox.xsb";
+// How long a finished level stays up before the next one replaces it.
+const LEVEL_COMPLETE_SECS: f32 = 3.0;
+const PACK_COMPLETE_LIGHT_COLOR: [f32; 3] = [1.0, 0.85, 0.45];
+// The level's walls, boxes and fixtures are all instances of this entity.
+const LEVEL_ENTITY: usize = 5;
+const LEVEL_ORIGIN: Vector3<f32> = Vector3::new(6.0, -5.5, -6.0);
+// Plate i gets trigger PLATE_TRIGGER_IDS + i. Triggers placed by hand use ids below this.
+const PLATE_TRIGGER_IDS: u32 = 100;
+// Checkpoint i gets trigger CHECKPOINT_TRIGGER_IDS + i, well clear of the plates'.
+const CHECKPOINT_TRIGGER_IDS: u32 = 200;
+// The floor the player walks on, at y = -5. Past its edges they fall.
+const FLOOR: Aabb =
+    Aabb { min: Vector3::new(-20.0, -5.5, -20.0), max: Vector3::new(20.0, -5.0, 20.0) };
+// Below this the player has fallen out of the world, unless the level sets its own.
+const DEFAULT_KILL_Y: f32 = -30.0;
+const DOOR_ANIMATION_SECS: f32 = 0.5;
+// How far an open door sinks: all the way into the floor, bar a sliver to show where it is.
+const DOOR_SINK_DEPTH: f32 = 0.95;
+// How long a pushed box takes to move one tile, sliding or not.
+const BOX_SECS_PER_TILE: f32 = 0.08;
+const BOX_HALF_EXTENTS: Vector3<f32> = Vector3::new(0.5, 0.5, 0.5);
+// How far from the eye a box can be grabbed.
+const GRAB_REACH: f32 = 2.0;
+// Instance tints, multiplied into whatever the instance's shader draws.
+pub const NO_TINT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const GOAL_TINT: [f32; 4] = [1.0, 0.8, 0.3, 1.0];
+const BOX_ON_GOAL_TINT: [f32; 4] = [0.6, 1.0, 0.6, 1.0];
+const GHOST_TINT: [f32; 4] = [1.0, 1.0, 1.0, 0.6];
+// Conveyors move what's on them one tile in this long. shaders.wgsl's fs_conveyor scrolls at the
+// same speed.
+const CONVEYOR_SECS_PER_TILE: f32 = 0.5;
+const JUMP_SPEED: f32 = 5.0;
+const PLAYER_MAX_SPEED: f32 = 10.0;
+// How much wider the field of view gets at top speed, in degrees, with the FOV kick on.
+const FOV_KICK_DEGREES: f32 = 8.0;
+// Fraction of the way the field of view moves towards its target each tick.
+const FOV_KICK_EASING: f32 = 0.1;
+// Degrees each mouse wheel notch narrows the field of view by, and the most it can be narrowed.
+const ZOOM_STEP_DEGREES: f32 = 5.0;
+const MAX_ZOOM_DEGREES: f32 = 30.0;
+const GHOST_PLAYER_SCALE: f32 = 0.25;
+const PARTNER_SCALE: f32 = 0.25;
+const PARTNER_TINT: [f32; 4] = [1.0, 0.55, 0.2, 1.0];
+const PARTNER_SECS_PER_TILE: f32 = 0.08;
+const OPPONENT_TINT: [f32; 4] = [1.0, 0.5, 0.5, 0.6];
+// The `Rng` streams of the game's random numbers, each seeded from the level.
+const GAMEPLAY_RNG_STREAM: u64 = 0;
+const PARTICLE_RNG_STREAM: u64 = 1;
+const UPSIDE_DOWN_ZONE_CENTER: Vector3<f32> = Vector3::new(12.0, -3.5, 8.0);
+// The rope bridge hangs between two posts this far apart, from links a little longer in all, so
+// that it sags.
+const BRIDGE_LEFT_POST: Vector3<f32> = Vector3::new(-13.0, -2.5, -4.0);
+const BRIDGE_SPAN: f32 = 3.6;
+const BRIDGE_LINKS: usize = 10;
+const BRIDGE_LINK_SCALE: f32 = 0.2;
+// Where the light sits with the day/night cycle off.
+const LIGHT_POSITION: [f32; 3] = [2.0, 2.0, 2.0];
+// One whole day and night, with the cycle on: ten minutes.
+const DAY_LENGTH_SECS: f32 = 600.0;
+// Time of day when the game starts, as a fraction of a day from midnight: mid-morning.
+const START_TIME_OF_DAY: f32 = 0.35;
+// Far enough from the level that the sun lights it all from the same direction.
+const SUN_DISTANCE: f32 = 1000.0;
+// Lighting through the day, by the sun's height above the horizon (the sine of its elevation). At
+// night the moon, opposite the sun, takes over the light.
+const LIGHT_BY_SUN_HEIGHT: [(f32, [f32; 3]); 4] = [
+    (-0.3, [0.15, 0.2, 0.35]),
+    (-0.05, [0.3, 0.25, 0.35]),
+    (0.1, [1.0, 0.6, 0.35]),
+    (0.4, [1.0, 0.95, 0.85]),
+];
+const SKY_ZENITH_BY_SUN_HEIGHT: [(f32, [f32; 3]); 4] = [
+    (-0.3, [0.01, 0.01, 0.04]),
+    (-0.05, [0.08, 0.08, 0.2]),
+    (0.1, [0.25, 0.35, 0.6]),
+    (0.4, [0.2, 0.45, 0.9]),
+];
+const SKY_HORIZON_BY_SUN_HEIGHT: [(f32, [f32; 3]); 4] = [
+    (-0.3, [0.03, 0.03, 0.06]),
+    (-0.05, [0.35, 0.2, 0.25]),
+    (0.1, [0.95, 0.55, 0.3]),
+    (0.4, [0.65, 0.8, 0.95]),
+];
+
+// How to draw an animated character this tick.
+pub struct CharacterPose {
+    pub instance: Instance,
+    // Name of the animation clip to play.
+    pub clip: &'static str,
+    pub time_secs: f32,
+}
 
 #[derive(Clone)]
 pub struct GameState {
@@ -30,26 +186,94 @@ pub struct GameState {
     tick: isize,
     update_instant: Instant,
     pub instanced_entities: Vec<ModelWithInstances>,
+    // Shared rather than cloned, like the script below, and only replaced by loading a level.
+    pub static_geometry: Arc<StaticGeometry>,
+    particles: ParticleSystem,
+    // Draws for gameplay, like the level script's, as opposed to the particles' own.
+    rng: Rng,
+    animations: Vec<EntityAnimation>,
+    // Shared rather than cloned: GameState is cloned every tick to hand it to the GPU thread.
+    script: Option<Arc<LevelScript>>,
+    // The level tile under the player.
+    player_tile: (i32, i32),
+    light_color: [f32; 3],
+    triggers: Triggers,
+    // Shared for the same reason as the script.
+    pack: Arc<Vec<Level>>,
+    // Index of the current level within the pack.
+    level_index: usize,
+    level: Level,
+    stats: LevelStats,
+    // Stats of each level finished so far, in order.
+    completed_levels: Vec<LevelStats>,
+    // When every box came to rest on a goal. Input is ignored from then on, until the next level.
+    level_complete_tick: Option<isize>,
+    // The game as the current level started, and the input given since. Together they make a
+    // replay of the attempt, kept if it turns out to be the level's fastest.
+    level_start: Option<Arc<GameState>>,
+    attempt: InputScript,
+    // The fastest run of each level this session, by level id.
+    best_runs: HashMap<u64, Arc<BestRun>>,
+    // Replays the current level's fastest run while it is being played again.
+    ghost: Option<Box<Ghost>>,
+    // Instances in instanced_entities[LEVEL_ENTITY] drawing the ghost's boxes, then its player.
+    ghost_instances: Vec<usize>,
+    placement: LevelPlacement,
+    // The other player, while playing co-op.
+    partner: Option<Partner>,
+    // The instance within instanced_entities[LEVEL_ENTITY] showing the other player of a race.
+    opponent_instance: Option<usize>,
+    // Indices of each door's and each box's instance within instanced_entities[LEVEL_ENTITY].
+    door_instances: Vec<usize>,
+    box_instances: Vec<usize>,
+    // Pushes made so far, most recent last.
+    undo_stack: Vec<Move>,
+    platforms: Vec<Platform>,
+    // Index of the platform the player is standing on.
+    player_platform: Option<usize>,
+    gravity_zones: Vec<GravityZone>,
+    // Bodies held together by joints.
+    contraptions: Vec<Contraption>,
+    // Falling below kill_y or into an out-of-bounds volume puts things back as they were at the
+    // checkpoint, which is the level's start until the player walks into a checkpoint volume.
+    kill_y: f32,
+    out_of_bounds: Vec<Aabb>,
+    checkpoint: Checkpoint,
+    // Whether the player has reached a checkpoint in this level. Restarting goes back to it then,
+    // rather than to the start.
+    checkpoint_reached: bool,
+    // Whether the player is resting on the surface of the gravity zone they are in.
+    player_on_zone_surface: bool,
+    movement: MovementMode,
+    // The step the player is taking in step mode, until they reach its last tile.
+    step: Option<StepTween>,
+    // The box the player holds in physics mode, while the interact key is down.
+    grab: Option<Grab>,
+    // The input of the last tick, to tell a button's press from it being held.
+    previous_input: InputState,
+    // Events raised on the current tick.
+    events: EventBus,
+    // The field of view before any kick, in degrees. Like fov_kick, this is a view setting rather
+    // than simulation state, so neither is hashed.
+    fov: f32,
+    fov_kick: bool,
+    // Whether events shake the camera. A view setting, like fov_kick.
+    camera_shake: bool,
+    // How far the mouse wheel has narrowed the field of view, in degrees. Also not hashed.
+    zoom: f32,
+    // Whether time of day moves the light and colors the sky. A view setting, like fov_kick.
+    day_night: bool,
+    // Fraction of a day since midnight. Only advances with day_night on, and not hashed since
+    // nothing in the simulation depends on it.
+    time_of_day: f32,
     // pub cube_instances: Vec<Instance>,
     //pub simple_cube_instances: Vec<Instance>,
 }
 impl GameState {
     pub fn new(aspect_ratio: f32) -> Self {
         let mut player_physics = Physics::new();
-        player_physics.collision = Collision::new(
-            [
-                Vector3::new(0.125, 0.125, 0.5),
-                Vector3::new(-0.125, 0.125, 0.5),
-                Vector3::new(0.125, -0.125, 0.5),
-                Vector3::new(-0.125, -0.125, 0.5),
-                Vector3::new(0.125, 0.125, -0.5),
-                Vector3::new(-0.125, 0.125, -0.5),
-                Vector3::new(0.125, -0.125, -0.5),
-                Vector3::new(-0.125, -0.125, -0.5),
-            ]
-            .into(),
-            [].into(),
-        );
+        let player_box = Aabb::from_center(Vector3::zero(), PLAYER_HALF_EXTENTS);
+        player_physics.collision = Collision::new(player_box.corners().into(), [].into());
         let mut instanced_entities = Vec::<ModelWithInstances>::new();
         const NUM_INSTANCES_PER_ROW: u32 = 10;
         const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(
@@ -77,92 +301,129 @@ impl GameState {
                             cgmath::Deg(45.0),
                         )
                     };
-                    Instance { position, scale: 1.0, rotation, shader: Shader::Texture }
+                    Instance {
+                        transform: Transform::new(position, rotation, 1.0),
+                        shader: Shader::Texture,
+                        tint: NO_TINT,
+                        layer: 0,
+                    }
                 })
             })
             .collect::<Vec<_>>();
         // Big floor instance.
         instances.push(Instance {
-            position: (0.0, -20.0, 0.0).into(),
-            scale: 11.0,
-            rotation: Rotor::identity(),
+            transform: Transform::new((0.0, -20.0, 0.0).into(), Rotor::identity(), 11.0),
             shader: Shader::Texture,
+            tint: NO_TINT,
+            layer: 0,
         });
         // Light instance.
         instances.push(Instance {
-            position: Vector3::new(2.0, 2.0, 2.0),
-            scale: 0.25,
-            rotation: Rotor::identity(),
+            transform: Transform::new(Vector3::new(2.0, 2.0, 2.0), Rotor::identity(), 0.25),
             shader: Shader::NonMaterial,
+            tint: NO_TINT,
+            layer: 0,
         });
         instanced_entities.push(ModelWithInstances { id: 0, instances });
-        let simple_cube_instances = vec![
+        let mut simple_cube_instances = vec![
             Instance {
-                position: (0.0, -4.5, 0.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
+                transform: Transform::new((0.0, -4.5, 0.0).into(), Rotor::identity(), 0.5),
                 shader: Shader::Pulse,
+                tint: NO_TINT,
+                layer: 0,
             },
             Instance {
-                position: (3.0, -4.5, 0.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
+                transform: Transform::new((3.0, -4.5, 0.0).into(), Rotor::identity(), 0.5),
                 shader: Shader::Ripple,
+                tint: NO_TINT,
+                layer: 0,
             },
             Instance {
-                position: (-3.0, -4.5, 0.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
+                transform: Transform::new((-3.0, -4.5, 0.0).into(), Rotor::identity(), 0.5),
                 shader: Shader::ColorTween,
+                tint: NO_TINT,
+                layer: 0,
             },
             // Interesting "bug": the spheres don't show up through this cube, because they are
             // drawn later in the scene. See
             // https://docs.godotengine.org/en/latest/tutorials/3d/3d_rendering_limitations.html#transparency-sorting.
             Instance {
-                position: (-6.0, -4.5, 0.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
+                transform: Transform::new((-6.0, -4.5, 0.0).into(), Rotor::identity(), 0.5),
                 shader: Shader::SimpleTransparency,
+                tint: NO_TINT,
+                layer: 0,
             },
             Instance {
-                position: (3.0, -4.5, 3.0).into(),
-                scale: 0.75,
-                rotation: Rotor::identity(),
+                transform: Transform::new((3.0, -4.5, 3.0).into(), Rotor::identity(), 0.75),
                 shader: Shader::Aerogel,
+                tint: NO_TINT,
+                layer: 0,
             }
         ];
+        // A lift for the platform below, low enough to jump onto.
+        simple_cube_instances.push(Instance {
+            transform: Transform::new((-9.0, -5.25, 0.0).into(), Rotor::identity(), 0.5),
+            shader: Shader::Matte,
+            tint: NO_TINT,
+            layer: 0,
+        });
         instanced_entities.push(ModelWithInstances { id: 1, instances: simple_cube_instances });
         instanced_entities.push(ModelWithInstances {
             id: 2,
             instances: vec![Instance {
-                position: (-3.0, -4.5, 3.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
+                transform: Transform::new((-3.0, -4.5, 3.0).into(), Rotor::identity(), 0.5),
                 shader: Shader::Pulse,
+                tint: NO_TINT,
+                layer: 0,
             }],
         });
         instanced_entities.push(ModelWithInstances {
             id: 3,
             instances: vec![Instance {
-                position: (-3.0, -4.5, 6.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
+                transform: Transform::new((-3.0, -4.5, 6.0).into(), Rotor::identity(), 0.5),
                 shader: Shader::ColorTween,
+                tint: NO_TINT,
+                layer: 0,
             }],
         });
         instanced_entities.push(ModelWithInstances {
             id: 4,
-            instances: vec![Instance {
-                position: (-6.0, -4.5, -3.0).into(),
-                scale: 0.5,
-                rotation: Rotor::identity(),
-                shader: Shader::SimpleTransparency,
-            }],
+            instances: vec![
+                Instance {
+                    transform: Transform::new((-6.0, -4.5, -3.0).into(), Rotor::identity(), 0.5),
+                    shader: Shader::SimpleTransparency,
+                    tint: NO_TINT,
+                    layer: 0,
+                },
+                // Marks the upside-down gravity zone below.
+                Instance {
+                    transform: Transform::new(UPSIDE_DOWN_ZONE_CENTER, Rotor::identity(), 2.0),
+                    shader: Shader::SimpleTransparency,
+                    tint: NO_TINT,
+                    layer: 0,
+                },
+            ],
+        });
+
+        let script = match LevelScript::load(LEVEL_SCRIPT) {
+            Ok(script) => Some(Arc::new(script)),
+            Err(e) => {
+                log_println!("no level script loaded from {}: {}", LEVEL_SCRIPT, e);
+                None
+            }
+        };
+
+        let pack = Level::load_pack(LEVEL_PACK).unwrap_or_else(|e| {
+            log_println!("no levels loaded from {}: {:#}", LEVEL_PACK, e);
+            Vec::new()
         });
+        // Filled in by load_level.
+        instanced_entities.push(ModelWithInstances { id: 5, instances: Vec::new() });
 
         const CAMERA_EYE_Y: f32 = 5.0;
         player_physics.position = (0.0, CAMERA_EYE_Y - CAMERA_PHYSICS_OFFSET, 10.0).into();
-        GameState {
+        let fov = 45.0;
+        let mut game_state = GameState {
             player: Player {
                 camera: Camera::new(
                     // position the camera 1 unit up and 2 units back
@@ -173,10 +434,11 @@ impl GameState {
                     // which way is "up"
                     Vector3::unit_y(),
                     aspect_ratio,
-                    45.0,
+                    fov,
                     0.1,
                     100.0,
                 ),
+                eye_follow: SpringFollow::new(CAMERA_STIFFNESS),
                 physics: player_physics,
             },
             tick: 0,
@@ -184,30 +446,1218 @@ impl GameState {
             // cube_instances: instances,
             //simple_cube_instances,
             instanced_entities,
+            static_geometry: Arc::new(StaticGeometry::default()),
+            particles: ParticleSystem::new(),
+            rng: Rng::new(0, GAMEPLAY_RNG_STREAM),
+            animations: Vec::new(),
+            script,
+            player_tile: (0, 0),
+            light_color: [1.0, 1.0, 1.0],
+            triggers: Triggers::new(),
+            pack: Arc::new(pack),
+            level_index: 0,
+            level: Level::empty(),
+            stats: LevelStats::default(),
+            completed_levels: Vec::new(),
+            level_complete_tick: None,
+            level_start: None,
+            attempt: InputScript::new(),
+            best_runs: HashMap::new(),
+            ghost: None,
+            ghost_instances: Vec::new(),
+            placement: LevelPlacement { origin: LEVEL_ORIGIN },
+            partner: None,
+            opponent_instance: None,
+            door_instances: Vec::new(),
+            box_instances: Vec::new(),
+            undo_stack: Vec::new(),
+            platforms: Vec::new(),
+            player_platform: None,
+            gravity_zones: Vec::new(),
+            contraptions: Vec::new(),
+            kill_y: DEFAULT_KILL_Y,
+            out_of_bounds: Vec::new(),
+            checkpoint: Checkpoint {
+                position: Point3::origin(),
+                boxes: Vec::new(),
+                moves: 0,
+                pushes: 0,
+            },
+            checkpoint_reached: false,
+            movement: MovementMode::default(),
+            step: None,
+            grab: None,
+            previous_input: InputState::new(),
+            player_on_zone_surface: false,
+            events: EventBus::new(),
+            fov,
+            fov_kick: false,
+            camera_shake: true,
+            zoom: 0.0,
+            day_night: false,
+            time_of_day: START_TIME_OF_DAY,
+        };
+        // Standing next to the aerogel cube changes the light, see the level script.
+        game_state.triggers.add(TriggerVolume::new(
+            1,
+            Aabb::from_center(Vector3::new(3.0, -5.0, 3.0), Vector3::new(1.5, 1.0, 1.5)),
+        ));
+        // Walking into this zone drops the player onto its ceiling.
+        game_state.gravity_zones.push(GravityZone {
+            bounds: Aabb::from_center(UPSIDE_DOWN_ZONE_CENTER, Vector3::new(2.0, 2.0, 2.0)),
+            gravity: Vector3::new(0.0, -GRAVITY, 0.0),
+        });
+        let lift = InstanceRef { entity: 1, instance: 5 };
+        let lift_start = game_state.instanced_entities[1].instances[5].transform.position;
+        game_state.platforms.push(Platform::new(
+            lift,
+            Vector3::new(0.5, 0.5, 0.5),
+            vec![
+                lift_start,
+                lift_start + Vector3::new(0.0, 0.0, 8.0),
+                lift_start + Vector3::new(0.0, 1.0, 8.0),
+            ],
+            300,
+        ));
+        game_state.add_rope_bridge();
+        // A checkpoint under the bridge.
+        game_state.add_checkpoint(Aabb::from_center(
+            BRIDGE_LEFT_POST + Vector3::new(BRIDGE_SPAN / 2.0, -2.0, 0.0),
+            Vector3::new(1.0, 1.0, 1.0),
+        ));
+        game_state.load_level(0);
+        // A patrolling platform: the color-tweening sphere eases back and forth.
+        let start = game_state.instanced_entities[3].instances[0];
+        let waypoint = |tick, offset: Vector3<f32>| Keyframe {
+            tick,
+            transform: Transform { position: start.transform.position + offset, ..start.transform },
+            easing: Easing::EaseInOut,
+        };
+        game_state.play_animation(
+            InstanceRef { entity: 3, instance: 0 },
+            AnimationClip::new(
+                vec![
+                    waypoint(0, Vector3::zero()),
+                    waypoint(150, Vector3::new(3.0, 0.0, 0.0)),
+                    waypoint(200, Vector3::new(3.0, 0.0, 0.0)),
+                    waypoint(350, Vector3::new(3.0, 0.0, 3.0)),
+                ],
+                Playback::PingPong,
+            ),
+        );
+        game_state
+    }
+    // Hangs a chain of small cubes between two posts, each hinged to the next, that sags under
+    // gravity and then falls asleep.
+    fn add_rope_bridge(&mut self) {
+        let mut bridge = Contraption::new();
+        let half_link = Vector3::new(BRIDGE_LINK_SCALE, 0.0, 0.0);
+        let axis = Vector3::unit_z();
+        let entity = &mut self.instanced_entities[1].instances;
+        for i in 0..BRIDGE_LINKS {
+            // Evenly across the span to start with. The joints pull them into shape.
+            let t = (i as f32 + 0.5) / BRIDGE_LINKS as f32;
+            let position = BRIDGE_LEFT_POST + Vector3::new(BRIDGE_SPAN * t, 0.0, 0.0);
+            entity.push(Instance {
+                transform: Transform::new(position, Rotor::identity(), BRIDGE_LINK_SCALE),
+                shader: Shader::Matte,
+                tint: NO_TINT,
+                layer: 0,
+            });
+            let mut physics = Physics::new();
+            physics.position = Point3::from_vec(position);
+            let target = InstanceRef { entity: 1, instance: entity.len() - 1 };
+            // A cube of mass 1 and side twice its scale.
+            let inertia = (2.0 * BRIDGE_LINK_SCALE).powi(2) / 6.0;
+            let index = bridge.add_body(physics, inertia, target);
+            let a = if index == 0 {
+                Anchor::World(BRIDGE_LEFT_POST)
+            } else {
+                Anchor::Body { index: index - 1, offset: half_link }
+            };
+            let kind = JointKind::Hinge { axis };
+            bridge.add_joint(Joint { a, b: index, offset_b: -half_link, kind });
+        }
+        bridge.add_joint(Joint {
+            a: Anchor::World(BRIDGE_LEFT_POST + Vector3::new(BRIDGE_SPAN, 0.0, 0.0)),
+            b: BRIDGE_LINKS - 1,
+            offset_b: half_link,
+            kind: JointKind::Hinge { axis },
+        });
+        self.contraptions.push(bridge);
+    }
+    // Steps every contraption and moves the instances drawing its bodies along.
+    fn update_contraptions(&mut self, delta_t: f32) {
+        for contraption in &mut self.contraptions {
+            contraption.reset_forces();
+            contraption.update(delta_t, PLAYER_MAX_SPEED);
+            for (target, position, rotation) in contraption.transforms() {
+                let instance =
+                    &mut self.instanced_entities[target.entity].instances[target.instance];
+                instance.transform.position = position;
+                instance.transform.rotation = rotation;
+            }
+        }
+    }
+    // Makes level `index` of the pack the current one: rebuilds its instances and plate triggers,
+    // resets the stats and undo history, and puts the player on its start tile if it has one.
+    fn load_level(&mut self, index: usize) {
+        for i in 0..self.level.plates.len() {
+            self.triggers.remove(PLATE_TRIGGER_IDS + i as u32);
+        }
+        self.animations.retain(|animation| animation.target.entity != LEVEL_ENTITY);
+        self.level = self.pack.get(index).cloned().unwrap_or_else(Level::empty);
+        self.level_index = index;
+        let (static_geometry, instances, door_instances, box_instances) =
+            Self::build_level_instances(&self.level, &self.placement);
+        self.static_geometry = Arc::new(static_geometry);
+        self.instanced_entities[LEVEL_ENTITY].instances = instances;
+        self.door_instances = door_instances;
+        self.box_instances = box_instances;
+        for (i, plate) in self.level.plates.iter().enumerate() {
+            // Slightly smaller than the tile, so a box on the next tile over doesn't press it.
+            let center = self.placement.tile_center(plate.x, plate.z);
+            self.triggers.add(TriggerVolume::new(
+                PLATE_TRIGGER_IDS + i as u32,
+                Aabb::from_center(
+                    center + Vector3::new(0.0, 0.125, 0.0),
+                    Vector3::new(0.4, 0.125, 0.4),
+                ),
+            ));
+        }
+        self.undo_stack.clear();
+        self.stats = LevelStats::default();
+        // Every attempt at a level rolls the same numbers, wherever it was started from.
+        self.rng = Rng::new(self.level.id, GAMEPLAY_RNG_STREAM);
+        self.particles.reseed(Rng::new(self.level.id, PARTICLE_RNG_STREAM));
+        self.level_complete_tick = None;
+        self.step = None;
+        self.grab = None;
+        // Replays start from a fresh game, which has no input before theirs.
+        self.previous_input = InputState::new();
+        if let Some(start) = self.level.player_start {
+            self.player.physics.velocity = Vector3::zero();
+            self.move_player_to_tile(start);
+        }
+        self.player_tile = self.tile_under_player();
+        self.save_checkpoint();
+        self.checkpoint_reached = false;
+        self.level_start = Some(Arc::new(self.snapshot()));
+        self.attempt = InputScript::new();
+        self.ghost =
+            self.best_runs.get(&self.level.id).map(|run| Box::new(Ghost::new(run.clone())));
+        self.ghost_instances.clear();
+        self.opponent_instance = None;
+        if self.ghost.is_some() {
+            let level_instances = &mut self.instanced_entities[LEVEL_ENTITY].instances;
+            for scale in vec![0.5; self.level.boxes.len()].into_iter().chain([GHOST_PLAYER_SCALE]) {
+                self.ghost_instances.push(level_instances.len());
+                level_instances.push(Instance {
+                    transform: Transform::new(Vector3::zero(), Rotor::identity(), scale),
+                    shader: Shader::Ghost,
+                    tint: GHOST_TINT,
+                    layer: 0,
+                });
+            }
+            self.update_ghost_instances();
+        }
+        if self.partner.is_some() {
+            self.place_partner();
+        }
+    }
+    // A copy of the game to replay the current attempt from, without this one's replays or the
+    // partner, whose steps aren't part of the attempt.
+    fn snapshot(&self) -> GameState {
+        let mut snapshot = self.clone();
+        snapshot.level_start = None;
+        snapshot.best_runs.clear();
+        snapshot.ghost = None;
+        snapshot.ghost_instances.clear();
+        snapshot.partner = None;
+        snapshot
+    }
+    // Removes one of the level entity's instances added after the level's own, like the
+    // partner's. Every instance the game keeps the index of that comes after it moves down one.
+    fn remove_extra_instance(&mut self, instance: usize) {
+        let target = InstanceRef { entity: LEVEL_ENTITY, instance };
+        self.animations.retain(|animation| animation.target != target);
+        self.instanced_entities[LEVEL_ENTITY].instances.remove(instance);
+        for animation in &mut self.animations {
+            if animation.target.entity == LEVEL_ENTITY && animation.target.instance > instance {
+                animation.target.instance -= 1;
+            }
+        }
+        let partner = self.partner.as_mut().map(|partner| &mut partner.instance);
+        for index in partner.into_iter().chain(self.opponent_instance.as_mut()) {
+            if *index > instance {
+                *index -= 1;
+            }
+        }
+    }
+    // Adds the partner's instance, on the free tile closest to the player's start.
+    fn place_partner(&mut self) {
+        let (x, z) = self.partner_start();
+        let level_instances = &mut self.instanced_entities[LEVEL_ENTITY].instances;
+        let instance = level_instances.len();
+        level_instances.push(Instance {
+            transform: Transform::new(
+                self.placement.tile_center(x, z) + Vector3::new(0.0, PLAYER_FEET_OFFSET, 0.0),
+                Rotor::identity(),
+                PARTNER_SCALE,
+            ),
+            shader: Shader::Matte,
+            tint: PARTNER_TINT,
+            layer: 0,
+        });
+        self.partner = Some(Partner { tile: (x, z), instance });
+    }
+    // The tile nearest the player's start, in steps, without a box or the player on it. Falls back
+    // to the start itself in a level with no room.
+    fn partner_start(&self) -> (i32, i32) {
+        let start = self.level.player_start.unwrap_or(self.player_tile);
+        let mut seen = vec![start];
+        let mut queue = VecDeque::from([start]);
+        while let Some((x, z)) = queue.pop_front() {
+            for direction in Direction::ALL {
+                let (dx, dz) = direction.offset();
+                let next = (x + dx, z + dz);
+                if seen.contains(&next) || self.level.is_blocked(next.0, next.1) {
+                    continue;
+                }
+                seen.push(next);
+                if self.level.box_at(next.0, next.1).is_some() {
+                    continue;
+                }
+                if next != self.player_tile {
+                    return next;
+                }
+                queue.push_back(next);
+            }
+        }
+        start
+    }
+    // Moves the ghost's instances to where its boxes and player are.
+    fn update_ghost_instances(&mut self) {
+        let Some(ghost) = &self.ghost else {
+            return;
+        };
+        let ghost_state = &ghost.state;
+        let positions = ghost_state
+            .box_instances
+            .iter()
+            .map(|&i| ghost_state.instanced_entities[LEVEL_ENTITY].instances[i].transform.position)
+            .chain([ghost_state.player.physics.position.to_vec()]);
+        for (&instance, position) in self.ghost_instances.iter().zip(positions) {
+            self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position = position;
+        }
+    }
+    // Highlights the boxes sitting on goals.
+    fn update_box_tints(&mut self) {
+        for (&instance, &(x, z)) in self.box_instances.iter().zip(&self.level.boxes) {
+            self.instanced_entities[LEVEL_ENTITY].instances[instance].tint =
+                if self.level.tile(x, z) == Tile::Goal { BOX_ON_GOAL_TINT } else { NO_TINT };
         }
     }
+    // Finishes the level once every box has come to rest on a goal, then moves on to the next
+    // level in the pack after a short pause. Finishing the last level leaves the game on the
+    // completion screen.
+    fn update_level_progress(&mut self) {
+        let last_level = self.level_index + 1 >= self.pack.len();
+        let Some(complete_tick) = self.level_complete_tick else {
+            let settled = (0..self.level.boxes.len()).all(|i| !self.box_animating(i));
+            if !settled || !self.level.is_solved() {
+                self.stats.ticks += 1;
+                return;
+            }
+            self.level_complete_tick = Some(self.tick);
+            self.completed_levels.push(self.stats);
+            let stats = self.stats;
+            self.events.publish(GameEvent::LevelCompleted { level: self.level.id, stats });
+            let fastest =
+                self.best_runs.get(&self.level.id).is_none_or(|run| stats.ticks < run.ticks);
+            if let (true, Some(start)) = (fastest, self.level_start.clone()) {
+                let inputs = self.attempt.clone();
+                let run = BestRun { start, inputs, ticks: stats.ticks };
+                self.best_runs.insert(self.level.id, Arc::new(run));
+            }
+            log_println!(
+                "level {} complete: {} moves, {} pushes, {:.2}s",
+                self.level_index + 1,
+                stats.moves,
+                stats.pushes,
+                stats.ticks as f32 * tick_rate::tick_secs()
+            );
+            for &(x, z) in &self.level.boxes {
+                let position = self.placement.tile_center(x, z) + Vector3::new(0.0, 1.0, 0.0);
+                self.particles.emit(ParticleEffect::GoalSparkle, position);
+            }
+            if last_level {
+                // The app shows the totals on its pack complete page.
+                self.light_color = PACK_COMPLETE_LIGHT_COLOR;
+                let total: LevelStats = self.completed_levels.iter().copied().sum();
+                log_println!(
+                    "pack complete: {} levels, {} moves, {} pushes, {:.2}s",
+                    self.completed_levels.len(),
+                    total.moves,
+                    total.pushes,
+                    total.ticks as f32 * tick_rate::tick_secs()
+                );
+            }
+            return;
+        };
+        if !last_level
+            && self.tick - complete_tick >= tick_rate::ticks(LEVEL_COMPLETE_SECS) as isize
+        {
+            self.load_level(self.level_index + 1);
+        }
+    }
+    // The level's walls and floor, which never move, then instances for its goals, ice,
+    // teleporters, conveyors, plates, doors and boxes, in that order. Walls, floor and boxes are
+    // drawn by Shader::Tiles from their layer of the tile texture array. Goals keep Shader::Goal,
+    // which glows and draws the colorblind stripes. Also returns the indices of the door and box
+    // instances.
+    fn build_level_instances(
+        level: &Level,
+        placement: &LevelPlacement,
+    ) -> (StaticGeometry, Vec<Instance>, Vec<usize>, Vec<usize>) {
+        // Unit cubes, either standing on the floor or sunk into it so only a thin slab shows.
+        let standing = |(x, z), shader| Instance {
+            transform: Transform::new(
+                placement.tile_center(x, z) + Vector3::new(0.0, 0.5, 0.0),
+                Rotor::identity(),
+                0.5,
+            ),
+            shader,
+            tint: NO_TINT,
+            layer: 0,
+        };
+        let sunk = |(x, z), shader| Instance {
+            transform: Transform::new(
+                placement.tile_center(x, z) - Vector3::new(0.0, 0.45, 0.0),
+                Rotor::identity(),
+                0.5,
+            ),
+            shader,
+            tint: NO_TINT,
+            layer: 0,
+        };
+        let tile = |tile, layer: TileLayer, place: &dyn Fn((i32, i32), Shader) -> Instance| {
+            Instance { layer: layer as u32, ..place(tile, Shader::Tiles) }
+        };
+        let fixed_tiles = StaticGeometry {
+            instances: level
+                .tiles_of(Tile::Wall)
+                .map(|wall| tile(wall, TileLayer::Wall, &standing))
+                .chain(
+                    level.tiles_of(Tile::Floor).map(|floor| tile(floor, TileLayer::Floor, &sunk)),
+                )
+                .collect(),
+        };
+        let mut instances = Vec::new();
+        instances.extend(
+            level
+                .tiles_of(Tile::Goal)
+                .map(|tile| Instance { tint: GOAL_TINT, ..sunk(tile, Shader::Goal) }),
+        );
+        instances
+            .extend(level.tiles_of(Tile::Ice).map(|tile| sunk(tile, Shader::SimpleTransparency)));
+        for id in 0..10 {
+            instances.extend(
+                level.tiles_of(Tile::Teleporter(id)).map(|tile| sunk(tile, Shader::Portal)),
+            );
+        }
+        // The belt pattern scrolls towards +x, so turn each conveyor to face its direction.
+        for (direction, degrees) in [
+            (Direction::East, 0.0),
+            (Direction::North, 90.0),
+            (Direction::West, 180.0),
+            (Direction::South, 270.0),
+        ] {
+            let rotation = Rotor::from_axis_angle(Vector3::unit_y(), cgmath::Deg(degrees));
+            instances.extend(level.tiles_of(Tile::Conveyor(direction)).map(|tile| {
+                let instance = sunk(tile, Shader::Conveyor);
+                Instance { transform: Transform { rotation, ..instance.transform }, ..instance }
+            }));
+        }
+        instances.extend(level.plates.iter().map(|plate| sunk((plate.x, plate.z), Shader::Ripple)));
+        let door_instances = (instances.len()..instances.len() + level.doors.len()).collect();
+        instances
+            .extend(level.doors.iter().map(|door| standing((door.x, door.z), Shader::ColorTween)));
+        let box_instances = (instances.len()..instances.len() + level.boxes.len()).collect();
+        instances
+            .extend(level.boxes.iter().map(|&box_tile| tile(box_tile, TileLayer::Box, &standing)));
+        (fixed_tiles, instances, door_instances, box_instances)
+    }
+    /// Starts `clip` on `target` from the current tick, replacing any animation already playing
+    /// on it.
+    pub fn play_animation(&mut self, target: InstanceRef, clip: AnimationClip) {
+        self.animations.retain(|animation| animation.target != target);
+        self.animations.push(EntityAnimation { target, clip, start_tick: self.tick });
+    }
     pub fn change_camera_aspect(&mut self, aspect_ratio: f32) {
         self.player.camera.set_aspect(aspect_ratio);
     }
+    /// `fov` is the vertical field of view in degrees, clamped to `camera::FOVY_RANGE`.
+    pub fn change_camera_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(FOVY_RANGE.0, FOVY_RANGE.1);
+        self.player.camera.set_fovy(self.fov);
+    }
+    /// How quickly the eye catches up after the player is knocked or snapped into place. Higher is
+    /// stiffer; see `SpringFollow`.
+    pub fn set_camera_stiffness(&mut self, stiffness: f32) {
+        self.player.eye_follow.stiffness = stiffness;
+    }
+    /// Whether hard landings, doors and the like shake the camera.
+    pub fn set_camera_shake(&mut self, enabled: bool) {
+        self.camera_shake = enabled;
+    }
+    /// Whether the field of view widens as the player speeds up.
+    pub fn set_fov_kick(&mut self, enabled: bool) {
+        self.fov_kick = enabled;
+    }
+    /// Whether time passes, moving the light and coloring the sky.
+    pub fn set_day_night(&mut self, enabled: bool) {
+        self.day_night = enabled;
+    }
     pub fn get_camera(&self) -> Camera {
         self.player.camera
     }
-    pub fn update(&mut self, input: &InputState, step_time: Instant) {
-        self.tick += 1;
-        self.update_instant = step_time;
-        self.player.physics.accel = (0.0, GRAVITY, 0.0).into();
+    /// Turns the camera to look along `direction`, which must be normalized.
+    pub fn set_camera_direction(&mut self, direction: Vector3<f32>) {
+        self.player.camera.direction = direction;
+    }
+    pub fn get_player_position(&self) -> Point3<f32> {
+        self.player.physics.position
+    }
+    pub fn get_tick(&self) -> isize {
+        self.tick
+    }
+    pub fn get_particles(&self) -> &ParticleSystem {
+        &self.particles
+    }
+    pub fn get_light_color(&self) -> [f32; 3] {
+        self.light_color
+    }
+    // The light, tinted by the level's light color, and the sky. Without the day/night cycle, that
+    // is the fixed light and no sky.
+    pub fn get_environment(&self) -> Environment {
+        if !self.day_night {
+            return Environment {
+                light_position: LIGHT_POSITION,
+                light_color: self.light_color,
+                sky: None,
+            };
+        }
+        // The sun rises at a quarter of the way through the day and sets at three quarters.
+        let angle = (self.time_of_day - 0.25) * std::f32::consts::TAU;
+        let sun = Vector3::new(angle.cos(), angle.sin(), 0.3).normalize();
+        let light = if sun.y >= 0.0 { sun } else { -sun };
+        let tint = sample_by_sun_height(&LIGHT_BY_SUN_HEIGHT, sun.y);
+        Environment {
+            light_position: (self.placement.origin + SUN_DISTANCE * light).into(),
+            light_color: [0, 1, 2].map(|i| self.light_color[i] * tint[i]),
+            sky: Some(SkyColors {
+                zenith: sample_by_sun_height(&SKY_ZENITH_BY_SUN_HEIGHT, sun.y),
+                horizon: sample_by_sun_height(&SKY_HORIZON_BY_SUN_HEIGHT, sun.y),
+            }),
+        }
+    }
+    pub fn get_events(&self) -> &EventBus {
+        &self.events
+    }
+    pub fn get_level_index(&self) -> usize {
+        self.level_index
+    }
+    pub fn get_level_count(&self) -> usize {
+        self.pack.len()
+    }
+    pub fn get_level(&self) -> &Level {
+        &self.level
+    }
+    pub fn get_level_placement(&self) -> LevelPlacement {
+        self.placement
+    }
+    /// The fastest finished attempt at the current level, if there is one.
+    pub fn get_best_run(&self) -> Option<Arc<BestRun>> {
+        self.best_runs.get(&self.level.id).cloned()
+    }
+    /// Makes `run` the one the level with id `level` shows as its ghost, from when it next starts.
+    pub fn set_best_run(&mut self, level: u64, run: Arc<BestRun>) {
+        self.best_runs.insert(level, run);
+    }
+    /// Stats of each level of the pack finished so far, in order.
+    pub fn get_completed_levels(&self) -> &[LevelStats] {
+        &self.completed_levels
+    }
+    pub fn get_player_tile(&self) -> (i32, i32) {
+        self.tile_under_player()
+    }
+    /// Switches to `pack` and starts its level `index`, as picked from the level select screen.
+    pub fn play_pack(&mut self, pack: Vec<Level>, index: usize) {
+        self.pack = Arc::new(pack);
+        self.completed_levels.clear();
+        self.light_color = [1.0, 1.0, 1.0];
+        self.load_level(index);
+    }
+    /// The grid direction closest to where the camera looks.
+    pub fn get_facing(&self) -> Direction {
+        nearest_direction(self.player.camera.direction)
+    }
+    pub fn get_movement_mode(&self) -> MovementMode {
+        self.movement
+    }
+    /// Switches how the input moves the player. Going into step mode stands the player on the
+    /// middle of their tile. The current attempt can't be replayed across the switch, so it starts
+    /// over from here.
+    pub fn set_movement_mode(&mut self, mode: MovementMode) {
+        if mode == self.movement {
+            return;
+        }
+        self.movement = mode;
+        self.step = None;
+        self.grab = None;
+        self.player.physics.velocity = Vector3::zero();
+        self.player.physics.ground_velocity = Vector3::zero();
+        if mode == MovementMode::Step {
+            self.player_platform = None;
+            self.player_on_zone_surface = false;
+            self.player.physics.position.y = -5.0;
+            self.player.camera.eye.y = -5.0 + CAMERA_PHYSICS_OFFSET;
+            self.move_player_to_tile(self.tile_under_player());
+        }
+        self.level_start = Some(Arc::new(self.snapshot()));
+        self.attempt = InputScript::new();
+    }
+    /// Puts a second player in the level for co-op, on the free tile closest to the player's
+    /// start. They stay, through level changes, until removed.
+    pub fn add_partner(&mut self) {
+        if self.partner.is_none() {
+            self.place_partner();
+        }
+    }
+    /// Takes the partner out of the level, once the other player has left.
+    pub fn remove_partner(&mut self) {
+        if let Some(partner) = self.partner.take() {
+            self.remove_extra_instance(partner.instance);
+        }
+    }
+    /// Shows the other player of a race as a ghost at `position`, or stops showing them.
+    pub fn show_opponent(&mut self, position: Option<Vector3<f32>>) {
+        match (position, self.opponent_instance) {
+            (Some(position), Some(instance)) => {
+                self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position =
+                    position;
+            }
+            (Some(position), None) => {
+                let level_instances = &mut self.instanced_entities[LEVEL_ENTITY].instances;
+                self.opponent_instance = Some(level_instances.len());
+                level_instances.push(Instance {
+                    transform: Transform::new(position, Rotor::identity(), GHOST_PLAYER_SCALE),
+                    shader: Shader::Ghost,
+                    tint: OPPONENT_TINT,
+                    layer: 0,
+                });
+            }
+            (None, Some(instance)) => {
+                self.opponent_instance = None;
+                self.remove_extra_instance(instance);
+            }
+            (None, None) => {}
+        }
+    }
+    /// Steps the partner one tile in `direction`, pushing any box in the way, as the player
+    /// would. The player blocks them like a wall, and they can't move once the level is complete.
+    /// Their moves and pushes count towards the level's stats.
+    pub fn step_partner(&mut self, direction: Direction) {
+        let Some(partner) = &self.partner else {
+            return;
+        };
+        let (from, instance) = (partner.tile, partner.instance);
+        if self.level_complete_tick.is_some() {
+            return;
+        }
+        let Some(resolved) = self.level.resolve_move(from, direction) else {
+            return;
+        };
+        let player_tile = self.player_tile;
+        let box_hits_player =
+            resolved.pushed_box.as_ref().is_some_and(|(_, path)| path.contains(&player_tile));
+        if resolved.player_path.contains(&player_tile) || box_hits_player {
+            return;
+        }
+        self.level.apply(&resolved);
+        self.stats.moves += 1;
+        if let Some((index, path)) = &resolved.pushed_box {
+            self.stats.pushes += 1;
+            self.box_moved(*index, path, tick_rate::ticks(BOX_SECS_PER_TILE));
+            // Undoing the player's earlier pushes could now move boxes through the partner's.
+            self.undo_stack.clear();
+        }
+        self.animate_instance(
+            instance,
+            &resolved.player_path,
+            tick_rate::ticks(PARTNER_SECS_PER_TILE),
+        );
+        if let Some(partner) = &mut self.partner {
+            partner.tile = *resolved.player_path.last().unwrap();
+        }
+    }
+    /// What the host of a co-op game sends the joining player. `pack` is the current pack's file
+    /// name.
+    pub fn shared_state(&self, pack: &str) -> SharedState {
+        SharedState {
+            pack: pack.to_string(),
+            level_index: self.level_index as u32,
+            level_id: self.level.id,
+            boxes: self.level.boxes.clone(),
+            doors_open: self.level.doors.iter().map(|door| door.open).collect(),
+            host_position: self.player.physics.position.into(),
+            partner_tile: self.partner.as_ref().map_or(self.player_tile, |partner| partner.tile),
+        }
+    }
+    /// Shows the state the host sent, on the joining side of a co-op game, where the level must
+    /// already be the host's. The player here stands on the host's partner tile, and the partner
+    /// stands in for the host. Boxes and doors that changed animate to their new places.
+    pub fn apply_shared_state(&mut self, state: &SharedState) {
+        self.add_partner();
+        for (i, &to) in state.boxes.iter().enumerate().take(self.level.boxes.len()) {
+            let from = self.level.boxes[i];
+            if from != to {
+                self.level.boxes[i] = to;
+                self.animate_box(i, &straight_path(from, to), tick_rate::ticks(BOX_SECS_PER_TILE));
+            }
+        }
+        for (i, &open) in state.doors_open.iter().enumerate().take(self.level.doors.len()) {
+            if self.level.doors[i].open != open {
+                self.level.doors[i].open = open;
+                self.animate_door(i);
+            }
+        }
+        self.update_box_tints();
+        if let Some(partner) = &mut self.partner {
+            let host_position = Vector3::from(state.host_position);
+            partner.tile = self.placement.tile_at(host_position);
+            self.instanced_entities[LEVEL_ENTITY].instances[partner.instance].transform.position =
+                host_position;
+        }
+        let before = self.player.physics.position;
+        self.move_player_to_tile(state.partner_tile);
+        // The eye eases over in update_replica rather than jumping along.
+        self.player.eye_follow.update(self.player.physics.position - before, 0.0);
+    }
+    // The player character stands on the physics body's feet, facing where the camera looks.
+    pub fn get_player_pose(&self) -> CharacterPose {
+        let physics = &self.player.physics;
+        let direction = self.player.camera.direction;
+        let yaw = cgmath::Rad(direction.x.atan2(direction.z));
+        let horizontal_speed =
+            Vector3::new(physics.velocity.x, 0.0, physics.velocity.z).magnitude();
+        let clip = if !self.player_grounded() {
+            "Jump"
+        } else if horizontal_speed > 0.5 {
+            "Walk"
+        } else {
+            "Idle"
+        };
+        CharacterPose {
+            instance: Instance {
+                transform: Transform::new(
+                    self.player_feet(),
+                    Rotor::from_axis_angle(Vector3::unit_y(), yaw),
+                    1.0,
+                ),
+                shader: Shader::Matte,
+                tint: NO_TINT,
+                layer: 0,
+            },
+            clip,
+            time_secs: self.tick as f32 * tick_rate::tick_secs(),
+        }
+    }
+    // The box the player holds, or else the box next to the player that walking the way the
+    // camera looks would push, if it would move. Boxes still sliding into place don't count.
+    pub fn get_targeted_box(&self) -> Option<Instance> {
+        if let Some(grab) = self.grab {
+            let instance = self.box_instances[grab.index];
+            return Some(self.instanced_entities[LEVEL_ENTITY].instances[instance]);
+        }
+        let facing = self.get_facing();
+        let (dx, dz) = facing.offset();
+        let (x, z) = self.player_tile;
+        let index = self.level.box_at(x + dx, z + dz)?;
+        if self.box_animating(index) {
+            return None;
+        }
+        self.level.resolve_move(self.player_tile, facing)?;
+        Some(self.instanced_entities[LEVEL_ENTITY].instances[self.box_instances[index]])
+    }
+    // Feeds everything that the simulation evolves into `hasher`. `update_instant` is skipped on
+    // purpose: it is wall-clock time, not simulation state.
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.tick as u64);
+        let camera = &self.player.camera;
+        hasher.write_f32s(&Into::<[f32; 3]>::into(camera.eye));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(camera.direction));
+        let physics = &self.player.physics;
+        hasher.write_f32s(&Into::<[f32; 3]>::into(physics.position));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(physics.velocity));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(physics.accel));
+        hasher.write_u64(physics.still_ticks as u64);
+        for entity in &self.instanced_entities {
+            hasher.write_u64(entity.id as u64);
+            for instance in &entity.instances {
+                hasher.write_f32s(&Into::<[f32; 3]>::into(instance.transform.position));
+                hasher.write_f32(instance.transform.scale);
+                hasher.write_f32s(&Into::<[f32; 4]>::into(instance.transform.rotation));
+                hasher.write_u64(instance.shader as u64);
+            }
+        }
+        self.particles.hash_into(hasher);
+        self.rng.hash_into(hasher);
+        for platform in &self.platforms {
+            platform.hash_into(hasher);
+        }
+        for contraption in &self.contraptions {
+            contraption.hash_into(hasher);
+        }
+        hasher.write_f32(self.kill_y);
+        for bounds in &self.out_of_bounds {
+            hasher.write_f32s(&Into::<[f32; 3]>::into(bounds.min));
+            hasher.write_f32s(&Into::<[f32; 3]>::into(bounds.max));
+        }
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.checkpoint.position));
+        for &(x, z) in &self.checkpoint.boxes {
+            hasher.write_u64(x as u64);
+            hasher.write_u64(z as u64);
+        }
+        hasher.write_u64(self.checkpoint.moves as u64);
+        hasher.write_u64(self.checkpoint.pushes as u64);
+        hasher.write_u64(self.checkpoint_reached as u64);
+        hasher.write_u64(self.player_platform.map_or(u64::MAX, |i| i as u64));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.player.physics.ground_velocity));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.player.physics.gravity));
+        hasher.write_u64(self.player_on_zone_surface as u64);
+        hasher.write_u64(self.movement as u64);
+        hasher.write_u64(self.previous_input.interact as u64);
+        if let Some(grab) = self.grab {
+            hasher.write_u64(grab.index as u64);
+            hasher.write_u64(grab.direction as u64);
+        }
+        if let Some(step) = &self.step {
+            hasher.write_u64(step.start_tick as u64);
+            for &(x, z) in &step.path {
+                hasher.write_u64(x as u64);
+                hasher.write_u64(z as u64);
+            }
+        }
+        hasher.write_u64(self.player_tile.0 as u64);
+        hasher.write_u64(self.player_tile.1 as u64);
+        if let Some(partner) = &self.partner {
+            hasher.write_u64(partner.tile.0 as u64);
+            hasher.write_u64(partner.tile.1 as u64);
+        }
+        hasher.write_f32s(&self.light_color);
+        self.triggers.hash_into(hasher);
+        for &(x, z) in &self.level.boxes {
+            hasher.write_u64(x as u64);
+            hasher.write_u64(z as u64);
+        }
+        hasher.write_u64(self.undo_stack.len() as u64);
+        hasher.write_u64(self.level_index as u64);
+        hasher.write_u64(self.stats.moves as u64);
+        hasher.write_u64(self.stats.pushes as u64);
+        hasher.write_u64(self.stats.ticks as u64);
+        hasher.write_u64(self.level_complete_tick.map_or(u64::MAX, |tick| tick as u64));
+        if let Some(ghost) = &self.ghost {
+            ghost.state.hash_into(hasher);
+        }
+        for door in &self.level.doors {
+            hasher.write_u64(door.open as u64);
+            hasher.write_u64(door.latched as u64);
+        }
+        self.events.hash_into(hasher);
+    }
+    /// A checksum of what the input decides: the player's movement and where they look, the
+    /// level's boxes and doors, and the stats. Unlike `hash_into`, it leaves out what settings
+    /// change, like how the eye follows the player, so games played with different settings agree
+    /// on it given the same input.
+    pub fn simulation_checksum(&self) -> u64 {
+        let mut hasher = StateHasher::new();
+        hasher.write_u64(self.tick as u64);
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.player.camera.direction));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.player.physics.position));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.player.physics.velocity));
+        hasher.write_u64(self.player_tile.0 as u64);
+        hasher.write_u64(self.player_tile.1 as u64);
+        hasher.write_u64(self.level_index as u64);
+        for &(x, z) in &self.level.boxes {
+            hasher.write_u64(x as u64);
+            hasher.write_u64(z as u64);
+        }
+        for door in &self.level.doors {
+            hasher.write_u64(door.open as u64);
+        }
+        hasher.write_u64(self.stats.moves as u64);
+        hasher.write_u64(self.stats.pushes as u64);
+        hasher.write_u64(self.stats.ticks as u64);
+        self.rng.hash_into(&mut hasher);
+        hasher.finish()
+    }
+    fn player_feet(&self) -> Vector3<f32> {
+        self.player.physics.position.to_vec() - Vector3::new(0.0, PLAYER_FEET_OFFSET, 0.0)
+    }
+    // Opens doors whose plates are pressed (or that the script latched open) and closes the rest,
+    // animating the ones that changed.
+    fn update_doors(&mut self) {
+        for i in 0..self.level.doors.len() {
+            let door = self.level.doors[i];
+            let pressed = self.level.plates.iter().enumerate().any(|(j, plate)| {
+                plate.id == door.id
+                    && self
+                        .triggers
+                        .get(PLATE_TRIGGER_IDS + j as u32)
+                        .is_some_and(|trigger| !trigger.occupants().is_empty())
+            });
+            let open = door.latched || pressed;
+            if open == door.open {
+                continue;
+            }
+            self.level.doors[i].open = open;
+            self.events.publish(if open {
+                GameEvent::DoorOpened { door: door.id }
+            } else {
+                GameEvent::DoorClosed { door: door.id }
+            });
+            self.animate_door(i);
+        }
+    }
+    // Sinks door `i` into the floor if it is open, or raises it if not.
+    fn animate_door(&mut self, i: usize) {
+        let door = self.level.doors[i];
+        let target = InstanceRef { entity: LEVEL_ENTITY, instance: self.door_instances[i] };
+        let from = self.instanced_entities[LEVEL_ENTITY].instances[target.instance];
+        let mut to_position = self.placement.tile_center(door.x, door.z);
+        to_position.y += if door.open { 0.5 - DOOR_SINK_DEPTH } else { 0.5 };
+        let keyframe = |tick, position| Keyframe {
+            tick,
+            transform: Transform { position, ..from.transform },
+            easing: Easing::EaseInOut,
+        };
+        self.play_animation(
+            target,
+            AnimationClip::new(
+                vec![
+                    keyframe(0, from.transform.position),
+                    keyframe(tick_rate::ticks(DOOR_ANIMATION_SECS), to_position),
+                ],
+                Playback::Once,
+            ),
+        );
+    }
+    fn player_bounds(&self) -> Aabb {
+        self.player.physics.collision.bounds_at(self.player.physics.position)
+    }
+    // Whether the player stands on the floor, rather than having walked off its edge.
+    fn player_on_floor(&self) -> bool {
+        (-5.001..=-4.999).contains(&self.player.physics.position.y)
+            && FLOOR.overlaps_xz(&self.player_bounds())
+    }
+    fn player_grounded(&self) -> bool {
+        self.player_on_floor() || self.player_platform.is_some() || self.player_on_zone_surface
+    }
+    /// Sets the height below which the player has fallen out of the world.
+    pub fn set_kill_y(&mut self, kill_y: f32) {
+        self.kill_y = kill_y;
+    }
+    /// Adds a volume that counts as out of the world, like the kill plane.
+    pub fn add_out_of_bounds(&mut self, bounds: Aabb) {
+        self.out_of_bounds.push(bounds);
+    }
+    /// Adds a volume that makes the player's position, and the boxes', the checkpoint when the
+    /// player walks into it.
+    pub fn add_checkpoint(&mut self, bounds: Aabb) {
+        let count = self.triggers.volumes.iter().filter(|v| v.id >= CHECKPOINT_TRIGGER_IDS).count();
+        self.triggers.add(TriggerVolume::new(CHECKPOINT_TRIGGER_IDS + count as u32, bounds));
+    }
+    /// The checkpoint the player would go back to, for the save game to keep.
+    pub fn get_checkpoint(&self) -> &Checkpoint {
+        &self.checkpoint
+    }
+    /// Puts the player, the boxes and the move counts back as `checkpoint` has them, and makes it
+    /// the checkpoint. Replays and ghosts then start from there. A checkpoint with a different
+    /// number of boxes than the level is from some other level, and is ignored.
+    pub fn resume_from_checkpoint(&mut self, checkpoint: Checkpoint) {
+        if checkpoint.boxes.len() != self.level.boxes.len() {
+            log_println!("Ignoring a checkpoint from another level");
+            return;
+        }
+        self.checkpoint = checkpoint;
+        self.checkpoint_reached = true;
+        self.restore_checkpoint();
+        self.level_start = Some(Arc::new(self.snapshot()));
+        self.attempt = InputScript::new();
+    }
+    fn save_checkpoint(&mut self) {
+        self.checkpoint = Checkpoint {
+            position: self.player.physics.position,
+            boxes: self.level.boxes.clone(),
+            moves: self.stats.moves,
+            pushes: self.stats.pushes,
+        };
+    }
+    // Puts everything back as it was at the checkpoint. The pushes since then can't be undone.
+    fn restore_checkpoint(&mut self) {
+        let physics = &mut self.player.physics;
+        physics.position = self.checkpoint.position;
+        physics.velocity = Vector3::zero();
+        physics.ground_velocity = Vector3::zero();
+        self.player_platform = None;
+        self.player_on_zone_surface = false;
+        self.player_tile = self.tile_under_player();
+        self.step = None;
+        self.grab = None;
+        // Cut to the checkpoint, rather than have the eye ease all the way over.
+        self.player.eye_follow = SpringFollow::new(self.player.eye_follow.stiffness);
+        self.player.camera.eye =
+            self.player.physics.position + Vector3::new(0.0, CAMERA_PHYSICS_OFFSET, 0.0);
+        for i in 0..self.box_instances.len() {
+            self.reset_box(i);
+        }
+        self.stats.moves = self.checkpoint.moves;
+        self.stats.pushes = self.checkpoint.pushes;
+        self.undo_stack.clear();
+    }
+    // Puts box `index` back on its checkpoint tile.
+    fn reset_box(&mut self, index: usize) {
+        let instance = self.box_instances[index];
+        let Some(&(x, z)) = self.checkpoint.boxes.get(index) else {
+            return;
+        };
+        self.animations
+            .retain(|animation| animation.target != InstanceRef { entity: LEVEL_ENTITY, instance });
+        self.level.boxes[index] = (x, z);
+        self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position =
+            self.placement.tile_center(x, z) + Vector3::new(0.0, 0.5, 0.0);
+    }
+    fn is_out_of_bounds(&self, bounds: &Aabb) -> bool {
+        bounds.max.y < self.kill_y || self.out_of_bounds.iter().any(|v| v.intersects(bounds))
+    }
+    // Sends the player, and any box, that has left the world back to the checkpoint.
+    fn update_out_of_bounds(&mut self) {
+        if self.is_out_of_bounds(&self.player_bounds()) {
+            self.restore_checkpoint();
+            self.events.publish(GameEvent::PlayerRespawned);
+            return;
+        }
+        for i in 0..self.box_instances.len() {
+            let instance = self.box_instances[i];
+            let position =
+                self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position;
+            if self.is_out_of_bounds(&Aabb::from_center(position, BOX_HALF_EXTENTS)) {
+                self.reset_box(i);
+            }
+        }
+    }
+    // Moves every platform one tick along its path, taking along any box resting on it. The
+    // player is carried by the physics step instead, through their ground velocity.
+    fn update_platforms(&mut self, delta_t: f32) {
+        let tick = self.tick as u32;
+        for platform in &mut self.platforms {
+            let carried = self
+                .box_instances
+                .iter()
+                .enumerate()
+                .filter(|&(_, &instance)| {
+                    let target = InstanceRef { entity: LEVEL_ENTITY, instance };
+                    let position = self.instanced_entities[LEVEL_ENTITY].instances[instance]
+                        .transform
+                        .position;
+                    // Boxes that are being pushed follow their own animation.
+                    !self.animations.iter().any(|animation| animation.target == target)
+                        && platform.supports(&Aabb::from_center(position, BOX_HALF_EXTENTS))
+                })
+                .map(|(i, &instance)| (i, instance))
+                .collect::<Vec<_>>();
+            let delta = platform.update(tick, delta_t);
+            let target = platform.target;
+            self.instanced_entities[target.entity].instances[target.instance].transform.position =
+                platform.position;
+            for (i, instance) in carried {
+                let box_instance = &mut self.instanced_entities[LEVEL_ENTITY].instances[instance];
+                box_instance.transform.position += delta;
+                self.level.boxes[i] = self.placement.tile_at(box_instance.transform.position);
+            }
+        }
+    }
+    fn tile_under_player(&self) -> (i32, i32) {
+        self.placement.tile_at(self.player.physics.position.to_vec())
+    }
+    // Walking from `from` into a box pushes it, if the level lets it move.
+    fn try_push(&mut self, from: (i32, i32), to: (i32, i32)) {
+        let Some(direction) = Direction::from_step(from, to) else {
+            return;
+        };
+        if self.level.box_at(to.0, to.1).is_some() {
+            self.push_box(from, direction);
+        }
+    }
+    // Pushes the box next to `from` in `direction` on from there, if the level lets it move.
+    // Returns whether it moved.
+    fn push_box(&mut self, from: (i32, i32), direction: Direction) -> bool {
+        let Some(resolved) = self.level.resolve_move(from, direction) else {
+            return false;
+        };
+        // The partner stops boxes like a wall would.
+        if let (Some(partner), Some((_, path))) = (&self.partner, &resolved.pushed_box) {
+            if path.contains(&partner.tile) {
+                return false;
+            }
+        }
+        self.level.apply(&resolved);
+        self.stats.pushes += 1;
+        if let Some((index, path)) = &resolved.pushed_box {
+            self.events.publish(GameEvent::BoxPushed { index: *index });
+            self.box_moved(*index, path, tick_rate::ticks(BOX_SECS_PER_TILE));
+        }
+        self.undo_stack.push(resolved);
+        true
+    }
+    // Animates a box that the level has already moved along `path`.
+    fn box_moved(&mut self, index: usize, path: &[(i32, i32)], ticks_per_tile: u32) {
+        self.animate_box(index, path, ticks_per_tile);
+        let (x, z) = *path.last().unwrap();
+        if self.level.tile(x, z) == Tile::Goal {
+            self.fire_script_event(ScriptEvent::BoxOnGoal { x, z });
+        }
+    }
+    fn box_animating(&self, index: usize) -> bool {
+        let target = InstanceRef { entity: LEVEL_ENTITY, instance: self.box_instances[index] };
+        self.animations.iter().any(|animation| animation.target == target)
+    }
+    // Starts each box that has come to rest on a conveyor moving to the next tile.
+    fn update_conveyors(&mut self) {
+        for index in 0..self.level.boxes.len() {
+            let (x, z) = self.level.boxes[index];
+            let Tile::Conveyor(direction) = self.level.tile(x, z) else {
+                continue;
+            };
+            if self.box_animating(index) {
+                continue;
+            }
+            if let Some(path) = self.level.resolve_box_move(index, direction) {
+                self.level.boxes[index] = *path.last().unwrap();
+                self.box_moved(index, &path, tick_rate::ticks(CONVEYOR_SECS_PER_TILE));
+            }
+        }
+    }
+    // Takes back the last push, returning the box and the player to where they started.
+    fn undo(&mut self) {
+        let Some(resolved) = self.undo_stack.pop() else {
+            return;
+        };
+        self.level.undo(&resolved);
+        self.events.publish(GameEvent::MoveUndone);
+        self.step = None;
+        if let Some((index, path)) = &resolved.pushed_box {
+            let back = path.iter().rev().copied().collect::<Vec<_>>();
+            self.animate_box(*index, &back, tick_rate::ticks(BOX_SECS_PER_TILE));
+        }
+        self.player.physics.velocity = Vector3::zero();
+        self.move_player_to_tile(resolved.player_path[0]);
+    }
+    // Centers the player on `tile` without changing their height.
+    fn move_player_to_tile(&mut self, (x, z): (i32, i32)) {
+        let center = self.placement.tile_center(x, z);
+        let position = &mut self.player.physics.position;
+        let delta = Vector3::new(center.x - position.x, 0.0, center.z - position.z);
+        *position += delta;
+        self.player.camera.eye += delta;
+        self.player_tile = (x, z);
+    }
+    // Moves box `index`'s instance through the tiles of `path`, at a constant speed. Teleports
+    // hold the box on the entrance for a tile's worth of time, then pop it out at the exit.
+    fn animate_box(&mut self, index: usize, path: &[(i32, i32)], ticks_per_tile: u32) {
+        self.animate_instance(self.box_instances[index], path, ticks_per_tile);
+    }
+    // Moves `instance` of the level's entity through the tiles of `path` like animate_box does.
+    // Boxes and the partner both sit half a tile above the floor.
+    fn animate_instance(&mut self, instance: usize, path: &[(i32, i32)], ticks_per_tile: u32) {
+        let target = InstanceRef { entity: LEVEL_ENTITY, instance };
+        let from = self.instanced_entities[LEVEL_ENTITY].instances[target.instance];
+        let keyframe = |tick, position| Keyframe {
+            tick,
+            transform: Transform { position, ..from.transform },
+            easing: Easing::Linear,
+        };
+        let mut keyframes = vec![keyframe(0, from.transform.position)];
+        for (i, &(x, z)) in path.iter().enumerate().skip(1) {
+            let position = self.placement.tile_center(x, z) + Vector3::new(0.0, 0.5, 0.0);
+            let mut next = keyframe(i as u32 * ticks_per_tile, position);
+            if Direction::from_step(path[i - 1], (x, z)).is_none() {
+                next.easing = Easing::Step;
+            }
+            keyframes.push(next);
+        }
+        self.play_animation(target, AnimationClip::new(keyframes, Playback::Once));
+    }
+    // Runs the level script's handler for `event` and applies whatever it asked for.
+    fn fire_script_event(&mut self, event: ScriptEvent) {
+        let Some(script) = self.script.clone() else {
+            return;
+        };
+        for command in script.handle(event, &mut self.rng) {
+            match command {
+                ScriptCommand::OpenDoor(id) => {
+                    for door in self.level.doors.iter_mut().filter(|door| door.id == id) {
+                        door.latched = true;
+                    }
+                }
+                ScriptCommand::SpawnInstance { entity, position, scale } => {
+                    self.spawn_script_instance(entity, position, scale);
+                }
+                ScriptCommand::SpawnAtTile { entity, tile: (x, z), height, scale } => {
+                    let position = self.placement.tile_center(x, z) + Vector3::unit_y() * height;
+                    self.spawn_script_instance(entity, position, scale);
+                }
+                ScriptCommand::SetLightColor(color) => self.light_color = color,
+            }
+        }
+    }
+    // Adds an instance a level script asked for to `entity`, drawn like the entity's others.
+    fn spawn_script_instance(&mut self, entity: usize, position: Vector3<f32>, scale: f32) {
+        let Some(entity) = self.instanced_entities.get_mut(entity) else {
+            log_println!("level script spawned into missing entity {}", entity);
+            return;
+        };
+        let shader = entity.instances.first().map_or(Shader::Texture, |i| i.shader);
+        entity.instances.push(Instance {
+            transform: Transform::new(position, Rotor::identity(), scale),
+            shader,
+            tint: NO_TINT,
+            layer: 0,
+        });
+    }
+    // Walks, runs and jumps the player as a physics body pushed around by the input.
+    fn update_physics_movement(&mut self, input: &InputState, delta_t: f32) {
+        let position = self.player.physics.position.to_vec();
+        let zone = self.gravity_zones.iter().position(|zone| zone.contains(position));
+        self.player.physics.gravity =
+            zone.map_or(Vector3::new(0.0, GRAVITY, 0.0), |i| self.gravity_zones[i].gravity);
+        self.player.physics.reset_forces();
         let lateral_force = PLAYER_FORCE
             * cgmath::Vector3::normalize(
                 [-self.player.camera.direction.z, 0.0, self.player.camera.direction.x].into(),
             );
 
-        let delta_t = (*TIME_PER_GAME_TICK).as_secs_f32();
+        let (x, z) = self.tile_under_player();
+        let ground_tile = self.level.tile(x, z);
+        let on_ice = ground_tile == Tile::Ice;
         if input.right && !input.left {
             self.player.physics.apply_force(lateral_force);
         } else if input.left && !input.right {
             self.player.physics.apply_force(-lateral_force);
-        } else {
-            // Neither or both are pressed, apply lateral damping.
+        } else if !on_ice {
+            // Neither or both are pressed, apply lateral damping. Ice has no friction to damp with.
             self.player.physics.apply_force(
                 -Vector3::dot(
                     self.player.physics.mass * self.player.physics.velocity,
@@ -226,7 +1676,7 @@ impl GameState {
             self.player.physics.apply_force(fwd_force);
         } else if input.backward && !input.forward {
             self.player.physics.apply_force(-fwd_force);
-        } else {
+        } else if !on_ice {
             // Neither or both are pressed, apply forward damping.
             self.player.physics.apply_force(
                 -Vector3::dot(
@@ -236,48 +1686,444 @@ impl GameState {
                     * (1.0 / (10.0 * delta_t)),
             );
         }
-        if input.jump && self.player.physics.position.y <= -4.999 {
-            self.player.physics.velocity += (0.0, 5.0, 0.0).into();
+        // Platforms move first. Whoever stood on one at the start of the tick rides along with it.
+        let player_before = self.player_bounds();
+        let standing_on =
+            self.player_platform.filter(|&i| self.platforms[i].supports(&player_before));
+        self.update_platforms(delta_t);
+        self.update_contraptions(delta_t);
+        let ground_velocity = match (standing_on, ground_tile) {
+            (Some(i), _) => Some(self.platforms[i].velocity),
+            (None, Tile::Conveyor(direction)) if self.player_on_floor() => {
+                let (dx, dz) = direction.offset();
+                let speed = 1.0
+                    / (tick_rate::ticks(CONVEYOR_SECS_PER_TILE) as f32 * tick_rate::tick_secs());
+                Some(speed * Vector3::new(dx as f32, 0.0, dz as f32))
+            }
+            _ => None,
+        };
+        if ground_velocity.is_none() {
+            // Stepping or jumping off a platform or conveyor keeps its momentum.
+            self.player.physics.velocity += self.player.physics.ground_velocity;
+        }
+        self.player.physics.ground_velocity = ground_velocity.unwrap_or(Vector3::zero());
+        if input.jump && self.player_grounded() {
+            // Away from whatever surface gravity holds the player against.
+            self.player.physics.velocity -= JUMP_SPEED * self.player.physics.gravity.normalize();
+            self.particles.emit(ParticleEffect::JumpDust, self.player_feet());
         }
-        let delta_pos = self.player.physics.update(delta_t, 10.0);
-        self.player.camera.eye += delta_pos;
-        if self.player.physics.position.y < -5.0 {
+        let position_before = self.player.physics.position;
+        let delta_pos = self.player.physics.update(delta_t, PLAYER_MAX_SPEED);
+        // Only from above: someone who walked off the edge and back underneath keeps falling.
+        if self.player.physics.position.y < -5.0
+            && position_before.y >= -5.0
+            && FLOOR.overlaps_xz(&self.player_bounds())
+        {
+            let landing_speed = -self.player.physics.velocity.y;
             self.player.physics.position.y = -5.0;
             self.player.physics.velocity.y = 0.0;
-            // TODO: clearly the player update code should be responsible for moving the eye /
-            // center-of-mass in tandem.
-            self.player.camera.eye =
-                self.player.physics.position + Vector3::new(0.0, CAMERA_PHYSICS_OFFSET, 0.0);
+            if landing_speed > LANDING_DUST_SPEED {
+                self.particles.emit(ParticleEffect::JumpDust, self.player_feet());
+                self.shake_camera(LANDING_TRAUMA_PER_SPEED * (landing_speed - LANDING_DUST_SPEED));
+            }
+        }
+        self.player_on_zone_surface = false;
+        if let Some(i) = zone {
+            self.player_on_zone_surface =
+                self.gravity_zones[i].land(&mut self.player.physics, PLAYER_HALF_EXTENTS);
         }
+        let player_after = self.player_bounds();
+        let physics = &self.player.physics;
+        self.player_platform = self.platforms.iter().position(|platform| {
+            physics.velocity.y <= 0.0
+                && (standing_on.is_some() && platform.bounds().overlaps_xz(&player_after)
+                    || platform.caught(&player_before, &player_after))
+        });
+        if let Some(i) = self.player_platform {
+            let feet_y = self.platforms[i].top();
+            let rise = feet_y + PLAYER_FEET_OFFSET - self.player.physics.position.y;
+            self.player.physics.position.y += rise;
+            self.player.physics.velocity.y = 0.0;
+        }
+        // The eye follows the step's motion exactly, and eases over the corrections after it.
+        let jump = self.player.physics.position - position_before - delta_pos;
+        let eye_offset = self.player.eye_follow.update(jump, delta_t);
+        self.player.camera.eye = self.player.physics.position
+            + Vector3::new(0.0, CAMERA_PHYSICS_OFFSET, 0.0)
+            + eye_offset;
+    }
+    // Takes the player one tile at a time in the direction the movement keys point, relative to
+    // the camera, pushing any box in the way. Only the platforms and contraptions still move
+    // under physics.
+    fn update_step_movement(&mut self, input: &InputState, delta_t: f32) {
+        self.update_platforms(delta_t);
+        self.update_contraptions(delta_t);
+        if self.step.as_ref().is_some_and(|step| step.is_finished(self.tick)) {
+            self.step = None;
+        }
+        if self.step.is_none() {
+            let camera = self.player.camera.direction;
+            let forward = Vector3::new(camera.x, 0.0, camera.z);
+            let right = Vector3::new(-camera.z, 0.0, camera.x);
+            let axis = |positive: bool, negative: bool| positive as i32 - negative as i32;
+            let wanted = forward * axis(input.forward, input.backward) as f32
+                + right * axis(input.right, input.left) as f32;
+            if !wanted.is_zero() {
+                self.start_step(nearest_direction(wanted));
+            }
+        }
+        let position_before = self.player.physics.position;
+        if let Some(step) = &self.step {
+            let (from, to, t) = step.at(self.tick);
+            // Teleports hold the player on the entrance, then pop them out at the exit.
+            let t = if Direction::from_step(from, to).is_some() { t } else { 0.0 };
+            let from = self.placement.tile_center(from.0, from.1);
+            let to = self.placement.tile_center(to.0, to.1);
+            let center = from + (to - from) * t;
+            self.player.physics.position.x = center.x;
+            self.player.physics.position.z = center.z;
+        } else {
+            let (x, z) = self.player_tile;
+            let center = self.placement.tile_center(x, z);
+            self.player.physics.position.x = center.x;
+            self.player.physics.position.z = center.z;
+        }
+        let delta_pos = self.player.physics.position - position_before;
+        // What the walk animation goes by.
+        self.player.physics.velocity = delta_pos / delta_t;
+        let eye_offset = self.player.eye_follow.update(Vector3::zero(), delta_t);
+        self.player.camera.eye = self.player.physics.position
+            + Vector3::new(0.0, CAMERA_PHYSICS_OFFSET, 0.0)
+            + eye_offset;
+    }
+    // Starts the player stepping one tile in `direction`, if the level lets them. The partner
+    // blocks them like a wall.
+    fn start_step(&mut self, direction: Direction) {
+        let from = self.player_tile;
+        let Some(resolved) = self.level.resolve_move(from, direction) else {
+            return;
+        };
+        if let Some(partner) = &self.partner {
+            let box_hits_partner =
+                resolved.pushed_box.as_ref().is_some_and(|(_, path)| path.contains(&partner.tile));
+            if resolved.player_path.contains(&partner.tile) || box_hits_partner {
+                return;
+            }
+        }
+        self.level.apply(&resolved);
+        self.stats.moves += 1;
+        let to = *resolved.player_path.last().unwrap();
+        self.events.publish(GameEvent::PlayerMoved { from, to });
+        self.player_tile = to;
+        self.step = Some(StepTween::new(
+            resolved.player_path.clone(),
+            self.tick,
+            tick_rate::ticks(BOX_SECS_PER_TILE),
+        ));
+        if let Some((index, path)) = &resolved.pushed_box {
+            self.stats.pushes += 1;
+            self.events.publish(GameEvent::BoxPushed { index: *index });
+            self.box_moved(*index, path, tick_rate::ticks(BOX_SECS_PER_TILE));
+            self.undo_stack.push(resolved);
+        }
+        self.fire_script_event(ScriptEvent::PlayerEnteredTile { x: to.0, z: to.1 });
+    }
+    // The box on the next tile over that the camera looks at, within reach, if it isn't moving.
+    fn grab_target(&self) -> Option<Grab> {
+        let eye = self.player.camera.eye.to_vec();
+        let look = self.player.camera.direction.normalize();
+        let (index, _) = self
+            .box_instances
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &instance)| {
+                let position =
+                    self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position;
+                let distance = Aabb::from_center(position, BOX_HALF_EXTENTS).ray_hit(eye, look)?;
+                (distance <= GRAB_REACH).then_some((i, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+        if self.box_animating(index) {
+            return None;
+        }
+        let direction = Direction::from_step(self.player_tile, self.level.boxes[index])?;
+        Some(Grab { index, direction })
+    }
+    // Pressing interact grabs the box the camera looks at, and holding it keeps the player in line
+    // with it.
+    // Walking into the box then pushes it a tile along the grid, unless a wall, another box or the
+    // partner is in the way, which stops the player at the box instead.
+    fn update_grab(&mut self, input: &InputState) {
+        if !input.interact {
+            self.grab = None;
+            return;
+        }
+        if Button::Interact.phase(&self.previous_input, input) == ButtonPhase::Pressed {
+            self.grab = self.grab_target();
+        }
+        let Some(grab) = self.grab else {
+            return;
+        };
+        let (dx, dz) = grab.direction.offset();
+        let (x, z) = self.player_tile;
+        // Let go once the box is no longer just ahead, after sliding off over ice or an undo.
+        let box_tile = self.level.boxes[grab.index];
+        if box_tile != (x + dx, z + dz) && box_tile != (x + 2 * dx, z + 2 * dz) {
+            self.grab = None;
+            return;
+        }
+        let axis = Vector3::new(dx as f32, 0.0, dz as f32);
+        let physics = &mut self.player.physics;
+        let center = self.placement.tile_center(x, z);
+        let off_line =
+            Vector3::new(physics.position.x - center.x, 0.0, physics.position.z - center.z);
+        let mut correction = -(off_line - axis * off_line.dot(axis));
+        physics.velocity -= Vector3::new(physics.velocity.x, 0.0, physics.velocity.z)
+            - axis * physics.velocity.dot(axis);
+        let instance = self.box_instances[grab.index];
+        let box_position =
+            self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position;
+        let contact = BOX_HALF_EXTENTS.x + PLAYER_HALF_EXTENTS.x;
+        let gap = (box_position - physics.position.to_vec()).dot(axis) - contact;
+        let touching = gap < 0.0 && box_tile == (x + dx, z + dz) && !self.box_animating(grab.index);
+        if touching && !self.push_box(self.player_tile, grab.direction) {
+            correction += axis * gap;
+            let physics = &mut self.player.physics;
+            physics.velocity -= axis * physics.velocity.dot(axis).max(0.0);
+        }
+        self.player.physics.position += correction;
+        self.player.camera.eye += correction;
+    }
+    // Catches up with the player having walked onto another tile: counts the move, pushes any box
+    // there and takes teleporters.
+    fn update_player_tile(&mut self) {
+        let tile = self.tile_under_player();
+        if tile != self.player_tile {
+            self.events.publish(GameEvent::PlayerMoved { from: self.player_tile, to: tile });
+            self.stats.moves += 1;
+            self.try_push(self.player_tile, tile);
+            self.player_tile = tile;
+            // Walking onto a teleporter comes out of its pair, keeping the player's momentum.
+            if let Some(exit) = self.level.teleporter_exit(tile.0, tile.1) {
+                if !self.level.is_blocked(exit.0, exit.1)
+                    && self.level.box_at(exit.0, exit.1).is_none()
+                {
+                    self.move_player_to_tile(exit);
+                }
+            }
+            let (x, z) = self.player_tile;
+            self.fire_script_event(ScriptEvent::PlayerEnteredTile { x, z });
+        }
+    }
+    // Moves the day/night cycle on by a tick, if it's on.
+    fn advance_time_of_day(&mut self) {
+        if self.day_night {
+            let day_ticks = DAY_LENGTH_SECS * tick_rate::ticks_per_second() as f32;
+            self.time_of_day = (self.time_of_day + 1.0 / day_ticks).fract();
+        }
+    }
+    pub fn update(&mut self, input: &InputState, step_time: Instant) {
+        self.tick += 1;
+        self.events.clear();
+        self.update_instant = step_time;
+        self.advance_time_of_day();
+        // Once the player reaches a checkpoint, restarting goes back to it instead of the start.
+        let to_checkpoint =
+            input.restart && self.checkpoint_reached && self.level_complete_tick.is_none();
+        if input.restart && !to_checkpoint {
+            self.load_level(self.level_index);
+        }
+        // The player can't act between finishing a level and the next one starting.
+        let frozen = InputState::new();
+        let input = if self.level_complete_tick.is_some() { &frozen } else { input };
+        // The attempt starts after a restart to the start, so replaying it mustn't restart again.
+        // Going back to a checkpoint is part of the attempt.
+        self.attempt.push_tick(&InputState { restart: to_checkpoint, ..input.clone() });
+        if to_checkpoint {
+            self.restore_checkpoint();
+        }
+        let delta_t = tick_rate::tick_secs();
+        match self.movement {
+            MovementMode::Physics => self.update_physics_movement(input, delta_t),
+            MovementMode::Step => self.update_step_movement(input, delta_t),
+        }
+        self.update_out_of_bounds();
 
+        if input.undo {
+            self.undo();
+        }
+        if self.movement == MovementMode::Physics {
+            self.update_grab(input);
+            self.update_player_tile();
+        }
+        let mut bodies = vec![(Body::Player, self.player_bounds())];
+        if let Some(partner) = &self.partner {
+            let (x, z) = partner.tile;
+            let center =
+                self.placement.tile_center(x, z) + Vector3::new(0.0, PLAYER_FEET_OFFSET, 0.0);
+            bodies.push((Body::Partner, Aabb::from_center(center, PLAYER_HALF_EXTENTS)));
+        }
+        for (i, &instance) in self.box_instances.iter().enumerate() {
+            let position =
+                self.instanced_entities[LEVEL_ENTITY].instances[instance].transform.position;
+            bodies.push((Body::Box(i), Aabb::from_center(position, BOX_HALF_EXTENTS)));
+        }
+        self.triggers.update(&bodies, &mut self.events);
+        let trigger_events = self
+            .events
+            .iter()
+            .filter_map(|event| match *event {
+                GameEvent::TriggerEntered { trigger, .. } => {
+                    Some(ScriptEvent::TriggerEntered { trigger })
+                }
+                GameEvent::TriggerExited { trigger, .. } => {
+                    Some(ScriptEvent::TriggerExited { trigger })
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let checkpoint_reached = self.events.iter().any(|event| {
+            matches!(*event, GameEvent::TriggerEntered { trigger, body: Body::Player }
+                if trigger >= CHECKPOINT_TRIGGER_IDS)
+        });
+        if checkpoint_reached {
+            self.save_checkpoint();
+            self.checkpoint_reached = true;
+            self.events.publish(GameEvent::CheckpointReached);
+        }
+        for event in trigger_events {
+            self.fire_script_event(event);
+        }
+        self.update_doors();
+        self.update_conveyors();
+        self.update_level_progress();
+
+        self.particles.update(delta_t);
+        self.update_animations();
+        if let Some(ghost) = &mut self.ghost {
+            ghost.update(step_time);
+        }
+        self.update_ghost_instances();
+        self.update_box_tints();
+        self.update_view(input, delta_t);
+        self.previous_input = input.clone();
+    }
+    /// Advances a game simulated elsewhere by one tick: the joining side of a co-op game, between
+    /// the states the host sends. Only animations, particles and the view move, and the input
+    /// only turns and zooms the camera.
+    pub fn update_replica(&mut self, input: &InputState, step_time: Instant) {
+        self.tick += 1;
+        self.events.clear();
+        self.update_instant = step_time;
+        self.advance_time_of_day();
+        let delta_t = tick_rate::tick_secs();
+        self.particles.update(delta_t);
+        self.update_animations();
+        let eye_offset = self.player.eye_follow.update(Vector3::zero(), delta_t);
+        self.player.camera.eye = self.player.physics.position
+            + Vector3::new(0.0, CAMERA_PHYSICS_OFFSET, 0.0)
+            + eye_offset;
+        self.update_view(input, delta_t);
+    }
+    fn update_animations(&mut self) {
+        for animation in &self.animations {
+            let target = animation.target;
+            animation.apply(
+                self.tick,
+                &mut self.instanced_entities[target.entity].instances[target.instance],
+            );
+        }
+        let tick = self.tick;
+        self.animations.retain(|animation| !animation.is_finished(tick));
+    }
+    // Turns the camera with the mouse, shakes it for this tick's events and zooms it with the
+    // wheel.
+    fn update_view(&mut self, input: &InputState, delta_t: f32) {
         const ROTATION_MOVEMENT_DEG: f32 = 0.1;
-        let lateral_rot = Rotor::from_axis_angle(
-            cgmath::Vector3::unit_y(),
+        self.player.camera.turn(
             cgmath::Deg(-ROTATION_MOVEMENT_DEG * input.mouse_x as f32),
-        );
-        let vertical_rot = Rotor::from_axis_angle(
-            cgmath::Vector3::normalize(
-                [self.player.camera.direction.z, 0.0, -self.player.camera.direction.x].into(),
-            ),
             cgmath::Deg(ROTATION_MOVEMENT_DEG * input.mouse_y as f32),
         );
-        // Prevent the camera from getting too close to a vertical pole, while still allowing for
-        // lateral movement.
-        const POLAR_THRESHOLD: f32 = 0.001;
-        let new_vertical =
-            cgmath::Vector3::normalize(vertical_rot.rotate_vector(self.player.camera.direction));
-        if abs(cgmath::Vector3::dot(new_vertical, cgmath::Vector3::unit_y()))
-            > 1.0 - POLAR_THRESHOLD
-        {
-            self.player.camera.direction =
-                cgmath::Vector3::normalize(lateral_rot.rotate_vector(self.player.camera.direction));
+        self.player.camera.update_shake(delta_t);
+        let trauma = self
+            .events
+            .iter()
+            .map(|event| match event {
+                GameEvent::DoorOpened { .. } | GameEvent::DoorClosed { .. } => DOOR_TRAUMA,
+                GameEvent::LevelCompleted { .. } => LEVEL_COMPLETE_TRAUMA,
+                _ => 0.0,
+            })
+            .sum();
+        self.shake_camera(trauma);
+        self.zoom =
+            (self.zoom + ZOOM_STEP_DEGREES * input.wheel as f32).clamp(0.0, MAX_ZOOM_DEGREES);
+        self.update_fov();
+    }
+    fn shake_camera(&mut self, trauma: f32) {
+        if self.camera_shake {
+            self.player.camera.add_trauma(trauma);
+        }
+    }
+    // Eases the field of view towards the base one, narrowed by the zoom and widened in proportion
+    // to horizontal speed if the kick is on.
+    fn update_fov(&mut self) {
+        let velocity = self.player.physics.velocity;
+        let speed = Vector3::new(velocity.x, 0.0, velocity.z).magnitude();
+        let kick = if self.fov_kick {
+            FOV_KICK_DEGREES * (speed / PLAYER_MAX_SPEED).min(1.0)
         } else {
-            self.player.camera.direction =
-                cgmath::Vector3::normalize(lateral_rot.rotate_vector(new_vertical));
+            0.0
+        };
+        let fovy = self.player.camera.fovy();
+        let target = self.fov - self.zoom + kick;
+        self.player.camera.set_fovy(fovy + (target - fovy) * FOV_KICK_EASING);
+    }
+}
+
+// The grid direction closest to `direction`, which only has to be along the ground.
+fn nearest_direction(direction: Vector3<f32>) -> Direction {
+    if direction.x.abs() > direction.z.abs() {
+        if direction.x > 0.0 {
+            Direction::East
+        } else {
+            Direction::West
         }
+    } else if direction.z > 0.0 {
+        Direction::South
+    } else {
+        Direction::North
     }
 }
 
+// The tiles from `from` to `to` if they share a row or column. Otherwise just the two, which
+// animates as a teleport.
+fn straight_path(from: (i32, i32), to: (i32, i32)) -> Vec<(i32, i32)> {
+    let (dx, dz) = ((to.0 - from.0).signum(), (to.1 - from.1).signum());
+    if dx != 0 && dz != 0 {
+        return vec![from, to];
+    }
+    let mut path = vec![from];
+    while *path.last().unwrap() != to {
+        let (x, z) = *path.last().unwrap();
+        path.push((x + dx, z + dz));
+    }
+    path
+}
+
+// Interpolates between the colors in `keyframes`, sorted by sun height, and holds the end ones past
+// either end.
+fn sample_by_sun_height(keyframes: &[(f32, [f32; 3])], height: f32) -> [f32; 3] {
+    let next = keyframes.iter().position(|&(at, _)| at > height).unwrap_or(keyframes.len());
+    if next == 0 || next == keyframes.len() {
+        return keyframes[next.saturating_sub(1)].1;
+    }
+    let (from_height, from) = keyframes[next - 1];
+    let (to_height, to) = keyframes[next];
+    let t = (height - from_height) / (to_height - from_height);
+    [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t)
+}
+
+#[derive(Clone, PartialEq)]
 pub struct InputState {
     pub mouse_x: i32,
     pub mouse_y: i32,
@@ -286,6 +2132,16 @@ pub struct InputState {
     pub left: bool,
     pub right: bool,
     pub jump: bool,
+    pub undo: bool,
+    // Start the current level over.
+    pub restart: bool,
+    // Held to grab the box the camera looks at and push it along the grid.
+    pub interact: bool,
+    // Menu navigation: accept the selected item, or go back a screen.
+    pub confirm: bool,
+    pub back: bool,
+    // Mouse wheel notches since the last tick, positive when rolled away from the player.
+    pub wheel: i32,
 }
 
 impl InputState {
@@ -298,17 +2154,18 @@ impl InputState {
             left: false,
             right: false,
             jump: false,
+            undo: false,
+            restart: false,
+            interact: false,
+            confirm: false,
+            back: false,
+            wheel: 0,
         }
     }
-    pub fn post_update_reset(&mut self) {
-        self.mouse_x = 0;
-        self.mouse_y = 0;
-        self.jump = false;
-    }
 }
 
 #[repr(u32)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
 pub enum Shader {
     Texture = 0,
     NonMaterial = 1,
@@ -317,22 +2174,67 @@ pub enum Shader {
     ColorTween = 4,
     SimpleTransparency = 5,
     Aerogel = 6,
+    // Plain lit grey, for models without textures.
+    Matte = 7,
+    // Glowing swirl for teleporter pads.
+    Portal = 8,
+    // Belt stripes scrolling towards +x.
+    Conveyor = 9,
+    // Translucent stand-in for the ghost of a best run.
+    Ghost = 10,
+    // The instance's layer of the material's tile texture array.
+    Tiles = 11,
+    // Goal tiles: Pulse's glow, striped in the colorblind-safe palettes so goals don't rely on
+    // hue alone.
+    Goal = 12,
+}
+impl Shader {
+    pub const ALL: [Shader; 13] = [
+        Shader::Texture,
+        Shader::NonMaterial,
+        Shader::Pulse,
+        Shader::Ripple,
+        Shader::ColorTween,
+        Shader::SimpleTransparency,
+        Shader::Aerogel,
+        Shader::Matte,
+        Shader::Portal,
+        Shader::Conveyor,
+        Shader::Ghost,
+        Shader::Tiles,
+        Shader::Goal,
+    ];
+}
+
+/// Layers of the tile texture array that Shader::Tiles draws from, in the order of
+/// `material::TILE_TEXTURES`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileLayer {
+    Wall = 0,
+    Floor = 1,
+    Goal = 2,
+    Box = 3,
 }
 
 #[derive(Clone, Copy)]
 pub struct Instance {
-    pub position: cgmath::Vector3<f32>,
-    pub scale: f32,
-    pub rotation: Rotor,
+    pub transform: Transform,
     pub shader: Shader,
+    // Only for show, so not hashed.
+    pub tint: [f32; 4],
+    // Which layer of the tile texture array Shader::Tiles draws with. Also only for show.
+    pub layer: u32,
 }
 impl Instance {
     pub fn to_raw(&self) -> InstanceRaw {
         InstanceRaw {
-            pos: self.position.into(),
-            scale: self.scale,
-            rot: self.rotation.into(),
+            pos: self.transform.position.into(),
+            scale: self.transform.scale,
+            rot: self.transform.rotation.into(),
             shader: self.shader as u32,
+            tint: self.tint,
+            layer: self.layer,
         }
     }
 }
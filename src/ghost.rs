@@ -0,0 +1,40 @@
+/* Ghost: a translucent rerun of a level's fastest attempt, played alongside the live game. */
+use std::{sync::Arc, time::Instant};
+
+use crate::{game_state::GameState, headless::InputScript};
+
+// A finished attempt at a level: the game as it was when the level started, and the input that
+// solved it from there.
+pub struct BestRun {
+    pub start: Arc<GameState>,
+    pub inputs: InputScript,
+    pub ticks: u32,
+}
+
+/// Replays a `BestRun` by feeding its input to a copy of its starting state, one tick per tick of
+/// the live game. The simulation is deterministic, so the copy retraces the run exactly.
+#[derive(Clone)]
+pub struct Ghost {
+    run: Arc<BestRun>,
+    pub state: GameState,
+    // The step of `run.inputs` being played, and how many of its ticks have been played.
+    step: usize,
+    step_tick: u32,
+}
+impl Ghost {
+    pub fn new(run: Arc<BestRun>) -> Self {
+        Ghost { state: (*run.start).clone(), run, step: 0, step_tick: 0 }
+    }
+    /// Plays the next tick of the run. Once the run is over, the ghost stays where it finished.
+    pub fn update(&mut self, step_time: Instant) {
+        let Some(step) = self.run.inputs.steps.get(self.step) else {
+            return;
+        };
+        self.state.update(&step.input, step_time);
+        self.step_tick += 1;
+        if self.step_tick >= step.ticks {
+            self.step += 1;
+            self.step_tick = 0;
+        }
+    }
+}
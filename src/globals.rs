@@ -0,0 +1,65 @@
+/* Globals: what every shader might want to know about the frame as a whole, bound as group 3 of
+ * the scene pipelines. */
+use std::time::Instant;
+
+use crate::tick_rate;
+
+/// Matches `Globals` in shaders.wgsl.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GlobalsUniform {
+    // Game time, from `GameClock`.
+    pub time_secs: f32,
+    // The game's tick, as of the latest snapshot.
+    pub ticks: u32,
+    // The window's client area, in pixels.
+    pub screen_size: [f32; 2],
+    // In pixels from the top-left corner of the client area.
+    pub cursor: [f32; 2],
+    _padding: [f32; 2],
+}
+impl GlobalsUniform {
+    pub fn new(screen_size: [f32; 2]) -> Self {
+        GlobalsUniform {
+            time_secs: 0.0,
+            ticks: 0,
+            screen_size,
+            cursor: [0.0, 0.0],
+            _padding: [0.0, 0.0],
+        }
+    }
+    /// The cursor's position across the screen, from 0.0 to 1.0 each way.
+    pub fn cursor_fraction(&self) -> [f32; 2] {
+        let [width, height] = self.screen_size;
+        let [x, y] = self.cursor;
+        [x / width.max(1.0), y / height.max(1.0)]
+    }
+}
+
+/// The time shaders animate by: the game's, going by its ticks rather than the wall clock, so
+/// animations stop while the game does and keep in step with the simulation.
+#[derive(Clone, Copy, Debug)]
+pub struct GameClock {
+    tick: isize,
+    // When the snapshot at `tick` was made.
+    made_at: Instant,
+    // Game seconds per real second: 1.0 while playing, 0.0 while paused.
+    time_scale: f32,
+}
+impl GameClock {
+    pub fn new(now: Instant) -> Self {
+        GameClock { tick: 0, made_at: now, time_scale: 0.0 }
+    }
+    /// Follows the snapshot of the game at `tick`, made at `made_at`.
+    pub fn set(&mut self, tick: isize, made_at: Instant, time_scale: f32) {
+        *self = GameClock { tick, made_at, time_scale };
+    }
+    /// The game time at `now`, in seconds: the snapshot's tick, plus however far into the next
+    /// tick `now` is at the time scale. Never more than a tick past the snapshot, so time doesn't
+    /// run ahead of a game that stalls.
+    pub fn secs_at(&self, now: Instant) -> f32 {
+        let tick_secs = tick_rate::tick_secs();
+        let since = now.saturating_duration_since(self.made_at).as_secs_f32() * self.time_scale;
+        self.tick.max(0) as f32 * tick_secs + since.min(tick_secs)
+    }
+}
@@ -0,0 +1,116 @@
+/* GPU cache: bind groups and render pipelines kept by what they were built from, so building one
+ * again, as hot reload and level changes do, hands back the one already built. */
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+type Cache<K, V> = LazyLock<Mutex<HashMap<K, Arc<V>>>>;
+
+static BIND_GROUPS: Cache<BindGroupKey, wgpu::BindGroup> = LazyLock::new(Default::default);
+static PIPELINES: Cache<PipelineKey, wgpu::RenderPipeline> = LazyLock::new(Default::default);
+
+// A resource as a bind group entry refers to it. Ids are never reused, so a resource built again
+// from the same file is a different one.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum ResourceKey {
+    Buffer { id: wgpu::Id<wgpu::Buffer>, offset: u64, size: Option<u64> },
+    TextureView(wgpu::Id<wgpu::TextureView>),
+    Sampler(wgpu::Id<wgpu::Sampler>),
+}
+impl ResourceKey {
+    // None for kinds of resource wgpu may add later, which aren't cached.
+    fn of(resource: &wgpu::BindingResource) -> Option<Vec<Self>> {
+        let buffer = |binding: &wgpu::BufferBinding| ResourceKey::Buffer {
+            id: binding.buffer.global_id(),
+            offset: binding.offset,
+            size: binding.size.map(u64::from),
+        };
+        let sampler = |sampler: &wgpu::Sampler| ResourceKey::Sampler(sampler.global_id());
+        let view = |view: &wgpu::TextureView| ResourceKey::TextureView(view.global_id());
+        Some(match resource {
+            wgpu::BindingResource::Buffer(binding) => vec![buffer(binding)],
+            wgpu::BindingResource::BufferArray(bindings) => bindings.iter().map(buffer).collect(),
+            wgpu::BindingResource::Sampler(one) => vec![sampler(one)],
+            wgpu::BindingResource::SamplerArray(samplers) => {
+                samplers.iter().map(|one| sampler(one)).collect()
+            }
+            wgpu::BindingResource::TextureView(one) => vec![view(one)],
+            wgpu::BindingResource::TextureViewArray(views) => {
+                views.iter().map(|one| view(one)).collect()
+            }
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct BindGroupKey {
+    layout: wgpu::Id<wgpu::BindGroupLayout>,
+    entries: Vec<(u32, Vec<ResourceKey>)>,
+}
+
+/// Everything `gpu_state::create_render_pipeline` builds a pipeline from, besides its label.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub layout: wgpu::Id<wgpu::PipelineLayout>,
+    pub color_format: wgpu::TextureFormat,
+    pub depth_format: Option<wgpu::TextureFormat>,
+    pub blend: Option<wgpu::BlendState>,
+    // Each vertex buffer's stride, step mode and attributes.
+    pub vertex_layouts: Vec<(u64, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)>,
+    pub source: String,
+    pub vertex_entrypoint: String,
+    pub fragment_entrypoint: Option<String>,
+}
+impl PipelineKey {
+    pub fn vertex_layouts(
+        layouts: &[wgpu::VertexBufferLayout],
+    ) -> Vec<(u64, wgpu::VertexStepMode, Vec<wgpu::VertexAttribute>)> {
+        layouts
+            .iter()
+            .map(|layout| (layout.array_stride, layout.step_mode, layout.attributes.to_vec()))
+            .collect()
+    }
+}
+
+/// The bind group `desc` describes, built only if one with the same layout and resources isn't
+/// already in use.
+pub fn bind_group(device: &wgpu::Device, desc: &wgpu::BindGroupDescriptor) -> Arc<wgpu::BindGroup> {
+    let entries = desc
+        .entries
+        .iter()
+        .map(|entry| Some((entry.binding, ResourceKey::of(&entry.resource)?)))
+        .collect::<Option<Vec<_>>>();
+    let Some(entries) = entries else {
+        return Arc::new(device.create_bind_group(desc));
+    };
+    let key = BindGroupKey { layout: desc.layout.global_id(), entries };
+    get_or_insert(&BIND_GROUPS, key, || device.create_bind_group(desc))
+}
+
+/// The pipeline for `key`, built with `build` only if one isn't already in use.
+pub fn render_pipeline(
+    key: PipelineKey,
+    build: impl FnOnce() -> wgpu::RenderPipeline,
+) -> Arc<wgpu::RenderPipeline> {
+    get_or_insert(&PIPELINES, key, build)
+}
+
+// Objects only the cache still holds are dropped whenever a new one is added, so a cache hit
+// needs the earlier object to be alive, as a model's old materials are until its reload is ready.
+fn get_or_insert<K: Eq + Hash, V>(
+    cache: &Cache<K, V>,
+    key: K,
+    build: impl FnOnce() -> V,
+) -> Arc<V> {
+    let mut cache = cache.lock().unwrap();
+    if let Some(object) = cache.get(&key) {
+        return Arc::clone(object);
+    }
+    cache.retain(|_, object| Arc::strong_count(object) > 1);
+    let object = Arc::new(build());
+    cache.insert(key, Arc::clone(&object));
+    object
+}
@@ -0,0 +1,99 @@
+/* GPU errors: wgpu validation errors caught rather than left to panic, logged and kept for a
+ * banner across the top of the screen. */
+use std::{
+    cell::Cell,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{log_println, ui::UiLayer};
+
+// How long the banner stays up after the latest error.
+const BANNER_TIME: Duration = Duration::from_secs(10);
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKDROP_COLOR: [f32; 4] = [0.6, 0.05, 0.05, 0.85];
+
+struct GpuError {
+    // The last line of wgpu's message, the innermost of the causes it lists, which says most
+    // closely what went wrong.
+    summary: String,
+    // How many times in a row the same error came, which an invalid pipeline does every frame.
+    count: u32,
+    last_seen: Instant,
+}
+
+static LATEST: Mutex<Option<GpuError>> = Mutex::new(None);
+
+thread_local! {
+    // The errors reported on this thread. wgpu calls the uncaptured handler on the thread that
+    // made the failing call.
+    static REPORTED: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Reports errors nothing else catches instead of panicking, which is wgpu's default.
+pub fn install(device: &wgpu::Device) {
+    device.on_uncaptured_error(Box::new(|error| report("uncaptured", &error)));
+}
+
+/// Logs `error` with `context`, saying where it came from, and puts it on the banner. An error
+/// repeating the last one is only counted, so a broken draw doesn't fill the log.
+pub fn report(context: &str, error: &wgpu::Error) {
+    REPORTED.with(|reported| reported.set(reported.get() + 1));
+    let message = error.to_string();
+    let summary = message.lines().map(str::trim).rfind(|line| !line.is_empty()).unwrap_or("");
+    let Ok(mut latest) = LATEST.lock() else {
+        return;
+    };
+    if let Some(latest) = latest.as_mut().filter(|latest| latest.summary == summary) {
+        latest.count += 1;
+        latest.last_seen = Instant::now();
+        return;
+    }
+    log_println!("GPU error ({}): {}", context, message);
+    *latest = Some(GpuError { summary: summary.to_string(), count: 1, last_seen: Instant::now() });
+}
+
+/// Runs `f` in an error scope, so its validation errors are reported with `context` rather than
+/// as uncaptured. Error scopes are shared by every thread using the device, so an error another
+/// thread causes meanwhile is reported with this context too.
+pub fn scoped<T>(device: &wgpu::Device, context: &str, f: impl FnOnce() -> T) -> T {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let result = f();
+    if let Some(error) = pollster::block_on(device.pop_error_scope()) {
+        report(context, &error);
+    }
+    result
+}
+
+/// Runs `f`, failing if it caused any GPU error, for work whose results are no good if it did.
+/// Unlike `scoped`, this works on any thread, since it counts the errors the uncaptured handler
+/// reports on this one.
+pub fn checked<T>(context: &str, f: impl FnOnce() -> T) -> anyhow::Result<T> {
+    let before = REPORTED.with(Cell::get);
+    let result = f();
+    let errors = REPORTED.with(Cell::get) - before;
+    if errors > 0 {
+        anyhow::bail!("{} GPU errors ({})", errors, context);
+    }
+    Ok(result)
+}
+
+/// Draws the latest error across the top of a window `width` pixels wide, for a while after it
+/// last happened, cut short to fit on one line.
+pub fn draw_banner(ui: &mut UiLayer, now: Instant, width: f32, scale: u32, label: &str) {
+    let Ok(latest) = LATEST.lock() else {
+        return;
+    };
+    let Some(error) = latest.as_ref().filter(|error| now - error.last_seen < BANNER_TIME) else {
+        return;
+    };
+    let mut text = format!("{}: {}", label, error.summary);
+    if error.count > 1 {
+        text += &format!(" (x{})", error.count);
+    }
+    let text_height = UiLayer::text_height(scale);
+    let padding = (text_height / 2.0).round();
+    while UiLayer::text_width(&text, scale) > width - 2.0 * padding && text.pop().is_some() {}
+    ui.rect(0.0, 0.0, width, text_height + 2.0 * padding, BACKDROP_COLOR);
+    ui.text(padding, padding, scale, TEXT_COLOR, &text);
+}
@@ -0,0 +1,120 @@
+/* GPU memory: the bytes allocated on the GPU through the engine's helpers, counted by category,
+ * with a warning when they add up to more than a budget. */
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::log_println;
+
+/// What an allocation is for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    // Vertex and index buffers.
+    Meshes,
+    Textures,
+    // Uniform buffers of bind groups.
+    Uniforms,
+}
+impl Category {
+    pub const ALL: [Category; 3] = [Category::Meshes, Category::Textures, Category::Uniforms];
+    /// The category's key in string tables, as `hud.memory.<key>`.
+    pub fn key(self) -> &'static str {
+        match self {
+            Category::Meshes => "meshes",
+            Category::Textures => "textures",
+            Category::Uniforms => "uniforms",
+        }
+    }
+    /// The category's English name in the stats HUD.
+    pub fn label(self) -> &'static str {
+        match self {
+            Category::Meshes => "Meshes",
+            Category::Textures => "Textures",
+            Category::Uniforms => "Uniforms",
+        }
+    }
+    fn index(self) -> usize {
+        Category::ALL.iter().position(|&category| category == self).unwrap()
+    }
+}
+
+// Bytes allocated, indexed like Category::ALL.
+static ALLOCATED: [AtomicU64; Category::ALL.len()] =
+    [const { AtomicU64::new(0) }; Category::ALL.len()];
+// In bytes, with 0 for no budget.
+static BUDGET: AtomicU64 = AtomicU64::new(0);
+// Whether the total has been over budget since it last went back under, so it is only warned
+// about once each time.
+static OVER_BUDGET: AtomicBool = AtomicBool::new(false);
+
+/// Bytes counted against a category for as long as this lives. Keep it beside the buffer or
+/// texture it counts, so they are dropped together.
+#[derive(Debug)]
+pub struct Allocation {
+    category: Category,
+    bytes: u64,
+}
+impl Allocation {
+    pub fn new(category: Category, bytes: u64) -> Self {
+        ALLOCATED[category.index()].fetch_add(bytes, Ordering::Relaxed);
+        check_budget();
+        Allocation { category, bytes }
+    }
+    /// Counts the texture `desc` describes, with all its layers and mip levels.
+    pub fn texture(desc: &wgpu::TextureDescriptor) -> Self {
+        let size = desc.size;
+        // Formats with a depth and a stencil part don't have one block size. None of them take
+        // more than 4 bytes a texel.
+        let texel_bytes = desc.format.block_size(None).unwrap_or(4) as u64;
+        let (block_width, block_height) = desc.format.block_dimensions();
+        let bytes = (0..desc.mip_level_count)
+            .map(|level| {
+                let width = (size.width >> level).max(1).div_ceil(block_width) as u64;
+                let height = (size.height >> level).max(1).div_ceil(block_height) as u64;
+                width * height * texel_bytes
+            })
+            .sum::<u64>()
+            * size.depth_or_array_layers as u64
+            * desc.sample_count as u64;
+        Self::new(Category::Textures, bytes)
+    }
+}
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        ALLOCATED[self.category.index()].fetch_sub(self.bytes, Ordering::Relaxed);
+        check_budget();
+    }
+}
+
+/// The bytes allocated for `category` and still alive.
+pub fn allocated(category: Category) -> u64 {
+    ALLOCATED[category.index()].load(Ordering::Relaxed)
+}
+
+pub fn total() -> u64 {
+    Category::ALL.into_iter().map(allocated).sum()
+}
+
+/// Warns whenever the total goes over `bytes`, or never for None.
+pub fn set_budget(bytes: Option<u64>) {
+    BUDGET.store(bytes.unwrap_or(0), Ordering::Relaxed);
+    check_budget();
+}
+
+pub fn is_over_budget() -> bool {
+    OVER_BUDGET.load(Ordering::Relaxed)
+}
+
+fn check_budget() {
+    let budget = BUDGET.load(Ordering::Relaxed);
+    let total = total();
+    let over = budget > 0 && total > budget;
+    if over && !OVER_BUDGET.swap(true, Ordering::Relaxed) {
+        log_println!("GPU memory over budget: {} of {} MB", megabytes(total), megabytes(budget));
+    } else if !over {
+        OVER_BUDGET.store(false, Ordering::Relaxed);
+    }
+}
+
+/// `bytes` in megabytes, to one decimal place, for showing.
+pub fn megabytes(bytes: u64) -> String {
+    format!("{:.1}", bytes as f64 / (1024.0 * 1024.0))
+}
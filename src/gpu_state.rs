@@ -1,17 +1,41 @@
 /* WebGPUState: data and behavior needed to create and render using WebGPU. */
 use crate::{
     camera::{Camera, CameraUniform},
-    game_state::{GameState, Instance},
-    light::LightUniform,
-    model::{self, DescribeVB, Material, Mesh, ModelVertex},
-    texture,
-    time::TimeUniform,
+    culling::{self, CullInput, InstanceCuller, ModelDraws},
+    debug_lines::{DebugLineRenderer, DebugLines},
+    game_state::{GameState, Instance, Shader, StaticGeometry},
+    globals::{GameClock, GlobalsUniform},
+    gpu_cache, gpu_errors,
+    gpu_memory::{Allocation, Category},
+    hot_reload::AssetWatcher,
+    jobs::{Job, JobPool},
+    lod, log_println,
+    material::{self, MaterialDef},
+    minimap::MinimapGrid,
+    model::{self, DescribeVB, Material, Mesh, SkinnedModel, SkinnedVertex},
+    outline_renderer::OutlineRenderer,
+    particle_renderer::ParticleRenderer,
+    pipeline_cache::{Blend, PipelineCache, PipelineKey},
+    post_process::{self, AntiAliasing, ExposureMode, PostProcess},
+    readback, reflection,
+    render_graph::{RenderGraph, Resource, TransientTextures},
+    scene::{self, Fog, SceneUniform},
+    settings::{Palette, Settings},
+    sky::SkyRenderer,
+    ssao::Ssao,
+    static_batch, texture,
+    ui::UiLayer,
+    ui_renderer::UiRenderer,
+    uploader::Uploader,
 };
 
+use anyhow::Context;
+use cgmath::{EuclideanSpace, InnerSpace, Point3};
 use std::{
     ffi::c_void,
     mem::{self},
     result::Result,
+    sync::Arc,
 };
 use std::{ops::Range, time::Instant};
 use wgpu::util::DeviceExt;
@@ -45,36 +69,217 @@ unsafe impl raw_window_handle::HasRawDisplayHandle for MyWindowHandle {
     }
 }
 
+// A model's instances that share a shader variant, drawn with that variant's pipeline.
+struct Batch {
+    variant: Shader,
+    instances: Vec<InstanceRaw>,
+}
+
+// Instances in raw form, grouped by shader variant. Sorted by pipeline key, so the opaque batches
+// come first and can be drawn together in the depth pre-pass.
+fn group_by_variant<'a>(instances: impl IntoIterator<Item = &'a Instance>) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = Vec::new();
+    for instance in instances {
+        match batches.iter_mut().find(|batch| batch.variant == instance.shader) {
+            Some(batch) => batch.instances.push(instance.to_raw()),
+            None => {
+                batches.push(Batch { variant: instance.shader, instances: vec![instance.to_raw()] })
+            }
+        }
+    }
+    batches.sort_by_key(|batch| PipelineKey::for_variant(batch.variant));
+    batches
+}
+
+// A model's instances drawn at one LOD.
+#[derive(Default)]
+struct LodData {
+    batches: Vec<Batch>,
+    // Where the batches are in the culler's shared buffers.
+    draws: ModelDraws,
+}
+impl LodData {
+    // How many of the batches, from the start, the depth pre-pass draws.
+    fn opaque_batches(&self) -> usize {
+        self.batches
+            .iter()
+            .take_while(|batch| Blend::for_variant(batch.variant) == Blend::Opaque)
+            .count()
+    }
+}
+
 struct ModelData {
     model: model::Model,
-    instances: Vec<InstanceRaw>,
-    buffer: wgpu::Buffer,
+    bounding_radius: f32,
+    // One per LOD of the model, full detail first.
+    lods: Vec<LodData>,
 }
 impl ModelData {
-    fn new(device: &wgpu::Device, model: model::Model, instances: &Vec<Instance>) -> Self {
-        let instances_raw = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Simple Cube Instance Buffer"),
-            contents: bytemuck::cast_slice(&instances_raw),
-            usage: wgpu::BufferUsages::VERTEX,
+    fn new(model: model::Model, instances: &[Instance], eye: Point3<f32>) -> Self {
+        let mut model_data = ModelData {
+            bounding_radius: culling::bounding_radius(&model),
+            model,
+            lods: Vec::new(),
+        };
+        model_data.set_instances(instances, eye);
+        model_data
+    }
+    // Sorts the instances into LODs by their distance from `eye`, then into batches.
+    fn set_instances(&mut self, instances: &[Instance], eye: Point3<f32>) {
+        let level_count = self.model.lod_count();
+        let mut by_level = vec![Vec::new(); level_count];
+        for instance in instances {
+            let distance = (instance.transform.position - eye.to_vec()).magnitude();
+            by_level[lod::select(distance, instance.transform.scale, level_count)].push(instance);
+        }
+        self.lods = by_level
+            .into_iter()
+            .map(|instances| LodData { batches: group_by_variant(instances), ..Default::default() })
+            .collect();
+    }
+    // Each LOD's meshes, with its instances.
+    fn lods(&self) -> impl Iterator<Item = (&[Mesh], &LodData)> {
+        self.lods.iter().enumerate().map(|(level, lod)| (self.model.lod_meshes(level), lod))
+    }
+}
+
+// Hands every model's batches to the culler, which packs them into its shared buffers.
+fn write_instances(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    culler: &mut InstanceCuller,
+    mut models: Vec<&mut ModelData>,
+) {
+    let inputs = models
+        .iter()
+        .flat_map(|model_data| {
+            model_data.lods().map(|(meshes, lod)| CullInput {
+                meshes,
+                bounding_radius: model_data.bounding_radius,
+                batches: lod.batches.iter().map(|batch| &batch.instances[..]).collect(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut draws = culler.write_instances(device, queue, &inputs).into_iter();
+    for lod in models.iter_mut().flat_map(|model_data| &mut model_data.lods) {
+        lod.draws = draws.next().unwrap();
+    }
+}
+
+// A skinned model drawn once, e.g. the player character.
+struct SkinnedModelData {
+    model: model::SkinnedModel,
+    instance_buffer: wgpu::Buffer,
+    bone_buffer: wgpu::Buffer,
+    // Group 3 of the skinned pipeline: the globals plus this model's bone matrices.
+    bind_group: wgpu::BindGroup,
+}
+impl SkinnedModelData {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        globals_buffer: &wgpu::Buffer,
+        model: model::SkinnedModel,
+    ) -> Self {
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Skinned Instance Buffer"),
+            size: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
         });
-        ModelData { model, instances: instances_raw, buffer }
+        let bone_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Bone Buffer"),
+            contents: bytemuck::cast_slice(&model.bone_matrices(None, 0.0)),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skinned Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: globals_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: bone_buffer.as_entire_binding() },
+            ],
+        });
+        SkinnedModelData { model, instance_buffer, bone_buffer, bind_group }
     }
 }
 
+// The models loaded from assets, by their index in `WebGPUState::models`.
+const MODEL_FILES: [(usize, &str); 3] =
+    [(0, "cube.obj"), (2, "sphere-flat.obj"), (3, "sphere.obj")];
+
+// Loads a model on the job pool, to replace the one at `index` once it's ready.
+fn spawn_model_load(
+    device: &Arc<wgpu::Device>,
+    queue: &Arc<wgpu::Queue>,
+    layout: &Arc<wgpu::BindGroupLayout>,
+    index: usize,
+    file_name: &'static str,
+) -> (usize, Job<anyhow::Result<model::Model>>) {
+    let device = Arc::clone(device);
+    let queue = Arc::clone(queue);
+    let layout = Arc::clone(layout);
+    let job = JobPool::global().spawn(move || {
+        gpu_errors::checked(file_name, || {
+            pollster::block_on(model::load_model(file_name, &device, &queue, &layout))
+        })
+        .and_then(|loaded| loaded)
+        .with_context(|| format!("loading {}", file_name))
+    });
+    (index, job)
+}
+
 pub struct WebGPUState {
+    adapter_info: wgpu::AdapterInfo,
     surface: wgpu::Surface,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
+    // Shared with the jobs loading models in the background.
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    // Every frame's uniforms, submitted ahead of the frame.
+    uploader: Uploader,
     config: wgpu::SurfaceConfiguration,
-    render_pipeline: wgpu::RenderPipeline,
+    // The scene pipelines, one per shader variant.
+    pipeline_cache: PipelineCache,
+    // Whether opaque geometry's depth is drawn before the scene pass.
+    depth_prepass: bool,
+    skinned_pipeline: Arc<wgpu::RenderPipeline>,
     background_color: wgpu::Color,
     depth_texture: texture::Texture,
     camera_group: BindGroupData<CameraUniform>,
-    light_group: BindGroupData<LightUniform>,
-    start_time: Instant,
-    time_group: BindGroupData<TimeUniform>,
+    // The uniform, then the environment map.
+    scene_group: BindGroupData<SceneUniform>,
+    sky_renderer: SkyRenderer,
+    // Whether the environment has a sky, drawn before the scene instead of clearing to the
+    // background color.
+    sky: bool,
+    clock: GameClock,
+    globals_group: BindGroupData<GlobalsUniform>,
     models: Vec<ModelData>,
+    // Baked from `static_geometry`, which is kept to notice when a new level replaces it.
+    static_geometry: Arc<StaticGeometry>,
+    static_models: Vec<ModelData>,
+    // Where the camera was for the last update, for choosing each instance's LOD.
+    camera_eye: Point3<f32>,
+    // Models still loading, with the index in `models` of the model each one replaces.
+    loading_models: Vec<(usize, Job<anyhow::Result<model::Model>>)>,
+    texture_bind_group_layout: Arc<wgpu::BindGroupLayout>,
+    // Bound for meshes without a material of their own.
+    default_material: Material,
+    // The files each loaded model was built from, by index in `models`.
+    asset_watcher: AssetWatcher<usize>,
+    // None when there is no player model in the assets, or until it has loaded.
+    player_character: Option<SkinnedModelData>,
+    loading_player_character: Option<Job<anyhow::Result<SkinnedModel>>>,
+    skinned_group_layout: wgpu::BindGroupLayout,
+    transient_textures: TransientTextures,
+    post_process: PostProcess,
+    ssao: Ssao,
+    particle_renderer: ParticleRenderer,
+    outline_renderer: OutlineRenderer,
+    debug_line_renderer: DebugLineRenderer,
+    instance_culler: InstanceCuller,
+    ui_renderer: UiRenderer,
+    last_frame: Instant,
 }
 impl WebGPUState {
     pub async fn new(window: HWND, hinstance: HINSTANCE, game_state: GameState) -> Self {
@@ -95,7 +300,7 @@ impl WebGPUState {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    features: InstanceCuller::wanted_features(&adapter),
                     label: None,
                     limits: wgpu::Limits::default(),
                 },
@@ -103,6 +308,8 @@ impl WebGPUState {
             )
             .await
             .unwrap();
+        gpu_errors::install(&device);
+        let (device, queue) = (Arc::new(device), Arc::new(queue));
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
             .formats
@@ -128,30 +335,7 @@ impl WebGPUState {
         };
         surface.configure(&device, &config);
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let texture_bind_group_layout = Arc::new(material::bind_group_layout(&device));
         let depth_texture = texture::create_depth_texture(&device, &config, "depth_texture");
 
         let camera_group = BindGroupData::<CameraUniform>::new(
@@ -162,102 +346,200 @@ impl WebGPUState {
             wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
         );
         let start_time = Instant::now();
-        let time_group = BindGroupData::<TimeUniform>::new(
-            TimeUniform::new(0.0),
+        let globals_group = BindGroupData::<GlobalsUniform>::new(
+            GlobalsUniform::new([config.width as f32, config.height as f32]),
             &device,
-            "Time",
+            "Globals",
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
         );
-        let light_group = BindGroupData::<LightUniform>::new(
-            LightUniform {
-                position: [2.0, 2.0, 2.0],
-                _padding: 0,
-                color: [1.0, 1.0, 1.0],
-                _padding2: 0,
-            },
+        let background_color = wgpu::Color { r: 0.2, g: 0.5, b: 0.3, a: 1.0 };
+        let environment_map = reflection::static_environment(&device, &queue);
+        let scene_group = BindGroupData::<SceneUniform>::with_resources(
+            SceneUniform::new([2.0, 2.0, 2.0], Fog::default(), fog_color(background_color)),
             &device,
-            "Light",
+            "Scene",
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             wgpu::ShaderStages::VERTEX_FRAGMENT,
+            &scene::environment_layout_entries(),
+            &[
+                wgpu::BindingResource::TextureView(&environment_map.view),
+                wgpu::BindingResource::Sampler(&environment_map.sampler),
+            ],
         );
 
-        let render_pipeline = {
+        let pipeline_cache = PipelineCache::new(
+            &device,
+            &[
+                &texture_bind_group_layout,
+                &camera_group.layout,
+                &scene_group.layout,
+                &globals_group.layout,
+            ],
+        );
+
+        // Same as the scene pipelines, except that group 3 also carries bone matrices.
+        let skinned_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    storage_buffer_layout_entry(1, wgpu::ShaderStages::VERTEX, true),
+                ],
+                label: Some("skinned_bind_group_layout"),
+            });
+        let skinned_pipeline = {
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("Render Pipeline Layout"),
+                label: Some("Skinned Pipeline Layout"),
                 bind_group_layouts: &[
                     &texture_bind_group_layout,
                     &camera_group.layout,
-                    &light_group.layout,
-                    &time_group.layout,
+                    &scene_group.layout,
+                    &skinned_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Shaders"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shaders.wgsl").into()),
-            };
             create_render_pipeline(
-                "Render Pipeline",
+                "Skinned Pipeline",
                 &device,
                 &layout,
-                config.format,
+                post_process::HDR_FORMAT,
                 Some(texture::DEPTH_FORMAT),
-                &[ModelVertex::describe_vb(), InstanceRaw::get_vertex_buffer_layout()],
-                shader,
-                "vs_main",
-                "fs_main",
+                Some(wgpu::BlendState::ALPHA_BLENDING),
+                &[SkinnedVertex::describe_vb(), InstanceRaw::describe_vb()],
+                wgpu::ShaderModuleDescriptor {
+                    label: Some("Shaders"),
+                    source: wgpu::ShaderSource::Wgsl(include_str!("shaders.wgsl").into()),
+                },
+                "vs_skinned",
+                Some("fs_main"),
             )
         };
+        // Models load on the job pool, so the first frames don't wait for them. Until a model is
+        // ready, its instances are drawn with a plain cube.
+        let loading_player_character = Some({
+            let device = Arc::clone(&device);
+            JobPool::global()
+                .spawn(move || pollster::block_on(model::load_skinned_model("player.glb", &device)))
+        });
+        let default_material = pollster::block_on(material::create_material(
+            "Default",
+            &MaterialDef::tiles(),
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+        ))
+        .unwrap();
+        let loading_models = MODEL_FILES
+            .iter()
+            .map(|&(index, file_name)| {
+                spawn_model_load(&device, &queue, &texture_bind_group_layout, index, file_name)
+            })
+            .collect();
 
-        let models = vec![
+        let mut transient_textures = TransientTextures::new(&device, width, height);
+        let post_process = PostProcess::new(&device, &mut transient_textures, config.format);
+        let ssao = Ssao::new(&device, &mut transient_textures, &depth_texture.view);
+        let particle_renderer = ParticleRenderer::new(&device, &game_state.get_camera());
+        let outline_renderer = OutlineRenderer::new(&device, &game_state.get_camera());
+        let sky_renderer = SkyRenderer::new(&device, &game_state.get_camera());
+        let debug_line_renderer = DebugLineRenderer::new(&device, &game_state.get_camera());
+        let mut instance_culler = InstanceCuller::new(&device, &game_state.get_camera());
+        let ui_renderer = UiRenderer::new(
+            &device,
+            &queue,
+            transient_textures.sampled_layout(),
+            config.format,
+            width,
+            height,
+        );
+
+        let camera_eye = game_state.get_camera().eye;
+        let mut models = vec![
+            // cube.obj, once loaded
             ModelData::new(
-                &device,
-                model::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-                    .await
-                    .unwrap(),
+                model::cube_model(&device),
                 &game_state.instanced_entities[0].instances,
+                camera_eye,
             ),
             // simple cube
             ModelData::new(
-                &device,
                 model::cube_model(&device),
                 &game_state.instanced_entities[1].instances,
+                camera_eye,
             ),
+            // sphere-flat.obj, once loaded
             ModelData::new(
-                &device,
-                model::load_model("sphere-flat.obj", &device, &queue, &texture_bind_group_layout)
-                    .await
-                    .unwrap(),
+                model::cube_model(&device),
                 &game_state.instanced_entities[2].instances,
+                camera_eye,
             ),
+            // sphere.obj, once loaded
             ModelData::new(
-                &device,
-                model::load_model("sphere.obj", &device, &queue, &texture_bind_group_layout)
-                    .await
-                    .unwrap(),
+                model::cube_model(&device),
                 &game_state.instanced_entities[3].instances,
+                camera_eye,
             ),
             ModelData::new(
-                &device,
                 model::double_cube_model(&device),
                 &game_state.instanced_entities[4].instances,
+                camera_eye,
+            ),
+            // level walls, boxes and fixtures
+            ModelData::new(
+                model::cube_model(&device),
+                &game_state.instanced_entities[5].instances,
+                camera_eye,
             ),
         ];
+        write_instances(&device, &queue, &mut instance_culler, models.iter_mut().collect());
 
         Self {
+            adapter_info: adapter.get_info(),
             surface,
+            uploader: Uploader::new(Arc::clone(&device)),
             device,
             queue,
             config,
-            render_pipeline,
-            background_color: wgpu::Color { r: 0.2, g: 0.5, b: 0.3, a: 1.0 },
+            pipeline_cache,
+            depth_prepass: true,
+            skinned_pipeline,
+            background_color,
             depth_texture,
             camera_group,
-            light_group,
-            start_time,
-            time_group,
+            scene_group,
+            sky_renderer,
+            sky: false,
+            clock: GameClock::new(start_time),
+            globals_group,
             models,
+            static_geometry: Arc::new(StaticGeometry::default()),
+            static_models: Vec::new(),
+            camera_eye,
+            loading_models,
+            texture_bind_group_layout,
+            default_material,
+            asset_watcher: AssetWatcher::new(),
+            player_character: None,
+            loading_player_character,
+            skinned_group_layout,
+            transient_textures,
+            post_process,
+            ssao,
+            particle_renderer,
+            outline_renderer,
+            debug_line_renderer,
+            instance_culler,
+            ui_renderer,
+            last_frame: start_time,
         }
     }
     pub fn resize(&mut self, rect: RECT) {
@@ -267,164 +549,709 @@ impl WebGPUState {
             self.config.width = (rect.right - rect.left) as u32;
             self.config.height = (rect.bottom - rect.top) as u32;
             self.surface.configure(&self.device, &self.config);
+            self.transient_textures.resize(&self.device, w, h);
+            self.ui_renderer.resize(&self.device, &self.queue, w, h);
+            self.globals_group.uniform.screen_size = [w as f32, h as f32];
         }
         self.depth_texture =
             texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+        self.ssao.resize(&self.device, &self.depth_texture.view);
     }
-    pub fn update_bg_color(&mut self, point: &POINT) {
-        self.background_color = wgpu::Color {
-            r: (point.x as f64) / 2560.0,
-            g: (point.y as f64) / 1440.0,
-            b: 0.5 + 0.25 * (point.x * point.y) as f64 / (2560.0 * 1440.0),
-            a: 1.0,
-        };
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+    pub fn surface_size(&self) -> (u32, u32) {
+        (self.config.width, self.config.height)
+    }
+    /// `cursor` is in pixels of the window's client area.
+    pub fn update_cursor(&mut self, cursor: POINT) {
+        self.globals_group.uniform.cursor = [cursor.x as f32, cursor.y as f32];
+        self.update_bg_color();
+    }
+    // Without a sky, the background follows the cursor across the screen.
+    fn update_bg_color(&mut self) {
+        let [x, y] = self.globals_group.uniform.cursor_fraction().map(f64::from);
+        self.background_color = wgpu::Color { r: x, g: y, b: 0.5 + 0.25 * x * y, a: 1.0 };
+        if !self.sky {
+            self.scene_group.uniform.fog_color = fog_color(self.background_color);
+            self.write_scene_uniform();
+        }
         // Not necessary anymore: new model is we repeatedly call render in a loop.
         // let _ = self.render();
     }
+    #[allow(unused)]
+    pub fn set_exposure_mode(&mut self, mode: ExposureMode) {
+        self.post_process.set_exposure_mode(mode);
+    }
+    #[allow(unused)]
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.post_process.set_anti_aliasing(anti_aliasing);
+    }
+    #[allow(unused)]
+    pub fn set_ssao_enabled(&mut self, enabled: bool) {
+        self.ssao.enabled = enabled;
+    }
+    #[allow(unused)]
+    pub fn set_depth_prepass(&mut self, enabled: bool) {
+        self.depth_prepass = enabled;
+    }
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.set_ssao_enabled(settings.ssao);
+        self.set_depth_prepass(settings.depth_prepass);
+        self.set_anti_aliasing(if settings.fxaa { AntiAliasing::Fxaa } else { AntiAliasing::None });
+        // The same parameters the post-process pass starts with.
+        self.set_exposure_mode(if settings.auto_exposure {
+            ExposureMode::Auto { key: 0.18, adaptation_speed: 1.5 }
+        } else {
+            ExposureMode::Manual(1.0)
+        });
+        self.set_vsync(settings.vsync);
+        self.post_process.set_palette(settings.palette);
+        let goal_patterns = (settings.palette != Palette::Standard) as u32;
+        if goal_patterns != self.scene_group.uniform.goal_patterns {
+            self.scene_group.uniform.goal_patterns = goal_patterns;
+            self.write_scene_uniform();
+        }
+    }
+    pub fn set_vsync(&mut self, vsync: bool) {
+        let present_mode =
+            if vsync { wgpu::PresentMode::AutoVsync } else { wgpu::PresentMode::AutoNoVsync };
+        if self.config.present_mode != present_mode {
+            self.config.present_mode = present_mode;
+            self.surface.configure(&self.device, &self.config);
+        }
+    }
     pub fn update_camera(&mut self, camera: Camera) {
         self.camera_group.uniform.update_view_proj(&camera);
-        self.queue.write_buffer(
-            &self.camera_group.buffer,
-            0,
-            bytemuck::cast_slice(&[self.camera_group.uniform]),
-        );
+        let uploader = &mut self.uploader;
+        uploader.upload(&self.camera_group.buffer, 0, &[self.camera_group.uniform]);
+        self.ssao.update_projection(uploader, &camera);
+        self.particle_renderer.update_camera(uploader, &camera);
+        self.outline_renderer.update_camera(uploader, &camera);
+        self.sky_renderer.update_camera(uploader, &camera);
+        self.debug_line_renderer.update_camera(uploader, &camera);
+        self.instance_culler.update_frustum(uploader, &camera);
+        self.camera_eye = camera.eye;
         // Not necessary anymore: new model is we repeatedly call render in a loop.
         // let _ = self.render();
     }
+    pub fn update_particles(&mut self, game_state: &GameState) {
+        self.particle_renderer.update_particles(
+            &self.device,
+            &self.queue,
+            &game_state.get_particles().to_raw(),
+        );
+    }
+    pub fn update_instances(&mut self, game_state: &GameState) {
+        self.reload_changed_models();
+        self.swap_in_loaded_models(game_state);
+        for (model_data, entity) in self.models.iter_mut().zip(&game_state.instanced_entities) {
+            model_data.set_instances(&entity.instances, self.camera_eye);
+        }
+        if !Arc::ptr_eq(&self.static_geometry, &game_state.static_geometry) {
+            self.static_geometry = game_state.static_geometry.clone();
+            self.static_models = static_batch::bake(&self.device, &self.static_geometry)
+                .into_iter()
+                .map(|(model, instance)| ModelData::new(model, &[instance], self.camera_eye))
+                .collect();
+        }
+        write_instances(
+            &self.device,
+            &self.queue,
+            &mut self.instance_culler,
+            self.models.iter_mut().chain(&mut self.static_models).collect(),
+        );
+    }
+    // The models of instanced entities, then the baked static geometry.
+    fn all_models(&self) -> impl Iterator<Item = &ModelData> {
+        self.models.iter().chain(&self.static_models)
+    }
+    pub fn update_outline(&mut self, game_state: &GameState) {
+        self.outline_renderer.update_target(&mut self.uploader, game_state.get_targeted_box());
+    }
+    pub fn update_debug_lines(&mut self, lines: &DebugLines) {
+        self.debug_line_renderer.update_lines(&self.device, &self.queue, lines);
+    }
+    /// Animates the shaders by the game's time, as of its snapshot made at `made_at`, passing at
+    /// `time_scale` times real time.
+    pub fn update_time(&mut self, game_state: &GameState, made_at: Instant, time_scale: f32) {
+        let tick = game_state.get_tick();
+        self.globals_group.uniform.ticks = tick.max(0) as u32;
+        self.clock.set(tick, made_at, time_scale);
+    }
+    // Lights the scene the way the game says, and fogs it towards the sky's horizon, or the
+    // background color without a sky.
+    pub fn update_scene(&mut self, game_state: &GameState) {
+        let environment = game_state.get_environment();
+        self.sky = environment.sky.is_some();
+        let fog = match environment.sky {
+            Some(sky) => {
+                self.sky_renderer.set_colors(&mut self.uploader, sky);
+                sky.horizon
+            }
+            None => fog_color(self.background_color),
+        };
+        let uniform = &mut self.scene_group.uniform;
+        let old = (uniform.light_position, uniform.light_color, uniform.fog_color);
+        if old == (environment.light_position, environment.light_color, fog) {
+            return;
+        }
+        uniform.light_position = environment.light_position;
+        uniform.light_color = environment.light_color;
+        uniform.fog_color = fog;
+        self.write_scene_uniform();
+    }
+    fn write_scene_uniform(&mut self) {
+        self.uploader.upload(&self.scene_group.buffer, 0, &[self.scene_group.uniform]);
+    }
+    pub fn update_ui(&mut self, layer: &UiLayer) {
+        self.ui_renderer.update(&self.device, &self.queue, layer);
+    }
+    pub fn update_hud(&mut self, layer: Option<&UiLayer>) {
+        self.ui_renderer.update_hud(&self.device, &self.queue, layer);
+    }
+    pub fn update_minimap(&mut self, grid: Option<&MinimapGrid>) {
+        self.ui_renderer.update_minimap(
+            &self.device,
+            &self.queue,
+            self.transient_textures.sampled_layout(),
+            grid,
+        );
+    }
+    // Starts loading again any model whose files have changed on disk. The current model stays
+    // until the new one is ready, and if the new one fails to load, it stays for good.
+    fn reload_changed_models(&mut self) {
+        for index in self.asset_watcher.poll(Instant::now()) {
+            if self.loading_models.iter().any(|(loading, _)| *loading == index) {
+                continue;
+            }
+            let Some(&(_, file_name)) = MODEL_FILES.iter().find(|(i, _)| *i == index) else {
+                continue;
+            };
+            log_println!("Reloading {}", file_name);
+            self.loading_models.push(spawn_model_load(
+                &self.device,
+                &self.queue,
+                &self.texture_bind_group_layout,
+                index,
+                file_name,
+            ));
+        }
+    }
+    // Replaces placeholders with the models that have finished loading since the last call.
+    fn swap_in_loaded_models(&mut self, game_state: &GameState) {
+        let mut loaded = Vec::new();
+        self.loading_models.retain_mut(|(index, job)| match job.try_take() {
+            Some(result) => {
+                loaded.push((*index, result.and_then(|model| model)));
+                false
+            }
+            None => true,
+        });
+        for (index, result) in loaded {
+            match result {
+                Ok(model) => {
+                    self.asset_watcher.watch(index, &model.sources);
+                    let instances = &game_state.instanced_entities[index].instances;
+                    self.models[index] = ModelData::new(model, instances, self.camera_eye);
+                }
+                Err(e) => {
+                    log_println!("Keeping the current model: {:?}", e);
+                }
+            }
+        }
+        if let Some(result) = self.loading_player_character.as_mut().and_then(Job::try_take) {
+            self.loading_player_character = None;
+            match result.and_then(|model| model) {
+                Ok(model) => {
+                    self.player_character = Some(SkinnedModelData::new(
+                        &self.device,
+                        &self.skinned_group_layout,
+                        &self.globals_group.buffer,
+                        model,
+                    ));
+                }
+                Err(e) => {
+                    log_println!("No player character: {}", e);
+                }
+            }
+        }
+    }
+    pub fn update_player_character(&mut self, game_state: &GameState) {
+        let Some(character) = &self.player_character else {
+            return;
+        };
+        let pose = game_state.get_player_pose();
+        let model = &character.model;
+        let clip =
+            model.clip_index(pose.clip).or(if model.clips.is_empty() { None } else { Some(0) });
+        let uploader = &mut self.uploader;
+        uploader.upload(&character.bone_buffer, 0, &model.bone_matrices(clip, pose.time_secs));
+        uploader.upload(&character.instance_buffer, 0, &[pose.instance.to_raw()]);
+    }
+    /// Submits the uploads made since the last frame on their own, for when the frame they were
+    /// for isn't going to be drawn.
+    pub fn flush_uploads(&mut self) {
+        self.uploader.flush(&self.queue);
+    }
+    // Submits `encoder`'s work after the uploads it may read.
+    fn submit(&mut self, encoder: wgpu::CommandEncoder) {
+        let uploads = self.uploader.finish();
+        self.queue.submit(uploads.into_iter().chain(std::iter::once(encoder.finish())));
+        self.uploader.recall();
+    }
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
+        let output = match self.surface.get_current_texture() {
+            Ok(output) => output,
+            Err(e) => {
+                self.flush_uploads();
+                return Err(e);
+            }
+        };
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
+        let now = Instant::now();
+        self.globals_group.uniform.time_secs = self.clock.secs_at(now);
+        self.uploader.upload(&self.globals_group.buffer, 0, &[self.globals_group.uniform]);
+        self.post_process.update(&mut self.uploader, (now - self.last_frame).as_secs_f32());
+        self.last_frame = now;
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.background_color),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: wgpu::StoreOp::Store,
-                    }),
-                    stencil_ops: None,
-                }),
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(1, &self.camera_group.bind_group, &[]);
-            render_pass.set_bind_group(2, &self.light_group.bind_group, &[]);
-            render_pass.set_bind_group(3, &self.time_group.bind_group, &[]);
-            let time = (Instant::now() - self.start_time).as_secs_f32();
-            self.queue.write_buffer(&self.time_group.buffer, 0, bytemuck::cast_slice(&[time]));
-
-            for model_data in &self.models {
-                render_pass.set_vertex_buffer(1, model_data.buffer.slice(..));
-                for mesh in &model_data.model.meshes {
-                    draw_mesh_instanced(
-                        &mut render_pass,
-                        mesh,
-                        if mesh.material.is_some() {
-                            Some(&model_data.model.materials[mesh.material.unwrap()])
-                        } else {
-                            None
-                        },
-                        0..model_data.instances.len() as u32,
-                    );
-                }
-            }
+            profiling::scope!("Render encode");
+            self.encode_frame(&mut encoder, &view);
+        }
+        {
+            profiling::scope!("Present");
+            self.submit(encoder);
+            output.present();
         }
-
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
 
         // BAD CODE ALERT: update the light's position each frame. I need to move this into the game
         // state. I'm just lazy right now.
-        // let old_position: cgmath::Vector3<_> = self.light_group.uniform.position.into();
-        // self.light_group.uniform.position =
+        // let old_position: cgmath::Vector3<_> = self.scene_group.uniform.light_position.into();
+        // self.scene_group.uniform.light_position =
         //     (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
         //         * old_position)
         //         .into();
         // self.queue.write_buffer(
-        //     &self.light_group.buffer,
+        //     &self.scene_group.buffer,
         //     0,
-        //     bytemuck::cast_slice(&[self.light_group.uniform]),
+        //     bytemuck::cast_slice(&[self.scene_group.uniform]),
         // );
 
         Ok(())
     }
+    // Records the sky and the scene into the HDR target, darkens it with SSAO, outlines the
+    // targeted box, adds particles and debug lines, then post-processes it into `output_view`
+    // and draws the UI on top.
+    fn encode_frame(&self, encoder: &mut wgpu::CommandEncoder, output_view: &wgpu::TextureView) {
+        const CULLED_INSTANCES: Resource = Resource::External("culled instances");
+        const DEPTH: Resource = Resource::External("depth");
+        const OUTPUT: Resource = Resource::External("output");
+        let hdr = Resource::Texture(self.post_process.hdr());
+        let mut graph = RenderGraph::new();
+        graph.add_pass("Compute Stage", &[], &[CULLED_INSTANCES], |encoder| {
+            self.encode_compute_stage(encoder)
+        });
+        if self.depth_prepass {
+            graph.add_pass("Depth Pre-pass", &[CULLED_INSTANCES], &[DEPTH], |encoder| {
+                self.encode_depth_prepass(encoder)
+            });
+        }
+        if self.sky {
+            graph.add_pass("Sky Pass", &[], &[hdr], |encoder| {
+                self.sky_renderer
+                    .encode(encoder, self.transient_textures.view(self.post_process.hdr()))
+            });
+        }
+        let mut scene_reads = vec![CULLED_INSTANCES];
+        if self.depth_prepass {
+            scene_reads.push(DEPTH);
+        }
+        if self.sky {
+            scene_reads.push(hdr);
+        }
+        graph.add_pass("Scene Pass", &scene_reads, &[hdr, DEPTH], |encoder| {
+            self.encode_scene_pass(encoder)
+        });
+        self.ssao.add_passes(&mut graph, &self.transient_textures, self.post_process.hdr(), DEPTH);
+        graph.add_pass("Outline Pass", &[hdr, DEPTH], &[hdr], |encoder| {
+            self.outline_renderer.encode(
+                encoder,
+                self.transient_textures.view(self.post_process.hdr()),
+                &self.depth_texture.view,
+            )
+        });
+        graph.add_pass("Particle Pass", &[hdr, DEPTH], &[hdr], |encoder| {
+            self.particle_renderer.encode(
+                encoder,
+                self.transient_textures.view(self.post_process.hdr()),
+                &self.depth_texture.view,
+            )
+        });
+        graph.add_pass("Debug Line Pass", &[hdr, DEPTH], &[hdr], |encoder| {
+            self.debug_line_renderer.encode(
+                encoder,
+                self.transient_textures.view(self.post_process.hdr()),
+                &self.depth_texture.view,
+            )
+        });
+        self.post_process.add_passes(&mut graph, &self.transient_textures, (OUTPUT, output_view));
+        graph.add_pass("Ui Pass", &[OUTPUT], &[OUTPUT], |encoder| {
+            self.ui_renderer.encode(encoder, output_view)
+        });
+        graph.execute(encoder, OUTPUT);
+    }
+    // Compute work that render passes later in the frame depend on. Anything added here runs
+    // before the scene pass, so it can produce instance, vertex or indirect buffers for it.
+    fn encode_compute_stage(&self, encoder: &mut wgpu::CommandEncoder) {
+        self.instance_culler.encode(encoder);
+    }
+    // Draws the depth of every opaque instance, without shading it, so the scene pass only runs
+    // each pixel's fragment shader for the surface that ends up in front. Blended instances are
+    // left out: what's behind them still has to be drawn.
+    fn encode_depth_prepass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Depth Pre-pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_pipeline(self.pipeline_cache.depth_prepass());
+        // Only the vertex stage runs, so the material makes no difference, but the layout needs
+        // one bound.
+        render_pass.set_bind_group(0, &self.default_material.bind_group, &[]);
+        render_pass.set_bind_group(1, &self.camera_group.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.scene_group.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.globals_group.bind_group, &[]);
+        let culler = &self.instance_culler;
+        if culler.first_instance_supported() {
+            render_pass.set_vertex_buffer(1, culler.instances().slice(..));
+        }
+        for (meshes, lod) in self.all_models().flat_map(ModelData::lods) {
+            let draws = &lod.draws;
+            let opaque_batches = lod.opaque_batches();
+            if opaque_batches == 0 {
+                continue;
+            }
+            for (mesh_index, mesh) in meshes.iter().enumerate() {
+                if culler.multi_draw_supported() {
+                    // A mesh's commands for consecutive batches are contiguous.
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    render_pass
+                        .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    render_pass.multi_draw_indexed_indirect(
+                        culler.commands(),
+                        draws.command_offset(mesh_index, 0),
+                        opaque_batches as u32,
+                    );
+                    continue;
+                }
+                for batch_index in 0..opaque_batches {
+                    bind_batch_instances(&mut render_pass, culler, draws, batch_index);
+                    draw_mesh_indirect(
+                        &mut render_pass,
+                        mesh,
+                        None,
+                        culler.commands(),
+                        draws.command_offset(mesh_index, batch_index),
+                    );
+                }
+            }
+        }
+    }
+    // Records the main scene pass (all models and their instances) into the HDR target. After the
+    // depth pre-pass, depth is already there, so it's kept rather than cleared, and likewise the
+    // sky is kept rather than cleared to the background color.
+    fn encode_scene_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.transient_textures.view(self.post_process.hdr()),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: if self.sky {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(self.background_color)
+                    },
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: if self.depth_prepass {
+                        wgpu::LoadOp::Load
+                    } else {
+                        wgpu::LoadOp::Clear(1.0)
+                    },
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_bind_group(1, &self.camera_group.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.scene_group.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.globals_group.bind_group, &[]);
+
+        let culler = &self.instance_culler;
+        if culler.first_instance_supported() {
+            render_pass.set_vertex_buffer(1, culler.instances().slice(..));
+        }
+        // Sorted by pipeline, so each is set once and blended variants draw last. Unlike the
+        // depth pre-pass, there's nothing for multi_draw_indexed_indirect to merge here: a LOD has
+        // one batch per variant, so every pipeline's commands for a mesh come down to one, and
+        // commands for different meshes need their own vertex, index and material bindings.
+        let mut draws = self
+            .all_models()
+            .flat_map(|model_data| {
+                model_data.lods().flat_map(move |(meshes, lod)| {
+                    lod.batches.iter().enumerate().map(move |(batch_index, batch)| {
+                        let key = PipelineKey::for_variant(batch.variant);
+                        (key, model_data, meshes, &lod.draws, batch_index)
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        draws.sort_by_key(|(key, ..)| *key);
+        let mut current_key = None;
+        for (key, model_data, meshes, lod_draws, batch_index) in draws {
+            if current_key != Some(key) {
+                render_pass.set_pipeline(self.pipeline_cache.get(key));
+                current_key = Some(key);
+            }
+            bind_batch_instances(&mut render_pass, culler, lod_draws, batch_index);
+            for (mesh_index, mesh) in meshes.iter().enumerate() {
+                draw_mesh_indirect(
+                    &mut render_pass,
+                    mesh,
+                    Some(match mesh.material {
+                        Some(index) => &model_data.model.materials[index],
+                        None => &self.default_material,
+                    }),
+                    culler.commands(),
+                    lod_draws.command_offset(mesh_index, batch_index),
+                );
+            }
+        }
+        if let Some(character) = &self.player_character {
+            // Groups 0-2 are compatible with the scene pipelines', so they stay bound.
+            render_pass.set_pipeline(&self.skinned_pipeline);
+            render_pass.set_bind_group(3, &character.bind_group, &[]);
+            render_pass.set_vertex_buffer(1, character.instance_buffer.slice(..));
+            for mesh in &character.model.meshes {
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(0..mesh.num_elements, 0, 0..1);
+            }
+        }
+    }
+    /// Color and depth at a single window pixel. See `read_region`.
+    #[allow(unused)]
+    pub fn read_pixel(&mut self, x: u32, y: u32) -> anyhow::Result<([u8; 4], f32)> {
+        let region = self.read_region(x, y, 1, 1)?;
+        Ok(region.pixel(0, 0))
+    }
+    /// Renders the current scene into an offscreen copy of the swapchain, then
+    /// reads back the `width` x `height` rectangle whose top-left corner is at window pixel
+    /// (`x`, `y`). Blocks until the GPU has finished, so this is meant for automated checks and
+    /// debugging, not for use every frame.
+    pub fn read_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<readback::Readback> {
+        anyhow::ensure!(
+            width > 0
+                && height > 0
+                && x + width <= self.config.width
+                && y + height <= self.config.height,
+            "readback region ({}, {}) {}x{} is outside of the {}x{} surface",
+            x,
+            y,
+            width,
+            height,
+            self.config.width,
+            self.config.height
+        );
+        let size = wgpu::Extent3d {
+            width: self.config.width,
+            height: self.config.height,
+            depth_or_array_layers: 1,
+        };
+        let color_texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Readback Color Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let color_view = color_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let color_layout = readback::BufferLayout::new(width, height, 4);
+        let depth_layout = readback::BufferLayout::new(width, height, 4);
+        let color_buffer = color_layout.create_buffer(&self.device, "Readback Color Buffer");
+        let depth_buffer = depth_layout.create_buffer(&self.device, "Readback Depth Buffer");
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Readback Encoder"),
+        });
+        self.encode_frame(&mut encoder, &color_view);
+        let origin = wgpu::Origin3d { x, y, z: 0 };
+        let copy_size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &color_texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            color_layout.image_copy_buffer(&color_buffer),
+            copy_size,
+        );
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &self.depth_texture.texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::DepthOnly,
+            },
+            depth_layout.image_copy_buffer(&depth_buffer),
+            copy_size,
+        );
+        self.submit(encoder);
+
+        let color_bytes = color_layout.map_and_read(&self.device, &color_buffer)?;
+        let depth_bytes = depth_layout.map_and_read(&self.device, &depth_buffer)?;
+        let bgra = matches!(
+            self.config.format,
+            wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        let colors = color_bytes
+            .chunks_exact(4)
+            .map(|c| if bgra { [c[2], c[1], c[0], c[3]] } else { [c[0], c[1], c[2], c[3]] })
+            .collect();
+        let depths = depth_bytes
+            .chunks_exact(4)
+            .map(|d| f32::from_ne_bytes([d[0], d[1], d[2], d[3]]))
+            .collect();
+        Ok(readback::Readback { width, height, colors, depths })
+    }
 }
 
-fn create_render_pipeline(
+pub(crate) fn create_render_pipeline(
     label: &str,
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
+    blend: Option<wgpu::BlendState>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
     vertex_entrypoint: &str,
-    fragment_entrypoint: &str,
-) -> wgpu::RenderPipeline {
-    let shader = device.create_shader_module(shader);
-    let vertex = wgpu::VertexState {
-        module: &shader,
-        entry_point: vertex_entrypoint,
-        buffers: vertex_layouts,
+    // None for depth-only pipelines.
+    fragment_entrypoint: Option<&str>,
+) -> Arc<wgpu::RenderPipeline> {
+    let wgpu::ShaderSource::Wgsl(source) = &shader.source else {
+        unreachable!("shaders are all WGSL");
     };
-    let color_target = [Some(wgpu::ColorTargetState {
-        format: color_format,
-        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-        write_mask: wgpu::ColorWrites::ALL,
-    })];
-    let fragment = Some(wgpu::FragmentState {
-        module: &shader,
-        entry_point: fragment_entrypoint,
-        targets: &color_target,
-    });
-    let primitive = wgpu::PrimitiveState {
-        topology: wgpu::PrimitiveTopology::TriangleList,
-        strip_index_format: None,
-        front_face: wgpu::FrontFace::Ccw,
-        cull_mode: Some(wgpu::Face::Back),
-        // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-        polygon_mode: wgpu::PolygonMode::Fill,
-        // Requires Features::DEPTH_CLIP_CONTROL
-        unclipped_depth: false,
-        // Requires Features::CONSERVATIVE_RASTERIZATION
-        conservative: false,
+    let key = gpu_cache::PipelineKey {
+        layout: layout.global_id(),
+        color_format,
+        depth_format,
+        blend,
+        vertex_layouts: gpu_cache::PipelineKey::vertex_layouts(vertex_layouts),
+        source: source.to_string(),
+        vertex_entrypoint: vertex_entrypoint.to_string(),
+        fragment_entrypoint: fragment_entrypoint.map(str::to_string),
     };
-    let depth_stencil = depth_format.map(|format| wgpu::DepthStencilState {
-        format,
-        depth_write_enabled: true,
-        depth_compare: wgpu::CompareFunction::Less,
-        stencil: wgpu::StencilState::default(),
-        bias: wgpu::DepthBiasState::default(),
-    });
-    let multisample =
-        wgpu::MultisampleState { count: 1, mask: !0, alpha_to_coverage_enabled: false };
-    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some(label),
-        layout: Some(layout),
-        vertex,
-        fragment,
-        primitive,
-        depth_stencil,
-        multisample,
-        multiview: None,
+    gpu_cache::render_pipeline(key, || {
+        gpu_errors::scoped(device, &format!("pipeline {}", label), || {
+            let shader = device.create_shader_module(shader);
+            let vertex = wgpu::VertexState {
+                module: &shader,
+                entry_point: vertex_entrypoint,
+                buffers: vertex_layouts,
+            };
+            let color_target = [Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })];
+            let fragment = fragment_entrypoint.map(|entry_point| wgpu::FragmentState {
+                module: &shader,
+                entry_point,
+                targets: &color_target,
+            });
+            let primitive = wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
+                polygon_mode: wgpu::PolygonMode::Fill,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            };
+            let depth_stencil = depth_format.map(|format| wgpu::DepthStencilState {
+                format,
+                depth_write_enabled: true,
+                // Not Less, so the scene pass can draw over depth laid down by the depth pre-pass.
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            });
+            let multisample =
+                wgpu::MultisampleState { count: 1, mask: !0, alpha_to_coverage_enabled: false };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex,
+                fragment,
+                primitive,
+                depth_stencil,
+                multisample,
+                multiview: None,
+            })
+        })
     })
 }
 
+// Without indirect first instance, every command starts at instance 0, so each batch's instances
+// are bound at their own offset. Otherwise they're all bound once, at the start of the pass.
+fn bind_batch_instances<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    culler: &'a InstanceCuller,
+    draws: &ModelDraws,
+    batch_index: usize,
+) {
+    if !culler.first_instance_supported() {
+        render_pass.set_vertex_buffer(1, culler.batch_instances(draws.first_instance(batch_index)));
+    }
+}
+
 #[allow(unused)]
 fn draw_mesh<'a>(
     render_pass: &mut wgpu::RenderPass<'a>,
@@ -433,6 +1260,7 @@ fn draw_mesh<'a>(
 ) {
     draw_mesh_instanced(render_pass, mesh, material, 0..1);
 }
+#[allow(unused)]
 fn draw_mesh_instanced<'a>(
     render_pass: &mut wgpu::RenderPass<'a>,
     mesh: &'a Mesh,
@@ -446,6 +1274,22 @@ fn draw_mesh_instanced<'a>(
     }
     render_pass.draw_indexed(0..mesh.num_elements, 0, instances);
 }
+// Like `draw_mesh_instanced`, but the instance count comes from a DrawIndexedIndirect in
+// `indirect_buffer` at `indirect_offset`, typically written by a compute pass.
+fn draw_mesh_indirect<'a>(
+    render_pass: &mut wgpu::RenderPass<'a>,
+    mesh: &'a Mesh,
+    material: Option<&'a Material>,
+    indirect_buffer: &'a wgpu::Buffer,
+    indirect_offset: wgpu::BufferAddress,
+) {
+    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+    render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+    if material.is_some() {
+        render_pass.set_bind_group(0, &material.unwrap().bind_group, &[]);
+    }
+    render_pass.draw_indexed_indirect(indirect_buffer, indirect_offset);
+}
 
 // Data for the graphics pipeline.
 #[repr(C)]
@@ -455,49 +1299,104 @@ pub struct InstanceRaw {
     pub scale: f32,
     pub rot: [f32; 4],
     pub shader: u32,
+    pub tint: [f32; 4],
+    pub layer: u32,
 }
-impl InstanceRaw {
-    fn get_vertex_buffer_layout() -> wgpu::VertexBufferLayout<'static> {
+// culling.wgsl copies instances as InstanceStride plain floats, and the attributes below assume
+// the fields are packed in declaration order.
+const _: () = assert!(mem::size_of::<InstanceRaw>() == 14 * mem::size_of::<f32>());
+const _: () = assert!(mem::offset_of!(InstanceRaw, tint) == 9 * mem::size_of::<f32>());
+impl DescribeVB for InstanceRaw {
+    fn describe_vb() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
             // We need to switch from using a step mode of Vertex to Instance
             // This means that our shaders will only change to use the next
             // instance when the shader starts processing a new instance
             step_mode: wgpu::VertexStepMode::Instance,
             attributes: &[
                 wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials,
-                    // we'll be using 2, 3, and 4, for Vertex. We'll start at
-                    // slot 5, not conflict with them later.
+                    offset: mem::offset_of!(InstanceRaw, pos) as wgpu::BufferAddress,
+                    // Vertices use locations 0 to 4, so instances start at 5.
                     shader_location: 5,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    offset: mem::offset_of!(InstanceRaw, scale) as wgpu::BufferAddress,
                     shader_location: 6,
                     format: wgpu::VertexFormat::Float32,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    offset: mem::offset_of!(InstanceRaw, rot) as wgpu::BufferAddress,
                     shader_location: 7,
                     format: wgpu::VertexFormat::Float32x4,
                 },
                 wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    offset: mem::offset_of!(InstanceRaw, shader) as wgpu::BufferAddress,
                     shader_location: 8,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                wgpu::VertexAttribute {
+                    offset: mem::offset_of!(InstanceRaw, tint) as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::offset_of!(InstanceRaw, layer) as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Uint32,
+                },
             ],
         }
     }
 }
 
-struct BindGroupData<T> {
+pub(crate) fn create_compute_pipeline(
+    label: &str,
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: wgpu::ShaderModuleDescriptor,
+    entrypoint: &str,
+) -> wgpu::ComputePipeline {
+    let shader = device.create_shader_module(shader);
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        module: &shader,
+        entry_point: entrypoint,
+    })
+}
+
+// Layout entry for a storage buffer, for use in a BindGroupLayoutDescriptor.
+pub(crate) fn storage_buffer_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// Fog fades to the clear color, so far surfaces and the background meet without a seam.
+fn fog_color(background_color: wgpu::Color) -> [f32; 3] {
+    [background_color.r as f32, background_color.g as f32, background_color.b as f32]
+}
+
+pub(crate) struct BindGroupData<T> {
     pub uniform: T,
     pub buffer: wgpu::Buffer,
     pub layout: wgpu::BindGroupLayout,
     pub bind_group: wgpu::BindGroup,
+    // Only held, to count the buffer for as long as it lives.
+    _memory: Allocation,
 }
 impl<T: bytemuck::Pod> BindGroupData<T> {
     pub fn new(
@@ -506,6 +1405,19 @@ impl<T: bytemuck::Pod> BindGroupData<T> {
         label: &str,
         usage: wgpu::BufferUsages,
         visibility: wgpu::ShaderStages,
+    ) -> BindGroupData<T> {
+        Self::with_resources(uniform, device, label, usage, visibility, &[], &[])
+    }
+    /// Like `new`, with `resources` bound after the uniform from binding 1 on, laid out as
+    /// `layout_entries`, which should number them the same way.
+    pub fn with_resources(
+        uniform: T,
+        device: &wgpu::Device,
+        label: &str,
+        usage: wgpu::BufferUsages,
+        visibility: wgpu::ShaderStages,
+        layout_entries: &[wgpu::BindGroupLayoutEntry],
+        resources: &[wgpu::BindingResource],
     ) -> BindGroupData<T> {
         let uniform = uniform;
         let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -513,24 +1425,33 @@ impl<T: bytemuck::Pod> BindGroupData<T> {
             contents: bytemuck::cast_slice(&[uniform]),
             usage: usage,
         });
+        let memory = Allocation::new(Category::Uniforms, buffer.size());
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout_entries: Vec<_> =
+            std::iter::once(uniform_entry).chain(layout_entries.iter().cloned()).collect();
         let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[wgpu::BindGroupLayoutEntry {
-                binding: 0,
-                visibility,
-                ty: wgpu::BindingType::Buffer {
-                    ty: wgpu::BufferBindingType::Uniform,
-                    has_dynamic_offset: false,
-                    min_binding_size: None,
-                },
-                count: None,
-            }],
+            entries: &layout_entries,
             label: Some(&format!("{} Bind Group Layout", label)),
         });
+        let entries: Vec<_> = std::iter::once(buffer.as_entire_binding())
+            .chain(resources.iter().cloned())
+            .enumerate()
+            .map(|(binding, resource)| wgpu::BindGroupEntry { binding: binding as u32, resource })
+            .collect();
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some(&format!("{} Bind Group", label)),
             layout: &layout,
-            entries: &[wgpu::BindGroupEntry { binding: 0, resource: buffer.as_entire_binding() }],
+            entries: &entries,
         });
-        BindGroupData { uniform, buffer, layout, bind_group }
+        BindGroupData { uniform, buffer, layout, bind_group, _memory: memory }
     }
 }
@@ -0,0 +1,149 @@
+/* Headless: run the game simulation without a window or WebGPUState, fed by scripted input. */
+use crate::{
+    game_state::{GameState, InputState},
+    tick_rate,
+};
+
+use anyhow::{anyhow, bail, Context};
+use std::time::Instant;
+
+// Aspect ratio doesn't affect the simulation, but the camera still wants one.
+pub const HEADLESS_ASPECT_RATIO: f32 = 16.0 / 9.0;
+
+#[derive(Clone)]
+pub struct ScriptStep {
+    pub ticks: u32,
+    pub input: InputState,
+}
+
+/// A sequence of inputs, each held for some number of game ticks.
+///
+/// The text format is one step per line: a tick count followed by any of `forward`, `backward`,
+/// `left`, `right`, `jump`, `undo`, `restart`, `mouse=<dx>,<dy>` and `wheel=<notches>`. The input
+/// is applied on every tick of the step, so `mouse=` and `wheel=` are per-tick deltas. Blank lines
+/// and `#` comments are ignored.
+#[derive(Clone, Default)]
+pub struct InputScript {
+    pub steps: Vec<ScriptStep>,
+}
+impl InputScript {
+    pub fn new() -> Self {
+        InputScript::default()
+    }
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut steps = Vec::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let step = Self::parse_step(line)
+                .with_context(|| format!("input script line {}", line_number + 1))?;
+            steps.push(step);
+        }
+        Ok(InputScript { steps })
+    }
+    fn parse_step(line: &str) -> anyhow::Result<ScriptStep> {
+        let mut words = line.split_whitespace();
+        let ticks = words.next().unwrap().parse::<u32>()?;
+        let mut input = InputState::new();
+        for word in words {
+            match word {
+                "forward" => input.forward = true,
+                "backward" => input.backward = true,
+                "left" => input.left = true,
+                "right" => input.right = true,
+                "jump" => input.jump = true,
+                "undo" => input.undo = true,
+                "restart" => input.restart = true,
+                "interact" => input.interact = true,
+                _ if word.starts_with("wheel=") => {
+                    input.wheel = word["wheel=".len()..].parse()?;
+                }
+                _ => {
+                    let (dx, dy) = word
+                        .strip_prefix("mouse=")
+                        .and_then(|delta| delta.split_once(','))
+                        .ok_or_else(|| anyhow!("unrecognized input '{}'", word))?;
+                    input.mouse_x = dx.parse()?;
+                    input.mouse_y = dy.parse()?;
+                }
+            }
+        }
+        Ok(ScriptStep { ticks, input })
+    }
+    /// Appends one tick of input, extending the last step if the input didn't change.
+    pub fn push_tick(&mut self, input: &InputState) {
+        match self.steps.last_mut() {
+            Some(step) if step.input == *input => step.ticks += 1,
+            _ => self.steps.push(ScriptStep { ticks: 1, input: input.clone() }),
+        }
+    }
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for step in &self.steps {
+            text += &step.ticks.to_string();
+            let input = &step.input;
+            for (held, name) in [
+                (input.forward, "forward"),
+                (input.backward, "backward"),
+                (input.left, "left"),
+                (input.right, "right"),
+                (input.jump, "jump"),
+                (input.undo, "undo"),
+                (input.restart, "restart"),
+                (input.interact, "interact"),
+            ] {
+                if held {
+                    text += " ";
+                    text += name;
+                }
+            }
+            if input.mouse_x != 0 || input.mouse_y != 0 {
+                text += &format!(" mouse={},{}", input.mouse_x, input.mouse_y);
+            }
+            if input.wheel != 0 {
+                text += &format!(" wheel={}", input.wheel);
+            }
+            text += "\n";
+        }
+        text
+    }
+    pub fn total_ticks(&self) -> u64 {
+        self.steps.iter().map(|step| step.ticks as u64).sum()
+    }
+}
+
+/// Ticks `game_state` through every step of `script`, exactly as the game thread would, but with
+/// tick instants synthesized from `start` instead of read from the clock.
+pub fn run(game_state: &mut GameState, script: &InputScript, start: Instant) {
+    let mut tick_instant = start;
+    for step in &script.steps {
+        for _ in 0..step.ticks {
+            tick_instant += tick_rate::time_per_tick();
+            game_state.update(&step.input, tick_instant);
+        }
+    }
+}
+
+/// Entry point for `cube --headless <script>`: runs the script from a fresh `GameState` and prints
+/// the final state.
+pub fn run_from_args(args: &[String]) -> anyhow::Result<GameState> {
+    let Some(script_path) = args.first() else {
+        bail!("usage: cube --headless <input script>");
+    };
+    let text = std::fs::read_to_string(script_path)
+        .with_context(|| format!("reading input script {}", script_path))?;
+    let script = InputScript::parse(&text)?;
+
+    println!("Running {} ticks headless", script.total_ticks());
+    let mut game_state = GameState::new(HEADLESS_ASPECT_RATIO);
+    run(&mut game_state, &script, Instant::now());
+
+    let position = game_state.get_player_position();
+    let direction = game_state.get_camera().direction;
+    println!("ticks: {}", game_state.get_tick());
+    println!("player position: ({}, {}, {})", position.x, position.y, position.z);
+    println!("camera direction: ({}, {}, {})", direction.x, direction.y, direction.z);
+    Ok(game_state)
+}
@@ -0,0 +1,51 @@
+/* Hot reload: noticing when asset files change on disk, so they can be rebuilt without
+ * restarting. */
+use crate::resources;
+
+use std::time::{Duration, Instant, SystemTime};
+
+// Checking modification times is cheap, but not free enough to do every frame.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Polls the modification times of the files each key was built from. `K` identifies whatever
+/// should be rebuilt when one of its files changes.
+pub struct AssetWatcher<K> {
+    files: Vec<(K, String, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+impl<K: Copy + PartialEq> AssetWatcher<K> {
+    pub fn new() -> Self {
+        AssetWatcher { files: Vec::new(), last_poll: Instant::now() }
+    }
+    /// Replaces the files watched for `key`, taking their current state as unchanged.
+    pub fn watch(&mut self, key: K, files: &[String]) {
+        self.files.retain(|(watched, _, _)| *watched != key);
+        for file in files {
+            self.files.push((key, file.clone(), resources::modified_time(file)));
+        }
+    }
+    /// The keys with a file modified since the last poll. Polls at most every POLL_INTERVAL; in
+    /// between, nothing has changed.
+    pub fn poll(&mut self, now: Instant) -> Vec<K> {
+        if now - self.last_poll < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = now;
+        let mut changed = Vec::new();
+        for (key, file, modified) in &mut self.files {
+            let latest = resources::modified_time(file);
+            if latest != *modified {
+                *modified = latest;
+                if !changed.contains(key) {
+                    changed.push(*key);
+                }
+            }
+        }
+        changed
+    }
+}
+impl<K: Copy + PartialEq> Default for AssetWatcher<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,199 @@
+/* Input: key presses and mouse movement as they arrive from the window, stamped with when they
+ * happened, and handed to the game one tick's worth at a time. */
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{game_state::InputState, settings::Control};
+
+/// How a button changed from one tick's input to the next.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ButtonPhase {
+    Up,
+    // Down this tick, but not the last.
+    Pressed,
+    Held,
+    // Down the last tick, but not this one.
+    Released,
+}
+impl ButtonPhase {
+    pub fn is_down(self) -> bool {
+        matches!(self, ButtonPhase::Pressed | ButtonPhase::Held)
+    }
+}
+
+/// A button of `InputState`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Button {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Jump,
+    Undo,
+    Restart,
+    Interact,
+    Confirm,
+    Back,
+}
+impl Button {
+    // Whether the button stays down in the input for as long as its key is held, rather than
+    // counting once per press.
+    fn is_held(self) -> bool {
+        matches!(
+            self,
+            Button::Forward | Button::Backward | Button::Left | Button::Right | Button::Interact
+        )
+    }
+    pub fn is_down(self, input: &InputState) -> bool {
+        match self {
+            Button::Forward => input.forward,
+            Button::Backward => input.backward,
+            Button::Left => input.left,
+            Button::Right => input.right,
+            Button::Jump => input.jump,
+            Button::Undo => input.undo,
+            Button::Restart => input.restart,
+            Button::Interact => input.interact,
+            Button::Confirm => input.confirm,
+            Button::Back => input.back,
+        }
+    }
+    /// The button's phase going from the `previous` tick's input to `input`. Buttons that count
+    /// once per press, like Jump, are Pressed on each tick they are down, and never Held.
+    pub fn phase(self, previous: &InputState, input: &InputState) -> ButtonPhase {
+        match (self.is_down(previous) && self.is_held(), self.is_down(input)) {
+            (false, false) => ButtonPhase::Up,
+            (false, true) => ButtonPhase::Pressed,
+            (true, true) => ButtonPhase::Held,
+            (true, false) => ButtonPhase::Released,
+        }
+    }
+    fn set(self, input: &mut InputState, down: bool) {
+        let field = match self {
+            Button::Forward => &mut input.forward,
+            Button::Backward => &mut input.backward,
+            Button::Left => &mut input.left,
+            Button::Right => &mut input.right,
+            Button::Jump => &mut input.jump,
+            Button::Undo => &mut input.undo,
+            Button::Restart => &mut input.restart,
+            Button::Interact => &mut input.interact,
+            Button::Confirm => &mut input.confirm,
+            Button::Back => &mut input.back,
+        };
+        *field = down;
+    }
+}
+impl From<Control> for Button {
+    fn from(control: Control) -> Self {
+        match control {
+            Control::Forward => Button::Forward,
+            Control::Backward => Button::Backward,
+            Control::Left => Button::Left,
+            Control::Right => Button::Right,
+            Control::Jump => Button::Jump,
+            Control::Undo => Button::Undo,
+            Control::Restart => Button::Restart,
+            Control::Interact => Button::Interact,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    Press(Button),
+    Release(Button),
+    // Mouse movement, in pixels.
+    Mouse { dx: i32, dy: i32 },
+    // Whole wheel notches, positive when rolled away from the player.
+    Wheel(i32),
+}
+
+/// Input events in the order they happened, waiting for the tick they belong to.
+///
+/// Each tick gets exactly the events stamped within its window, between the end of the last tick
+/// and its own end, however late the game gets to it. A key tapped and let go within one window
+/// still counts for that tick, and a press is never seen by two.
+#[derive(Clone)]
+pub struct InputBuffer {
+    events: VecDeque<(Instant, InputEvent)>,
+    // The held buttons that were down at the end of the last tick taken.
+    held: InputState,
+}
+impl InputBuffer {
+    pub fn new() -> Self {
+        InputBuffer { events: VecDeque::new(), held: InputState::new() }
+    }
+    /// Queues `event`, which happened `at`. Events have to be pushed in the order they happened.
+    pub fn push(&mut self, at: Instant, event: InputEvent) {
+        self.events.push_back((at, event));
+    }
+    /// The input of the tick that ends at `tick_end`, made from the events up to then, which are
+    /// taken out of the buffer. A held button counts as down for the tick if it was down at any
+    /// point in it.
+    pub fn take_tick(&mut self, tick_end: Instant) -> InputState {
+        let mut input = self.held.clone();
+        while let Some(&(at, event)) = self.events.front() {
+            if at > tick_end {
+                break;
+            }
+            self.events.pop_front();
+            match event {
+                InputEvent::Press(button) => {
+                    button.set(&mut input, true);
+                    if button.is_held() {
+                        button.set(&mut self.held, true);
+                    }
+                }
+                InputEvent::Release(button) => {
+                    if button.is_held() {
+                        button.set(&mut self.held, false);
+                    }
+                }
+                InputEvent::Mouse { dx, dy } => {
+                    input.mouse_x += dx;
+                    input.mouse_y += dy;
+                }
+                InputEvent::Wheel(notches) => input.wheel += notches,
+            }
+        }
+        input
+    }
+}
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Which keys are down, by virtual key code, to tell a key's first press from the repeats the
+/// window sends while it is held.
+#[derive(Clone)]
+pub struct KeyTable {
+    down: [bool; 256],
+}
+impl KeyTable {
+    pub fn new() -> Self {
+        KeyTable { down: [false; 256] }
+    }
+    /// Marks `key` down. Returns whether it was up, which makes this a press rather than a repeat.
+    pub fn press(&mut self, key: u16) -> bool {
+        self.down.get_mut(key as usize).is_some_and(|down| !std::mem::replace(down, true))
+    }
+    /// Marks `key` up. Returns whether it was down.
+    pub fn release(&mut self, key: u16) -> bool {
+        self.down.get_mut(key as usize).is_some_and(|down| std::mem::replace(down, false))
+    }
+    pub fn is_down(&self, key: u16) -> bool {
+        self.down.get(key as usize).copied().unwrap_or(false)
+    }
+    /// Marks every key up, as when the window loses focus and won't hear their releases. Returns
+    /// the keys that were down, to release whatever they held.
+    pub fn release_all(&mut self) -> Vec<u16> {
+        (0..self.down.len() as u16).filter(|&key| self.release(key)).collect()
+    }
+}
+impl Default for KeyTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,97 @@
+/* Jobs: a work-stealing pool for background work that would stall the game or render thread. */
+use anyhow::anyhow;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, OnceLock};
+use std::thread::Thread;
+
+// The game and render threads keep a core each.
+const RESERVED_THREADS: usize = 2;
+
+// The pool's threads are named this followed by their index.
+const THREAD_NAME_PREFIX: &str = "job-";
+
+static POOL: OnceLock<JobPool> = OnceLock::new();
+
+/// A job's result, once it's done.
+pub struct Job<T> {
+    receiver: mpsc::Receiver<std::thread::Result<T>>,
+}
+impl<T> Job<T> {
+    /// The result if the job has finished, without waiting, or an error if it panicked. It is
+    /// only returned once.
+    pub fn try_take(&mut self) -> Option<anyhow::Result<T>> {
+        self.receiver.try_recv().ok().map(Self::check)
+    }
+    /// Blocks until the job finishes. Fails if the job panicked.
+    pub fn wait(self) -> anyhow::Result<T> {
+        Self::check(self.receiver.recv().map_err(|_| anyhow!("job dropped before finishing"))?)
+    }
+    fn check(result: std::thread::Result<T>) -> anyhow::Result<T> {
+        result.map_err(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("no message");
+            anyhow!("job panicked: {}", message)
+        })
+    }
+}
+
+/// Whether `thread` is one of the pool's. A panic there is caught and handed to whoever waits
+/// on the job, so it doesn't bring the game down.
+pub fn is_job_thread(thread: &Thread) -> bool {
+    thread.name().is_some_and(|name| name.starts_with(THREAD_NAME_PREFIX))
+}
+
+pub struct JobPool {
+    pool: rayon::ThreadPool,
+}
+impl JobPool {
+    /// The shared pool, started on first use with a thread for each core beyond the game and
+    /// render threads' own.
+    pub fn global() -> &'static JobPool {
+        POOL.get_or_init(|| {
+            let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+            JobPool::new((cores.saturating_sub(RESERVED_THREADS)).max(1))
+        })
+    }
+    pub fn new(threads: usize) -> Self {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("{}{}", THREAD_NAME_PREFIX, i))
+            .build()
+            .expect("failed to start the job threads");
+        JobPool { pool }
+    }
+    /// Runs `job` on one of the pool's threads. Idle threads steal queued jobs from busy ones. If
+    /// `job` panics, the panic is caught and the job's `wait` or `try_take` reports it.
+    pub fn spawn<T, F>(&self, job: F) -> Job<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        self.pool.spawn(move || {
+            // Left to unwind, a panic would make rayon abort the whole process.
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            // The receiver may have been dropped by a caller that stopped caring.
+            let _ = sender.send(result);
+        });
+        Job { receiver }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panicking_job_reports_an_error() {
+        let pool = JobPool::new(1);
+        let error = pool.spawn(|| -> u32 { panic!("bad pack") }).wait().unwrap_err();
+        assert!(error.to_string().contains("bad pack"));
+        // The pool's thread survives to run the next job.
+        assert_eq!(pool.spawn(|| 7).wait().unwrap(), 7);
+    }
+}
@@ -0,0 +1,258 @@
+/* Joints: hinges and sliders tying physics bodies to each other or to the world, for
+ * contraptions like swinging doors, lifts on rails and rope bridges. */
+use cgmath::{EuclideanSpace, InnerSpace, Rad, Vector3, Zero};
+
+use crate::{
+    animation::InstanceRef,
+    determinism::StateHasher,
+    physics::{Physics, SLEEP_SECS, SLEEP_SPEED},
+    rotor::Rotor,
+    tick_rate,
+};
+
+// Each tick is stepped in SUBSTEPS smaller steps, each followed by SOLVER_ITERATIONS passes over
+// every joint. A pass fixes one joint at a time, undoing a little of what the others did, and
+// chains come out straight far sooner from more, shorter steps than from more passes.
+const SUBSTEPS: u32 = 8;
+const SOLVER_ITERATIONS: u32 = 4;
+// The fraction of their velocity that joined bodies keep each substep, for the friction in their
+// joints. Without it a chain would swing for ever, and never fall asleep.
+const DAMPING: f32 = 0.99;
+
+/// One end of a joint.
+#[derive(Clone, Copy, Debug)]
+pub enum Anchor {
+    /// A point fixed in the world, which nothing can move.
+    World(Vector3<f32>),
+    /// A point on `bodies[index]`, in the body's own space.
+    Body { index: usize, offset: Vector3<f32> },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum JointKind {
+    /// Holds the two anchors together and turns the body only about `axis`, like a door on its
+    /// hinges or a link of a chain. The axis is in world space, so it doesn't turn with what the
+    /// body hangs from, and must be normalized.
+    Hinge { axis: Vector3<f32> },
+    /// Lets the body's anchor move only along `axis` from the other anchor, between `min` and
+    /// `max` along it, like a lift on a rail. The body doesn't turn. The axis is in the other
+    /// body's space, or world space for a world anchor, and must be normalized.
+    Slider { axis: Vector3<f32>, min: f32, max: f32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Joint {
+    pub a: Anchor,
+    // The body the joint holds to `a`, and the point on it that is held, in its own space.
+    pub b: usize,
+    pub offset_b: Vector3<f32>,
+    pub kind: JointKind,
+}
+
+/// Bodies and the joints between them, stepped together. Joints are solved by moving and turning
+/// the bodies back onto them after each step, heavier bodies less, and then taking the velocities
+/// from where they ended up, so a joint's pull never has to be worked out as a force.
+///
+/// A contraption sleeps as a whole, rather than its bodies each on their own, since a body can't
+/// stay put while what it is tied to moves.
+#[derive(Clone)]
+pub struct Contraption {
+    pub bodies: Vec<Physics>,
+    // Each body's moment of inertia, the same about every axis.
+    inertias: Vec<f32>,
+    // The instance drawn for each body.
+    pub targets: Vec<InstanceRef>,
+    pub joints: Vec<Joint>,
+    // Ticks in a row every body started still, up to SLEEP_SECS worth, when the contraption is
+    // asleep.
+    still_ticks: u32,
+}
+impl Contraption {
+    pub fn new() -> Self {
+        Contraption {
+            bodies: Vec::new(),
+            inertias: Vec::new(),
+            targets: Vec::new(),
+            joints: Vec::new(),
+            still_ticks: 0,
+        }
+    }
+    pub fn is_sleeping(&self) -> bool {
+        self.still_ticks >= tick_rate::ticks(SLEEP_SECS)
+    }
+    pub fn wake(&mut self) {
+        self.still_ticks = 0;
+    }
+    /// Pushes `bodies[index]`, waking the contraption if the force isn't zero.
+    pub fn apply_force(&mut self, index: usize, force: Vector3<f32>) {
+        if !force.is_zero() {
+            self.wake();
+        }
+        self.bodies[index].apply_force(force);
+    }
+    /// Returns the body's index, for joints to refer to it by. `inertia` is how hard the body is
+    /// to turn: a cube of mass `m` and side `s` has `m s² / 6`.
+    pub fn add_body(&mut self, physics: Physics, inertia: f32, target: InstanceRef) -> usize {
+        self.bodies.push(physics);
+        self.inertias.push(inertia);
+        self.targets.push(target);
+        self.bodies.len() - 1
+    }
+    pub fn add_joint(&mut self, joint: Joint) {
+        self.joints.push(joint);
+    }
+    /// Steps every body with the forces applied to it since the last `reset_forces`, then puts the
+    /// bodies back onto their joints. Does nothing while the contraption sleeps.
+    pub fn update(&mut self, delta_t: f32, max_vel: f32) {
+        if self.is_sleeping() {
+            return;
+        }
+        let still = self.bodies.iter().all(|body| {
+            body.velocity.magnitude() < SLEEP_SPEED
+                && body.angular_velocity.magnitude() < SLEEP_SPEED
+        });
+        self.still_ticks = if still { self.still_ticks + 1 } else { 0 };
+        if self.is_sleeping() {
+            for body in &mut self.bodies {
+                body.velocity = Vector3::zero();
+                body.angular_velocity = Vector3::zero();
+            }
+            return;
+        }
+        for _ in 0..SUBSTEPS {
+            self.step(delta_t / SUBSTEPS as f32, max_vel);
+        }
+    }
+    fn step(&mut self, delta_t: f32, max_vel: f32) {
+        let before =
+            self.bodies.iter().map(|body| (body.position, body.rotation)).collect::<Vec<_>>();
+        for body in &mut self.bodies {
+            body.update(delta_t, max_vel);
+            let spin = body.angular_velocity * delta_t;
+            if !spin.is_zero() {
+                body.rotation =
+                    Rotor::from_axis_angle(spin.normalize(), Rad(spin.magnitude())) * body.rotation;
+            }
+        }
+        for _ in 0..SOLVER_ITERATIONS {
+            for joint in &self.joints {
+                solve(&mut self.bodies, &self.inertias, joint);
+            }
+        }
+        for (body, (position, rotation)) in self.bodies.iter_mut().zip(before) {
+            body.velocity = (body.position - position) * (DAMPING / delta_t);
+            body.angular_velocity = turn(body.rotation * rotation.inverse()) * (DAMPING / delta_t);
+        }
+    }
+    pub fn reset_forces(&mut self) {
+        for body in &mut self.bodies {
+            body.reset_forces();
+        }
+    }
+    /// Where each body is drawn, with the rotation its joints gave it.
+    pub fn transforms(&self) -> impl Iterator<Item = (InstanceRef, Vector3<f32>, Rotor)> + '_ {
+        self.targets
+            .iter()
+            .zip(&self.bodies)
+            .map(|(&target, body)| (target, body.position.to_vec(), body.rotation))
+    }
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        for body in &self.bodies {
+            hasher.write_f32s(&Into::<[f32; 3]>::into(body.position));
+            hasher.write_f32s(&Into::<[f32; 3]>::into(body.velocity));
+            hasher.write_f32s(&Into::<[f32; 4]>::into(body.rotation));
+            hasher.write_f32s(&Into::<[f32; 3]>::into(body.angular_velocity));
+        }
+        hasher.write_u64(self.still_ticks as u64);
+    }
+}
+impl Default for Contraption {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The axis `rotor` turns about, as long as the angle it turns by, in radians.
+fn turn(rotor: Rotor) -> Vector3<f32> {
+    // The rotor is cos(angle / 2) minus sin(angle / 2) times the plane the axis is normal to.
+    let axis = Vector3::new(-rotor.yz, rotor.xz, -rotor.xy);
+    let sin = axis.magnitude();
+    if sin == 0.0 {
+        return Vector3::zero();
+    }
+    // Its negation turns the same way, the short way round.
+    let sign = if rotor.s < 0.0 { -1.0 } else { 1.0 };
+    axis * (sign * 2.0 * sin.atan2(rotor.s.abs()) / sin)
+}
+
+// An end of a joint as solving sees it: where it is, the arm to there from its body's center, how
+// easily it moves (1 over its body's mass) and how easily it turns.
+struct End {
+    point: Vector3<f32>,
+    arm: Vector3<f32>,
+    inverse_mass: f32,
+    inverse_inertia: f32,
+}
+fn locate(bodies: &[Physics], inertias: &[f32], anchor: Anchor) -> End {
+    match anchor {
+        Anchor::World(point) => {
+            End { point, arm: Vector3::zero(), inverse_mass: 0.0, inverse_inertia: 0.0 }
+        }
+        Anchor::Body { index, offset } => {
+            let body = &bodies[index];
+            let arm = body.rotation.rotate_vector(offset);
+            End {
+                point: body.position.to_vec() + arm,
+                arm,
+                inverse_mass: 1.0 / body.mass,
+                inverse_inertia: 1.0 / inertias[index],
+            }
+        }
+    }
+}
+
+// Moves `body` as a push of `impulse` at the end's arm would. Only the part of the turn about
+// `axis` is kept, and sliders don't turn at all.
+fn push(body: &mut Physics, end: &End, impulse: Vector3<f32>, axis: Option<Vector3<f32>>) {
+    body.position += impulse * end.inverse_mass;
+    let Some(axis) = axis else {
+        return;
+    };
+    let angle = end.arm.cross(impulse).dot(axis) * end.inverse_inertia;
+    if angle != 0.0 {
+        body.rotation = Rotor::from_axis_angle(axis, Rad(angle)) * body.rotation;
+    }
+}
+
+// Moves and turns the two ends of `joint` to satisfy it, each in proportion to how easily it
+// moves and turns.
+fn solve(bodies: &mut [Physics], inertias: &[f32], joint: &Joint) {
+    let a = locate(bodies, inertias, joint.a);
+    let b = locate(bodies, inertias, Anchor::Body { index: joint.b, offset: joint.offset_b });
+    let (error, axis) = match joint.kind {
+        JointKind::Hinge { axis } => (a.point - b.point, Some(axis)),
+        JointKind::Slider { axis, min, max } => {
+            let axis = match joint.a {
+                Anchor::World(_) => axis,
+                Anchor::Body { index, .. } => bodies[index].rotation.rotate_vector(axis),
+            };
+            let along = (b.point - a.point).dot(axis).clamp(min, max);
+            (a.point + axis * along - b.point, None)
+        }
+    };
+    let distance = error.magnitude();
+    if distance == 0.0 {
+        return;
+    }
+    let direction = error / distance;
+    // How far each end gives way to a unit push: moving, plus turning about the axis.
+    let give = |end: &End| {
+        let turning = axis.map_or(0.0, |axis| end.arm.cross(direction).dot(axis));
+        end.inverse_mass + turning * turning * end.inverse_inertia
+    };
+    let impulse = direction * (distance / (give(&a) + give(&b)));
+    push(&mut bodies[joint.b], &b, impulse, axis);
+    if let Anchor::Body { index, .. } = joint.a {
+        push(&mut bodies[index], &a, -impulse, axis);
+    }
+}
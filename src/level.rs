@@ -0,0 +1,360 @@
+/* Level: a Sokoban puzzle grid read from XSB text, with pressure plates and doors wired by id. */
+use anyhow::{bail, Context};
+use cgmath::Vector3;
+
+use crate::{determinism::StateHasher, resources};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tile {
+    Floor,
+    Wall,
+    Goal,
+    // Anything that moves onto ice keeps going until something stops it.
+    Ice,
+    // Linked to the other teleporter with the same id: whatever moves onto one comes out of the
+    // other.
+    Teleporter(u32),
+    // Carries boxes and the player standing on it towards `Direction`.
+    Conveyor(Direction),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    // -z, towards the first row of the level file.
+    North,
+    South,
+    East,
+    West,
+}
+impl Direction {
+    pub const ALL: [Direction; 4] =
+        [Direction::North, Direction::East, Direction::South, Direction::West];
+    pub fn offset(&self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::South => (0, 1),
+            Direction::East => (1, 0),
+            Direction::West => (-1, 0),
+        }
+    }
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::North => Direction::South,
+            Direction::South => Direction::North,
+            Direction::East => Direction::West,
+            Direction::West => Direction::East,
+        }
+    }
+    // A quarter turn clockwise, seen from above with north up.
+    pub fn clockwise(self) -> Self {
+        match self {
+            Direction::North => Direction::East,
+            Direction::East => Direction::South,
+            Direction::South => Direction::West,
+            Direction::West => Direction::North,
+        }
+    }
+    // The direction of a single step between neighbouring tiles, if it is one.
+    pub fn from_step(from: (i32, i32), to: (i32, i32)) -> Option<Self> {
+        match (to.0 - from.0, to.1 - from.1) {
+            (0, -1) => Some(Direction::North),
+            (0, 1) => Some(Direction::South),
+            (1, 0) => Some(Direction::East),
+            (-1, 0) => Some(Direction::West),
+            _ => None,
+        }
+    }
+}
+
+// A resolved player move. Paths list every tile passed through, starting where the mover began, so
+// animations can follow them and undo can walk them back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Move {
+    pub direction: Direction,
+    pub player_path: Vec<(i32, i32)>,
+    // The pushed box's index and path.
+    pub pushed_box: Option<(usize, Vec<(i32, i32)>)>,
+}
+
+// Pressed while a box or the player rests on it. Opens every door with the same id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plate {
+    pub id: u32,
+    pub x: i32,
+    pub z: i32,
+}
+
+// A wall segment that sinks into the floor while open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Door {
+    pub id: u32,
+    pub x: i32,
+    pub z: i32,
+    pub open: bool,
+    // Held open by the level script, whatever its plates say.
+    pub latched: bool,
+}
+
+/// A puzzle grid, where x counts columns and z counts rows of the level file.
+///
+/// The file format is XSB: `#` wall, space/`-`/`_` floor, `.` goal, `$` box, `*` box on a goal,
+/// `@` player, `+` player on a goal. On top of that, `~` is ice, `a` to `u` are pressure plates and
+/// `A` to `U` are doors; a plate opens the doors with the same letter. `0` to `9` are teleporters,
+/// which come in pairs of the same digit. `^`, `v`, `<` and `>` are conveyors running north, south,
+/// west and east. Lines starting with `;` are comments.
+#[derive(Clone)]
+pub struct Level {
+    // Hash of the level's rows, which stays the same wherever the level appears. Keys its records.
+    pub id: u64,
+    pub width: i32,
+    pub depth: i32,
+    tiles: Vec<Tile>,
+    pub player_start: Option<(i32, i32)>,
+    pub boxes: Vec<(i32, i32)>,
+    pub plates: Vec<Plate>,
+    pub doors: Vec<Door>,
+}
+impl Level {
+    pub fn empty() -> Self {
+        Level {
+            id: 0,
+            width: 0,
+            depth: 0,
+            tiles: Vec::new(),
+            player_start: None,
+            boxes: Vec::new(),
+            plates: Vec::new(),
+            doors: Vec::new(),
+        }
+    }
+    /// Parses the first level in `text`.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let rows = text
+            .lines()
+            .filter(|line| !line.starts_with(';'))
+            .map(|line| line.trim_end())
+            .skip_while(|line| line.is_empty())
+            .take_while(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        Self::parse_rows(&rows)
+    }
+    /// Parses every level in `text`. Levels in a pack are separated by blank lines.
+    pub fn parse_pack(text: &str) -> anyhow::Result<Vec<Self>> {
+        let lines = text
+            .lines()
+            .filter(|line| !line.starts_with(';'))
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>();
+        lines
+            .split(|line| line.is_empty())
+            .filter(|rows| !rows.is_empty())
+            .enumerate()
+            .map(|(i, rows)| Self::parse_rows(rows).with_context(|| format!("level {}", i + 1)))
+            .collect()
+    }
+    pub fn load_pack(file_name: &str) -> anyhow::Result<Vec<Self>> {
+        let text = pollster::block_on(resources::load_string(file_name))?;
+        Self::parse_pack(&text).with_context(|| format!("parsing level pack {}", file_name))
+    }
+    /// Loads a pack from anywhere on disk, like one dropped on the window, rather than from the
+    /// assets.
+    pub fn load_pack_path(path: &str) -> anyhow::Result<Vec<Self>> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading level pack {}", path))?;
+        Self::parse_pack(&text).with_context(|| format!("parsing level pack {}", path))
+    }
+    fn parse_rows(rows: &[&str]) -> anyhow::Result<Self> {
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0) as i32;
+        let mut hasher = StateHasher::new();
+        for row in rows {
+            hasher.write_bytes(row.as_bytes());
+            hasher.write_bytes(b"\n");
+        }
+        let mut level = Level {
+            id: hasher.finish(),
+            width,
+            depth: rows.len() as i32,
+            tiles: vec![Tile::Floor; width as usize * rows.len()],
+            ..Level::empty()
+        };
+        for (z, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let (x, z) = (x as i32, z as i32);
+                let tile = match c {
+                    '#' => Tile::Wall,
+                    '.' | '*' | '+' => Tile::Goal,
+                    '~' => Tile::Ice,
+                    '0'..='9' => Tile::Teleporter(c as u32 - '0' as u32),
+                    '^' => Tile::Conveyor(Direction::North),
+                    'v' => Tile::Conveyor(Direction::South),
+                    '<' => Tile::Conveyor(Direction::West),
+                    '>' => Tile::Conveyor(Direction::East),
+                    _ => Tile::Floor,
+                };
+                level.tiles[(z * width + x) as usize] = tile;
+                match c {
+                    '#' | ' ' | '-' | '_' | '.' | '~' | '0'..='9' | '^' | 'v' | '<' | '>' => {}
+                    '$' | '*' => level.boxes.push((x, z)),
+                    '@' | '+' => {
+                        if level.player_start.replace((x, z)).is_some() {
+                            bail!("level has more than one player, second at ({}, {})", x, z);
+                        }
+                    }
+                    'a'..='u' => level.plates.push(Plate { id: c as u32 - 'a' as u32, x, z }),
+                    'A'..='U' => level.doors.push(Door {
+                        id: c as u32 - 'A' as u32,
+                        x,
+                        z,
+                        open: false,
+                        latched: false,
+                    }),
+                    _ => bail!("unknown level character '{}' at ({}, {})", c, x, z),
+                }
+            }
+        }
+        for id in 0..10 {
+            let count = level.tiles_of(Tile::Teleporter(id)).count();
+            if count != 0 && count != 2 {
+                bail!("teleporter {} appears {} times, expected a pair", id, count);
+            }
+        }
+        Ok(level)
+    }
+    pub fn load(file_name: &str) -> anyhow::Result<Self> {
+        let text = pollster::block_on(resources::load_string(file_name))?;
+        Self::parse(&text).with_context(|| format!("parsing level {}", file_name))
+    }
+    /// Tiles outside the grid count as walls.
+    pub fn tile(&self, x: i32, z: i32) -> Tile {
+        if x < 0 || z < 0 || x >= self.width || z >= self.depth {
+            return Tile::Wall;
+        }
+        self.tiles[(z * self.width + x) as usize]
+    }
+    // Walls and closed doors stop the player and boxes.
+    pub fn is_blocked(&self, x: i32, z: i32) -> bool {
+        self.tile(x, z) == Tile::Wall
+            || self.doors.iter().any(|door| !door.open && (door.x, door.z) == (x, z))
+    }
+    // Where something stepping onto (x, z) comes out, if (x, z) is a teleporter.
+    pub fn teleporter_exit(&self, x: i32, z: i32) -> Option<(i32, i32)> {
+        let Tile::Teleporter(id) = self.tile(x, z) else {
+            return None;
+        };
+        self.tiles_of(Tile::Teleporter(id)).find(|&tile| tile != (x, z))
+    }
+    pub fn box_at(&self, x: i32, z: i32) -> Option<usize> {
+        self.boxes.iter().position(|&tile| tile == (x, z))
+    }
+    /// Whether every box sits on a goal. A level without boxes is never solved.
+    pub fn is_solved(&self) -> bool {
+        !self.boxes.is_empty() && self.boxes.iter().all(|&(x, z)| self.tile(x, z) == Tile::Goal)
+    }
+    /// Resolves the player at `player` stepping one tile in `direction`, pushing a box if there is
+    /// one in the way. Returns None if the step is blocked. Boxes and the player keep sliding
+    /// across ice until the next tile is blocked or occupied, and come out of a teleporter's pair
+    /// when its exit is free. A teleport shows up in a path as a jump between distant tiles.
+    pub fn resolve_move(&self, player: (i32, i32), direction: Direction) -> Option<Move> {
+        let (dx, dz) = direction.offset();
+        let target = (player.0 + dx, player.1 + dz);
+        if self.is_blocked(target.0, target.1) {
+            return None;
+        }
+        let pushed_box = match self.box_at(target.0, target.1) {
+            Some(index) => Some((index, self.resolve_box_move(index, direction)?)),
+            None => None,
+        };
+        let box_end = pushed_box.as_ref().map(|(_, path)| *path.last().unwrap());
+        let player_path = self.slide(player, direction, |tile| {
+            Some(tile) == box_end || (tile != target && self.box_at(tile.0, tile.1).is_some())
+        });
+        Some(Move { direction, player_path, pushed_box })
+    }
+    /// The path box `index` takes when moved one tile in `direction`, or None if it can't move.
+    pub fn resolve_box_move(&self, index: usize, direction: Direction) -> Option<Vec<(i32, i32)>> {
+        let path =
+            self.slide(self.boxes[index], direction, |tile| self.box_at(tile.0, tile.1).is_some());
+        if path.len() < 2 {
+            return None;
+        }
+        Some(path)
+    }
+    // Moves from `from` in `direction`: always one step if possible, then onwards while on ice.
+    // Stepping onto a teleporter ends the move, at its exit if that is free.
+    fn slide(
+        &self,
+        from: (i32, i32),
+        direction: Direction,
+        occupied: impl Fn((i32, i32)) -> bool,
+    ) -> Vec<(i32, i32)> {
+        let (dx, dz) = direction.offset();
+        let mut path = vec![from];
+        loop {
+            let current = *path.last().unwrap();
+            let next = (current.0 + dx, current.1 + dz);
+            let moving = path.len() == 1 || self.tile(current.0, current.1) == Tile::Ice;
+            if !moving || self.is_blocked(next.0, next.1) || occupied(next) {
+                return path;
+            }
+            path.push(next);
+            if let Some(exit) = self.teleporter_exit(next.0, next.1) {
+                if !self.is_blocked(exit.0, exit.1) && !occupied(exit) {
+                    path.push(exit);
+                }
+                return path;
+            }
+        }
+    }
+    pub fn apply(&mut self, resolved: &Move) {
+        if let Some((index, path)) = &resolved.pushed_box {
+            self.boxes[*index] = *path.last().unwrap();
+        }
+    }
+    pub fn undo(&mut self, resolved: &Move) {
+        if let Some((index, path)) = &resolved.pushed_box {
+            self.boxes[*index] = path[0];
+        }
+    }
+    // Every (x, z) holding a tile of type `tile`, row by row.
+    pub fn tiles_of(&self, tile: Tile) -> impl Iterator<Item = (i32, i32)> + '_ {
+        (0..self.depth)
+            .flat_map(move |z| (0..self.width).map(move |x| (x, z)))
+            .filter(move |&(x, z)| self.tile(x, z) == tile)
+    }
+}
+
+// How a level was played, for the end-of-level summary.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    // Tiles the player stepped onto.
+    pub moves: u32,
+    pub pushes: u32,
+    pub ticks: u32,
+}
+impl std::iter::Sum for LevelStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(LevelStats::default(), |a, b| LevelStats {
+            moves: a.moves + b.moves,
+            pushes: a.pushes + b.pushes,
+            ticks: a.ticks + b.ticks,
+        })
+    }
+}
+
+/// Where a level sits in the world: tile (0, 0) spans `origin` to `origin + (1, 0, 1)`, and the
+/// level's floor is at `origin.y`.
+#[derive(Clone, Copy, Debug)]
+pub struct LevelPlacement {
+    pub origin: Vector3<f32>,
+}
+impl LevelPlacement {
+    // Center of the tile's top face, on the floor.
+    pub fn tile_center(&self, x: i32, z: i32) -> Vector3<f32> {
+        self.origin + Vector3::new(x as f32 + 0.5, 0.0, z as f32 + 0.5)
+    }
+    // The tile containing world position `position`.
+    pub fn tile_at(&self, position: Vector3<f32>) -> (i32, i32) {
+        let local = position - self.origin;
+        (local.x.floor() as i32, local.z.floor() as i32)
+    }
+}
@@ -0,0 +1,77 @@
+// The game's modules live in a library so that benches (and anything else outside of the binary)
+// can drive the simulation directly. main.rs owns the window and the threads.
+#![feature(lazy_cell)]
+
+pub mod achievements;
+pub mod animation;
+pub mod app;
+pub mod bug_report;
+pub mod camera;
+pub mod constants;
+pub mod crash;
+pub mod debug_lines;
+pub mod culling;
+pub mod determinism;
+pub mod display;
+pub mod events;
+pub mod fixed;
+pub mod frame_times;
+pub mod free_camera;
+pub mod game_state;
+pub mod ghost;
+pub mod globals;
+pub mod gpu_cache;
+pub mod gpu_errors;
+pub mod gpu_memory;
+pub mod gpu_state;
+pub mod headless;
+pub mod hot_reload;
+pub mod input;
+pub mod jobs;
+pub mod joints;
+pub mod level;
+pub mod lod;
+pub mod material;
+pub mod metrics;
+pub mod minimap;
+pub mod model;
+pub mod motor;
+pub mod movement;
+pub mod net;
+pub mod noise;
+pub mod outline_renderer;
+pub mod pacing;
+pub mod particle_renderer;
+pub mod particles;
+pub mod physics;
+pub mod pipeline_cache;
+pub mod platforms;
+pub mod post_process;
+pub mod profiler;
+pub mod readback;
+pub mod records;
+pub mod reflection;
+pub mod render_graph;
+pub mod replay;
+pub mod resources;
+pub mod rng;
+pub mod rollback;
+pub mod rotor;
+pub mod save;
+pub mod scene;
+pub mod scripting;
+pub mod settings;
+pub mod sky;
+pub mod ssao;
+pub mod static_batch;
+pub mod stats_hud;
+pub mod strings;
+pub mod text_field;
+pub mod texture;
+pub mod tick_rate;
+pub mod toasts;
+pub mod transform;
+pub mod triggers;
+pub mod ui;
+pub mod ui_renderer;
+pub mod uploader;
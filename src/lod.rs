@@ -0,0 +1,116 @@
+/* LOD: coarser versions of models, drawn in place of the full model far from the camera. */
+use crate::model::ModelVertex;
+
+use cgmath::{InnerSpace, Vector3};
+use std::collections::HashMap;
+
+/// Instances switch to the next coarser level at each of these distances from the camera. The
+/// distances are for an instance of scale 1 and grow with its scale.
+pub const LOD_DISTANCES: [f32; 2] = [25.0, 50.0];
+
+/// Models with fewer triangles than this get no automatic levels; there is little to save.
+pub const AUTO_LOD_MIN_TRIANGLES: usize = 256;
+/// Grid resolution of each automatic level, coarsest last.
+pub const AUTO_LOD_RESOLUTIONS: [u32; 2] = [8, 4];
+
+/// The level to draw an instance at `distance` from the camera with, for a model that has
+/// `level_count` levels including the full one.
+pub fn select(distance: f32, scale: f32, level_count: usize) -> usize {
+    LOD_DISTANCES
+        .iter()
+        .take(level_count.saturating_sub(1))
+        .filter(|&&threshold| distance > threshold * scale)
+        .count()
+}
+
+/// Where an authored level of `file_name` would be, e.g. `sphere_lod1.obj` for level 1 of
+/// `sphere.obj`.
+pub fn file_name(file_name: &str, level: usize) -> String {
+    match file_name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{}_lod{}.{}", stem, level, extension),
+        None => format!("{}_lod{}", file_name, level),
+    }
+}
+
+// The attributes of a cluster's vertices, added up, and how many vertices there are.
+struct ClusterSum {
+    position: Vector3<f32>,
+    tex_coords: [f32; 2],
+    normal: Vector3<f32>,
+    count: f32,
+}
+
+/// A coarser copy of a mesh, by vertex clustering. Vertices are snapped to a `resolution`^3 grid
+/// over the mesh's bounds, and the vertices in each cell facing roughly the same way are merged
+/// into their average. Triangles left with less than three distinct vertices are dropped.
+pub fn decimate(
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    resolution: u32,
+) -> (Vec<ModelVertex>, Vec<u32>) {
+    let Some(first) = vertices.first() else {
+        return (Vec::new(), Vec::new());
+    };
+    let (mut min, mut max) = (Vector3::from(first.position), Vector3::from(first.position));
+    for vertex in vertices {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(vertex.position[axis]);
+            max[axis] = max[axis].max(vertex.position[axis]);
+        }
+    }
+    let size = max - min;
+    let cell = |position: [f32; 3]| {
+        let mut coords = [0u32; 3];
+        for axis in 0..3 {
+            let t = if size[axis] > 0.0 { (position[axis] - min[axis]) / size[axis] } else { 0.0 };
+            coords[axis] = ((t * resolution as f32) as u32).min(resolution - 1);
+        }
+        coords
+    };
+    // Which way the normal mostly points, so hard edges like a box's corners stay hard.
+    let facing = |normal: [f32; 3]| {
+        let axis = (0..3).max_by(|&a, &b| normal[a].abs().total_cmp(&normal[b].abs())).unwrap();
+        (axis as u8) * 2 + (normal[axis] < 0.0) as u8
+    };
+
+    // Per cluster: the sum of its vertices' attributes and how many there are.
+    let mut clusters: HashMap<([u32; 3], u8), u32> = HashMap::new();
+    let mut sums: Vec<ClusterSum> = Vec::new();
+    let remap = vertices
+        .iter()
+        .map(|vertex| {
+            let key = (cell(vertex.position), facing(vertex.normal));
+            let index = *clusters.entry(key).or_insert_with(|| {
+                sums.push(ClusterSum {
+                    position: Vector3::new(0.0, 0.0, 0.0),
+                    tex_coords: [0.0; 2],
+                    normal: Vector3::new(0.0, 0.0, 0.0),
+                    count: 0.0,
+                });
+                sums.len() as u32 - 1
+            });
+            let sum = &mut sums[index as usize];
+            sum.position += Vector3::from(vertex.position);
+            sum.tex_coords[0] += vertex.tex_coords[0];
+            sum.tex_coords[1] += vertex.tex_coords[1];
+            sum.normal += Vector3::from(vertex.normal);
+            sum.count += 1.0;
+            index
+        })
+        .collect::<Vec<_>>();
+    let merged = sums
+        .into_iter()
+        .map(|ClusterSum { position, tex_coords, normal, count }| ModelVertex {
+            position: (position / count).into(),
+            tex_coords: [tex_coords[0] / count, tex_coords[1] / count],
+            normal: if normal.magnitude2() > 0.0 { normal.normalize().into() } else { [0.0; 3] },
+        })
+        .collect();
+    let merged_indices = indices
+        .chunks_exact(3)
+        .map(|triangle| triangle.iter().map(|&i| remap[i as usize]).collect::<Vec<_>>())
+        .filter(|t| t[0] != t[1] && t[1] != t[2] && t[2] != t[0])
+        .flatten()
+        .collect();
+    (merged, merged_indices)
+}
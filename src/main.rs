@@ -1,46 +1,136 @@
 // This hides the console window when launching cube.exe,
-// at the cost of suppressing println! statements.
+// at the cost of suppressing println! statements. Panics still leave a crash log, see crash.rs.
 /* #![windows_subsystem = "windows"] */
-#![feature(lazy_cell)]
 
-mod camera;
-mod constants;
-mod game_state;
-mod gpu_state;
-mod light;
-mod model;
-mod physics;
-mod resources;
-mod rotor;
-mod texture;
-mod time;
-
-use crate::constants::{MIN_TIME_PER_RENDER_FRAME, TIME_PER_GAME_TICK};
-use crate::game_state::{GameState, InputState};
-use crate::gpu_state::WebGPUState;
+use cube::app::{App, Frame};
+use cube::bug_report::{self, InputHistory};
+use cube::crash;
+use cube::determinism;
+use cube::display::{self, WindowPlacement, WINDOW_FILE};
+use cube::frame_times::FrameTimes;
+use cube::free_camera::FlyInput;
+use cube::game_state::GameState;
+use cube::gpu_memory;
+use cube::gpu_state::WebGPUState;
+use cube::headless::{self, InputScript};
+use cube::input::{Button, InputBuffer, InputEvent, KeyTable};
+use cube::log_println;
+use cube::metrics::{FrameSample, Metrics, TickSample};
+use cube::net::{self, Client, Host, RacePeer, Session};
+use cube::pacing::Pacer;
+use cube::profiler;
+use cube::resources;
+use cube::settings::Settings;
+use cube::stats_hud::StatsHud;
+use cube::text_field::TextKey;
+use cube::tick_rate;
+use cube::ui::UiLayer;
 
 use cgmath::num_traits::abs;
 use debug_print::debug_println;
 use pollster::block_on;
 use std::collections::VecDeque;
 use std::mem::{self};
-use std::sync::{mpsc, Arc, Mutex};
-use std::thread::{self};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread::{self, Thread};
 use std::time::{Duration, Instant};
-use windows::Win32::UI::Input::KeyboardAndMouse::{VIRTUAL_KEY, VK_DOWN, VK_LEFT, VK_RIGHT, VK_SPACE, VK_UP};
+use windows::Win32::Globalization::{MultiByteToWideChar, CP_ACP, HIMC, MB_ERR_INVALID_CHARS};
+use windows::Win32::UI::Input::Ime::{
+    ImmAssociateContextEx, ImmGetCompositionStringW, ImmGetContext, ImmReleaseContext,
+    GCS_RESULTSTR, IACE_DEFAULT,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    VIRTUAL_KEY, VK_A, VK_BACK, VK_D, VK_E, VK_ESCAPE, VK_F10, VK_F2, VK_F3, VK_F4, VK_F6, VK_F7,
+    VK_F8, VK_OEM_MINUS, VK_OEM_PLUS, VK_Q, VK_RETURN, VK_S, VK_W,
+};
 use windows::Win32::{Foundation::POINT, System::LibraryLoader::GetModuleHandleA};
 use windows::{
     core::*,
     Win32::{
         Foundation::{HWND, LPARAM, LRESULT, RECT, WPARAM},
-        Graphics::Gdi::ValidateRect,
+        Graphics::Gdi::{ClientToScreen, ScreenToClient, ValidateRect},
+        UI::Shell::{DragFinish, DragQueryFileW, HDROP},
         UI::WindowsAndMessaging::*,
     },
 };
 
 const EVENT_QUEUE_SIZE_IN_BYTES: i32 = std::mem::size_of::<*mut Arc<Mutex<EventQueue>>>() as i32;
+// How often the render thread looks for the window to come back into sight while nothing is being
+// drawn. Restoring a minimized window wakes it straight away.
+const OUT_OF_SIGHT_POLL_TIME: Duration = Duration::from_millis(50);
 
 fn main() -> windows::core::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    // --tick-rate <ticks per second> simulates at another rate than the default, headless runs
+    // included. Replays recorded at one rate don't play back at another, and both games of a
+    // session need the same one.
+    if let Some(rate) =
+        args.iter().position(|arg| arg == "--tick-rate").and_then(|i| args.get(i + 1))
+    {
+        let set = rate.parse::<u32>().map_err(anyhow::Error::from);
+        if let Err(e) = set.and_then(tick_rate::set_ticks_per_second) {
+            eprintln!("Ignoring tick rate {:?}: {:#}", rate, e);
+        }
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--headless") {
+        if let Err(e) = headless::run_from_args(&args[i + 1..]) {
+            eprintln!("Headless run failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--determinism") {
+        if let Err(e) = determinism::run_from_args(&args[i + 1..]) {
+            eprintln!("Determinism check failed: {:?}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    crash::install_panic_hook();
+    profiler::start();
+    // Debug builds read assets straight from the source tree rather than the copy made at build
+    // time, so edits there are picked up by hot reload.
+    #[cfg(debug_assertions)]
+    if let Err(e) =
+        resources::mount(std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/assets")))
+    {
+        log_println!("Failed to mount the source assets: {:#}", e);
+    }
+    // Mods are mounted over those, and an archive named with --mount over the mods.
+    match resources::mount_all_in(std::path::Path::new(resources::MODS_DIR)) {
+        Ok(0) => {}
+        Ok(count) => log_println!("Mounted {} asset sources from {}", count, resources::MODS_DIR),
+        Err(e) => log_println!("Failed to mount {}: {:#}", resources::MODS_DIR, e),
+    }
+    for (i, _) in args.iter().enumerate().filter(|(_, arg)| *arg == "--mount") {
+        if let Some(path) = args.get(i + 1) {
+            if let Err(e) = resources::mount(std::path::Path::new(path)) {
+                log_println!("Failed to mount {}: {:#}", path, e);
+            }
+        }
+    }
+    // When set, every tick's input is recorded and written out as an input script on exit, for
+    // replaying with --headless or --determinism.
+    let record_input_path =
+        args.iter().position(|arg| arg == "--record-input").and_then(|i| args.get(i + 1).cloned());
+    let input_recording = Arc::new(Mutex::new(InputScript::new()));
+    // When set, both threads time every tick and frame, written out as CSV on exit.
+    let metrics_path =
+        args.iter().position(|arg| arg == "--metrics").and_then(|i| args.get(i + 1).cloned());
+    let metrics = metrics_path.as_ref().map(|_| Arc::new(Mutex::new(Metrics::new(Instant::now()))));
+    let input_history = Arc::new(Mutex::new(InputHistory::new()));
+    // Both threads' frame and tick times, for the stats HUD, with their histograms logged on exit.
+    let frame_times = Arc::new(Mutex::new(FrameTimes::new()));
+    // In megabytes. Going over it is logged and turns the GPU memory line of the stats red.
+    let gpu_budget =
+        args.iter().position(|arg| arg == "--gpu-budget").and_then(|i| args.get(i + 1));
+    if let Some(megabytes) = gpu_budget {
+        match megabytes.parse::<u64>() {
+            Ok(megabytes) => gpu_memory::set_budget(Some(megabytes * 1024 * 1024)),
+            Err(e) => log_println!("Ignoring GPU budget {:?}: {}", megabytes, e),
+        }
+    }
+
     let hinstance = unsafe { GetModuleHandleA(None) }?;
     let window_class_name = s!("window");
     let wc = WNDCLASSA {
@@ -55,26 +145,46 @@ fn main() -> windows::core::Result<()> {
     let atom = unsafe { RegisterClassA(&wc) };
     debug_assert!(atom != 0);
 
-    const WINDOW_INITIAL_WIDTH: i32 = 2560;
-    const WINDOW_INITIAL_HEIGHT: i32 = 1440;
-    // const WINDOW_INITIAL_WIDTH: i32 = 1920;
-    // const WINDOW_INITIAL_HEIGHT: i32 = 1080;
+    // The window goes back where it was last session, as long as that monitor is still there, and
+    // otherwise covers the primary monitor.
+    let placement = WindowPlacement::load(WINDOW_FILE)
+        .unwrap_or_else(|e| {
+            log_println!("Ignoring window placement: {:?}", e);
+            None
+        })
+        .filter(WindowPlacement::is_on_its_monitor)
+        .or_else(WindowPlacement::primary);
+    let rect = match &placement {
+        Some(placement) => {
+            if placement.fullscreen {
+                *WINDOWED_RECT.lock().unwrap() = Some(placement.windowed);
+            }
+            placement.rect()
+        }
+        // Only if Windows can't say where the primary monitor is.
+        None => RECT { left: 0, top: 0, right: 1920, bottom: 1080 },
+    };
+    let (window_width, window_height) = (rect.right - rect.left, rect.bottom - rect.top);
     let window = unsafe {
         CreateWindowExA(
-            WINDOW_EX_STYLE::default(),
+            // Level packs and replays can be dropped on the window to open them.
+            WS_EX_ACCEPTFILES,
             window_class_name,
             s!("My sample window"),
             WS_VISIBLE | WS_POPUP,
-            CW_USEDEFAULT,
-            CW_USEDEFAULT,
-            WINDOW_INITIAL_WIDTH,
-            WINDOW_INITIAL_HEIGHT,
+            rect.left,
+            rect.top,
+            window_width,
+            window_height,
             None,
             None,
             hinstance,
             None,
         )
     };
+    // The IME stays off until a text field is focused, or it would turn the movement keys into
+    // a composition.
+    set_text_entry(window, false);
 
     // These will get manipulated directly by wndproc.
     let gpu_event_queue = Arc::new(Mutex::new(EventQueue::new()));
@@ -97,216 +207,388 @@ fn main() -> windows::core::Result<()> {
     // Set sleep granularity to 1ms.
     unsafe { windows::Win32::Media::timeBeginPeriod(1) };
 
-    let mut game_state = GameState::new(WINDOW_INITIAL_WIDTH as f32 / WINDOW_INITIAL_HEIGHT as f32);
+    let game_state = GameState::new(window_width as f32 / window_height as f32);
     let mut gpu_state: WebGPUState = block_on(WebGPUState::new(window, hinstance.into(), game_state.clone()));
-    let mut input_state = InputState::new();
-    let (tx, rx) = mpsc::channel();
-    macro_rules! printUnexpected {
-        ($event_name:expr) => {
-            debug_println!(
-                "Unexpected occurrence: {} event was created with incorrect EventData",
-                $event_name
-            );
-        };
+    let mut app = App::new(game_state);
+    // --time-scale <scale> starts the game in slow motion or fast-forward, which F8 steps between.
+    if let Some(scale) =
+        args.iter().position(|arg| arg == "--time-scale").and_then(|i| args.get(i + 1))
+    {
+        match scale.parse::<f32>() {
+            Ok(scale) => app.set_time_scale(scale),
+            Err(e) => log_println!("Ignoring time scale {:?}: {}", scale, e),
+        }
     }
+    // --host [port] hosts a co-op game, which one other player joins with --join <address>.
+    // --race-host [port] and --race-join <address> do the same for a race.
+    if let Some(i) = args.iter().position(|arg| arg == "--host") {
+        let port = args.get(i + 1).and_then(|port| port.parse().ok()).unwrap_or(net::DEFAULT_PORT);
+        match Host::bind(port) {
+            Ok(host) => app.set_session(Session::Host(host)),
+            Err(e) => log_println!("Failed to host: {:#}", e),
+        }
+    } else if let Some(address) =
+        args.iter().position(|arg| arg == "--join").and_then(|i| args.get(i + 1))
+    {
+        match Client::connect(address, Instant::now()) {
+            Ok(client) => app.set_session(Session::Client(client)),
+            Err(e) => log_println!("Failed to join {}: {:#}", address, e),
+        }
+    } else if let Some(i) = args.iter().position(|arg| arg == "--race-host") {
+        let port = args.get(i + 1).and_then(|port| port.parse().ok()).unwrap_or(net::DEFAULT_PORT);
+        match RacePeer::host(port) {
+            Ok(peer) => app.set_session(Session::Race(peer)),
+            Err(e) => log_println!("Failed to host a race: {:#}", e),
+        }
+    } else if let Some(address) =
+        args.iter().position(|arg| arg == "--race-join").and_then(|i| args.get(i + 1))
+    {
+        match RacePeer::join(address, Instant::now()) {
+            Ok(peer) => app.set_session(Session::Race(peer)),
+            Err(e) => log_println!("Failed to join the race at {}: {:#}", address, e),
+        }
+    }
+    let mut input_buffer = InputBuffer::new();
+    let mut key_table = KeyTable::new();
+    let mut fly_input = FlyInput::new();
+    // Wheel rotation short of a whole notch, from wheels that report finer steps.
+    let mut wheel_remainder: i32 = 0;
+    let (tx, rx) = mpsc::channel();
     {
         let gpu_event_queue = Arc::clone(&gpu_event_queue);
-        let _gpu_thread = thread::spawn(move || {
+        let input_history = Arc::clone(&input_history);
+        let metrics = metrics.clone();
+        let frame_times = Arc::clone(&frame_times);
+        let gpu_thread = thread::spawn(move || {
+            profiling::register_thread!("Render");
+            let mut latest_game_state: Option<GameState> = None;
+            // When the game thread made the frame being drawn.
+            let mut latest_made_at: Option<Instant> = None;
+            // How fast the game's time was passing then.
+            let mut latest_time_scale = 0.0;
+            let mut applied_settings: Option<Settings> = None;
+            let mut refresh_rate = display::refresh_rate(window);
+            let mut pacer = Pacer::new();
             let mut last_render = Instant::now();
             let _ = gpu_state.render();
+            let mut last_present = Instant::now();
 
-            let mut last_fps_print = last_render;
-            let mut frames = 0;
+            let mut last_pacing_print = last_render;
+            let mut stats_hud = StatsHud::new(last_render);
             loop {
+                // Wait before taking the latest frame, so what's rendered is as fresh as it can be.
+                let min_frame_time =
+                    applied_settings.unwrap_or_default().min_frame_time(refresh_rate);
+                if let Some(min_frame_time) = min_frame_time {
+                    pacer.wait_until(last_render + min_frame_time);
+                }
+                let gpu_queue_depth;
                 {
                     let mut queue = gpu_event_queue.lock().unwrap();
-                    while !(*queue).is_empty() {
-                        let event = (*queue).pop_front().expect("queue somehow empty?");
-                        match event.message {
-                            WM_MOUSEMOVE => match event.data {
-                                EventData::MouseMoveData(point) => {
-                                    gpu_state.update_bg_color(&point);
+                    gpu_queue_depth = queue.len();
+                    while let Some((_, event)) = queue.pop_front() {
+                        match event {
+                            WindowEvent::MouseDelta { mut cursor, .. } => {
+                                let _ = unsafe { ScreenToClient(window, &mut cursor) };
+                                gpu_state.update_cursor(cursor);
+                            }
+                            WindowEvent::Resize(rect) => {
+                                gpu_state.resize(rect);
+                                // The window may have moved to another monitor.
+                                refresh_rate = display::refresh_rate(window);
+                            }
+                            WindowEvent::KeyAction { key: VK_F3, down: true } => {
+                                stats_hud.toggle();
+                            }
+                            WindowEvent::KeyAction { key: VK_F10, down: true } => {
+                                let result = bug_report::write_bug_report(
+                                    &mut gpu_state,
+                                    latest_game_state.as_ref(),
+                                    &input_history.lock().unwrap(),
+                                );
+                                match result {
+                                    Ok(path) => log_println!("Wrote bug report {}", path),
+                                    Err(e) => log_println!("Bug report failed: {:?}", e),
                                 }
-                                _ => {
-                                    printUnexpected!("WM_MOUSEMOVE");
-                                }
-                            },
-                            WM_PAINT => match event.data {
-                                EventData::EmptyData() => {}
-                                _ => {
-                                    printUnexpected!("WM_PAINT");
-                                }
-                            },
-                            WM_SIZE => match event.data {
-                                EventData::ResizeData(rect) => {
-                                    gpu_state.resize(rect);
-                                }
-                                _ => {
-                                    printUnexpected!("WM_SIZE");
-                                }
-                            },
+                            }
+                            WindowEvent::AppQuit => return,
                             _ => (),
                         }
                     }
                 }
-                let mut game_state_res = rx.try_recv();
-                if game_state_res.is_ok() {
+                let mut frames_received = 0;
+                let mut frame_res = rx.try_recv();
+                if frame_res.is_ok() {
+                    profiling::scope!("Snapshot upload");
+                    frames_received += 1;
                     let mut next = rx.try_recv();
                     while next.is_ok() {
-                        game_state_res = next;
+                        frames_received += 1;
+                        frame_res = next;
                         next = rx.try_recv();
                     }
-                    let game_state: GameState = game_state_res.unwrap();
-                    gpu_state.update_camera(game_state.get_camera());
+                    let Frame {
+                        game_state,
+                        camera,
+                        ui,
+                        minimap,
+                        settings,
+                        debug_lines,
+                        made_at,
+                        time_scale,
+                    } = frame_res.unwrap();
+                    gpu_state.update_time(&game_state, made_at, time_scale);
+                    gpu_state.update_camera(camera);
+                    gpu_state.update_instances(&game_state);
+                    gpu_state.update_particles(&game_state);
+                    gpu_state.update_player_character(&game_state);
+                    gpu_state.update_outline(&game_state);
+                    gpu_state.update_debug_lines(&debug_lines);
+                    gpu_state.update_scene(&game_state);
+                    gpu_state.update_ui(&ui);
+                    gpu_state.update_minimap(minimap.as_ref());
+                    if applied_settings != Some(settings) {
+                        if applied_settings.map(|applied| applied.language)
+                            != Some(settings.language)
+                        {
+                            stats_hud.set_language(settings.language);
+                        }
+                        gpu_state.apply_settings(&settings);
+                        applied_settings = Some(settings);
+                    }
+                    stats_hud.record_snapshots(frames_received, game_state.get_tick());
+                    latest_game_state = Some(game_state);
+                    latest_made_at = Some(made_at);
+                    latest_time_scale = time_scale;
+                }
+                if Instant::now() >= last_pacing_print + Duration::from_secs(2) {
+                    log_println!("Render pacing: {}", pacer.take_stats());
+                    last_pacing_print = Instant::now();
                 }
-                if Instant::now() >= last_fps_print + Duration::from_secs(2) {
-                    debug_println!("FPS = {}", frames as f32 / 2.0);
-                    frames = 0;
-                    last_fps_print = Instant::now();
+                // Frames still get taken from the game, so they don't pile up, but aren't drawn
+                // while no one could see them. Their uploads are sent on their own.
+                if display::is_out_of_sight(window) {
+                    frame_times.lock().unwrap().skip_frame();
+                    gpu_state.flush_uploads();
+                    thread::park_timeout(OUT_OF_SIGHT_POLL_TIME);
+                    continue;
                 }
-                let next = Instant::now();
-                if next >= last_render + *MIN_TIME_PER_RENDER_FRAME {
-                    last_render = next;
-                    frames += 1;
-                    let _ = gpu_state.render();
+                last_render = Instant::now();
+                stats_hud.record_frame(last_render, latest_made_at, latest_time_scale);
+                let (width, height) = gpu_state.surface_size();
+                let mut hud = if stats_hud.is_visible() {
+                    stats_hud.draw(height as f32, &frame_times.lock().unwrap())
                 } else {
-                    let time_to_next_frame = last_render + *MIN_TIME_PER_RENDER_FRAME - next;
-                    if time_to_next_frame > Duration::from_micros(1500) {
-                        thread::sleep(Duration::from_millis(
-                            time_to_next_frame.as_millis() as u64 - 1,
-                        ));
-                    }
+                    UiLayer::new()
+                };
+                stats_hud.draw_gpu_error(&mut hud, last_render, width as f32, height as f32);
+                gpu_state.update_hud(Some(&hud));
+                let _ = gpu_state.render();
+                profiling::finish_frame!();
+                let present = Instant::now();
+                if let Some(metrics) = &metrics {
+                    metrics.lock().unwrap().record_frame(FrameSample {
+                        at: present,
+                        frame_time: present - last_present,
+                        latency: latest_made_at.map(|made_at| present - made_at),
+                        gpu_queue_depth,
+                        frames_received,
+                    });
                 }
+                frame_times.lock().unwrap().record_frame(present - last_present, min_frame_time);
+                last_present = present;
             }
         });
+        let _ = RENDER_THREAD.set(gpu_thread.thread().clone());
     }
     {
         let input_event_queue = Arc::clone(&input_event_queue);
+        let input_recording = Arc::clone(&input_recording);
+        let input_history = Arc::clone(&input_history);
+        let recording = record_input_path.is_some();
+        let metrics = metrics.clone();
+        let frame_times = Arc::clone(&frame_times);
         let _game_thread = thread::spawn(move || {
+            profiling::register_thread!("Game");
             let mut last_tick = Instant::now();
+            let mut pacer = Pacer::new();
+            let mut last_pacing_print = last_tick;
             let mut game_rect: RECT = unsafe { mem::zeroed() };
             let _ = unsafe { GetClientRect(window, &mut game_rect) };
+            // Whether the window was last told a text field is focused.
+            let mut entering_text = false;
             loop {
+                let input_queue_depth;
                 {
+                    profiling::scope!("Input drain");
                     let mut queue = input_event_queue.lock().unwrap();
-                    while !(*queue).is_empty() {
-                        let event = (*queue).pop_front().expect("queue somehow empty?");
-                        match event.message {
-                            WM_KEYDOWN => match event.data {
-                                EventData::KeyDownData(wparam, lparam) => {
-                                    let virtual_key = VIRTUAL_KEY(wparam.0 as u16);
-                                    match virtual_key {
-                                        VK_LEFT => {
-                                            // TODO: this does not work as expected. Some internet
-                                            // discussions suggest that maybe I need to wait longer
-                                            // to see these fields get populated?
-                                            // https://stackoverflow.com/questions/44897991/wm-keydown-repeat-count
-                                            let key_flags = lparam.0 as u32;
-                                            let was_key_already_down: bool =
-                                                (key_flags & KF_REPEAT) == KF_REPEAT;
-                                            if !was_key_already_down {
-                                                input_state.left = true;
-                                            }
-                                        }
-                                        VK_RIGHT => {
-                                            let key_flags = lparam.0 as u32;
-                                            let was_key_already_down: bool =
-                                                (key_flags & KF_REPEAT) == KF_REPEAT;
-                                            if !was_key_already_down {
-                                                input_state.right = true;
-                                            }
-                                        }
-                                        VK_UP => {
-                                            input_state.forward = true;
-                                        }
-                                        VK_DOWN => {
-                                            input_state.backward = true;
-                                        }
-                                        VK_SPACE => {
-                                            input_state.jump = true;
-                                        }
-                                        _ => {}
-                                    }
+                    input_queue_depth = queue.len();
+                    while let Some((at, event)) = queue.pop_front() {
+                        match event {
+                            WindowEvent::KeyAction { key, down: true } => {
+                                // Windows repeats a key's press while it is held. Only the first
+                                // one is a press.
+                                if !key_table.press(key.0) {
+                                    continue;
                                 }
-                                _ => {
-                                    printUnexpected!("WM_KEYDOWN");
-                                }
-                            },
-                            WM_KEYUP => match event.data {
-                                EventData::KeyUpData(wparam, _lparam) => {
-                                    let virtual_key = VIRTUAL_KEY(wparam.0 as u16);
-                                    match virtual_key {
-                                        VK_LEFT => {
-                                            input_state.left = false;
-                                        }
-                                        VK_RIGHT => {
-                                            input_state.right = false;
-                                        }
-                                        VK_UP => {
-                                            input_state.forward = false;
-                                        }
-                                        VK_DOWN => {
-                                            input_state.backward = false;
-                                        }
-                                        _ => {}
+                                // While typing, keys are text rather than controls. Only the
+                                // keys that edit the text are handled here, as characters come
+                                // as their own events.
+                                if app.is_entering_text() {
+                                    let text_key = match key {
+                                        VK_BACK => Some(TextKey::Backspace),
+                                        VK_RETURN => Some(TextKey::Enter),
+                                        VK_ESCAPE => Some(TextKey::Escape),
+                                        _ => None,
+                                    };
+                                    if let Some(text_key) = text_key {
+                                        app.press_text_key(text_key);
                                     }
+                                    continue;
                                 }
-                                _ => {
-                                    printUnexpected!("WM_KEYUP");
-                                }
-                            },
-                            WM_MOUSEMOVE => match event.data {
-                                EventData::MouseMoveData(pt) => {
-                                    let center_x = (game_rect.right + game_rect.left) / 2;
-                                    let center_y = (game_rect.bottom + game_rect.top) / 2;
-                                    let near_center_x = abs(pt.x - center_x)
-                                        < (game_rect.right - game_rect.left) / 4;
-                                    let near_center_y = abs(pt.y - center_y)
-                                        < (game_rect.bottom - game_rect.top) / 4;
-                                    if near_center_x && near_center_y {
-                                        input_state.mouse_x += pt.x - center_x;
-                                        input_state.mouse_y += pt.y - center_y;
-                                    } else  {
-                                        debug_println!("Detected mouse outside of central box. Mouse entering window for first time?");
+                                let control = app.settings().bindings.control(key.0);
+                                let binding = app.is_binding_key();
+                                match (key, control) {
+                                    _ if binding => {
+                                        app.bind_key(key.0);
+                                    }
+                                    (VK_RETURN, _) => {
+                                        input_buffer.push(at, InputEvent::Press(Button::Confirm));
+                                    }
+                                    (VK_ESCAPE, _) => {
+                                        input_buffer.push(at, InputEvent::Press(Button::Back));
+                                    }
+                                    (VK_F2, _) => {
+                                        fly_input.toggle = true;
+                                    }
+                                    (VK_F4, _) => {
+                                        app.toggle_grid_overlay();
+                                    }
+                                    (VK_F6, _) => {
+                                        app.toggle_rotation_check();
+                                    }
+                                    (VK_F7, _) => {
+                                        app.toggle_movement_mode();
                                     }
+                                    (VK_F8, _) => {
+                                        app.cycle_time_scale();
+                                    }
+                                    (VK_OEM_PLUS, _) => {
+                                        fly_input.speed_steps += 1;
+                                    }
+                                    (VK_OEM_MINUS, _) => {
+                                        fly_input.speed_steps -= 1;
+                                    }
+                                    (_, Some(control)) => {
+                                        input_buffer.push(at, InputEvent::Press(control.into()));
+                                    }
+                                    _ => {}
                                 }
-                                _ => {
-                                    printUnexpected!("WM_MOUSEMOVE");
+                                if !binding {
+                                    set_fly_key(&mut fly_input, key, true);
                                 }
-                            },
-                            WM_SIZE => match event.data {
-                                EventData::ResizeData(rect) => {
-                                    game_rect = rect;
-                                    let width = rect.right - rect.left;
-                                    let height = rect.bottom - rect.top;
-                                    game_state.change_camera_aspect(width as f32 / height as f32);
+                            }
+                            WindowEvent::KeyAction { key, down: false } => {
+                                key_table.release(key.0);
+                                let control = app.settings().bindings.control(key.0);
+                                if let Some(control) = control {
+                                    input_buffer.push(at, InputEvent::Release(control.into()));
                                 }
-                                _ => {
-                                    printUnexpected!("WM_SIZE");
+                                set_fly_key(&mut fly_input, key, false);
+                            }
+                            WindowEvent::FocusLost => {
+                                for key in key_table.release_all() {
+                                    let control = app.settings().bindings.control(key);
+                                    if let Some(control) = control {
+                                        input_buffer.push(at, InputEvent::Release(control.into()));
+                                    }
+                                    set_fly_key(&mut fly_input, VIRTUAL_KEY(key), false);
                                 }
-                            },
-                            _ => (),
+                            }
+                            WindowEvent::Char(c) => {
+                                app.type_char(c);
+                            }
+                            WindowEvent::MouseWheel(delta) => {
+                                wheel_remainder += delta as i32;
+                                let notches = wheel_remainder / WHEEL_DELTA as i32;
+                                wheel_remainder %= WHEEL_DELTA as i32;
+                                if notches != 0 {
+                                    input_buffer.push(at, InputEvent::Wheel(notches));
+                                }
+                            }
+                            WindowEvent::MouseDelta { dx, dy, .. } => {
+                                input_buffer.push(at, InputEvent::Mouse { dx, dy });
+                            }
+                            WindowEvent::FileDropped(path) => {
+                                app.open_file(&path);
+                            }
+                            WindowEvent::Resize(rect) => {
+                                game_rect = rect;
+                                let width = rect.right - rect.left;
+                                let height = rect.bottom - rect.top;
+                                app.game_state_mut()
+                                    .change_camera_aspect(width as f32 / height as f32);
+                            }
+                            WindowEvent::AppQuit => return,
                         }
                     }
                 }
                 let current_time = Instant::now();
+                // Ticks come faster in fast-forward and slower in slow motion, each still moving
+                // the game on by the same time.
+                let tick_time = app.real_time_per_tick();
                 // I am assuming it's rare or impossible for current_time - last_tick to be more
                 // than two frames. That means I expect this loop to always run to 0 or 1
                 // iterations.
                 //
-                // If the time window does span multiple frames, I just pass the same input on
-                // every frame.
-                while current_time - last_tick >= *TIME_PER_GAME_TICK {
-                    last_tick = last_tick + *TIME_PER_GAME_TICK;
-                    game_state.update(&input_state, last_tick);
-                    input_state.post_update_reset();
+                // If the time window does span multiple frames, each tick still only gets the
+                // input that happened within its own window.
+                while current_time - last_tick >= tick_time {
+                    last_tick = last_tick + tick_time;
+                    let update_start = Instant::now();
+                    let input = {
+                        profiling::scope!("Game tick");
+                        let input_state = input_buffer.take_tick(last_tick);
+                        app.update(&input_state, &fly_input, last_tick)
+                    };
+                    let at = Instant::now();
+                    frame_times.lock().unwrap().record_tick(at - update_start, tick_time);
+                    if let Some(metrics) = &metrics {
+                        metrics.lock().unwrap().record_tick(TickSample {
+                            at,
+                            duration: at - update_start,
+                            input_queue_depth,
+                        });
+                    }
+                    // Only ticks that reached the game are recorded, so replays line up.
+                    if let Some(input) = input {
+                        if recording {
+                            input_recording.lock().unwrap().push_tick(&input);
+                        }
+                        input_history.lock().unwrap().push_tick(&input);
+                    }
+                    fly_input.post_update_reset();
+                }
+                if app.is_entering_text() != entering_text {
+                    entering_text = app.is_entering_text();
+                    let wparam = WPARAM(entering_text as usize);
+                    let _ = unsafe { PostMessageA(window, WM_TEXT_ENTRY, wparam, LPARAM(0)) };
+                }
+                if app.take_quit_request() {
+                    let _ = unsafe { PostMessageA(window, WM_CLOSE, WPARAM(0), LPARAM(0)) };
+                }
+                let width = (game_rect.right - game_rect.left) as f32;
+                let height = (game_rect.bottom - game_rect.top) as f32;
+                {
+                    profiling::scope!("Snapshot send");
+                    let _ = tx.send(app.frame(width, height));
                 }
-                let _ = tx.send(game_state.clone());
 
-                let time_to_next_tick = last_tick + *TIME_PER_GAME_TICK - Instant::now();
-                if time_to_next_tick > Duration::from_micros(1500) {
-                    thread::sleep(Duration::from_millis(time_to_next_tick.as_millis() as u64 - 1));
+                if Instant::now() >= last_pacing_print + Duration::from_secs(2) {
+                    log_println!("Game pacing: {}", pacer.take_stats());
+                    last_pacing_print = Instant::now();
                 }
+                pacer.wait_until(last_tick + app.real_time_per_tick());
             }
         });
     }
@@ -314,29 +596,171 @@ fn main() -> windows::core::Result<()> {
     let mut message = MSG::default();
     unsafe {
         while GetMessageA(&mut message, None, 0, 0).into() {
+            // Turns key presses into the WM_CHAR messages text fields are typed with.
+            TranslateMessage(&message);
             DispatchMessageA(&message);
         }
     }
 
+    if let Some(path) = record_input_path {
+        let text = input_recording.lock().unwrap().to_text();
+        if let Err(e) = std::fs::write(&path, text) {
+            log_println!("Failed to write input recording to {}: {}", path, e);
+        }
+    }
+    frame_times.lock().unwrap().log_summary();
+    if let (Some(path), Some(metrics)) = (metrics_path, metrics) {
+        if let Err(e) = metrics.lock().unwrap().write_csv(&path) {
+            log_println!("Failed to write metrics: {:?}", e);
+        }
+    }
+
     Ok(())
 }
 
-#[derive(Clone, Copy)]
-struct WindowsEvent {
-    message: u32,
-    data: EventData,
+// What the window tells the game and render threads, translated from its messages once in
+// wndproc so neither thread has to know about them.
+#[derive(Clone, Debug)]
+enum WindowEvent {
+    // The client area's new size, empty while the window is minimized.
+    Resize(RECT),
+    // How far the cursor moved from the middle of the window before going back there, and where
+    // it got to, in screen coordinates.
+    MouseDelta { dx: i32, dy: i32, cursor: POINT },
+    // The wheel's rotation, in multiples of WHEEL_DELTA for a notched wheel.
+    MouseWheel(i16),
+    // A key went down or up, with or without Alt held. Held keys keep going down.
+    KeyAction { key: VIRTUAL_KEY, down: bool },
+    // A character typed, or one of the characters the IME composed.
+    Char(char),
+    // The window lost the keyboard, so keys held now won't say when they're let go.
+    FocusLost,
+    // The path of a file dropped on the window. Dropping several sends one event each.
+    FileDropped(String),
+    // The window is closing, and the thread should stop.
+    AppQuit,
 }
 
-#[derive(Clone, Copy)]
-enum EventData {
-    ResizeData(RECT),
-    EmptyData(),
-    MouseMoveData(POINT),
-    KeyDownData(WPARAM, LPARAM),
-    KeyUpData(WPARAM, LPARAM),
+// Events in the order they happened, stamped with when the window got them.
+type EventQueue = VecDeque<(Instant, WindowEvent)>;
+
+// Posted by the game thread with a wparam of 1 when a text field gets focused, and 0 when it
+// stops being. The IME belongs to the window's thread, so that's where it's switched.
+const WM_TEXT_ENTRY: u32 = WM_APP;
+
+// Turns the IME on for typing into a text field, or off for playing.
+fn set_text_entry(window: HWND, on: bool) {
+    // With no context and no flags, the window is left without an IME. IACE_DEFAULT gives it
+    // back the default one.
+    let flags = if on { IACE_DEFAULT } else { 0 };
+    unsafe { ImmAssociateContextEx(window, HIMC(0), flags) };
+}
+
+// Decodes a WM_CHAR character. The window is an ANSI one, so characters come in the system code
+// page. The ones needing two bytes only come from the IME, which is read as UTF-16 instead.
+fn decode_char(byte: u8) -> Option<char> {
+    if byte.is_ascii() {
+        return Some(byte as char);
+    }
+    let mut wide = [0u16; 2];
+    let len =
+        unsafe { MultiByteToWideChar(CP_ACP, MB_ERR_INVALID_CHARS, &[byte], Some(&mut wide)) };
+    char::decode_utf16(wide[..len.max(0) as usize].iter().copied()).next()?.ok()
+}
+
+// Takes the text the IME has finished composing.
+fn ime_result(window: HWND) -> Vec<char> {
+    unsafe {
+        let context = ImmGetContext(window);
+        if context.is_invalid() {
+            return Vec::new();
+        }
+        // Called without a buffer, it gives the size of the text in bytes.
+        let size = ImmGetCompositionStringW(context, GCS_RESULTSTR, None, 0);
+        let mut wide = vec![0u16; size.max(0) as usize / 2];
+        let buffer = wide.as_mut_ptr() as *mut std::ffi::c_void;
+        ImmGetCompositionStringW(context, GCS_RESULTSTR, Some(buffer), size.max(0) as u32);
+        ImmReleaseContext(window, context);
+        char::decode_utf16(wide).filter_map(|c| c.ok()).collect()
+    }
+}
+
+// Parked while the window is out of sight, for wndproc to wake when it comes back.
+static RENDER_THREAD: OnceLock<Thread> = OnceLock::new();
+
+fn wake_render_thread() {
+    if let Some(thread) = RENDER_THREAD.get() {
+        thread.unpark();
+    }
 }
 
-type EventQueue = VecDeque<WindowsEvent>;
+// Where the window was before Alt+Enter made it cover its monitor, while it does.
+static WINDOWED_RECT: Mutex<Option<RECT>> = Mutex::new(None);
+
+// Switches the window between covering the whole of its monitor and where it was before.
+fn toggle_fullscreen(window: HWND) {
+    let mut windowed = WINDOWED_RECT.lock().unwrap();
+    let rect = match windowed.take() {
+        Some(rect) => rect,
+        None => {
+            let mut current = RECT::default();
+            if unsafe { GetWindowRect(window, &mut current) }.is_err() {
+                return;
+            }
+            let Some(monitor) = display::monitor_rect(window) else {
+                return;
+            };
+            *windowed = Some(current);
+            monitor
+        }
+    };
+    let (width, height) = (rect.right - rect.left, rect.bottom - rect.top);
+    let flags = SWP_NOZORDER | SWP_NOACTIVATE;
+    let moved = unsafe { SetWindowPos(window, None, rect.left, rect.top, width, height, flags) };
+    if let Err(e) = moved {
+        log_println!("Failed to resize the window: {}", e);
+        return;
+    }
+    // Mouse look keeps the cursor in the middle of the window, which has just moved. Left where
+    // it was, the cursor's next move would be taken for a jump across the screen.
+    let mut client = RECT::default();
+    if unsafe { GetClientRect(window, &mut client) }.is_ok() {
+        let center = client_center(window, client);
+        let _ = unsafe { SetCursorPos(center.x, center.y) };
+    }
+}
+
+// Saves where the window is, to put it back there on the next launch.
+fn save_window_placement(window: HWND) {
+    let windowed = *WINDOWED_RECT.lock().unwrap();
+    let Some(placement) = WindowPlacement::of(window, windowed) else {
+        log_println!("Failed to find where the window is");
+        return;
+    };
+    if let Err(e) = placement.save(WINDOW_FILE) {
+        log_println!("Failed to save the window placement: {:?}", e);
+    }
+}
+
+// The middle of `rect`, the window's client area, in screen coordinates like the cursor's.
+fn client_center(window: HWND, rect: RECT) -> POINT {
+    let mut center = POINT { x: (rect.right + rect.left) / 2, y: (rect.bottom + rect.top) / 2 };
+    let _ = unsafe { ClientToScreen(window, &mut center) };
+    center
+}
+
+// The free camera flies with fixed keys, whatever the player's controls are bound to.
+fn set_fly_key(fly_input: &mut FlyInput, key: VIRTUAL_KEY, down: bool) {
+    match key {
+        VK_W => fly_input.forward = down,
+        VK_S => fly_input.backward = down,
+        VK_A => fly_input.left = down,
+        VK_D => fly_input.right = down,
+        VK_E => fly_input.up = down,
+        VK_Q => fly_input.down = down,
+        _ => {}
+    }
+}
 
 extern "system" fn wndproc(window: HWND, message: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     let gpu_queue_ptr = unsafe { GetWindowLongPtrA(window, WINDOW_LONG_PTR_INDEX(0)) }
@@ -345,21 +769,28 @@ extern "system" fn wndproc(window: HWND, message: u32, wparam: WPARAM, lparam: L
         unsafe { GetWindowLongPtrA(window, WINDOW_LONG_PTR_INDEX(EVENT_QUEUE_SIZE_IN_BYTES)) }
             as *mut Arc<Mutex<EventQueue>>;
     if gpu_queue_ptr.is_null() || input_queue_ptr.is_null() {
-        debug_println!("Exiting wndproc early due to null event queues.");
+        log_println!("Exiting wndproc early due to null event queues.");
         return unsafe { DefWindowProcA(window, message, wparam, lparam) };
     }
+    // Queues `event` for the render or game thread.
+    let push = |queue: *mut Arc<Mutex<EventQueue>>, event: WindowEvent| {
+        unsafe { (*queue).lock().unwrap() }.push_back((Instant::now(), event));
+    };
+    let to_gpu = |event| push(gpu_queue_ptr, event);
+    let to_game = |event| push(input_queue_ptr, event);
+    let key = VIRTUAL_KEY(wparam.0 as u16);
     match message {
         WM_PAINT => {
             debug_println!("WM_PAINT");
-            {
-                let mut queue = unsafe { (*gpu_queue_ptr).lock().unwrap() };
-                (*queue).push_back(WindowsEvent { message, data: EventData::EmptyData() });
-            }
             unsafe { ValidateRect(window, None) };
             LRESULT(0)
         }
         WM_DESTROY => {
-            debug_println!("WM_DESTROY");
+            log_println!("WM_DESTROY");
+            save_window_placement(window);
+            to_gpu(WindowEvent::AppQuit);
+            to_game(WindowEvent::AppQuit);
+            wake_render_thread();
             unsafe { PostQuitMessage(0) };
             LRESULT(0)
         }
@@ -367,15 +798,22 @@ extern "system" fn wndproc(window: HWND, message: u32, wparam: WPARAM, lparam: L
             debug_println!("WM_SIZE");
             let mut rect: RECT = unsafe { mem::zeroed() };
             let _ = unsafe { GetClientRect(window, &mut rect) };
-            let event = WindowsEvent { message, data: EventData::ResizeData(rect) };
-            {
-                let mut gpu_queue = unsafe { (*gpu_queue_ptr).lock().unwrap() };
-                (*gpu_queue).push_back(event);
-            }
-            {
-                let mut input_queue = unsafe { (*input_queue_ptr).lock().unwrap() };
-                (*input_queue).push_back(event);
+            to_gpu(WindowEvent::Resize(rect));
+            to_game(WindowEvent::Resize(rect));
+            // Restoring a minimized window resizes it.
+            wake_render_thread();
+            LRESULT(0)
+        }
+        // Coming to the front can bring the window back into sight.
+        WM_ACTIVATE => {
+            if wparam.0 as u16 as u32 == WA_INACTIVE {
+                to_game(WindowEvent::FocusLost);
             }
+            wake_render_thread();
+            unsafe { DefWindowProcA(window, message, wparam, lparam) }
+        }
+        WM_KILLFOCUS => {
+            to_game(WindowEvent::FocusLost);
             LRESULT(0)
         }
         WM_MOUSEACTIVATE => {
@@ -384,45 +822,109 @@ extern "system" fn wndproc(window: HWND, message: u32, wparam: WPARAM, lparam: L
         }
         WM_MOUSEMOVE => {
             // debug_println!("WM_MOUSEMOVE");
-            let mut pt: POINT = unsafe { mem::zeroed() };
-            let _ = unsafe { GetCursorPos(&mut pt) };
-
-            let mut gpu_queue = unsafe { (*gpu_queue_ptr).lock().unwrap() };
-            (*gpu_queue).push_back(WindowsEvent { message, data: EventData::MouseMoveData(pt) });
-            let mut input_queue = unsafe { (*input_queue_ptr).lock().unwrap() };
-            (*input_queue).push_back(WindowsEvent { message, data: EventData::MouseMoveData(pt) });
-
+            let mut cursor: POINT = unsafe { mem::zeroed() };
+            let _ = unsafe { GetCursorPos(&mut cursor) };
             let mut rect: RECT = unsafe { mem::zeroed() };
             let _ = unsafe { GetClientRect(window, &mut rect) };
-            let center_x = (rect.right + rect.left) / 2;
-            let center_y = (rect.bottom + rect.top) / 2;
+            let center = client_center(window, rect);
+            let (dx, dy) = (cursor.x - center.x, cursor.y - center.y);
+            to_gpu(WindowEvent::MouseDelta { dx, dy, cursor });
+            let near_center_x = abs(dx) < (rect.right - rect.left) / 4;
+            let near_center_y = abs(dy) < (rect.bottom - rect.top) / 4;
+            if near_center_x && near_center_y {
+                to_game(WindowEvent::MouseDelta { dx, dy, cursor });
+            } else {
+                debug_println!(
+                    "Detected mouse outside of central box. Mouse entering window for first time?"
+                );
+            }
             unsafe {
-                let _ = SetCursorPos(center_x, center_y);
+                let _ = SetCursorPos(center.x, center.y);
             }
 
             LRESULT(0)
         }
+        WM_MOUSEWHEEL => {
+            // The high word of wparam is the signed rotation.
+            let delta = (wparam.0 >> 16) as u16 as i16;
+            to_game(WindowEvent::MouseWheel(delta));
+            LRESULT(0)
+        }
+        // F3 toggles the stats HUD, which belongs to the render thread.
+        WM_KEYDOWN if key == VK_F3 => {
+            to_gpu(WindowEvent::KeyAction { key, down: true });
+            LRESULT(0)
+        }
         WM_KEYDOWN => {
-            {
-                let mut queue = unsafe { (*input_queue_ptr).lock().unwrap() };
-                (*queue).push_back(WindowsEvent {
-                    message,
-                    data: EventData::KeyDownData(wparam, lparam),
-                });
-            }
+            to_game(WindowEvent::KeyAction { key, down: true });
             LRESULT(0)
         }
         WM_KEYUP => {
             debug_println!("WM_KEYUP");
-            {
-                let mut queue = unsafe { (*input_queue_ptr).lock().unwrap() };
-                (*queue).push_back(WindowsEvent {
-                    message,
-                    data: EventData::KeyUpData(wparam, lparam),
-                });
+            to_game(WindowEvent::KeyAction { key, down: false });
+            LRESULT(0)
+        }
+        // F10 is a system key, so it arrives as WM_SYSKEYDOWN rather than WM_KEYDOWN.
+        WM_SYSKEYDOWN if key == VK_F10 => {
+            to_gpu(WindowEvent::KeyAction { key, down: true });
+            LRESULT(0)
+        }
+        // Windows closes the window on Alt+F4.
+        WM_SYSKEYDOWN if key == VK_F4 => unsafe { DefWindowProcA(window, message, wparam, lparam) },
+        WM_SYSKEYDOWN if key == VK_RETURN => {
+            // Bit 29 is set while Alt is down, and bit 30 when the key already was: a repeat.
+            if lparam.0 & (1 << 29) != 0 && lparam.0 & (1 << 30) == 0 {
+                toggle_fullscreen(window);
+            }
+            LRESULT(0)
+        }
+        // Every other key pressed with Alt held goes to the game like any other key. Windows
+        // would treat it as a menu shortcut, and on releasing Alt freeze the game in menu mode.
+        WM_SYSKEYDOWN | WM_SYSKEYUP => {
+            to_game(WindowEvent::KeyAction { key, down: message == WM_SYSKEYDOWN });
+            LRESULT(0)
+        }
+        WM_DROPFILES => {
+            debug_println!("WM_DROPFILES");
+            let drop = HDROP(wparam.0 as isize);
+            unsafe {
+                // Index 0xFFFFFFFF asks how many files there are, and no buffer a path's length.
+                let count = DragQueryFileW(drop, u32::MAX, None);
+                for i in 0..count {
+                    let mut path = vec![0; DragQueryFileW(drop, i, None) as usize + 1];
+                    let len = DragQueryFileW(drop, i, Some(&mut path)) as usize;
+                    to_game(WindowEvent::FileDropped(String::from_utf16_lossy(&path[..len])));
+                }
+                DragFinish(drop);
             }
             LRESULT(0)
         }
+        WM_CHAR => {
+            if let Some(c) = decode_char(wparam.0 as u8) {
+                to_game(WindowEvent::Char(c));
+            }
+            LRESULT(0)
+        }
+        // Alt+letter would otherwise look for a menu to open, and beep when there isn't one.
+        WM_SYSCHAR => LRESULT(0),
+        // Left to DefWindowProc, the finished text would come as WM_CHAR a byte at a time in the
+        // system code page, which can't hold every character the IME composes. It's read as
+        // UTF-16 here instead, and the rest of the message still goes to DefWindowProc to draw
+        // the composition.
+        WM_IME_COMPOSITION => {
+            let mut lparam = lparam;
+            if lparam.0 as u32 & GCS_RESULTSTR.0 != 0 {
+                for c in ime_result(window) {
+                    to_game(WindowEvent::Char(c));
+                }
+                lparam = LPARAM(lparam.0 & !(GCS_RESULTSTR.0 as isize));
+            }
+            unsafe { DefWindowProcA(window, message, wparam, lparam) }
+        }
+        WM_TEXT_ENTRY => {
+            set_text_entry(window, wparam.0 != 0);
+            LRESULT(0)
+        }
         WM_SETCURSOR => unsafe {
             // debug_println!("WM_SETCURSOR");
             SetCursor(HCURSOR { 0: 0 });
@@ -0,0 +1,285 @@
+/* Material: how a surface looks, defined in RON files so it isn't limited to what an MTL file
+ * can say. */
+use crate::{
+    game_state::Shader,
+    gpu_cache,
+    gpu_memory::{Allocation, Category},
+    model::Material,
+    resources, texture,
+};
+
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+/// Material definitions live here, one per file, named `<material name>.ron`. A definition
+/// replaces the MTL material of the same name in every model that uses it.
+pub const MATERIALS_DIR: &str = "materials";
+
+/// The tile textures, one per `game_state::TileLayer` in order. Models without materials draw
+/// Shader::Tiles instances from these.
+pub const TILE_TEXTURES: [&str; 4] =
+    ["tiles/wall.png", "tiles/floor.png", "tiles/goal.png", "tiles/box.png"];
+
+/// Any field can be left out. Textures left out are plain: white diffuse, a flat normal, white
+/// emissive (so the emission color alone sets the glow), and a single white layer. For example:
+/// ```text
+/// (diffuse: Some("cube-diffuse.jpg"), emissive: Some("glow.png"), emission: (4.0, 2.0, 1.0))
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialDef {
+    pub diffuse: Option<String>,
+    // Tangent-space, like the MTL map_Bump.
+    pub normal: Option<String>,
+    pub emissive: Option<String>,
+    // Multiplies the emissive texture, in linear HDR units; values above 1.0 bloom. Black, the
+    // default, doesn't glow at all.
+    pub emission: [f32; 3],
+    // How much of the environment the surface mirrors, tinted by its color, from 0.0 to 1.0. Only
+    // used by Shader::Texture and Shader::Tiles, which shade with the material.
+    pub reflectivity: f32,
+    // Layers of the texture array Shader::Tiles draws from, picked by each instance's layer.
+    // Layers are scaled to the size of the first.
+    pub layers: Vec<String>,
+    // Multiplies the diffuse texture, and the layers, alpha included.
+    pub tint: [f32; 4],
+    // Instances drawn with Shader::Texture are shaded with this instead. Texture keeps the
+    // textured look.
+    pub shader: Shader,
+}
+impl Default for MaterialDef {
+    fn default() -> Self {
+        MaterialDef {
+            diffuse: None,
+            normal: None,
+            emissive: None,
+            emission: [0.0, 0.0, 0.0],
+            reflectivity: 0.0,
+            layers: Vec::new(),
+            tint: [1.0, 1.0, 1.0, 1.0],
+            shader: Shader::Texture,
+        }
+    }
+}
+impl MaterialDef {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+    /// The definition for the material `name`, or None if there isn't one.
+    pub async fn load(name: &str) -> anyhow::Result<Option<Self>> {
+        let file_name = file_name(name);
+        if !resources::exists(&file_name) {
+            return Ok(None);
+        }
+        let text = resources::load_string(&file_name).await?;
+        Ok(Some(Self::parse(&text).map_err(|e| e.context(format!("parsing {}", file_name)))?))
+    }
+    /// What an MTL material says: its diffuse and bump maps.
+    pub fn from_mtl(material: &tobj::Material) -> Self {
+        MaterialDef {
+            diffuse: material.diffuse_texture.clone(),
+            normal: material.normal_texture.clone(),
+            ..Default::default()
+        }
+    }
+    /// The definition for models without materials: plain, with the tile textures as its layers.
+    pub fn tiles() -> Self {
+        MaterialDef {
+            layers: TILE_TEXTURES.iter().map(|file_name| file_name.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+    /// The texture files the definition names.
+    pub fn textures(&self) -> impl Iterator<Item = &str> {
+        [&self.diffuse, &self.normal, &self.emissive]
+            .into_iter()
+            .flatten()
+            .chain(&self.layers)
+            .map(String::as_str)
+    }
+}
+
+/// Where the definition for the material `name` would be.
+pub fn file_name(name: &str) -> String {
+    format!("{}/{}.ron", MATERIALS_DIR, name)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    tint: [f32; 4],
+    emission: [f32; 3],
+    shader: u32,
+    has_normal_map: u32,
+    reflectivity: f32,
+    _padding: [u32; 2],
+}
+const _: () = assert!(std::mem::size_of::<MaterialUniform>() == 48);
+
+/// Group 0 of the scene pipelines: diffuse, sampler, normal, emissive, the uniform, then the
+/// layers.
+pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    };
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            texture_entry(0, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                // This should match the filterable field of the texture entries.
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            texture_entry(2, wgpu::TextureViewDimension::D2),
+            texture_entry(3, wgpu::TextureViewDimension::D2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(5, wgpu::TextureViewDimension::D2Array),
+        ],
+        label: Some("texture_bind_group_layout"),
+    })
+}
+
+async fn load_image(
+    file_name: Option<&str>,
+    fallback: [u8; 4],
+) -> anyhow::Result<image::DynamicImage> {
+    Ok(match file_name {
+        Some(file_name) => image::load_from_memory(&resources::load_binary(file_name).await?)?,
+        None => image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba(fallback),
+        )),
+    })
+}
+
+// The named texture, or a single pixel of `fallback` when the definition leaves it out.
+async fn load_or_plain(
+    file_name: Option<&str>,
+    fallback: [u8; 4],
+    format: wgpu::TextureFormat,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let img = load_image(file_name, fallback).await?;
+    texture::Texture::from_image_with_format(device, queue, &img, file_name, format)
+}
+
+// The named layers as a texture array, or a single white layer when there are none.
+async fn load_layers(
+    name: &str,
+    layers: &[String],
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let mut images = Vec::with_capacity(layers.len().max(1));
+    for file_name in layers {
+        images.push(load_image(Some(file_name), [255, 255, 255, 255]).await?);
+    }
+    if images.is_empty() {
+        images.push(load_image(None, [255, 255, 255, 255]).await?);
+    }
+    texture::Texture::array_from_images(
+        device,
+        queue,
+        &images,
+        Some(&format!("{} Layers", name)),
+        wgpu::TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+pub async fn create_material(
+    name: &str,
+    def: &MaterialDef,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+) -> anyhow::Result<Material> {
+    let srgb = wgpu::TextureFormat::Rgba8UnormSrgb;
+    let diffuse_texture =
+        load_or_plain(def.diffuse.as_deref(), [255, 255, 255, 255], srgb, device, queue).await?;
+    let normal_texture = load_or_plain(
+        def.normal.as_deref(),
+        [128, 128, 255, 255],
+        wgpu::TextureFormat::Rgba8Unorm,
+        device,
+        queue,
+    )
+    .await?;
+    let emissive_texture =
+        load_or_plain(def.emissive.as_deref(), [255, 255, 255, 255], srgb, device, queue).await?;
+    let layers_texture = load_layers(name, &def.layers, device, queue).await?;
+    let uniform = MaterialUniform {
+        tint: def.tint,
+        emission: def.emission,
+        shader: def.shader as u32,
+        has_normal_map: def.normal.is_some() as u32,
+        reflectivity: def.reflectivity,
+        _padding: [0, 0],
+    };
+    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} Material Buffer", name)),
+        contents: bytemuck::cast_slice(&[uniform]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let uniform_memory = Allocation::new(Category::Uniforms, uniform_buffer.size());
+    let bind_group = gpu_cache::bind_group(
+        device,
+        &wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&emissive_texture.view),
+                },
+                wgpu::BindGroupEntry { binding: 4, resource: uniform_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&layers_texture.view),
+                },
+            ],
+            label: Some(name),
+        },
+    );
+
+    Ok(Material {
+        name: name.to_string(),
+        diffuse_texture,
+        normal_texture,
+        emissive_texture,
+        layers_texture,
+        uniform_buffer,
+        bind_group,
+        uniform_memory,
+    })
+}
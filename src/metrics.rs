@@ -0,0 +1,80 @@
+/* Metrics: per-tick and per-frame timings from both threads, written out as CSV. */
+use anyhow::Context;
+use std::time::{Duration, Instant};
+
+pub struct TickSample {
+    // When the tick finished.
+    pub at: Instant,
+    // How long App::update took.
+    pub duration: Duration,
+    // Window events waiting for the game thread when it drained its queue.
+    pub input_queue_depth: usize,
+}
+
+pub struct FrameSample {
+    // When the frame was presented.
+    pub at: Instant,
+    // Since the previous frame was presented.
+    pub frame_time: Duration,
+    // From the game thread making the frame to it being presented. None before the first frame.
+    pub latency: Option<Duration>,
+    // Window events waiting for the render thread when it drained its queue.
+    pub gpu_queue_depth: usize,
+    // Frames taken off the channel for this one. Only the newest is drawn, so more than one means
+    // some were skipped.
+    pub frames_received: usize,
+}
+
+/// Samples from the game and render threads, kept for a `--metrics <file>` run and written out on
+/// exit.
+///
+/// The CSV has one row per tick or frame, in time order, with the columns
+/// `thread,time_s,duration_ms,latency_ms,queue_depth,frames_received`. For a game row the duration
+/// is the tick's update time; for a render row it's the frame time. Columns that don't apply to a
+/// row are left empty.
+pub struct Metrics {
+    start: Instant,
+    ticks: Vec<TickSample>,
+    frames: Vec<FrameSample>,
+}
+impl Metrics {
+    pub fn new(start: Instant) -> Self {
+        Metrics { start, ticks: Vec::new(), frames: Vec::new() }
+    }
+    pub fn record_tick(&mut self, sample: TickSample) {
+        self.ticks.push(sample);
+    }
+    pub fn record_frame(&mut self, sample: FrameSample) {
+        self.frames.push(sample);
+    }
+    pub fn to_csv(&self) -> String {
+        let ms = |duration: Duration| format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        let mut rows: Vec<(Instant, String)> = Vec::new();
+        for tick in &self.ticks {
+            let time = tick.at.saturating_duration_since(self.start).as_secs_f64();
+            let row =
+                format!("game,{:.4},{},,{},\n", time, ms(tick.duration), tick.input_queue_depth);
+            rows.push((tick.at, row));
+        }
+        for frame in &self.frames {
+            let time = frame.at.saturating_duration_since(self.start).as_secs_f64();
+            let row = format!(
+                "render,{:.4},{},{},{},{}\n",
+                time,
+                ms(frame.frame_time),
+                frame.latency.map_or(String::new(), ms),
+                frame.gpu_queue_depth,
+                frame.frames_received
+            );
+            rows.push((frame.at, row));
+        }
+        rows.sort_by_key(|(at, _)| *at);
+        let mut csv =
+            "thread,time_s,duration_ms,latency_ms,queue_depth,frames_received\n".to_string();
+        csv.extend(rows.into_iter().map(|(_, row)| row));
+        csv
+    }
+    pub fn write_csv(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_csv()).with_context(|| format!("writing metrics to {}", path))
+    }
+}
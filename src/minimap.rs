@@ -0,0 +1,86 @@
+/* Minimap: a small top-down map of the puzzle grid. The UI renderer redraws it into its own
+ * texture whenever it changes and shows that in a corner of the screen. */
+use crate::{
+    level::{Level, Tile},
+    ui::UiLayer,
+};
+
+// Texels along each edge of a tile. The texture is shown at one texel per screen pixel.
+const TILE_PIXELS: u32 = 8;
+const FLOOR_COLOR: [f32; 4] = [0.25, 0.25, 0.28, 1.0];
+const WALL_COLOR: [f32; 4] = [0.7, 0.7, 0.75, 1.0];
+const GOAL_COLOR: [f32; 4] = [1.0, 0.8, 0.3, 1.0];
+const BOX_COLOR: [f32; 4] = [0.6, 0.4, 0.2, 1.0];
+const BOX_ON_GOAL_COLOR: [f32; 4] = [0.6, 1.0, 0.6, 1.0];
+const PLAYER_COLOR: [f32; 4] = [0.3, 0.6, 1.0, 1.0];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MinimapTile {
+    Floor,
+    Wall,
+    Goal,
+}
+
+/// What the minimap shows: the level's floor, walls and goals, its boxes and the player. Rows
+/// are in the order of the level file, so north is up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MinimapGrid {
+    width: i32,
+    depth: i32,
+    tiles: Vec<MinimapTile>,
+    boxes: Vec<(i32, i32)>,
+    player: (i32, i32),
+}
+impl MinimapGrid {
+    pub fn new(level: &Level, player: (i32, i32)) -> Self {
+        let tiles = (0..level.depth)
+            .flat_map(|z| (0..level.width).map(move |x| (x, z)))
+            .map(|(x, z)| match level.tile(x, z) {
+                Tile::Wall => MinimapTile::Wall,
+                Tile::Goal => MinimapTile::Goal,
+                _ => MinimapTile::Floor,
+            })
+            .collect();
+        MinimapGrid {
+            width: level.width,
+            depth: level.depth,
+            tiles,
+            boxes: level.boxes.clone(),
+            player,
+        }
+    }
+    /// The size of the texture the grid is drawn into, in texels.
+    pub fn texture_size(&self) -> (u32, u32) {
+        (self.width.max(1) as u32 * TILE_PIXELS, self.depth.max(1) as u32 * TILE_PIXELS)
+    }
+    /// Rectangles filling a texture of `texture_size`: a square per tile, with boxes and the
+    /// player inset on top.
+    pub fn draw(&self) -> UiLayer {
+        let mut ui = UiLayer::new();
+        let size = TILE_PIXELS as f32;
+        let mut square = |(x, z): (i32, i32), inset: f32, color| {
+            let (left, top) = (x as f32 * size + inset, z as f32 * size + inset);
+            ui.rect(left, top, size - 2.0 * inset, size - 2.0 * inset, color);
+        };
+        for (i, tile) in self.tiles.iter().enumerate() {
+            let color = match tile {
+                MinimapTile::Floor => FLOOR_COLOR,
+                MinimapTile::Wall => WALL_COLOR,
+                MinimapTile::Goal => GOAL_COLOR,
+            };
+            square((i as i32 % self.width, i as i32 / self.width), 0.0, color);
+        }
+        for &(x, z) in &self.boxes {
+            let on_goal = self.tile(x, z) == Some(MinimapTile::Goal);
+            square((x, z), 1.0, if on_goal { BOX_ON_GOAL_COLOR } else { BOX_COLOR });
+        }
+        square(self.player, 2.0, PLAYER_COLOR);
+        ui
+    }
+    fn tile(&self, x: i32, z: i32) -> Option<MinimapTile> {
+        if x < 0 || z < 0 || x >= self.width || z >= self.depth {
+            return None;
+        }
+        Some(self.tiles[(z * self.width + x) as usize])
+    }
+}
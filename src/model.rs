@@ -1,7 +1,14 @@
-use crate::resources;
+use crate::{
+    gpu_memory::{Allocation, Category},
+    lod,
+    material::{self, MaterialDef},
+    resources,
+};
 
 use anyhow::Result;
+use cgmath::{Matrix4, Quaternion, SquareMatrix, Vector3, VectorSpace};
 use std::io::Cursor;
+use std::sync::Arc;
 use std::{io::BufReader, mem::size_of};
 use wgpu::util::DeviceExt;
 use wgpu::{
@@ -14,12 +21,38 @@ use crate::texture;
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    // The asset files the model was built from: the .obj, its .mtl files and textures. Empty for
+    // the built-in shapes.
+    pub sources: Vec<String>,
+    // Coarser versions of `meshes`, each for drawing further away than the last. Their meshes use
+    // `materials` too.
+    pub lods: Vec<Vec<Mesh>>,
+}
+impl Model {
+    /// The meshes of LOD `level`, where level 0 is the full model.
+    pub fn lod_meshes(&self, level: usize) -> &[Mesh] {
+        if level == 0 {
+            &self.meshes
+        } else {
+            &self.lods[level - 1]
+        }
+    }
+    /// How many levels there are, counting the full model.
+    pub fn lod_count(&self) -> usize {
+        1 + self.lods.len()
+    }
 }
 
+/// Built from a `MaterialDef`; see `material::create_material`.
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
-    pub bind_group: wgpu::BindGroup,
+    pub normal_texture: texture::Texture,
+    pub emissive_texture: texture::Texture,
+    pub layers_texture: texture::Texture,
+    pub uniform_buffer: Buffer,
+    pub bind_group: Arc<wgpu::BindGroup>,
+    pub uniform_memory: Allocation,
 }
 
 pub struct Mesh {
@@ -30,6 +63,8 @@ pub struct Mesh {
     pub material: Option<usize>,
     pub raw_vertices: Vec<ModelVertex>,
     pub raw_indices: Vec<u32>,
+    // Counts both buffers.
+    pub memory: Allocation,
 }
 pub trait DescribeVB {
     fn describe_vb() -> VertexBufferLayout<'static>;
@@ -64,6 +99,48 @@ impl DescribeVB for ModelVertex {
     }
 }
 
+// Vertex of a mesh deformed by a skeleton: each vertex follows up to four joints.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub joints: [u32; 4],
+    pub weights: [f32; 4],
+}
+impl DescribeVB for SkinnedVertex {
+    fn describe_vb() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: size_of::<SkinnedVertex>() as BufferAddress,
+            attributes: &[
+                VertexAttribute { format: VertexFormat::Float32x3, offset: 0, shader_location: 0 },
+                VertexAttribute {
+                    format: VertexFormat::Float32x2,
+                    offset: size_of::<[f32; 3]>() as BufferAddress,
+                    shader_location: 1,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x3,
+                    offset: size_of::<[f32; 5]>() as BufferAddress,
+                    shader_location: 2,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Uint32x4,
+                    offset: size_of::<[f32; 8]>() as BufferAddress,
+                    shader_location: 3,
+                },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: size_of::<[f32; 12]>() as BufferAddress,
+                    shader_location: 4,
+                },
+            ],
+            step_mode: VertexStepMode::Vertex,
+        }
+    }
+}
+
 pub fn cube_mesh(device: &Device, inverted: bool) -> Mesh {
     let vertices: Vec<ModelVertex> =
         [
@@ -193,37 +270,27 @@ pub fn cube_mesh(device: &Device, inverted: bool) -> Mesh {
         0, 1, 2, 1, 3, 2, 4, 6, 5, 5, 6, 7, 8, 9, 10, 10, 9, 11, 12, 14, 13, 13, 14, 15, 16, 17,
         18, 18, 17, 19, 20, 22, 21, 21, 22, 23,
     ];
-    if inverted { indices.reverse(); }
-    let name = "Simple_Cube";
-    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some(&format!("{:?} Vertex Buffer", name)),
-        contents: bytemuck::cast_slice(&vertices),
-        usage: wgpu::BufferUsages::VERTEX,
-    });
-    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some(&format!("{:?} Index Buffer", name)),
-        contents: bytemuck::cast_slice(&indices),
-        usage: wgpu::BufferUsages::INDEX,
-    });
-    Mesh {
-        name: name.to_string(),
-        vertex_buffer,
-        index_buffer,
-        num_elements: indices.len() as u32,
-        material: None,
-        raw_vertices: vertices,
-        raw_indices: indices.into(),
+    if inverted {
+        indices.reverse();
     }
+    create_mesh(device, "Simple_Cube", vertices, indices.into())
 }
 
 pub fn cube_model(device: &Device) -> Model {
-    Model { materials: vec![], meshes: vec![cube_mesh(device, false)] }
+    Model {
+        materials: vec![],
+        meshes: vec![cube_mesh(device, false)],
+        sources: vec![],
+        lods: vec![],
+    }
 }
 
 pub fn double_cube_model(device: &Device) -> Model {
     Model {
         materials: vec![],
         meshes: vec![cube_mesh(device, true), cube_mesh(device, false)],
+        sources: vec![],
+        lods: vec![],
     }
 }
 
@@ -234,10 +301,81 @@ pub async fn load_model(
     layout: &BindGroupLayout,
 ) -> Result<Model> {
     let obj_text = resources::load_string(file_name).await?;
+    let mut sources = vec![file_name.to_string()];
+    sources.extend(mtllibs(&obj_text));
+    let (models, obj_materials) = load_obj(obj_text).await?;
+
+    let mut materials: Vec<Material> = Vec::new();
+    for m in obj_materials {
+        // Watched even when missing, so adding a definition is picked up too.
+        sources.push(material::file_name(&m.name));
+        let def = match MaterialDef::load(&m.name).await? {
+            Some(def) => def,
+            None => MaterialDef::from_mtl(&m),
+        };
+        sources.extend(def.textures().map(str::to_string));
+        materials.push(material::create_material(&m.name, &def, device, queue, layout).await?);
+    }
+
+    let meshes =
+        models.into_iter().map(|m| obj_mesh(device, file_name, m.mesh)).collect::<Vec<_>>();
+
+    // Authored levels are used if there are any; otherwise detailed models get automatic ones.
+    let mut lods = Vec::new();
+    while resources::exists(&lod::file_name(file_name, lods.len() + 1)) {
+        let lod_file_name = lod::file_name(file_name, lods.len() + 1);
+        let lod_text = resources::load_string(&lod_file_name).await?;
+        sources.push(lod_file_name.clone());
+        sources.extend(mtllibs(&lod_text));
+        let (lod_models, lod_materials) = load_obj(lod_text).await?;
+        // The level's materials are matched to the full model's by name.
+        let material_index = |id: usize| {
+            let name = &lod_materials.get(id)?.name;
+            materials.iter().position(|material| &material.name == name)
+        };
+        lods.push(
+            lod_models
+                .into_iter()
+                .map(|m| {
+                    let mut mesh = obj_mesh(device, &lod_file_name, m.mesh);
+                    mesh.material = mesh.material.and_then(material_index);
+                    mesh
+                })
+                .collect(),
+        );
+    }
+    let triangles = meshes.iter().map(|mesh| mesh.raw_indices.len() / 3).sum::<usize>();
+    if lods.is_empty() && triangles >= lod::AUTO_LOD_MIN_TRIANGLES {
+        for resolution in lod::AUTO_LOD_RESOLUTIONS {
+            lods.push(
+                meshes
+                    .iter()
+                    .map(|mesh| {
+                        let (vertices, indices) =
+                            lod::decimate(&mesh.raw_vertices, &mesh.raw_indices, resolution);
+                        let name = format!("{} (LOD {})", mesh.name, lods.len() + 1);
+                        let mut lod_mesh = create_mesh(device, &name, vertices, indices);
+                        lod_mesh.material = mesh.material;
+                        lod_mesh
+                    })
+                    .collect(),
+            );
+        }
+    }
+
+    Ok(Model { meshes, materials, sources, lods })
+}
+
+// The .mtl files an .obj file refers to.
+fn mtllibs(obj_text: &str) -> impl Iterator<Item = String> + '_ {
+    obj_text.lines().filter_map(|line| line.strip_prefix("mtllib ")).map(|p| p.trim().to_string())
+}
+
+async fn load_obj(obj_text: String) -> Result<(Vec<tobj::Model>, Vec<tobj::Material>)> {
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
+    let (models, materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() },
         |p| async move {
@@ -247,46 +385,272 @@ pub async fn load_model(
     )
     .await?;
 
-    let mut materials: Vec<Material> = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture =
-            resources::load_texture(&(m.diffuse_texture.unwrap()), device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
+    Ok((models, materials?))
+}
+
+fn obj_mesh(device: &Device, file_name: &str, mesh: tobj::Mesh) -> Mesh {
+    let vertices = (0..mesh.positions.len() / 3)
+        .map(|i| ModelVertex {
+            position: [mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]],
+            tex_coords: [mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1]],
+            normal: [mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]],
+        })
+        .collect::<Vec<_>>();
+    let mut model_mesh = create_mesh(device, file_name, vertices, mesh.indices);
+    model_mesh.material = mesh.material_id;
+    model_mesh
+}
+
+/// A mesh with no material, with its buffers uploaded.
+pub fn create_mesh(
+    device: &Device,
+    name: &str,
+    vertices: Vec<ModelVertex>,
+    indices: Vec<u32>,
+) -> Mesh {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Vertex Buffer", name)),
+        contents: bytemuck::cast_slice(&vertices),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{:?} Index Buffer", name)),
+        contents: bytemuck::cast_slice(&indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    let memory = Allocation::new(Category::Meshes, vertex_buffer.size() + index_buffer.size());
+
+    Mesh {
+        name: name.to_string(),
+        vertex_buffer,
+        index_buffer,
+        num_elements: indices.len() as u32,
+        material: None,
+        raw_vertices: vertices,
+        raw_indices: indices,
+        memory,
+    }
+}
+
+pub struct SkinnedMesh {
+    pub vertex_buffer: Buffer,
+    pub index_buffer: Buffer,
+    pub num_elements: u32,
+    // Counts both buffers.
+    pub memory: Allocation,
+}
+
+// A joint's transform relative to its parent.
+#[derive(Clone, Copy, Debug)]
+pub struct JointTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+impl JointTransform {
+    fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+pub struct Joint {
+    pub name: String,
+    // Index into Skeleton::joints. Parents always come before their children.
+    pub parent: Option<usize>,
+    pub inverse_bind: Matrix4<f32>,
+    pub rest: JointTransform,
+}
+
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+}
+
+enum ChannelValues {
+    Translations(Vec<Vector3<f32>>),
+    Rotations(Vec<Quaternion<f32>>),
+    Scales(Vec<Vector3<f32>>),
+}
 
-        materials.push(Material { name: m.name, diffuse_texture, bind_group })
+struct AnimationChannel {
+    joint: usize,
+    times: Vec<f32>,
+    values: ChannelValues,
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub duration_secs: f32,
+    channels: Vec<AnimationChannel>,
+}
+
+pub struct SkinnedModel {
+    pub meshes: Vec<SkinnedMesh>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+impl SkinnedModel {
+    pub fn clip_index(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|clip| clip.name == name)
+    }
+    /// The matrices the skinned vertex shader applies, one per joint, for `clip` at `time_secs`
+    /// (looping). With no clip, this is the bind pose.
+    pub fn bone_matrices(&self, clip: Option<usize>, time_secs: f32) -> Vec<[[f32; 4]; 4]> {
+        let joints = &self.skeleton.joints;
+        let mut locals = joints.iter().map(|joint| joint.rest).collect::<Vec<_>>();
+        if let Some(clip) = clip.map(|index| &self.clips[index]) {
+            let time = if clip.duration_secs > 0.0 { time_secs % clip.duration_secs } else { 0.0 };
+            for channel in &clip.channels {
+                let (i, t) = keyframe_position(&channel.times, time);
+                let local = &mut locals[channel.joint];
+                match &channel.values {
+                    ChannelValues::Translations(values) => {
+                        local.translation = values[i].lerp(values[i + 1], t)
+                    }
+                    ChannelValues::Rotations(values) => {
+                        local.rotation = values[i].nlerp(values[i + 1], t)
+                    }
+                    ChannelValues::Scales(values) => local.scale = values[i].lerp(values[i + 1], t),
+                }
+            }
+        }
+        let mut globals: Vec<Matrix4<f32>> = Vec::with_capacity(joints.len());
+        for (joint, local) in joints.iter().zip(&locals) {
+            let parent = joint.parent.map(|parent| globals[parent]).unwrap_or(Matrix4::identity());
+            globals.push(parent * local.to_matrix());
+        }
+        globals
+            .iter()
+            .zip(joints)
+            .map(|(global, joint)| (global * joint.inverse_bind).into())
+            .collect()
     }
+}
+
+// Returns the keyframe just before `time`, and how far `time` is towards the next one. Single
+// keyframe channels are padded on load so there is always a next keyframe.
+fn keyframe_position(times: &[f32], time: f32) -> (usize, f32) {
+    let next = times.partition_point(|&t| t <= time).clamp(1, times.len() - 1);
+    let (start, end) = (times[next - 1], times[next]);
+    let t = if end > start { ((time - start) / (end - start)).clamp(0.0, 1.0) } else { 0.0 };
+    (next - 1, t)
+}
+
+fn joint_transform(transform: gltf::scene::Transform) -> JointTransform {
+    let (translation, [x, y, z, w], scale) = transform.decomposed();
+    JointTransform {
+        translation: translation.into(),
+        rotation: Quaternion::new(w, x, y, z),
+        scale: scale.into(),
+    }
+}
+
+/// Loads the first skin in a binary glTF (.glb) file, along with every mesh and animation.
+/// Only linear keyframe interpolation is supported; step and cubic spline channels are sampled as
+/// if they were linear.
+pub async fn load_skinned_model(file_name: &str, device: &Device) -> Result<SkinnedModel> {
+    let data = resources::load_binary(file_name).await?;
+    let gltf = gltf::Gltf::from_slice(&data)?;
+    let blob = gltf.blob.as_deref();
+    let buffer_data = |buffer: gltf::Buffer| match buffer.source() {
+        gltf::buffer::Source::Bin => blob,
+        gltf::buffer::Source::Uri(_) => None,
+    };
+    let skin = gltf.skins().next().ok_or_else(|| anyhow::anyhow!("{} has no skin", file_name))?;
 
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
+    // Order joints so that parents come before children, which lets bone_matrices compute every
+    // global transform in one pass.
+    let mut node_parents = vec![None; gltf.nodes().len()];
+    for node in gltf.nodes() {
+        for child in node.children() {
+            node_parents[child.index()] = Some(node.index());
+        }
+    }
+    let skin_nodes = skin.joints().map(|node| node.index()).collect::<Vec<_>>();
+    let depth = |mut node: usize| {
+        let mut depth = 0;
+        while let Some(parent) = node_parents[node] {
+            node = parent;
+            depth += 1;
+        }
+        depth
+    };
+    let mut order = (0..skin_nodes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| depth(skin_nodes[i]));
+    // Maps a skin joint index (what the vertices refer to) to its index in Skeleton::joints.
+    let mut skin_to_joint = vec![0; skin_nodes.len()];
+    for (joint, &skin_joint) in order.iter().enumerate() {
+        skin_to_joint[skin_joint] = joint;
+    }
+    let node_to_joint =
+        |node: usize| skin_nodes.iter().position(|&n| n == node).map(|i| skin_to_joint[i]);
+
+    let inverse_binds = skin
+        .reader(buffer_data)
+        .read_inverse_bind_matrices()
+        .map(|matrices| matrices.map(Matrix4::from).collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![Matrix4::identity(); skin_nodes.len()]);
+    let nodes = gltf.nodes().collect::<Vec<_>>();
+    let joints = order
+        .iter()
+        .map(|&skin_joint| {
+            let node = &nodes[skin_nodes[skin_joint]];
+            // The nearest ancestor that is also a joint.
+            let mut parent = node_parents[node.index()];
+            while let Some(p) = parent {
+                if node_to_joint(p).is_some() {
+                    break;
+                }
+                parent = node_parents[p];
+            }
+            Joint {
+                name: node.name().unwrap_or_default().to_string(),
+                parent: parent.and_then(node_to_joint),
+                inverse_bind: inverse_binds[skin_joint],
+                rest: joint_transform(node.transform()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut meshes = Vec::new();
+    for mesh in gltf.meshes() {
+        for primitive in mesh.primitives() {
+            let reader = primitive.reader(buffer_data);
+            let positions = reader
+                .read_positions()
+                .ok_or_else(|| anyhow::anyhow!("{} has a mesh without positions", file_name))?
+                .collect::<Vec<_>>();
+            let count = positions.len();
+            let normals = reader
+                .read_normals()
+                .map(|normals| normals.collect())
+                .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; count]);
+            let tex_coords = reader
+                .read_tex_coords(0)
+                .map(|coords| coords.into_f32().collect())
+                .unwrap_or_else(|| vec![[0.0, 0.0]; count]);
+            let skin_joints = reader
+                .read_joints(0)
+                .map(|joints| joints.into_u16().collect())
+                .unwrap_or_else(|| vec![[0; 4]; count]);
+            let weights = reader
+                .read_weights(0)
+                .map(|weights| weights.into_f32().collect())
+                .unwrap_or_else(|| vec![[1.0, 0.0, 0.0, 0.0]; count]);
+            let vertices = (0..count)
+                .map(|i| SkinnedVertex {
+                    position: positions[i],
+                    tex_coords: tex_coords[i],
+                    normal: normals[i],
+                    joints: skin_joints[i].map(|joint| skin_to_joint[joint as usize] as u32),
+                    weights: weights[i],
                 })
                 .collect::<Vec<_>>();
+            let indices = reader
+                .read_indices()
+                .map(|indices| indices.into_u32().collect())
+                .unwrap_or_else(|| (0..count as u32).collect::<Vec<_>>());
 
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_name)),
@@ -295,21 +659,65 @@ pub async fn load_model(
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
+                contents: bytemuck::cast_slice(&indices),
                 usage: wgpu::BufferUsages::INDEX,
             });
-
-            Mesh {
-                name: file_name.to_string(),
+            let memory =
+                Allocation::new(Category::Meshes, vertex_buffer.size() + index_buffer.size());
+            meshes.push(SkinnedMesh {
                 vertex_buffer,
                 index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id,
-                raw_vertices: vertices,
-                raw_indices: m.mesh.indices.clone(),
+                num_elements: indices.len() as u32,
+                memory,
+            });
+        }
+    }
+
+    let mut clips = Vec::new();
+    for animation in gltf.animations() {
+        let mut channels = Vec::new();
+        let mut duration_secs: f32 = 0.0;
+        for channel in animation.channels() {
+            let Some(joint) = node_to_joint(channel.target().node().index()) else {
+                continue;
+            };
+            let reader = channel.reader(buffer_data);
+            let (Some(inputs), Some(outputs)) = (reader.read_inputs(), reader.read_outputs())
+            else {
+                continue;
+            };
+            let mut times = inputs.collect::<Vec<_>>();
+            let mut values = match outputs {
+                gltf::animation::util::ReadOutputs::Translations(values) => {
+                    ChannelValues::Translations(values.map(Vector3::from).collect())
+                }
+                gltf::animation::util::ReadOutputs::Rotations(values) => ChannelValues::Rotations(
+                    values.into_f32().map(|[x, y, z, w]| Quaternion::new(w, x, y, z)).collect(),
+                ),
+                gltf::animation::util::ReadOutputs::Scales(values) => {
+                    ChannelValues::Scales(values.map(Vector3::from).collect())
+                }
+                gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => continue,
+            };
+            if times.is_empty() {
+                continue;
             }
-        })
-        .collect::<Vec<_>>();
+            if times.len() == 1 {
+                times.push(times[0]);
+                match &mut values {
+                    ChannelValues::Translations(v) | ChannelValues::Scales(v) => v.push(v[0]),
+                    ChannelValues::Rotations(v) => v.push(v[0]),
+                }
+            }
+            duration_secs = duration_secs.max(*times.last().unwrap());
+            channels.push(AnimationChannel { joint, times, values });
+        }
+        clips.push(AnimationClip {
+            name: animation.name().unwrap_or_default().to_string(),
+            duration_secs,
+            channels,
+        });
+    }
 
-    Ok(Model { meshes, materials })
+    Ok(SkinnedModel { meshes, skeleton: Skeleton { joints }, clips })
 }
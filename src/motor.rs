@@ -0,0 +1,120 @@
+/* Motor: rigid motions, a turn and a move together, as the rotor's counterpart in projective
+ * geometric algebra. */
+use cgmath::{BaseFloat, Vector3};
+use std::ops::Mul;
+
+use crate::rotor::{scalar, Rotor};
+
+/// A rotor times a translator. The translator moving by `t` is `1 + ½ e0 t`, where `e0` is the
+/// null basis vector that squares to 0, so a motor is a rotor plus `e0` times a second, "dual"
+/// rotor-shaped part: eight numbers in all. Composing motors is their product, just as with rotors,
+/// and keeps the turn and the move together without building a matrix.
+///
+/// With `e0` dropped from the dual part, its product rules are the rotor's, which is what `Mul`
+/// uses.
+///
+/// So far only `Transform`'s composition goes through motors. Instances reach the vertex shader
+/// as a position, rotor and scale, which it applies without building a matrix.
+#[derive(Clone, Copy, Debug)]
+pub struct Motor<S = f32> {
+    pub real: Rotor<S>,
+    // The e0 part: half the translation times the rotation.
+    pub dual: Rotor<S>,
+}
+impl<S: BaseFloat> Motor<S> {
+    pub fn identity() -> Self {
+        Motor::from_rotor(Rotor::identity())
+    }
+    pub fn from_rotor(rotor: Rotor<S>) -> Self {
+        Motor { real: rotor, dual: Rotor::new(S::zero(), S::zero(), S::zero(), S::zero()) }
+    }
+    pub fn from_translation(translation: Vector3<S>) -> Self {
+        Motor { real: Rotor::identity(), dual: scaled(bivector(translation), scalar(0.5)) }
+    }
+    /// Turns by `rotation`, then moves by `translation`.
+    pub fn new(rotation: Rotor<S>, translation: Vector3<S>) -> Self {
+        Motor { real: rotation, dual: scaled(bivector(translation) * rotation, scalar(0.5)) }
+    }
+    pub fn rotation(&self) -> Rotor<S> {
+        self.real
+    }
+    /// Where the motor takes the origin.
+    pub fn translation(&self) -> Vector3<S> {
+        let t = scaled(self.dual * self.real.inverse(), scalar(2.0));
+        Vector3::new(-t.yz, t.xz, -t.xy)
+    }
+    /// The motor moving back: reversing both parts reverses the product they came from.
+    pub fn inverse(&self) -> Self {
+        Motor { real: self.real.inverse(), dual: self.dual.inverse() }
+    }
+    pub fn transform_point(&self, point: Vector3<S>) -> Vector3<S> {
+        self.real.rotate_vector(point) + self.translation()
+    }
+    /// Turns `vector` without moving it, as directions and normals don't move.
+    pub fn transform_vector(&self, vector: Vector3<S>) -> Vector3<S> {
+        self.real.rotate_vector(vector)
+    }
+}
+/// `a * b` moves by `b`, then by `a`.
+impl<S: BaseFloat> Mul for Motor<S> {
+    type Output = Self;
+    fn mul(self, b: Self) -> Self {
+        let a = self;
+        // e0 squares to 0, so the dual parts' product drops out.
+        Motor { real: a.real * b.real, dual: sum(a.real * b.dual, a.dual * b.real) }
+    }
+}
+
+// The vector `v` as the rotor-shaped bivector it is dual to, as `Rotor::from_axis_angle` turns an
+// axis into a plane.
+fn bivector<S: BaseFloat>(v: Vector3<S>) -> Rotor<S> {
+    Rotor::new(S::zero(), -v.z, v.y, -v.x)
+}
+
+fn scaled<S: BaseFloat>(r: Rotor<S>, k: S) -> Rotor<S> {
+    Rotor::new(k * r.s, k * r.xy, k * r.xz, k * r.yz)
+}
+
+fn sum<S: BaseFloat>(a: Rotor<S>, b: Rotor<S>) -> Rotor<S> {
+    Rotor::new(a.s + b.s, a.xy + b.xy, a.xz + b.xz, a.yz + b.yz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, InnerSpace};
+
+    fn assert_vectors_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 0.0001, "{a:?} != {b:?}");
+    }
+    fn motors() -> [Motor; 3] {
+        let axis = Vector3::new(1.0, 2.0, -2.0).normalize();
+        [
+            Motor::new(Rotor::from_axis_angle(axis, Deg(70.0)), Vector3::new(1.0, -2.0, 3.0)),
+            Motor::new(Rotor::from_axis_angle(Vector3::unit_y(), Deg(-135.0)), Vector3::unit_x()),
+            Motor::from_translation(Vector3::new(-4.0, 0.5, 0.0)),
+        ]
+    }
+
+    #[test]
+    fn composing_motors_applies_the_right_one_first() {
+        let p = Vector3::new(0.5, -1.0, 2.0);
+        for a in motors() {
+            for b in motors() {
+                assert_vectors_close(
+                    (a * b).transform_point(p),
+                    a.transform_point(b.transform_point(p)),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_moves_back() {
+        let p = Vector3::new(0.5, -1.0, 2.0);
+        for motor in motors() {
+            assert_vectors_close(motor.inverse().transform_point(motor.transform_point(p)), p);
+            assert_vectors_close((motor * motor.inverse()).transform_point(p), p);
+        }
+    }
+}
@@ -0,0 +1,56 @@
+/* Movement: the ways the player can get around a level, either free physics movement or classic
+ * Sokoban steps of one tile per key press. */
+
+/// How the player's input moves them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MovementMode {
+    /// Walking, running and jumping as a physics body, pushing boxes by walking into them.
+    #[default]
+    Physics,
+    /// One tile per press of a movement key, pushing any box in the way.
+    Step,
+}
+impl MovementMode {
+    pub fn toggled(self) -> Self {
+        match self {
+            MovementMode::Physics => MovementMode::Step,
+            MovementMode::Step => MovementMode::Physics,
+        }
+    }
+}
+
+/// The player going from tile to tile along a path in step mode, a fixed number of ticks per
+/// tile. A teleport shows up in the path as a jump between distant tiles, which takes a tile's
+/// worth of time on the entrance, then pops out at the exit, like boxes do.
+#[derive(Clone, Debug)]
+pub struct StepTween {
+    pub path: Vec<(i32, i32)>,
+    pub start_tick: isize,
+    pub ticks_per_tile: u32,
+}
+impl StepTween {
+    pub fn new(path: Vec<(i32, i32)>, start_tick: isize, ticks_per_tile: u32) -> Self {
+        StepTween { path, start_tick, ticks_per_tile }
+    }
+    pub fn is_finished(&self, tick: isize) -> bool {
+        self.elapsed(tick) >= self.duration()
+    }
+    /// The tiles the player is between at `tick`, and how far they are from the first to the
+    /// second, from 0 to 1.
+    pub fn at(&self, tick: isize) -> ((i32, i32), (i32, i32), f32) {
+        let last = *self.path.last().unwrap();
+        if self.is_finished(tick) {
+            return (last, last, 0.0);
+        }
+        let elapsed = self.elapsed(tick);
+        let leg = (elapsed / self.ticks_per_tile) as usize;
+        let into_leg = (elapsed % self.ticks_per_tile) as f32 / self.ticks_per_tile as f32;
+        (self.path[leg], self.path[leg + 1], into_leg)
+    }
+    fn elapsed(&self, tick: isize) -> u32 {
+        (tick - self.start_tick).max(0) as u32
+    }
+    fn duration(&self) -> u32 {
+        (self.path.len() as u32 - 1) * self.ticks_per_tile
+    }
+}
@@ -0,0 +1,742 @@
+/* Net: two-player games over UDP. In co-op, one game hosts and runs the simulation; the other
+ * joins, sends the steps its player takes, and shows the state the host streams back. In a race,
+ * each game runs its own simulation and sends the other its input. */
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::{game_state::InputState, level::Direction, log_println};
+
+pub const DEFAULT_PORT: u16 = 27960;
+// Starts every packet, so stray datagrams and other versions of the game are ignored.
+const MAGIC: u32 = 0x4355_4245;
+const PROTOCOL_VERSION: u8 = 2;
+// Large enough for any datagram.
+const RECEIVE_BUFFER_BYTES: usize = 65536;
+// States kept on both ends to send and read deltas against. A state older than this can't be a
+// baseline, so the host falls back to sending the whole state.
+const HISTORY_LENGTH: usize = 64;
+// Hearing nothing from the other game for this long means it has gone.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Most ticks of input a race packet carries. Older unacknowledged ones wait for the next packet.
+const MAX_RACE_INPUTS: usize = 64;
+
+const INPUT_MESSAGE: u8 = 0;
+const STATE_MESSAGE: u8 = 1;
+const RACE_MESSAGE: u8 = 2;
+
+/// The part of the game both players see the same way: the level being played, where its boxes
+/// are and which doors are open, and where the two players stand. Sent by the host every tick.
+///
+/// The joining player moves tile by tile, so only their tile is sent. The host's player moves
+/// freely, so their position is sent as is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SharedState {
+    // The pack's file name, as in `SaveGame`, and the level's index and id within it. The id
+    // catches the two games having different versions of a pack.
+    pub pack: String,
+    pub level_index: u32,
+    pub level_id: u64,
+    pub boxes: Vec<(i32, i32)>,
+    pub doors_open: Vec<bool>,
+    pub host_position: [f32; 3],
+    pub partner_tile: (i32, i32),
+}
+impl SharedState {
+    // Whether a delta against `baseline` can describe this state: it must be of the same level.
+    fn same_level(&self, baseline: &SharedState) -> bool {
+        self.pack == baseline.pack
+            && self.level_index == baseline.level_index
+            && self.level_id == baseline.level_id
+            && self.boxes.len() == baseline.boxes.len()
+            && self.doors_open.len() == baseline.doors_open.len()
+    }
+}
+
+/// Either end of a co-op game, or of a race.
+pub enum Session {
+    Host(Host),
+    Client(Client),
+    Race(RacePeer),
+}
+
+/// What the host heard from the joining player.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HostEvent {
+    Joined(SocketAddr),
+    // The joining player stopped answering.
+    Left,
+    // A step for the joining player's character, in the order they were taken.
+    Step(Direction),
+}
+
+struct Peer {
+    address: SocketAddr,
+    last_heard: Instant,
+}
+
+/// The hosting end. Waits for a player to join, then applies their steps and sends them the
+/// shared state every tick.
+///
+/// Each state is sent as a delta against the last one the joining player acknowledged, and
+/// carries the sequence number of the last step applied, so they know which steps to stop
+/// resending. Only one player can join; packets from anyone else are ignored.
+pub struct Host {
+    socket: UdpSocket,
+    partner: Option<Peer>,
+    // Sequence number of the last step applied, or 0 before the first. The joining player counts
+    // from 1.
+    last_step: u32,
+    // The newest state the joining player has acknowledged, by tick.
+    state_ack: Option<u32>,
+    // States sent, oldest first, by tick.
+    history: VecDeque<(u32, SharedState)>,
+}
+impl Host {
+    pub fn bind(port: u16) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("binding port {} to host on", port))?;
+        socket.set_nonblocking(true)?;
+        log_println!("Hosting on port {}", port);
+        Ok(Host { socket, partner: None, last_step: 0, state_ack: None, history: VecDeque::new() })
+    }
+    /// Reads every packet that has arrived. Steps come out in order, each once, however many
+    /// times they were sent.
+    pub fn receive(&mut self, now: Instant) -> anyhow::Result<Vec<HostEvent>> {
+        let mut events = Vec::new();
+        let mut buffer = vec![0; RECEIVE_BUFFER_BYTES];
+        loop {
+            let (length, address) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // Windows reports an earlier send that went unanswered on the next receive.
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e).context("receiving from the joining player"),
+            };
+            if self.partner.as_ref().is_some_and(|peer| peer.address != address) {
+                continue;
+            }
+            let input = match decode_input(&buffer[..length]) {
+                Ok(input) => input,
+                Err(e) => {
+                    log_println!("Ignoring packet from {}: {:?}", address, e);
+                    continue;
+                }
+            };
+            if self.partner.is_none() {
+                events.push(HostEvent::Joined(address));
+            }
+            self.partner = Some(Peer { address, last_heard: now });
+            self.state_ack = self.state_ack.max(input.state_ack);
+            for (sequence, direction) in input.steps {
+                if sequence > self.last_step {
+                    events.push(HostEvent::Step(direction));
+                    self.last_step = sequence;
+                }
+            }
+        }
+        if self.partner.as_ref().is_some_and(|peer| now - peer.last_heard > TIMEOUT) {
+            self.partner = None;
+            self.last_step = 0;
+            self.state_ack = None;
+            self.history.clear();
+            events.push(HostEvent::Left);
+        }
+        Ok(events)
+    }
+    /// Sends the state after game tick `tick` to the joining player, if there is one.
+    pub fn send_state(&mut self, tick: u32, state: SharedState) -> anyhow::Result<()> {
+        let Some(partner) = &self.partner else {
+            return Ok(());
+        };
+        let baseline = self.state_ack.and_then(|ack| {
+            self.history.iter().find(|(sent, baseline)| *sent == ack && state.same_level(baseline))
+        });
+        let mut writer = Writer::new(STATE_MESSAGE);
+        writer.u32(tick);
+        writer.u32(self.last_step);
+        writer.option_u32(baseline.map(|(sent, _)| *sent));
+        write_state(&mut writer, &state, baseline.map(|(_, baseline)| baseline));
+        self.socket
+            .send_to(&writer.bytes, partner.address)
+            .context("sending state to the joining player")?;
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back((tick, state));
+        Ok(())
+    }
+}
+
+/// The joining end. Sends every step its player takes until the host acknowledges it, and
+/// rebuilds the host's state from the deltas it sends.
+///
+/// Steps aren't predicted locally: the player only moves once the host's state says so, a round
+/// trip after the key press.
+pub struct Client {
+    socket: UdpSocket,
+    next_step: u32,
+    // Steps the host hasn't acknowledged yet, oldest first, by sequence number.
+    unacked: VecDeque<(u32, Direction)>,
+    // States received, oldest first, by tick.
+    history: VecDeque<(u32, SharedState)>,
+    last_heard: Instant,
+}
+impl Client {
+    /// `address` is the host's, like "192.168.1.2:27960". The port defaults to `DEFAULT_PORT`.
+    pub fn connect(address: &str, now: Instant) -> anyhow::Result<Self> {
+        let address = with_default_port(address);
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(&address).with_context(|| format!("joining {}", address))?;
+        socket.set_nonblocking(true)?;
+        log_println!("Joining {}", address);
+        Ok(Client {
+            socket,
+            next_step: 1,
+            unacked: VecDeque::new(),
+            history: VecDeque::new(),
+            last_heard: now,
+        })
+    }
+    pub fn push_step(&mut self, direction: Direction) {
+        self.unacked.push_back((self.next_step, direction));
+        self.next_step += 1;
+    }
+    /// Sends the unacknowledged steps, and acknowledges the newest state received. Also tells
+    /// the host this end is still here, so it is sent every tick even with nothing new.
+    pub fn send(&mut self) -> anyhow::Result<()> {
+        let mut writer = Writer::new(INPUT_MESSAGE);
+        writer.option_u32(self.history.back().map(|(tick, _)| *tick));
+        writer.u16(self.unacked.len() as u16);
+        for &(sequence, direction) in &self.unacked {
+            writer.u32(sequence);
+            writer.direction(direction);
+        }
+        match self.socket.send(&writer.bytes) {
+            // Nobody listening yet. The host may still be starting up.
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => Ok(()),
+            result => result.map(|_| ()).context("sending input to the host"),
+        }
+    }
+    /// Reads every packet that has arrived, returning the newest state among them if it is newer
+    /// than any before. States that arrive out of order, or against a baseline no longer kept,
+    /// are dropped.
+    pub fn receive(&mut self, now: Instant) -> anyhow::Result<Option<SharedState>> {
+        let mut newest = None;
+        let mut buffer = vec![0; RECEIVE_BUFFER_BYTES];
+        loop {
+            let length = match self.socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e).context("receiving from the host"),
+            };
+            match self.read_state(&buffer[..length]) {
+                Ok(Some(state)) => {
+                    self.last_heard = now;
+                    newest = Some(state);
+                }
+                Ok(None) => self.last_heard = now,
+                Err(e) => log_println!("Ignoring packet from the host: {:?}", e),
+            }
+        }
+        Ok(newest)
+    }
+    /// Whether the host has been silent for too long, or never answered at all.
+    pub fn timed_out(&self, now: Instant) -> bool {
+        now - self.last_heard > TIMEOUT
+    }
+    // Decodes a state packet. Returns None for one that is valid but of no use.
+    fn read_state(&mut self, packet: &[u8]) -> anyhow::Result<Option<SharedState>> {
+        let mut reader = Reader::new(packet, STATE_MESSAGE)?;
+        let tick = reader.u32()?;
+        let input_ack = reader.u32()?;
+        let baseline_tick = reader.option_u32()?;
+        self.unacked.retain(|&(sequence, _)| sequence > input_ack);
+        if self.history.back().is_some_and(|&(newest, _)| tick <= newest) {
+            return Ok(None);
+        }
+        let baseline = match baseline_tick {
+            Some(baseline_tick) => {
+                match self.history.iter().find(|(received, _)| *received == baseline_tick) {
+                    Some((_, baseline)) => Some(baseline),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+        let state = read_state(&mut reader, baseline)?;
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history.push_back((tick, state.clone()));
+        Ok(Some(state))
+    }
+}
+
+/// Which race is being run: a level the hosting end picked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RaceStart {
+    // Counts up with each race the host starts, so input from an earlier one is ignored.
+    pub id: u32,
+    // As in `SharedState`.
+    pub pack: String,
+    pub level_index: u32,
+    pub level_id: u64,
+}
+
+/// What a race end heard from the other.
+#[derive(Clone, PartialEq)]
+pub enum RaceEvent {
+    Joined(SocketAddr),
+    // The other end stopped answering.
+    Left,
+    // The host started a race. Only the joining end hears this.
+    Start(RaceStart),
+    // The other game's input for race tick `tick`, counting from 1, and the checksum of its
+    // simulation after that tick. Each tick's input is heard once, in order.
+    Input { tick: u32, input: InputState, checksum: u64 },
+}
+
+/// One end of a race. Both play the same level, each running its own game, and each sends the
+/// other the input of every tick it plays until acknowledged. The hosting end picks the level.
+pub struct RacePeer {
+    socket: UdpSocket,
+    hosting: bool,
+    // The other end. The joining end knows it from the start; the host learns it.
+    peer: Option<Peer>,
+    race: Option<RaceStart>,
+    // This game's input and checksum for each tick not yet acknowledged, oldest first, the first
+    // being for tick `first_unacked`.
+    unacked: VecDeque<(InputState, u64)>,
+    first_unacked: u32,
+    // The last tick of the other game's input heard.
+    received: u32,
+}
+impl RacePeer {
+    pub fn host(port: u16) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("binding port {} to host a race on", port))?;
+        socket.set_nonblocking(true)?;
+        log_println!("Hosting a race on port {}", port);
+        Ok(Self::new(socket, true, None))
+    }
+    /// `address` is the host's, with the port defaulting to `DEFAULT_PORT` as for `Client`.
+    pub fn join(address: &str, now: Instant) -> anyhow::Result<Self> {
+        let address = with_default_port(address);
+        let resolved = address
+            .to_socket_addrs()
+            .with_context(|| format!("looking up {}", address))?
+            .next()
+            .ok_or_else(|| anyhow!("no address for {}", address))?;
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_nonblocking(true)?;
+        log_println!("Joining a race at {}", resolved);
+        Ok(Self::new(socket, false, Some(Peer { address: resolved, last_heard: now })))
+    }
+    fn new(socket: UdpSocket, hosting: bool, peer: Option<Peer>) -> Self {
+        RacePeer {
+            socket,
+            hosting,
+            peer,
+            race: None,
+            unacked: VecDeque::new(),
+            first_unacked: 1,
+            received: 0,
+        }
+    }
+    pub fn is_hosting(&self) -> bool {
+        self.hosting
+    }
+    /// Whether the other end has been heard from, on the hosting end. The joining end always has
+    /// one.
+    pub fn has_peer(&self) -> bool {
+        self.peer.is_some()
+    }
+    /// Starts a new race, on the hosting end. Its id is set here.
+    pub fn start(&mut self, mut race: RaceStart) -> RaceStart {
+        race.id = self.race.as_ref().map_or(1, |previous| previous.id + 1);
+        self.race = Some(race.clone());
+        self.reset_input();
+        race
+    }
+    /// Queues this game's input for its next race tick, with the checksum of its simulation
+    /// after it.
+    pub fn push_input(&mut self, input: &InputState, checksum: u64) {
+        self.unacked.push_back((input.clone(), checksum));
+    }
+    /// Sends the race being run, acknowledges the other game's input and resends this game's
+    /// unacknowledged input. Sent every tick, to show this end is still here.
+    pub fn send(&mut self) -> anyhow::Result<()> {
+        let Some(peer) = &self.peer else {
+            return Ok(());
+        };
+        let mut writer = Writer::new(RACE_MESSAGE);
+        writer.bool(self.race.is_some());
+        if let Some(race) = &self.race {
+            writer.u32(race.id);
+            writer.str(&race.pack);
+            writer.u32(race.level_index);
+            writer.u64(race.level_id);
+        }
+        writer.u32(self.received);
+        writer.u32(self.first_unacked);
+        let count = self.unacked.len().min(MAX_RACE_INPUTS);
+        writer.u16(count as u16);
+        for (input, checksum) in self.unacked.iter().take(count) {
+            writer.input(input);
+            writer.u64(*checksum);
+        }
+        match self.socket.send_to(&writer.bytes, peer.address) {
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => Ok(()),
+            result => result.map(|_| ()).context("sending race input"),
+        }
+    }
+    /// Reads every packet that has arrived from the other end.
+    pub fn receive(&mut self, now: Instant) -> anyhow::Result<Vec<RaceEvent>> {
+        let mut events = Vec::new();
+        let mut buffer = vec![0; RECEIVE_BUFFER_BYTES];
+        loop {
+            let (length, address) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e).context("receiving race input"),
+            };
+            if self.peer.as_ref().is_some_and(|peer| peer.address != address) {
+                continue;
+            }
+            if let Err(e) = self.read_packet(&buffer[..length], &mut events) {
+                log_println!("Ignoring packet from {}: {:?}", address, e);
+                continue;
+            }
+            if self.peer.is_none() {
+                events.insert(0, RaceEvent::Joined(address));
+            }
+            self.peer = Some(Peer { address, last_heard: now });
+        }
+        if self.peer.as_ref().is_some_and(|peer| now - peer.last_heard > TIMEOUT) {
+            // The joining end keeps the host's address, though there is nobody there to send to.
+            if self.hosting {
+                self.peer = None;
+                self.race = None;
+            }
+            self.reset_input();
+            events.push(RaceEvent::Left);
+        }
+        Ok(events)
+    }
+    fn reset_input(&mut self) {
+        self.unacked.clear();
+        self.first_unacked = 1;
+        self.received = 0;
+    }
+    fn read_packet(&mut self, packet: &[u8], events: &mut Vec<RaceEvent>) -> anyhow::Result<()> {
+        let mut reader = Reader::new(packet, RACE_MESSAGE)?;
+        let race = match reader.bool()? {
+            true => Some(RaceStart {
+                id: reader.u32()?,
+                pack: reader.str()?,
+                level_index: reader.u32()?,
+                level_id: reader.u64()?,
+            }),
+            false => None,
+        };
+        let ack = reader.u32()?;
+        let first = reader.u32()?;
+        let count = reader.u16()?;
+        let inputs = (0..count)
+            .map(|_| Ok((reader.input()?, reader.u64()?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if !self.hosting && race.is_some() && race != self.race {
+            self.race = race.clone();
+            self.reset_input();
+            events.push(RaceEvent::Start(race.clone().unwrap()));
+        }
+        // Input sent before the current race started belongs to the last one.
+        if race.is_none() || race.map(|race| race.id) != self.race.as_ref().map(|race| race.id) {
+            return Ok(());
+        }
+        while self.first_unacked <= ack && self.unacked.pop_front().is_some() {
+            self.first_unacked += 1;
+        }
+        for (i, (input, checksum)) in inputs.into_iter().enumerate() {
+            let tick = first + i as u32;
+            if tick == self.received + 1 {
+                events.push(RaceEvent::Input { tick, input, checksum });
+                self.received = tick;
+            }
+        }
+        Ok(())
+    }
+}
+
+// `address`, with `DEFAULT_PORT` if it has no port of its own.
+fn with_default_port(address: &str) -> String {
+    if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, DEFAULT_PORT)
+    }
+}
+
+// What the joining player sends every tick.
+struct Input {
+    // The tick of the newest state received.
+    state_ack: Option<u32>,
+    // Steps not yet acknowledged, oldest first, by sequence number.
+    steps: Vec<(u32, Direction)>,
+}
+
+fn decode_input(packet: &[u8]) -> anyhow::Result<Input> {
+    let mut reader = Reader::new(packet, INPUT_MESSAGE)?;
+    let state_ack = reader.option_u32()?;
+    let count = reader.u16()?;
+    let steps = (0..count)
+        .map(|_| Ok((reader.u32()?, reader.direction()?)))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Input { state_ack, steps })
+}
+
+// Without a baseline the whole state is written. With one, only the boxes that moved and the
+// doors that changed are, along with the players, who are always written.
+fn write_state(writer: &mut Writer, state: &SharedState, baseline: Option<&SharedState>) {
+    for value in state.host_position {
+        writer.f32(value);
+    }
+    writer.tile(state.partner_tile);
+    let Some(baseline) = baseline else {
+        writer.str(&state.pack);
+        writer.u32(state.level_index);
+        writer.u64(state.level_id);
+        writer.u16(state.boxes.len() as u16);
+        for &tile in &state.boxes {
+            writer.tile(tile);
+        }
+        writer.u16(state.doors_open.len() as u16);
+        for &open in &state.doors_open {
+            writer.bool(open);
+        }
+        return;
+    };
+    let moved = (0..state.boxes.len()).filter(|&i| state.boxes[i] != baseline.boxes[i]);
+    let moved = moved.collect::<Vec<_>>();
+    writer.u16(moved.len() as u16);
+    for i in moved {
+        writer.u16(i as u16);
+        writer.tile(state.boxes[i]);
+    }
+    let changed = (0..state.doors_open.len())
+        .filter(|&i| state.doors_open[i] != baseline.doors_open[i])
+        .collect::<Vec<_>>();
+    writer.u16(changed.len() as u16);
+    for i in changed {
+        writer.u16(i as u16);
+        writer.bool(state.doors_open[i]);
+    }
+}
+
+fn read_state(reader: &mut Reader, baseline: Option<&SharedState>) -> anyhow::Result<SharedState> {
+    let host_position = [reader.f32()?, reader.f32()?, reader.f32()?];
+    let partner_tile = reader.tile()?;
+    let Some(baseline) = baseline else {
+        let pack = reader.str()?;
+        let level_index = reader.u32()?;
+        let level_id = reader.u64()?;
+        let boxes = (0..reader.u16()?).map(|_| reader.tile()).collect::<anyhow::Result<_>>()?;
+        let doors_open =
+            (0..reader.u16()?).map(|_| reader.bool()).collect::<anyhow::Result<_>>()?;
+        return Ok(SharedState {
+            pack,
+            level_index,
+            level_id,
+            boxes,
+            doors_open,
+            host_position,
+            partner_tile,
+        });
+    };
+    let mut state = SharedState { host_position, partner_tile, ..baseline.clone() };
+    for _ in 0..reader.u16()? {
+        let i = reader.u16()? as usize;
+        let tile = reader.tile()?;
+        *state.boxes.get_mut(i).ok_or_else(|| anyhow!("no box {}", i))? = tile;
+    }
+    for _ in 0..reader.u16()? {
+        let i = reader.u16()? as usize;
+        let open = reader.bool()?;
+        *state.doors_open.get_mut(i).ok_or_else(|| anyhow!("no door {}", i))? = open;
+    }
+    Ok(state)
+}
+
+// Builds a packet, little-endian, starting with the header for a message of type `kind`.
+struct Writer {
+    bytes: Vec<u8>,
+}
+impl Writer {
+    fn new(kind: u8) -> Self {
+        let mut writer = Writer { bytes: Vec::new() };
+        writer.u32(MAGIC);
+        writer.u8(PROTOCOL_VERSION);
+        writer.u8(kind);
+        writer
+    }
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+    fn option_u32(&mut self, value: Option<u32>) {
+        self.bool(value.is_some());
+        self.u32(value.unwrap_or(0));
+    }
+    fn tile(&mut self, (x, z): (i32, i32)) {
+        self.bytes.extend_from_slice(&x.to_le_bytes());
+        self.bytes.extend_from_slice(&z.to_le_bytes());
+    }
+    fn direction(&mut self, direction: Direction) {
+        self.u8(match direction {
+            Direction::North => 0,
+            Direction::South => 1,
+            Direction::East => 2,
+            Direction::West => 3,
+        });
+    }
+    fn str(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+    // The buttons as bits, in the order of the fields, then the mouse and wheel.
+    fn input(&mut self, input: &InputState) {
+        let buttons = [
+            input.forward,
+            input.backward,
+            input.left,
+            input.right,
+            input.jump,
+            input.undo,
+            input.restart,
+            input.interact,
+            input.confirm,
+            input.back,
+        ];
+        let bits = buttons.iter().enumerate().fold(0, |bits, (i, &down)| bits | (down as u16) << i);
+        self.u16(bits);
+        self.bytes.extend_from_slice(&input.mouse_x.to_le_bytes());
+        self.bytes.extend_from_slice(&input.mouse_y.to_le_bytes());
+        self.bytes.extend_from_slice(&input.wheel.to_le_bytes());
+    }
+}
+
+// Reads a packet written by `Writer`, after checking its header is for a message of type `kind`.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], kind: u8) -> anyhow::Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.u32()? != MAGIC {
+            bail!("not a packet of ours");
+        }
+        let version = reader.u8()?;
+        if version != PROTOCOL_VERSION {
+            bail!("protocol version {}, expected {}", version, PROTOCOL_VERSION);
+        }
+        let found = reader.u8()?;
+        if found != kind {
+            bail!("message type {}, expected {}", found, kind);
+        }
+        Ok(reader)
+    }
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        if self.bytes.len() < N {
+            bail!("packet ends early");
+        }
+        let (taken, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(taken.try_into().unwrap())
+    }
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+    fn bool(&mut self) -> anyhow::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+    fn option_u32(&mut self) -> anyhow::Result<Option<u32>> {
+        let present = self.bool()?;
+        let value = self.u32()?;
+        Ok(present.then_some(value))
+    }
+    fn tile(&mut self) -> anyhow::Result<(i32, i32)> {
+        Ok((i32::from_le_bytes(self.take()?), i32::from_le_bytes(self.take()?)))
+    }
+    fn direction(&mut self) -> anyhow::Result<Direction> {
+        match self.u8()? {
+            0 => Ok(Direction::North),
+            1 => Ok(Direction::South),
+            2 => Ok(Direction::East),
+            3 => Ok(Direction::West),
+            other => bail!("no direction {}", other),
+        }
+    }
+    fn input(&mut self) -> anyhow::Result<InputState> {
+        let bits = self.u16()?;
+        let down = |i: u32| bits & (1 << i) != 0;
+        Ok(InputState {
+            forward: down(0),
+            backward: down(1),
+            left: down(2),
+            right: down(3),
+            jump: down(4),
+            undo: down(5),
+            restart: down(6),
+            interact: down(7),
+            confirm: down(8),
+            back: down(9),
+            mouse_x: i32::from_le_bytes(self.take()?),
+            mouse_y: i32::from_le_bytes(self.take()?),
+            wheel: i32::from_le_bytes(self.take()?),
+        })
+    }
+    fn str(&mut self) -> anyhow::Result<String> {
+        let length = self.u16()? as usize;
+        if self.bytes.len() < length {
+            bail!("packet ends early");
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(std::str::from_utf8(taken)?.to_string())
+    }
+}
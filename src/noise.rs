@@ -0,0 +1,22 @@
+/* Noise: smooth pseudo-random signals, for procedural motion like camera shake. */
+
+// A pseudo-random gradient in [-1, 1] for lattice point `i` of the signal picked by `seed`.
+fn gradient(i: i32, seed: u32) -> f32 {
+    let mut h = (i as u32).wrapping_mul(0x9e37_79b1) ^ seed.wrapping_mul(0x85eb_ca77);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    (h & 0xffff) as f32 / 32767.5 - 1.0
+}
+
+/// One-dimensional Perlin noise, within [-1, 1]. It passes through zero at whole numbers and
+/// varies smoothly between them. Different seeds give unrelated signals.
+pub fn perlin1(x: f32, seed: u32) -> f32 {
+    let i = x.floor();
+    let t = x - i;
+    let (g0, g1) = (gradient(i as i32, seed), gradient(i as i32 + 1, seed));
+    // Quintic fade, so the signal's slope and curvature are continuous across lattice points.
+    let fade = t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    // Each gradient contributes at most 0.5 in between, so double to fill [-1, 1].
+    2.0 * (g0 * t + (g1 * (t - 1.0) - g0 * t) * fade)
+}
@@ -0,0 +1,54 @@
+// Inverted hull outline: the targeted box drawn again, a little bigger and inside out, so only a
+// rim of its back faces shows around the box itself.
+struct OutlineCamera {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: OutlineCamera;
+
+// Same layout as InstanceInput in shaders.wgsl.
+struct InstanceInput {
+    @location(5) position: vec3<f32>,
+    @location(6) scale: f32,
+    @location(7) rotation: vec4<f32>,
+    @location(8) shader: u32,
+    @location(9) tint: vec4<f32>,
+    @location(10) layer: u32,
+};
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+// World units the outline stands out from the surface by. Models are centered on the origin and
+// span -1 to 1, so growing the scale by this much moves each face out by about as far.
+const OutlineWidth = 0.04;
+// Brighter than white, so it stays visible through tone mapping.
+const OutlineColor = vec4<f32>(1.6, 1.3, 0.5, 1.0);
+
+// Same as apply_rotor_to_vector in shaders.wgsl.
+fn apply_rotor_to_vector(rotor: vec4<f32>, vector: vec3<f32>) -> vec3<f32> {
+    let s_x = rotor.x * vector.x + rotor.y * vector.y + rotor.z * vector.z;
+    let s_y = rotor.x * vector.y - rotor.y * vector.x + rotor.w * vector.z;
+    let s_z = rotor.x * vector.z - rotor.w * vector.y - rotor.z * vector.x;
+    let s_xyz = rotor.y * vector.z + rotor.w * vector.x - rotor.z * vector.y;
+    var out: vec3<f32>;
+    out.x = s_x * rotor.x + s_y * rotor.y + s_xyz * rotor.w + s_z * rotor.z;
+    out.y = s_y * rotor.x - s_x * rotor.y + s_z * rotor.w - s_xyz * rotor.z;
+    out.z = s_z * rotor.x + s_xyz * rotor.y - s_y * rotor.w + s_x * rotor.z;
+    return out;
+}
+
+@vertex
+fn vs_outline(model: VertexInput, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let grown = (instance.scale + OutlineWidth) * model.position;
+    let world_position = apply_rotor_to_vector(instance.rotation, grown) + instance.position;
+    return camera.view_proj * vec4<f32>(world_position, 1.0);
+}
+
+@fragment
+fn fs_outline() -> @location(0) vec4<f32> {
+    return OutlineColor;
+}
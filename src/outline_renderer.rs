@@ -0,0 +1,145 @@
+/* OutlineRenderer: draws a bright rim around the box the player is about to push. */
+use crate::{
+    camera::Camera,
+    game_state::Instance,
+    gpu_state::{BindGroupData, InstanceRaw},
+    model::{self, DescribeVB, Mesh, ModelVertex},
+    post_process::HDR_FORMAT,
+    texture,
+    uploader::Uploader,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineCameraUniform {
+    view_proj: [[f32; 4]; 4],
+}
+impl OutlineCameraUniform {
+    fn from_camera(camera: &Camera) -> Self {
+        OutlineCameraUniform { view_proj: camera.build_view_projection_matrix().into() }
+    }
+}
+
+pub struct OutlineRenderer {
+    camera_group: BindGroupData<OutlineCameraUniform>,
+    pipeline: wgpu::RenderPipeline,
+    // Boxes are drawn with the plain cube, so the outline is too.
+    cube: Mesh,
+    instance_buffer: wgpu::Buffer,
+    // Whether there is a box to outline this frame.
+    visible: bool,
+}
+impl OutlineRenderer {
+    pub fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+        let camera_group = BindGroupData::<OutlineCameraUniform>::new(
+            OutlineCameraUniform::from_camera(camera),
+            device,
+            "Outline Camera",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::VERTEX,
+        );
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&camera_group.layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Outline Shaders"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("outline.wgsl").into()),
+        });
+        // Not built with create_render_pipeline: the hull is drawn inside out, so front faces are
+        // culled instead of back ones, and it leaves depth alone so nothing after it sees it.
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Outline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_outline",
+                buffers: &[ModelVertex::describe_vb(), InstanceRaw::describe_vb()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_outline",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Front),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Instance Buffer"),
+            size: std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        OutlineRenderer {
+            camera_group,
+            pipeline,
+            cube: model::cube_mesh(device, false),
+            instance_buffer,
+            visible: false,
+        }
+    }
+    pub fn update_camera(&mut self, uploader: &mut Uploader, camera: &Camera) {
+        self.camera_group.uniform = OutlineCameraUniform::from_camera(camera);
+        uploader.upload(&self.camera_group.buffer, 0, &[self.camera_group.uniform]);
+    }
+    /// Outlines `target` from now on, or nothing if it's None.
+    pub fn update_target(&mut self, uploader: &mut Uploader, target: Option<Instance>) {
+        self.visible = target.is_some();
+        if let Some(instance) = target {
+            uploader.upload(&self.instance_buffer, 0, &[instance.to_raw()]);
+        }
+    }
+    /// Draws the outline over `hdr_view`, hidden where the scene's `depth_view` is in front of it.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        if !self.visible {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: hdr_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.camera_group.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.cube.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_index_buffer(self.cube.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(0..self.cube.num_elements, 0, 0..1);
+    }
+}
@@ -0,0 +1,83 @@
+/* Pacing: waiting for frame and tick deadlines more precisely than thread::sleep alone. */
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+// The final stretch before a deadline is spun rather than slept.
+const SPIN_TIME: Duration = Duration::from_micros(200);
+// How late a sleep can wake, even with timeBeginPeriod(1).
+const SLEEP_OVERSHOOT: Duration = Duration::from_millis(1);
+
+/// How late waits woke, relative to their deadlines.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JitterStats {
+    pub waits: u32,
+    total_late: Duration,
+    pub max_late: Duration,
+}
+impl JitterStats {
+    pub fn mean_late(&self) -> Duration {
+        if self.waits == 0 {
+            Duration::ZERO
+        } else {
+            self.total_late / self.waits
+        }
+    }
+    fn record(&mut self, late: Duration) {
+        self.waits += 1;
+        self.total_late += late;
+        self.max_late = self.max_late.max(late);
+    }
+}
+impl fmt::Display for JitterStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} waits, {}us late on average, {}us at most",
+            self.waits,
+            self.mean_late().as_micros(),
+            self.max_late.as_micros()
+        )
+    }
+}
+
+/// Waits for deadlines by sleeping most of the way, then spinning for the last `SPIN_TIME`.
+/// Sleeping alone routinely wakes up to a millisecond late, which shows up as jitter; spinning
+/// alone burns a core.
+pub struct Pacer {
+    stats: JitterStats,
+}
+impl Pacer {
+    pub fn new() -> Self {
+        Pacer { stats: JitterStats::default() }
+    }
+    /// Returns at `deadline`, or straight away if it has passed. Only waits that had to wait are
+    /// counted in the stats.
+    pub fn wait_until(&mut self, deadline: Instant) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return;
+        }
+        // Leave enough time that a sleep waking late still lands before the spin.
+        if remaining > SLEEP_OVERSHOOT + SPIN_TIME {
+            thread::sleep(remaining - SLEEP_OVERSHOOT - SPIN_TIME);
+        }
+        while deadline.saturating_duration_since(Instant::now()) > SPIN_TIME {
+            thread::yield_now();
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+        self.stats.record(Instant::now() - deadline);
+    }
+    /// The stats since the last call, starting afresh.
+    pub fn take_stats(&mut self) -> JitterStats {
+        std::mem::take(&mut self.stats)
+    }
+}
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,182 @@
+/* ParticleRenderer: draws the game state's particles as instanced, camera-facing quads. */
+use std::mem;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{
+    camera::Camera, gpu_state::BindGroupData, particles::ParticleRaw, post_process::HDR_FORMAT,
+    texture, uploader::Uploader,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleCameraUniform {
+    view_proj: [[f32; 4]; 4],
+    // Billboard axes in world space.
+    right: [f32; 3],
+    _padding: f32,
+    up: [f32; 3],
+    _padding2: f32,
+}
+impl ParticleCameraUniform {
+    fn from_camera(camera: &Camera) -> Self {
+        let forward = camera.direction.normalize();
+        let right = forward.cross(camera.up()).normalize();
+        let up: Vector3<f32> = right.cross(forward);
+        ParticleCameraUniform {
+            view_proj: camera.build_view_projection_matrix().into(),
+            right: right.into(),
+            _padding: 0.0,
+            up: up.into(),
+            _padding2: 0.0,
+        }
+    }
+}
+
+fn describe_particle_vb() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: mem::size_of::<ParticleRaw>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
+pub struct ParticleRenderer {
+    camera_group: BindGroupData<ParticleCameraUniform>,
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    // Capacity of `buffer` in particles; it grows as needed.
+    capacity: usize,
+    count: u32,
+}
+impl ParticleRenderer {
+    pub fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+        let camera_group = BindGroupData::<ParticleCameraUniform>::new(
+            ParticleCameraUniform::from_camera(camera),
+            device,
+            "Particle Camera",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::VERTEX,
+        );
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Pipeline Layout"),
+            bind_group_layouts: &[&camera_group.layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Shaders"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particles.wgsl").into()),
+        });
+        // Not built with create_render_pipeline: particles are translucent, so they're depth
+        // tested against the scene but don't write depth, and quads are never culled.
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Particles"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_particle",
+                buffers: &[describe_particle_vb()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_particle",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+        const INITIAL_CAPACITY: usize = 256;
+        let buffer = Self::create_buffer(device, INITIAL_CAPACITY);
+        ParticleRenderer { camera_group, pipeline, buffer, capacity: INITIAL_CAPACITY, count: 0 }
+    }
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Instance Buffer"),
+            size: (capacity * mem::size_of::<ParticleRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn update_camera(&mut self, uploader: &mut Uploader, camera: &Camera) {
+        self.camera_group.uniform = ParticleCameraUniform::from_camera(camera);
+        uploader.upload(&self.camera_group.buffer, 0, &[self.camera_group.uniform]);
+    }
+    pub fn update_particles(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        particles: &[ParticleRaw],
+    ) {
+        if particles.len() > self.capacity {
+            self.capacity = particles.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(particles));
+        self.count = particles.len() as u32;
+    }
+    /// Draws the particles over `hdr_view`, depth tested against the scene's `depth_view`.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+    ) {
+        if self.count == 0 {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Particle Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: hdr_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.camera_group.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.draw(0..6, 0..self.count);
+    }
+}
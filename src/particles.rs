@@ -0,0 +1,178 @@
+/* Particles: short-lived cosmetic effects, simulated on the game thread and drawn as billboards. */
+use cgmath::Vector3;
+
+use crate::{constants::GRAVITY, determinism::StateHasher, rng::Rng};
+
+// Oldest particles are dropped first once this many are alive.
+const MAX_PARTICLES: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParticleEffect {
+    // Low puff of dust along the floor behind a box being pushed.
+    SlideDust,
+    // Bright upward burst when a box comes to rest on a goal.
+    GoalSparkle,
+    // Ring of dust at the player's feet on take-off and landing.
+    JumpDust,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    // Color is in linear HDR units; values above 1.0 will bloom.
+    pub color: [f32; 4],
+    pub size: f32,
+    pub age: f32,
+    pub lifetime: f32,
+    // Fraction of gravity applied: dust hangs in the air, sparkles fall.
+    pub gravity_scale: f32,
+    // Fraction of velocity lost per second.
+    pub drag: f32,
+}
+
+// Per-particle data for the billboard pipeline.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ParticleRaw {
+    pub position: [f32; 3],
+    pub size: f32,
+    pub color: [f32; 4],
+}
+
+#[derive(Clone)]
+pub struct ParticleSystem {
+    particles: Vec<Particle>,
+    // Particles are spawned with a seeded generator so that replays produce the same state.
+    rng: Rng,
+}
+impl ParticleSystem {
+    pub fn new() -> Self {
+        ParticleSystem { particles: Vec::new(), rng: Rng::new(0, 0) }
+    }
+    /// Draws the jitter of particles spawned from now on from `rng`.
+    pub fn reseed(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+    fn next_f32(&mut self) -> f32 {
+        self.rng.next_f32()
+    }
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        self.rng.range(min, max)
+    }
+    pub fn emit(&mut self, effect: ParticleEffect, position: Vector3<f32>) {
+        match effect {
+            ParticleEffect::SlideDust => {
+                for _ in 0..6 {
+                    let velocity = Vector3::new(
+                        self.range(-0.4, 0.4),
+                        self.range(0.1, 0.4),
+                        self.range(-0.4, 0.4),
+                    );
+                    let offset = Vector3::new(self.range(-0.3, 0.3), 0.0, self.range(-0.3, 0.3));
+                    let grey = self.range(0.45, 0.6);
+                    let particle = Particle {
+                        position: position + offset,
+                        velocity,
+                        color: [grey, grey * 0.9, grey * 0.75, 0.5],
+                        size: self.range(0.08, 0.15),
+                        age: 0.0,
+                        lifetime: self.range(0.5, 0.9),
+                        gravity_scale: 0.0,
+                        drag: 2.0,
+                    };
+                    self.push(particle);
+                }
+            }
+            ParticleEffect::GoalSparkle => {
+                for _ in 0..24 {
+                    let angle = self.range(0.0, std::f32::consts::TAU);
+                    let speed = self.range(0.5, 1.5);
+                    let velocity = Vector3::new(
+                        speed * angle.cos(),
+                        self.range(2.0, 4.0),
+                        speed * angle.sin(),
+                    );
+                    let particle = Particle {
+                        position,
+                        velocity,
+                        color: [4.0, 3.2, 1.0, 1.0],
+                        size: self.range(0.03, 0.06),
+                        age: 0.0,
+                        lifetime: self.range(0.6, 1.2),
+                        gravity_scale: 0.5,
+                        drag: 0.5,
+                    };
+                    self.push(particle);
+                }
+            }
+            ParticleEffect::JumpDust => {
+                const COUNT: usize = 12;
+                for i in 0..COUNT {
+                    let angle = std::f32::consts::TAU * (i as f32 + self.next_f32()) / COUNT as f32;
+                    let speed = self.range(0.8, 1.2);
+                    let velocity = Vector3::new(
+                        speed * angle.cos(),
+                        self.range(0.05, 0.2),
+                        speed * angle.sin(),
+                    );
+                    let grey = self.range(0.5, 0.65);
+                    let particle = Particle {
+                        position,
+                        velocity,
+                        color: [grey, grey, grey * 0.9, 0.4],
+                        size: self.range(0.06, 0.12),
+                        age: 0.0,
+                        lifetime: self.range(0.3, 0.5),
+                        gravity_scale: 0.0,
+                        drag: 4.0,
+                    };
+                    self.push(particle);
+                }
+            }
+        }
+    }
+    fn push(&mut self, particle: Particle) {
+        if self.particles.len() >= MAX_PARTICLES {
+            self.particles.remove(0);
+        }
+        self.particles.push(particle);
+    }
+    pub fn update(&mut self, delta_t: f32) {
+        for particle in &mut self.particles {
+            particle.age += delta_t;
+            particle.velocity += delta_t * particle.gravity_scale * Vector3::new(0.0, GRAVITY, 0.0);
+            particle.velocity *= (1.0 - particle.drag * delta_t).max(0.0);
+            particle.position += delta_t * particle.velocity;
+        }
+        self.particles.retain(|particle| particle.age < particle.lifetime);
+    }
+    // Particles fade out and shrink slightly over their lifetime.
+    pub fn to_raw(&self) -> Vec<ParticleRaw> {
+        self.particles
+            .iter()
+            .map(|particle| {
+                let remaining = 1.0 - particle.age / particle.lifetime;
+                let [r, g, b, a] = particle.color;
+                ParticleRaw {
+                    position: particle.position.into(),
+                    size: particle.size * (0.5 + 0.5 * remaining),
+                    color: [r, g, b, a * remaining],
+                }
+            })
+            .collect()
+    }
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.particles.len() as u64);
+        self.rng.hash_into(hasher);
+        for particle in &self.particles {
+            hasher.write_f32s(&Into::<[f32; 3]>::into(particle.position));
+            hasher.write_f32(particle.age);
+        }
+    }
+}
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,52 @@
+// Camera-facing particle billboards. Each instance is one particle; the quad's corners come from
+// the vertex index so no vertex buffer is needed.
+struct ParticleCamera {
+    view_proj: mat4x4<f32>,
+    right: vec3<f32>,
+    up: vec3<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: ParticleCamera;
+
+struct ParticleInput {
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) color: vec4<f32>,
+}
+
+struct ParticleOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_particle(@builtin(vertex_index) index: u32, particle: ParticleInput) -> ParticleOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[index];
+    let world_position = particle.position
+        + particle.size * (corner.x * camera.right + corner.y * camera.up);
+    var out: ParticleOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.corner = corner;
+    out.color = particle.color;
+    return out;
+}
+
+@fragment
+fn fs_particle(in: ParticleOutput) -> @location(0) vec4<f32> {
+    // Soft round sprite.
+    let falloff = 1.0 - smoothstep(0.4, 1.0, length(in.corner));
+    let alpha = in.color.a * falloff;
+    if alpha < 0.005 {
+        discard;
+    }
+    return vec4<f32>(in.color.rgb, alpha);
+}
@@ -1,87 +1,491 @@
-use cgmath::{InnerSpace, One, Point3, Quaternion, Vector3, Zero};
+use cgmath::{BaseFloat, EuclideanSpace, InnerSpace, Point3, Vector3, Zero};
 
-use crate::constants::GRAVITY;
+#[cfg(feature = "fixed-point")]
+use crate::fixed::{Fixed, FixedVector3};
+use crate::{
+    constants::GRAVITY,
+    rotor::{scalar, Rotor},
+    tick_rate,
+    transform::Transform,
+};
 
+// A body that can sleep falls asleep after this long, in steps in a row starting slower than
+// SLEEP_SPEED.
+pub const SLEEP_SECS: f32 = 0.5;
+pub const SLEEP_SPEED: f32 = 0.01;
+
+/// How `Physics::update` steps a body from its acceleration.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Integrator {
+    /// Velocity first, then position from the new velocity. Cheap, keeps the energy of an orbit or
+    /// a spring from drifting off, and the velocity clamp applies to the same velocity that moves
+    /// the body.
+    #[default]
+    SymplecticEuler,
+    /// Position from the average of the old and new velocities: exact under constant forces, but
+    /// only until the clamp kicks in and switches to the new velocity alone.
+    Midpoint,
+    /// Fourth-order Runge-Kutta. Far more accurate for forces that change over a step, for four
+    /// times the work, and still drifts.
+    Rk4,
+}
+
+/// Generic over the float type, like `Rotor`, to try simulating in f64. The collision shape stays
+/// f32, like the level it collides with.
 #[derive(Clone)]
-pub struct Physics {
-    pub position: Point3<f32>,
-    pub velocity: Vector3<f32>,
-    pub accel: Vector3<f32>,
-    pub mass: f32,
-    pub angular_position: Quaternion<f32>,
-    pub angular_velocity: Vector3<f32>,
-    pub angular_accel: Vector3<f32>,
+pub struct Physics<S = f32> {
+    pub position: Point3<S>,
+    pub velocity: Vector3<S>,
+    pub accel: Vector3<S>,
+    pub mass: S,
+    pub rotation: Rotor<S>,
+    pub angular_velocity: Vector3<S>,
+    pub angular_accel: Vector3<S>,
     pub collision: Collision,
+    // Velocity of whatever the body is standing on, which carries it along on top of its own
+    // velocity.
+    pub ground_velocity: Vector3<S>,
+    // This body's own gravity, so that gravity zones can change it.
+    pub gravity: Vector3<S>,
+    pub integrator: Integrator,
+    // Whether the body stops being stepped once it has been still for a while, as resting boxes
+    // should, to keep ticks cheap. Bodies moved by input, like the player, shouldn't.
+    pub can_sleep: bool,
+    // Steps in a row the body started still, up to SLEEP_SECS worth, when it is asleep.
+    pub still_ticks: u32,
 }
-impl Physics {
+impl<S: BaseFloat> Physics<S> {
     pub fn new() -> Self {
+        let gravity = Vector3::new(S::zero(), scalar(GRAVITY as f64), S::zero());
         Physics {
-            position: Point3::new(0.0, 0.0, 0.0),
+            position: Point3::origin(),
             velocity: Vector3::zero(),
-            accel: Vector3::new(0.0, GRAVITY, 0.0),
-            mass: 1.0,
-            angular_position: Quaternion::one(),
+            accel: gravity,
+            mass: S::one(),
+            rotation: Rotor::identity(),
             angular_velocity: Vector3::zero(),
             angular_accel: Vector3::zero(),
             collision: Collision::new([].into(), [].into()),
+            ground_velocity: Vector3::zero(),
+            gravity,
+            integrator: Integrator::default(),
+            can_sleep: false,
+            still_ticks: 0,
         }
     }
-    pub fn apply_force(&mut self, force: Vector3<f32>) {
+    /// Whether `update` leaves the body where it is. A sleeping body wakes when a force is applied
+    /// to it, when what it stands on moves, or when something touching it calls `wake`.
+    pub fn is_sleeping(&self) -> bool {
+        self.can_sleep && self.still_ticks >= tick_rate::ticks(SLEEP_SECS)
+    }
+    pub fn wake(&mut self) {
+        self.still_ticks = 0;
+    }
+    // Counts the step towards falling asleep. Returns whether the body is asleep, and so not to be
+    // stepped.
+    fn settle(&mut self) -> bool {
+        if !self.can_sleep {
+            return false;
+        }
+        if self.ground_velocity != Vector3::zero() {
+            self.wake();
+        }
+        if self.is_sleeping() {
+            // Left alone, a speed under SLEEP_SPEED would still creep the body along once it wakes.
+            self.velocity = Vector3::zero();
+            return true;
+        }
+        if self.velocity.magnitude() < scalar(SLEEP_SPEED as f64) {
+            self.still_ticks += 1;
+        } else {
+            self.still_ticks = 0;
+        }
+        false
+    }
+    /// Where the body is and how it is turned, at its own size.
+    pub fn transform(&self) -> Transform<S> {
+        Transform::new(self.position.to_vec(), self.rotation, S::one())
+    }
+    // Starts a new step's forces off with just gravity.
+    pub fn reset_forces(&mut self) {
+        self.accel = self.gravity;
+    }
+    pub fn apply_force(&mut self, force: Vector3<S>) {
+        if force != Vector3::zero() {
+            self.wake();
+        }
         self.accel += force / self.mass;
     }
     // Returns the delta in position.
-    pub fn update(&mut self, delta_t: f32, max_vel: f32) -> Vector3<f32> {
+    #[cfg(not(feature = "fixed-point"))]
+    pub fn update(&mut self, delta_t: S, max_vel: S) -> Vector3<S> {
+        let accel = self.accel;
+        self.update_with(delta_t, max_vel, |_, _| accel)
+    }
+    /// Steps the body with `accel` giving its acceleration at a position and velocity, for forces
+    /// that change over the step, like springs. `update` holds `self.accel` instead. Always in
+    /// floats, even with the fixed-point feature. Returns the delta in position.
+    pub fn update_with(
+        &mut self,
+        delta_t: S,
+        max_vel: S,
+        accel: impl Fn(Point3<S>, Vector3<S>) -> Vector3<S>,
+    ) -> Vector3<S> {
+        if self.settle() {
+            return Vector3::zero();
+        }
         let old_pos = self.position;
-        let delta_v = self.accel * delta_t;
-        let delta_pos = delta_t * (self.velocity + 0.5 * delta_v);
-        self.velocity += delta_v;
+        let (x, v) = (self.position, self.velocity);
+        let half = delta_t * scalar(0.5);
+        let (position, velocity) = match self.integrator {
+            Integrator::SymplecticEuler => {
+                let velocity = v + accel(x, v) * delta_t;
+                (x + velocity * delta_t, velocity)
+            }
+            Integrator::Midpoint => {
+                let delta_v = accel(x, v) * delta_t;
+                (x + (v + delta_v * scalar(0.5)) * delta_t, v + delta_v)
+            }
+            Integrator::Rk4 => {
+                // Each stage is (velocity, acceleration), the derivatives of (position, velocity).
+                let k1 = (v, accel(x, v));
+                let k2 = (v + k1.1 * half, accel(x + k1.0 * half, v + k1.1 * half));
+                let k3 = (v + k2.1 * half, accel(x + k2.0 * half, v + k2.1 * half));
+                let k4 = (v + k3.1 * delta_t, accel(x + k3.0 * delta_t, v + k3.1 * delta_t));
+                let (two, sixth) = (scalar::<S>(2.0), delta_t / scalar(6.0));
+                (
+                    x + (k1.0 + (k2.0 + k3.0) * two + k4.0) * sixth,
+                    v + (k1.1 + (k2.1 + k3.1) * two + k4.1) * sixth,
+                )
+            }
+        };
+        self.velocity = velocity;
 
         // TODO: this affects vertical velocity.
-        if max_vel < 0.0 || self.velocity.magnitude() > max_vel {
+        if max_vel < S::zero() || self.velocity.magnitude() > max_vel {
+            // Too fast: the body moves at the top speed for the whole step instead.
             self.velocity = self.velocity.normalize_to(max_vel);
             self.position += self.velocity * delta_t;
         } else {
-            self.position += delta_pos;
+            self.position = position;
         }
+        self.position += self.ground_velocity * delta_t;
         self.position - old_pos
     }
+    // The same step in fixed point, so it comes out the same whatever the machine's floats do.
+    // The body's state is rounded to the nearest 2^-16 on the way in.
+    #[cfg(feature = "fixed-point")]
+    pub fn update(&mut self, delta_t: S, max_vel: S) -> Vector3<S> {
+        if self.settle() {
+            return Vector3::zero();
+        }
+        let (delta_t, max_vel) = (Fixed::from_float(delta_t), Fixed::from_float(max_vel));
+        let old_pos = FixedVector3::from_vector(self.position.to_vec());
+        let mut position = old_pos;
+        let mut velocity = FixedVector3::from_vector(self.velocity);
+        let delta_v = FixedVector3::from_vector(self.accel) * delta_t;
+        let delta_pos = match self.integrator {
+            Integrator::SymplecticEuler => (velocity + delta_v) * delta_t,
+            // Under the constant acceleration `update` steps with, Runge-Kutta comes out the same
+            // as the midpoint step.
+            Integrator::Midpoint | Integrator::Rk4 => {
+                (velocity + delta_v * Fixed::from_float(0.5)) * delta_t
+            }
+        };
+        velocity = velocity + delta_v;
+
+        // TODO: this affects vertical velocity.
+        if max_vel < Fixed::ZERO || velocity.magnitude() > max_vel {
+            velocity = velocity.normalize_to(max_vel);
+            position = position + velocity * delta_t;
+        } else {
+            position = position + delta_pos;
+        }
+        position = position + FixedVector3::from_vector(self.ground_velocity) * delta_t;
+        self.velocity = velocity.to_vector();
+        self.position = Point3::from_vec(position.to_vector());
+        (position - old_pos).to_vector()
+    }
 }
 
+// A body's collision shape, relative to its position.
 #[derive(Clone)]
 pub struct Collision {
     pub vertices: Vec<Vector3<f32>>,
+    // The box around the vertices, or an empty one at the origin if there are none.
+    pub bounds: Aabb,
 }
 impl Collision {
     pub fn new(vertices: Vec<Vector3<f32>>, _indices: Vec<u32>) -> Self {
-        Collision { vertices }
-    }
-    #[allow(unused)]
-    fn calculate_bounding_box(vertices: &Vec<Vector3<f32>>) -> (Vector3<f32>, Vector3<f32>) {
-        if vertices.is_empty() {
-            return (Vector3::zero(), Vector3::zero());
-        }
-        let mut max: Vector3<f32> = vertices[0];
-        let mut min: Vector3<f32> = vertices[0];
-        for vertex in vertices {
-            if vertex.x > max.x {
-                max.x = vertex.x;
-            }
-            if vertex.y > max.y {
-                max.x = vertex.y;
-            }
-            if vertex.z > max.z {
-                max.x = vertex.z;
-            }
-            if vertex.x > min.x {
-                min.x = vertex.x;
+        let bounds =
+            Aabb::from_points(&vertices).unwrap_or(Aabb::new(Vector3::zero(), Vector3::zero()));
+        Collision { vertices, bounds }
+    }
+    // Where the shape is with the body at `position`.
+    pub fn bounds_at(&self, position: Point3<f32>) -> Aabb {
+        self.bounds.translated(position.to_vec())
+    }
+}
+
+// Axis-aligned box in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+impl Aabb {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Aabb { min, max }
+    }
+    pub fn from_center(center: Vector3<f32>, half_extents: Vector3<f32>) -> Self {
+        Aabb { min: center - half_extents, max: center + half_extents }
+    }
+    // The smallest box holding every point, if there are any.
+    pub fn from_points(points: &[Vector3<f32>]) -> Option<Self> {
+        let (first, rest) = points.split_first()?;
+        let mut aabb = Aabb::new(*first, *first);
+        for point in rest {
+            aabb.min.x = aabb.min.x.min(point.x);
+            aabb.min.y = aabb.min.y.min(point.y);
+            aabb.min.z = aabb.min.z.min(point.z);
+            aabb.max.x = aabb.max.x.max(point.x);
+            aabb.max.y = aabb.max.y.max(point.y);
+            aabb.max.z = aabb.max.z.max(point.z);
+        }
+        Some(aabb)
+    }
+    pub fn translated(&self, offset: Vector3<f32>) -> Self {
+        Aabb { min: self.min + offset, max: self.max + offset }
+    }
+    // The eight corners, in no particular order.
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vector3::new(min.x, min.y, min.z),
+            Vector3::new(max.x, min.y, min.z),
+            Vector3::new(min.x, max.y, min.z),
+            Vector3::new(max.x, max.y, min.z),
+            Vector3::new(min.x, min.y, max.z),
+            Vector3::new(max.x, min.y, max.z),
+            Vector3::new(min.x, max.y, max.z),
+            Vector3::new(max.x, max.y, max.z),
+        ]
+    }
+    // Whether the two boxes overlap when seen from above.
+    pub fn overlaps_xz(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+    // Boxes that only touch on a face count as overlapping.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+            && self.min.z <= other.max.z
+            && other.min.z <= self.max.z
+    }
+    // How far along the ray from `origin` towards `direction` it first enters the box, in lengths
+    // of `direction`, or 0 if it starts inside. None if it misses.
+    pub fn ray_hit(&self, origin: Vector3<f32>, direction: Vector3<f32>) -> Option<f32> {
+        let (mut enter, mut exit) = (0.0f32, f32::INFINITY);
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                if origin[axis] < self.min[axis] || origin[axis] > self.max[axis] {
+                    return None;
+                }
+                continue;
             }
-            if vertex.y > min.y {
-                min.x = vertex.y;
+            let to_min = (self.min[axis] - origin[axis]) / direction[axis];
+            let to_max = (self.max[axis] - origin[axis]) / direction[axis];
+            enter = enter.max(to_min.min(to_max));
+            exit = exit.min(to_min.max(to_max));
+        }
+        (enter <= exit).then_some(enter)
+    }
+}
+
+// A volume that replaces the gravity of bodies whose center is inside it. Bodies fall onto the
+// zone's own face in the direction of its gravity, so a zone can have a floor on a wall or ceiling.
+#[derive(Clone, Debug)]
+pub struct GravityZone {
+    pub bounds: Aabb,
+    // Should point along an axis for the zone's surface to make sense.
+    pub gravity: Vector3<f32>,
+}
+impl GravityZone {
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        self.bounds.intersects(&Aabb::new(point, point))
+    }
+    // The axis gravity mostly points along, and whether it points towards +axis.
+    fn down_axis(&self) -> (usize, bool) {
+        let g = self.gravity;
+        let axis = if g.x.abs() >= g.y.abs() && g.x.abs() >= g.z.abs() {
+            0
+        } else if g.y.abs() >= g.z.abs() {
+            1
+        } else {
+            2
+        };
+        (axis, g[axis] > 0.0)
+    }
+    /// Stops `physics`, a body with the given half extents, from falling through the zone's
+    /// surface. Returns whether the body is resting on it.
+    pub fn land(&self, physics: &mut Physics, half_extents: Vector3<f32>) -> bool {
+        let (axis, positive) = self.down_axis();
+        let sign = if positive { 1.0 } else { -1.0 };
+        let surface = if positive { self.bounds.max[axis] } else { self.bounds.min[axis] };
+        let resting_center = surface - sign * half_extents[axis];
+        if sign * (physics.position[axis] - resting_center) < -0.001 {
+            return false;
+        }
+        physics.position[axis] = resting_center;
+        if sign * physics.velocity[axis] > 0.0 {
+            physics.velocity[axis] = 0.0;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELTA_T: f32 = 1.0 / 60.0;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vector3::zero(), Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn from_points_bounds_every_point() {
+        let points = [
+            Vector3::new(1.0, -2.0, 0.5),
+            Vector3::new(-1.0, 3.0, 0.0),
+            Vector3::new(0.0, 0.0, 2.0),
+        ];
+        let aabb = Aabb::from_points(&points).unwrap();
+        assert_eq!(aabb, Aabb::new(Vector3::new(-1.0, -2.0, 0.0), Vector3::new(1.0, 3.0, 2.0)));
+        assert_eq!(Aabb::from_points(&[]), None);
+    }
+
+    #[test]
+    fn translated_moves_both_corners() {
+        let moved = unit_box().translated(Vector3::new(2.0, -1.0, 0.5));
+        assert_eq!(moved, Aabb::new(Vector3::new(2.0, -1.0, 0.5), Vector3::new(3.0, 0.0, 1.5)));
+    }
+
+    #[test]
+    fn corners_are_the_eight_distinct_extremes() {
+        let aabb = Aabb::new(Vector3::new(-1.0, -2.0, -3.0), Vector3::new(1.0, 2.0, 3.0));
+        let corners = aabb.corners();
+        for (i, corner) in corners.iter().enumerate() {
+            for axis in 0..3 {
+                assert!(corner[axis] == aabb.min[axis] || corner[axis] == aabb.max[axis]);
             }
-            if vertex.z > min.z {
-                min.x = vertex.z;
+            assert!(!corners[..i].contains(corner));
+        }
+        assert_eq!(Aabb::from_points(&corners), Some(aabb));
+    }
+
+    #[test]
+    fn boxes_touching_on_a_face_overlap() {
+        let aabb = unit_box();
+        assert!(aabb.intersects(&aabb.translated(Vector3::new(0.5, 0.5, 0.5))));
+        assert!(aabb.intersects(&aabb.translated(Vector3::new(1.0, 0.0, 0.0))));
+        assert!(!aabb.intersects(&aabb.translated(Vector3::new(1.01, 0.0, 0.0))));
+        // Apart vertically, but overlapping from above.
+        let above = aabb.translated(Vector3::new(0.5, 2.0, 0.5));
+        assert!(!aabb.intersects(&above));
+        assert!(aabb.overlaps_xz(&above));
+        assert!(!aabb.overlaps_xz(&aabb.translated(Vector3::new(0.0, 0.0, -1.5))));
+    }
+
+    #[test]
+    fn zone_contains_points_inside_and_on_its_faces() {
+        let zone = GravityZone { bounds: unit_box(), gravity: Vector3::new(0.0, GRAVITY, 0.0) };
+        assert!(zone.contains(Vector3::new(0.5, 0.5, 0.5)));
+        assert!(zone.contains(Vector3::new(1.0, 0.0, 0.5)));
+        assert!(!zone.contains(Vector3::new(0.5, 1.5, 0.5)));
+        assert!(!zone.contains(Vector3::new(-0.01, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn falling_body_never_gains_energy_and_rests_on_the_floor() {
+        // Resting bodies are centered half_extents above the zone's bottom face.
+        let floor = GravityZone {
+            bounds: Aabb::new(Vector3::new(-5.0, 0.0, -5.0), Vector3::new(5.0, 20.0, 5.0)),
+            gravity: Vector3::new(0.0, GRAVITY, 0.0),
+        };
+        let half_extents = Vector3::new(0.5, 0.5, 0.5);
+        // Per unit of mass.
+        let energy =
+            |physics: &Physics| 0.5 * physics.velocity.magnitude2() - GRAVITY * physics.position.y;
+        for integrator in [Integrator::SymplecticEuler, Integrator::Midpoint, Integrator::Rk4] {
+            let mut physics = Physics::new();
+            physics.integrator = integrator;
+            physics.position = Point3::new(0.0, 10.0, 0.0);
+            let start_energy = energy(&physics);
+            let mut landed = false;
+            for _ in 0..180 {
+                physics.reset_forces();
+                physics.update(DELTA_T, 100.0);
+                landed = floor.land(&mut physics, half_extents);
+                assert!(energy(&physics) <= start_energy + 1e-3, "{integrator:?} gained energy");
+                assert!(physics.position.y >= 0.5, "{integrator:?} fell through the floor");
             }
+            assert!(landed);
+            assert_eq!(physics.position.y, 0.5);
+            assert_eq!(physics.velocity.y, 0.0);
         }
-        (min, max)
+    }
+
+    #[test]
+    fn velocity_past_the_limit_is_clamped_to_it() {
+        let max_vel = 2.0;
+        for integrator in [Integrator::SymplecticEuler, Integrator::Midpoint, Integrator::Rk4] {
+            let mut physics = Physics::new();
+            physics.integrator = integrator;
+            // A step of gravity takes the speed past max_vel.
+            physics.velocity = Vector3::new(max_vel, 0.0, 0.0);
+            let delta = physics.update(DELTA_T, max_vel);
+            let speed = physics.velocity.magnitude();
+            assert!(speed <= max_vel + 1e-3, "{integrator:?} moves at {speed}");
+            assert!((delta.magnitude() - max_vel * DELTA_T).abs() < 1e-3, "{integrator:?}");
+            assert!((delta - physics.velocity * DELTA_T).magnitude() < 1e-3, "{integrator:?}");
+        }
+    }
+
+    #[test]
+    fn spring_energy_drift_is_bounded_and_ordered_by_integrator() {
+        let stiffness = 4.0;
+        let energy = |physics: &Physics| {
+            0.5 * physics.velocity.magnitude2()
+                + 0.5 * stiffness * physics.position.to_vec().magnitude2()
+        };
+        // The largest change in energy over 100 seconds, about 30 swings, relative to the start.
+        let drift = |integrator| {
+            let mut physics = Physics::new();
+            physics.integrator = integrator;
+            physics.position = Point3::new(1.0, 0.0, 0.0);
+            let start = energy(&physics);
+            let mut drift = 0.0f32;
+            for _ in 0..6000 {
+                physics.update_with(DELTA_T, 100.0, |x, _| -x.to_vec() * stiffness);
+                drift = drift.max((energy(&physics) - start).abs() / start);
+            }
+            drift
+        };
+        let symplectic_euler = drift(Integrator::SymplecticEuler);
+        let midpoint = drift(Integrator::Midpoint);
+        let rk4 = drift(Integrator::Rk4);
+        // Symplectic Euler's energy wobbles within a step's worth but doesn't build up, the
+        // midpoint step's grows every swing, and Runge-Kutta's barely moves.
+        assert!(symplectic_euler < 0.02, "symplectic Euler drifted {symplectic_euler}");
+        assert!(midpoint > 1.0, "midpoint drifted only {midpoint}");
+        assert!(rk4 < 0.0001, "Runge-Kutta drifted {rk4}");
+        assert!(rk4 < symplectic_euler && symplectic_euler < midpoint);
     }
 }
@@ -0,0 +1,121 @@
+/* Pipeline cache: a scene pipeline per shader variant, so each draw runs only its own variant's
+ * code rather than branching on a per-instance shader id. */
+use crate::{
+    game_state::Shader,
+    gpu_state::{create_render_pipeline, InstanceRaw},
+    model::{DescribeVB, ModelVertex},
+    post_process, texture,
+};
+
+use std::{collections::HashMap, sync::Arc};
+
+const SHADER_SOURCE: &str = include_str!("shaders.wgsl");
+// The line of shaders.wgsl that each variant's copy replaces. As written, the shader branches on
+// the instance's shader id, which is what the skinned pipeline uses.
+const VARIANT_LINE: &str = "const Variant = AnyVariant;";
+
+/// Whether a variant's output is blended over what's behind it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Blend {
+    Opaque,
+    Alpha,
+}
+impl Blend {
+    /// Textured instances are treated as opaque, even if their material's tint or texture has
+    /// alpha.
+    pub fn for_variant(variant: Shader) -> Self {
+        match variant {
+            Shader::SimpleTransparency | Shader::Aerogel | Shader::Ghost => Blend::Alpha,
+            _ => Blend::Opaque,
+        }
+    }
+    fn state(self) -> Option<wgpu::BlendState> {
+        match self {
+            Blend::Opaque => None,
+            Blend::Alpha => Some(wgpu::BlendState::ALPHA_BLENDING),
+        }
+    }
+}
+
+/// Sorting draws by key puts the opaque ones first, so blended ones land on top of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PipelineKey {
+    pub blend: Blend,
+    pub variant: Shader,
+}
+impl PipelineKey {
+    pub fn for_variant(variant: Shader) -> Self {
+        PipelineKey { blend: Blend::for_variant(variant), variant }
+    }
+}
+
+pub struct PipelineCache {
+    layout: wgpu::PipelineLayout,
+    pipelines: HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>,
+    depth_prepass: Arc<wgpu::RenderPipeline>,
+}
+impl PipelineCache {
+    /// Builds the pipeline for every variant up front, so drawing never waits on a compile.
+    pub fn new(device: &wgpu::Device, bind_group_layouts: &[&wgpu::BindGroupLayout]) -> Self {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        // Only the vertex stage runs, and it's the same for every variant, so the unmodified
+        // source will do.
+        let depth_prepass = create_render_pipeline(
+            "Depth Pre-pass Pipeline",
+            device,
+            &layout,
+            post_process::HDR_FORMAT,
+            Some(texture::DEPTH_FORMAT),
+            None,
+            &[ModelVertex::describe_vb(), InstanceRaw::describe_vb()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Shaders"),
+                source: wgpu::ShaderSource::Wgsl(SHADER_SOURCE.into()),
+            },
+            "vs_main",
+            None,
+        );
+        let mut cache = PipelineCache { layout, pipelines: HashMap::new(), depth_prepass };
+        for variant in Shader::ALL {
+            cache.prepare(device, PipelineKey::for_variant(variant));
+        }
+        cache
+    }
+    /// Builds the pipeline for `key`, unless it's already built.
+    pub fn prepare(&mut self, device: &wgpu::Device, key: PipelineKey) {
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+        debug_assert!(SHADER_SOURCE.contains(VARIANT_LINE));
+        let source = SHADER_SOURCE
+            .replace(VARIANT_LINE, &format!("const Variant = {}u;", key.variant as u32));
+        let pipeline = create_render_pipeline(
+            &format!("Render Pipeline ({:?}, {:?})", key.variant, key.blend),
+            device,
+            &self.layout,
+            post_process::HDR_FORMAT,
+            Some(texture::DEPTH_FORMAT),
+            key.blend.state(),
+            &[ModelVertex::describe_vb(), InstanceRaw::describe_vb()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Shaders"),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            },
+            "vs_main",
+            Some("fs_main"),
+        );
+        self.pipelines.insert(key, pipeline);
+    }
+    /// The pipeline for `key`, which must have been prepared.
+    pub fn get(&self, key: PipelineKey) -> &wgpu::RenderPipeline {
+        &self.pipelines[&key]
+    }
+    /// Writes only depth, for laying down the opaque geometry before the scene pass shades it.
+    pub fn depth_prepass(&self) -> &wgpu::RenderPipeline {
+        &self.depth_prepass
+    }
+}
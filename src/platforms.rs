@@ -0,0 +1,85 @@
+/* Platforms: kinematic boxes that move along waypoints each tick and carry what stands on them. */
+use cgmath::{Vector3, Zero};
+
+use crate::{animation::InstanceRef, determinism::StateHasher, physics::Aabb};
+
+// How far a body's bottom can be from a platform's top and still count as standing on it.
+const STANDING_TOLERANCE: f32 = 0.01;
+
+#[derive(Clone)]
+pub struct Platform {
+    // The instance drawn for this platform.
+    pub target: InstanceRef,
+    pub half_extents: Vector3<f32>,
+    // Visited in order, then in reverse, forever.
+    waypoints: Vec<Vector3<f32>>,
+    ticks_per_leg: u32,
+    pub position: Vector3<f32>,
+    // Change in position over the last tick, per second.
+    pub velocity: Vector3<f32>,
+}
+impl Platform {
+    /// `waypoints` must not be empty.
+    pub fn new(
+        target: InstanceRef,
+        half_extents: Vector3<f32>,
+        waypoints: Vec<Vector3<f32>>,
+        ticks_per_leg: u32,
+    ) -> Self {
+        assert!(!waypoints.is_empty(), "a platform needs at least one waypoint");
+        Platform {
+            target,
+            half_extents,
+            position: waypoints[0],
+            waypoints,
+            ticks_per_leg: ticks_per_leg.max(1),
+            velocity: Vector3::zero(),
+        }
+    }
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_center(self.position, self.half_extents)
+    }
+    pub fn top(&self) -> f32 {
+        self.position.y + self.half_extents.y
+    }
+    // Where the platform is `tick` ticks in, moving at a constant speed along each leg.
+    fn position_at(&self, tick: u32) -> Vector3<f32> {
+        let legs = self.waypoints.len() as u32 - 1;
+        if legs == 0 {
+            return self.waypoints[0];
+        }
+        let phase = tick % (2 * legs * self.ticks_per_leg);
+        // Past the last waypoint, run the path backwards.
+        let phase = if phase > legs * self.ticks_per_leg {
+            2 * legs * self.ticks_per_leg - phase
+        } else {
+            phase
+        };
+        let leg = (phase / self.ticks_per_leg).min(legs - 1);
+        let t = (phase - leg * self.ticks_per_leg) as f32 / self.ticks_per_leg as f32;
+        let (from, to) = (self.waypoints[leg as usize], self.waypoints[leg as usize + 1]);
+        from + (to - from) * t
+    }
+    /// Moves the platform to where it is at `tick`, returning how far it moved.
+    pub fn update(&mut self, tick: u32, delta_t: f32) -> Vector3<f32> {
+        let next = self.position_at(tick);
+        let delta = next - self.position;
+        self.position = next;
+        self.velocity = delta / delta_t;
+        delta
+    }
+    /// Whether a body with bounds `body` rests on top of this platform.
+    pub fn supports(&self, body: &Aabb) -> bool {
+        (body.min.y - self.top()).abs() <= STANDING_TOLERANCE && self.bounds().overlaps_xz(body)
+    }
+    /// Whether a body falling from `before` to `after` this tick passed down through the top.
+    pub fn caught(&self, before: &Aabb, after: &Aabb) -> bool {
+        before.min.y >= self.top() - STANDING_TOLERANCE
+            && after.min.y <= self.top()
+            && self.bounds().overlaps_xz(after)
+    }
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.position));
+        hasher.write_f32s(&Into::<[f32; 3]>::into(self.velocity));
+    }
+}
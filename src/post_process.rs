@@ -0,0 +1,380 @@
+/* PostProcess: the scene is rendered into an HDR offscreen texture, then bloomed, tonemapped,
+ * and vignetted onto the swapchain. */
+use crate::{
+    gpu_state::{create_render_pipeline, BindGroupData},
+    render_graph::{RenderGraph, Resource, TextureDesc, TextureId, TextureSize, TransientTextures},
+    settings::Palette,
+    uploader::Uploader,
+};
+
+use std::{cell::Cell, sync::Arc};
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PostProcessUniform {
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub vignette_strength: f32,
+    pub exposure: f32,
+    // 0 for manual exposure, 1 for auto.
+    pub auto_exposure: u32,
+    // The scene luminance that auto exposure maps to middle grey.
+    pub auto_exposure_key: f32,
+    // Higher adapts faster; roughly 1/seconds.
+    pub adaptation_speed: f32,
+    pub delta_secs: f32,
+    // Multiplies the tonemapped color; see `palette_matrix`. Columns, padded to four floats.
+    pub palette: [[f32; 4]; 3],
+}
+impl PostProcessUniform {
+    pub fn new() -> Self {
+        PostProcessUniform {
+            bloom_threshold: 0.7,
+            bloom_intensity: 0.6,
+            vignette_strength: 0.35,
+            exposure: 1.0,
+            auto_exposure: 0,
+            auto_exposure_key: 0.18,
+            adaptation_speed: 1.5,
+            delta_secs: 0.0,
+            palette: palette_matrix(Palette::Standard),
+        }
+    }
+}
+impl Default for PostProcessUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// How a dichromat sees linear RGB, from Machado, Oliveira and Fernandes (2009) at full severity.
+// Rows, so each multiplies the input color to give one output channel.
+const DEUTERANOPIA: [[f32; 3]; 3] = [
+    [0.367322, 0.860646, -0.227968],
+    [0.280085, 0.672501, 0.047413],
+    [-0.011820, 0.042940, 0.968881],
+];
+const PROTANOPIA: [[f32; 3]; 3] = [
+    [0.152286, 1.052583, -0.204868],
+    [0.114503, 0.786281, 0.099216],
+    [-0.003882, -0.048116, 1.051998],
+];
+// Where the color lost to a red-green deficiency goes: into green and blue, which are still seen.
+const ERROR_SHIFT: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [0.7, 1.0, 0.0], [0.7, 0.0, 1.0]];
+
+// Daltonizes: adds back the difference between a color and how it's seen, shifted into channels
+// that are. That is `I + ERROR_SHIFT * (I - simulation)`, as columns for WGSL's mat3x3. The
+// standard palette is seen as it is, so nothing is added.
+fn palette_matrix(palette: Palette) -> [[f32; 4]; 3] {
+    let simulation = match palette {
+        Palette::Standard => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        Palette::Deuteranopia => DEUTERANOPIA,
+        Palette::Protanopia => PROTANOPIA,
+    };
+    let identity = |row: usize, column: usize| if row == column { 1.0 } else { 0.0 };
+    let mut columns = [[0.0; 4]; 3];
+    for (column, out) in columns.iter_mut().enumerate() {
+        for (row, value) in out.iter_mut().take(3).enumerate() {
+            let lost = |k: usize| identity(k, column) - simulation[k][column];
+            *value =
+                identity(row, column) + (0..3).map(|k| ERROR_SHIFT[row][k] * lost(k)).sum::<f32>();
+        }
+    }
+    columns
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ExposureMode {
+    /// A fixed multiplier applied to the HDR image before tonemapping.
+    Manual(f32),
+    /// Exposure follows the average scene luminance, easing towards `key / average`.
+    Auto { key: f32, adaptation_speed: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AntiAliasing {
+    None,
+    /// Fast approximate anti-aliasing on the tonemapped image. Unlike MSAA, this also smooths
+    /// aliasing inside the procedural shader patterns.
+    Fxaa,
+}
+
+// Auto exposure averages log-luminance over a grid this many texels on a side.
+const LUMINANCE_GRID_SIZE: u32 = 16;
+
+pub struct PostProcess {
+    params: BindGroupData<PostProcessUniform>,
+    anti_aliasing: AntiAliasing,
+    hdr: TextureId,
+    // Bloom is computed at half resolution, ping-ponging between the two targets while blurring.
+    bloom_a: TextureId,
+    bloom_b: TextureId,
+    // Tonemapped output, only used when an anti-aliasing pass runs after the composite.
+    ldr: TextureId,
+    log_luminance: TextureId,
+    // 1x1 targets holding the adapted luminance for the current and previous frames. Which one is
+    // current flips every frame.
+    adapted_luminance: [TextureId; 2],
+    current_adapted: Cell<usize>,
+    log_luminance_pipeline: Arc<wgpu::RenderPipeline>,
+    adapt_luminance_pipeline: Arc<wgpu::RenderPipeline>,
+    bright_pass_pipeline: Arc<wgpu::RenderPipeline>,
+    blur_horizontal_pipeline: Arc<wgpu::RenderPipeline>,
+    blur_vertical_pipeline: Arc<wgpu::RenderPipeline>,
+    composite_pipeline: Arc<wgpu::RenderPipeline>,
+    fxaa_pipeline: Arc<wgpu::RenderPipeline>,
+}
+impl PostProcess {
+    pub fn new(
+        device: &wgpu::Device,
+        textures: &mut TransientTextures,
+        output_format: wgpu::TextureFormat,
+    ) -> Self {
+        let texture_layout = textures.sampled_layout();
+        let params = BindGroupData::<PostProcessUniform>::new(
+            PostProcessUniform::new(),
+            device,
+            "Post Process",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::FRAGMENT,
+        );
+
+        let single_source_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Process Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, &params.layout],
+            push_constant_ranges: &[],
+        });
+        let two_source_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Adapt Luminance Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, &params.layout, texture_layout],
+            push_constant_ranges: &[],
+        });
+        let composite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Composite Pipeline Layout"),
+            bind_group_layouts: &[texture_layout, &params.layout, texture_layout, texture_layout],
+            push_constant_ranges: &[],
+        });
+        let fullscreen_pipeline = |label: &str,
+                                   layout: &wgpu::PipelineLayout,
+                                   format,
+                                   fragment_entrypoint: &str| {
+            create_render_pipeline(
+                label,
+                device,
+                layout,
+                format,
+                None,
+                Some(wgpu::BlendState::REPLACE),
+                &[],
+                wgpu::ShaderModuleDescriptor {
+                    label: Some("Post Process Shaders"),
+                    source: wgpu::ShaderSource::Wgsl(
+                        concat!(include_str!("fullscreen.wgsl"), include_str!("post_process.wgsl"))
+                            .into(),
+                    ),
+                },
+                "vs_fullscreen",
+                Some(fragment_entrypoint),
+            )
+        };
+        let log_luminance_pipeline = fullscreen_pipeline(
+            "Log Luminance",
+            &single_source_layout,
+            HDR_FORMAT,
+            "fs_log_luminance",
+        );
+        let adapt_luminance_pipeline = fullscreen_pipeline(
+            "Adapt Luminance",
+            &two_source_layout,
+            HDR_FORMAT,
+            "fs_adapt_luminance",
+        );
+        let bright_pass_pipeline =
+            fullscreen_pipeline("Bright Pass", &single_source_layout, HDR_FORMAT, "fs_bright_pass");
+        let blur_horizontal_pipeline = fullscreen_pipeline(
+            "Blur Horizontal",
+            &single_source_layout,
+            HDR_FORMAT,
+            "fs_blur_horizontal",
+        );
+        let blur_vertical_pipeline = fullscreen_pipeline(
+            "Blur Vertical",
+            &single_source_layout,
+            HDR_FORMAT,
+            "fs_blur_vertical",
+        );
+        let composite_pipeline =
+            fullscreen_pipeline("Composite", &composite_layout, output_format, "fs_composite");
+        let fxaa_pipeline =
+            fullscreen_pipeline("FXAA", &single_source_layout, output_format, "fs_fxaa");
+
+        let mut declare =
+            |label, format, size| textures.declare(device, TextureDesc { label, format, size });
+        let hdr = declare("HDR Target", HDR_FORMAT, TextureSize::Surface);
+        let bloom_a = declare("Bloom Target A", HDR_FORMAT, TextureSize::SurfaceFraction(2));
+        let bloom_b = declare("Bloom Target B", HDR_FORMAT, TextureSize::SurfaceFraction(2));
+        let ldr = declare("LDR Target", output_format, TextureSize::Surface);
+        let log_luminance = declare(
+            "Log Luminance Target",
+            HDR_FORMAT,
+            TextureSize::Fixed(LUMINANCE_GRID_SIZE, LUMINANCE_GRID_SIZE),
+        );
+        let adapted_luminance = [
+            declare("Adapted Luminance A", HDR_FORMAT, TextureSize::Fixed(1, 1)),
+            declare("Adapted Luminance B", HDR_FORMAT, TextureSize::Fixed(1, 1)),
+        ];
+        PostProcess {
+            params,
+            anti_aliasing: AntiAliasing::Fxaa,
+            hdr,
+            bloom_a,
+            bloom_b,
+            ldr,
+            log_luminance,
+            adapted_luminance,
+            current_adapted: Cell::new(0),
+            log_luminance_pipeline,
+            adapt_luminance_pipeline,
+            bright_pass_pipeline,
+            blur_horizontal_pipeline,
+            blur_vertical_pipeline,
+            composite_pipeline,
+            fxaa_pipeline,
+        }
+    }
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.anti_aliasing = anti_aliasing;
+    }
+    /// The target the scene pass should render into.
+    pub fn hdr(&self) -> TextureId {
+        self.hdr
+    }
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.params.uniform.palette = palette_matrix(palette);
+    }
+    pub fn set_exposure_mode(&mut self, mode: ExposureMode) {
+        let uniform = &mut self.params.uniform;
+        match mode {
+            ExposureMode::Manual(exposure) => {
+                uniform.auto_exposure = 0;
+                uniform.exposure = exposure;
+            }
+            ExposureMode::Auto { key, adaptation_speed } => {
+                uniform.auto_exposure = 1;
+                uniform.auto_exposure_key = key;
+                uniform.adaptation_speed = adaptation_speed;
+            }
+        }
+    }
+    /// Uploads the parameters for this frame. `delta_secs` drives the auto exposure adaptation.
+    pub fn update(&mut self, uploader: &mut Uploader, delta_secs: f32) {
+        self.params.uniform.delta_secs = delta_secs;
+        uploader.upload(&self.params.buffer, 0, &[self.params.uniform]);
+    }
+    /// Adds exposure, bloom, the final composite and anti-aliasing to `graph`, reading the HDR
+    /// target and writing to `output`.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        textures: &'a TransientTextures,
+        output: (Resource, &'a wgpu::TextureView),
+    ) {
+        let previous_adapted = self.current_adapted.get();
+        let current_adapted = 1 - previous_adapted;
+        self.current_adapted.set(current_adapted);
+        let (output, output_view) = output;
+        let composite_target = match self.anti_aliasing {
+            AntiAliasing::None => output,
+            AntiAliasing::Fxaa => Resource::Texture(self.ldr),
+        };
+        // The previous frame's adapted luminance is carried over, not produced this frame.
+        graph.import(Resource::Texture(self.adapted_luminance[previous_adapted]));
+        // Each pass samples `sources` and overwrites `target`.
+        let mut passes: Vec<(_, _, Vec<TextureId>, _)> = vec![
+            (
+                "Log Luminance Pass",
+                &self.log_luminance_pipeline,
+                vec![self.hdr],
+                Resource::Texture(self.log_luminance),
+            ),
+            (
+                "Adapt Luminance Pass",
+                &self.adapt_luminance_pipeline,
+                vec![self.log_luminance, self.adapted_luminance[previous_adapted]],
+                Resource::Texture(self.adapted_luminance[current_adapted]),
+            ),
+            (
+                "Bright Pass",
+                &self.bright_pass_pipeline,
+                vec![self.hdr],
+                Resource::Texture(self.bloom_a),
+            ),
+            (
+                "Blur Horizontal Pass",
+                &self.blur_horizontal_pipeline,
+                vec![self.bloom_a],
+                Resource::Texture(self.bloom_b),
+            ),
+            (
+                "Blur Vertical Pass",
+                &self.blur_vertical_pipeline,
+                vec![self.bloom_b],
+                Resource::Texture(self.bloom_a),
+            ),
+            (
+                "Composite Pass",
+                &self.composite_pipeline,
+                vec![self.hdr, self.bloom_a, self.adapted_luminance[current_adapted]],
+                composite_target,
+            ),
+        ];
+        if let AntiAliasing::Fxaa = self.anti_aliasing {
+            passes.push(("FXAA Pass", &self.fxaa_pipeline, vec![self.ldr], output));
+        }
+        for (label, pipeline, sources, target) in passes {
+            let reads = sources.iter().map(|id| Resource::Texture(*id)).collect::<Vec<_>>();
+            graph.add_pass(label, &reads, &[target], move |encoder| {
+                let target_view = match target {
+                    Resource::Texture(id) => textures.view(id),
+                    Resource::External(_) => output_view,
+                };
+                let bind_groups =
+                    sources.iter().map(|id| textures.bind_group(*id)).collect::<Vec<_>>();
+                self.encode_fullscreen_pass(encoder, label, pipeline, &bind_groups, target_view);
+            });
+        }
+    }
+    // `sources` are bound at groups 0, 2 and 3 as needed; the params always go in group 1.
+    fn encode_fullscreen_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        sources: &[&wgpu::BindGroup],
+        target: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, sources[0], &[]);
+        pass.set_bind_group(1, &self.params.bind_group, &[]);
+        for (group, source) in [2, 3].into_iter().zip(&sources[1..]) {
+            pass.set_bind_group(group, source, &[]);
+        }
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,161 @@
+struct PostProcessParams {
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    vignette_strength: f32,
+    exposure: f32,
+    auto_exposure: u32,
+    auto_exposure_key: f32,
+    adaptation_speed: f32,
+    delta_secs: f32,
+    // Remaps the tonemapped color for the colorblind-safe palettes; identity otherwise.
+    palette: mat3x3<f32>,
+}
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(1) @binding(0)
+var<uniform> params: PostProcessParams;
+// Bloom for the composite pass; the previous frame's adapted luminance for the average pass.
+@group(2) @binding(0)
+var t_second: texture_2d<f32>;
+@group(2) @binding(1)
+var s_second: sampler;
+// Only bound for the composite pass: the 1x1 adapted scene luminance.
+@group(3) @binding(0)
+var t_adapted_luminance: texture_2d<f32>;
+@group(3) @binding(1)
+var s_adapted_luminance: sampler;
+
+fn luminance(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+// Keeps only the part of each pixel that is brighter than the bloom threshold.
+@fragment
+fn fs_bright_pass(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(t_source, s_source, in.uv).rgb;
+    let lum = luminance(color);
+    let contribution = max(lum - params.bloom_threshold, 0.0) / max(lum, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+// 9-tap separable gaussian.
+const BlurWeights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    var weights = BlurWeights;
+    let texel = direction / vec2<f32>(textureDimensions(t_source));
+    var result = textureSample(t_source, s_source, uv).rgb * weights[0];
+    for (var i = 1; i < 5; i += 1) {
+        let offset = texel * f32(i);
+        result += textureSample(t_source, s_source, uv + offset).rgb * weights[i];
+        result += textureSample(t_source, s_source, uv - offset).rgb * weights[i];
+    }
+    return vec4<f32>(result, 1.0);
+}
+@fragment
+fn fs_blur_horizontal(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+@fragment
+fn fs_blur_vertical(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+// Downsamples the HDR target into a small grid of log-luminance values.
+@fragment
+fn fs_log_luminance(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let lum = luminance(textureSample(t_source, s_source, in.uv).rgb);
+    return vec4<f32>(log(lum + 0.0001), 0.0, 0.0, 1.0);
+}
+
+// Runs on a 1x1 target: averages the log-luminance grid and eases the previous frame's adapted
+// value towards it, so exposure changes gradually like an eye adjusting.
+@fragment
+fn fs_adapt_luminance(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(t_source);
+    var total = 0.0;
+    for (var y = 0u; y < size.y; y += 1u) {
+        for (var x = 0u; x < size.x; x += 1u) {
+            total += textureLoad(t_source, vec2<u32>(x, y), 0).r;
+        }
+    }
+    let average = exp(total / f32(size.x * size.y));
+    let previous = textureLoad(t_second, vec2<u32>(0u, 0u), 0).r;
+    let blend = 1.0 - exp(-params.delta_secs * params.adaptation_speed);
+    return vec4<f32>(previous + (average - previous) * blend, 0.0, 0.0, 1.0);
+}
+
+fn current_exposure() -> f32 {
+    if params.auto_exposure == 0u {
+        return params.exposure;
+    }
+    let adapted = textureLoad(t_adapted_luminance, vec2<u32>(0u, 0u), 0).r;
+    return clamp(params.auto_exposure_key / max(adapted, 0.0001), 0.05, 20.0);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn tonemap_aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_source, s_source, in.uv).rgb
+        + params.bloom_intensity * textureSample(t_second, s_second, in.uv).rgb;
+    let tonemapped = tonemap_aces(hdr * current_exposure());
+    let mapped = clamp(params.palette * tonemapped, vec3<f32>(0.0), vec3<f32>(1.0));
+    let vignette =
+        1.0 - params.vignette_strength * smoothstep(0.4, 0.8, distance(in.uv, vec2<f32>(0.5)));
+    // The swapchain is sRGB, so the hardware applies the gamma curve on write.
+    return vec4<f32>(mapped * vignette, 1.0);
+}
+
+// FXAA, following the structure of Timothy Lottes' original "PC" version. Luma is estimated from
+// the linear color with a square root, since the edges we care about are perceptual.
+const FxaaReduceMin = 0.0078125;
+const FxaaReduceMul = 0.125;
+const FxaaSpanMax = 8.0;
+fn fxaa_luma(color: vec3<f32>) -> f32 {
+    return dot(sqrt(color), vec3<f32>(0.299, 0.587, 0.114));
+}
+fn fxaa_sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSample(t_source, s_source, uv).rgb;
+}
+@fragment
+fn fs_fxaa(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let rgb_m = fxaa_sample(in.uv);
+    let luma_nw = fxaa_luma(fxaa_sample(in.uv + vec2<f32>(-1.0, -1.0) * texel));
+    let luma_ne = fxaa_luma(fxaa_sample(in.uv + vec2<f32>(1.0, -1.0) * texel));
+    let luma_sw = fxaa_luma(fxaa_sample(in.uv + vec2<f32>(-1.0, 1.0) * texel));
+    let luma_se = fxaa_luma(fxaa_sample(in.uv + vec2<f32>(1.0, 1.0) * texel));
+    let luma_m = fxaa_luma(rgb_m);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, i.e. perpendicular to the luma gradient.
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let dir_reduce =
+        max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FxaaReduceMul, FxaaReduceMin);
+    let rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, vec2<f32>(-FxaaSpanMax), vec2<f32>(FxaaSpanMax)) * texel;
+
+    let rgb_a = 0.5 * (fxaa_sample(in.uv + dir * (1.0 / 3.0 - 0.5))
+        + fxaa_sample(in.uv + dir * (2.0 / 3.0 - 0.5)));
+    let rgb_b = rgb_a * 0.5
+        + 0.25 * (fxaa_sample(in.uv - dir * 0.5) + fxaa_sample(in.uv + dir * 0.5));
+    let luma_b = fxaa_luma(rgb_b);
+    if luma_b < luma_min || luma_b > luma_max {
+        return vec4<f32>(rgb_a, 1.0);
+    }
+    return vec4<f32>(rgb_b, 1.0);
+}
@@ -0,0 +1,26 @@
+/* Profiler: starts whichever profiling backend the build enabled, if any. */
+#[cfg(feature = "profile-with-puffin")]
+use crate::log_println;
+
+/// Call once at startup, before any `profiling::scope!` is entered. With `profile-with-tracy`,
+/// connect the Tracy profiler to the running game; with `profile-with-puffin`, point
+/// puffin_viewer at 127.0.0.1:8585.
+pub fn start() {
+    #[cfg(feature = "profile-with-tracy")]
+    profiling::tracy_client::Client::start();
+    #[cfg(feature = "profile-with-puffin")]
+    start_puffin();
+}
+
+#[cfg(feature = "profile-with-puffin")]
+fn start_puffin() {
+    profiling::puffin::set_scopes_on(true);
+    let address = format!("127.0.0.1:{}", puffin_http::DEFAULT_PORT);
+    match puffin_http::Server::new(&address) {
+        // The server stops serving when dropped, and it should serve until the game exits.
+        Ok(server) => std::mem::forget(server),
+        Err(e) => {
+            log_println!("Failed to start the puffin server: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+/* Readback: CPU-side copies of rendered color/depth, for automated checks and picking. */
+use std::sync::mpsc;
+
+pub struct Readback {
+    pub width: u32,
+    pub height: u32,
+    // Row-major, top row first. Colors are RGBA regardless of the surface's channel order.
+    pub colors: Vec<[u8; 4]>,
+    pub depths: Vec<f32>,
+}
+impl Readback {
+    /// (x, y) is relative to the top-left corner of the region that was read back.
+    pub fn pixel(&self, x: u32, y: u32) -> ([u8; 4], f32) {
+        let i = (y * self.width + x) as usize;
+        (self.colors[i], self.depths[i])
+    }
+}
+
+// Texture-to-buffer copies require each row to start on a COPY_BYTES_PER_ROW_ALIGNMENT boundary,
+// so the buffer is generally wider than the region being read.
+pub struct BufferLayout {
+    width: u32,
+    height: u32,
+    bytes_per_pixel: u32,
+    padded_bytes_per_row: u32,
+}
+impl BufferLayout {
+    pub fn new(width: u32, height: u32, bytes_per_pixel: u32) -> Self {
+        let unpadded = width * bytes_per_pixel;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded.div_ceil(align) * align;
+        BufferLayout { width, height, bytes_per_pixel, padded_bytes_per_row }
+    }
+    pub fn create_buffer(&self, device: &wgpu::Device, label: &str) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (self.padded_bytes_per_row * self.height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        })
+    }
+    pub fn image_copy_buffer<'a>(&self, buffer: &'a wgpu::Buffer) -> wgpu::ImageCopyBuffer<'a> {
+        wgpu::ImageCopyBuffer {
+            buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(self.padded_bytes_per_row),
+                rows_per_image: Some(self.height),
+            },
+        }
+    }
+    /// Blocks until `buffer` is mapped, then returns its contents with the row padding removed.
+    pub fn map_and_read(
+        &self,
+        device: &wgpu::Device,
+        buffer: &wgpu::Buffer,
+    ) -> anyhow::Result<Vec<u8>> {
+        let slice = buffer.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        rx.recv()??;
+
+        let row_bytes = (self.width * self.bytes_per_pixel) as usize;
+        let mut out = Vec::with_capacity(row_bytes * self.height as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(self.padded_bytes_per_row as usize) {
+                out.extend_from_slice(&row[..row_bytes]);
+            }
+        }
+        buffer.unmap();
+        Ok(out)
+    }
+}
@@ -0,0 +1,80 @@
+/* Records: each level's fewest moves, fewest pushes and fastest time, kept on disk. */
+use crate::level::LevelStats;
+
+use anyhow::{anyhow, Context};
+use std::collections::BTreeMap;
+
+pub const RECORDS_FILE: &str = "records.txt";
+
+/// Best stats per level, keyed by `Level::id`. Each stat is tracked on its own, so one level's
+/// fewest moves and fastest time can come from different attempts.
+///
+/// The file format is one level per line: the level id in hex, then moves, pushes and ticks.
+pub struct Records {
+    levels: BTreeMap<u64, LevelStats>,
+}
+impl Records {
+    pub fn new() -> Self {
+        Records { levels: BTreeMap::new() }
+    }
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut records = Records::new();
+        for (line_number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (id, stats) = Self::parse_line(line)
+                .with_context(|| format!("records line {}", line_number + 1))?;
+            records.levels.insert(id, stats);
+        }
+        Ok(records)
+    }
+    fn parse_line(line: &str) -> anyhow::Result<(u64, LevelStats)> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let [id, moves, pushes, ticks] = fields[..] else {
+            return Err(anyhow!("expected 4 fields, found {}", fields.len()));
+        };
+        let stats =
+            LevelStats { moves: moves.parse()?, pushes: pushes.parse()?, ticks: ticks.parse()? };
+        Ok((u64::from_str_radix(id, 16)?, stats))
+    }
+    /// A missing file means no records yet.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("parsing records {}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Records::new()),
+            Err(e) => Err(e).with_context(|| format!("reading records {}", path)),
+        }
+    }
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text: String = self
+            .levels
+            .iter()
+            .map(|(id, stats)| {
+                format!("{:016x} {} {} {}\n", id, stats.moves, stats.pushes, stats.ticks)
+            })
+            .collect();
+        std::fs::write(path, text).with_context(|| format!("writing records to {}", path))
+    }
+    pub fn get(&self, level: u64) -> Option<LevelStats> {
+        self.levels.get(&level).copied()
+    }
+    /// Folds a finished attempt at `level` into its records. Returns whether any record was beaten.
+    pub fn submit(&mut self, level: u64, stats: LevelStats) -> bool {
+        let Some(best) = self.levels.get_mut(&level) else {
+            self.levels.insert(level, stats);
+            return true;
+        };
+        let improved =
+            stats.moves < best.moves || stats.pushes < best.pushes || stats.ticks < best.ticks;
+        best.moves = best.moves.min(stats.moves);
+        best.pushes = best.pushes.min(stats.pushes);
+        best.ticks = best.ticks.min(stats.ticks);
+        improved
+    }
+}
+impl Default for Records {
+    fn default() -> Self {
+        Self::new()
+    }
+}
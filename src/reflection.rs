@@ -0,0 +1,105 @@
+/* Reflection: the environment cubemap that reflective materials mirror. A static HDR sky for
+ * now, which probes rendered from inside the level could later replace. */
+use cgmath::{InnerSpace, Vector3};
+
+use crate::{gpu_memory::Allocation, texture};
+
+// Texels along each edge of a face. The sky is smooth, so this is plenty.
+const FACE_SIZE: u32 = 32;
+const ZENITH: [f32; 3] = [0.2, 0.45, 0.9];
+const HORIZON: [f32; 3] = [0.65, 0.8, 0.95];
+const GROUND: [f32; 3] = [0.15, 0.13, 0.1];
+// Far brighter than the rest of the sky, so the highlight it leaves survives tonemapping.
+const SUN_COLOR: [f32; 3] = [40.0, 36.0, 30.0];
+// The exponent on the cosine to the sun; higher is a smaller sun.
+const SUN_SHARPNESS: f32 = 400.0;
+
+/// A cube texture in HDR, viewed as a cube for sampling by direction.
+pub fn static_environment(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+    let size = wgpu::Extent3d { width: FACE_SIZE, height: FACE_SIZE, depth_or_array_layers: 6 };
+    let desc = wgpu::TextureDescriptor {
+        label: Some("Environment Cubemap"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    };
+    let texture = device.create_texture(&desc);
+    let mut texels = Vec::with_capacity((6 * FACE_SIZE * FACE_SIZE * 4) as usize);
+    for face in 0..6 {
+        for y in 0..FACE_SIZE {
+            for x in 0..FACE_SIZE {
+                let [r, g, b] = radiance(face_direction(face, x, y));
+                texels.extend([r, g, b, 1.0].map(f16_bits));
+            }
+        }
+    }
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&texels),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(8 * FACE_SIZE),
+            rows_per_image: Some(FACE_SIZE),
+        },
+        size,
+    );
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Environment Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    texture::Texture { texture, view, sampler, memory: Allocation::texture(&desc) }
+}
+
+// The direction through the center of texel (x, y) of `face`, in the order and orientation wgpu
+// lays out cube faces: +X, -X, +Y, -Y, +Z, -Z.
+fn face_direction(face: u32, x: u32, y: u32) -> Vector3<f32> {
+    let s = 2.0 * (x as f32 + 0.5) / FACE_SIZE as f32 - 1.0;
+    let t = 2.0 * (y as f32 + 0.5) / FACE_SIZE as f32 - 1.0;
+    let direction = match face {
+        0 => Vector3::new(1.0, -t, -s),
+        1 => Vector3::new(-1.0, -t, s),
+        2 => Vector3::new(s, 1.0, t),
+        3 => Vector3::new(s, -1.0, -t),
+        4 => Vector3::new(s, -t, 1.0),
+        _ => Vector3::new(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+// The sky seen along `direction`: a gradient up from the horizon, plain ground below it, and the
+// sun in the direction of the default light.
+fn radiance(direction: Vector3<f32>) -> [f32; 3] {
+    let (from, to, t) = if direction.y >= 0.0 {
+        (HORIZON, ZENITH, direction.y.sqrt())
+    } else {
+        (HORIZON, GROUND, (-direction.y).sqrt())
+    };
+    let sun = direction.dot(Vector3::new(1.0, 1.0, 1.0).normalize()).max(0.0).powf(SUN_SHARPNESS);
+    [0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * t + SUN_COLOR[i] * sun)
+}
+
+// Half-float bits for a non-negative `value`, truncating the mantissa. Values too small for a
+// normal half flush to zero, and values too large for one saturate.
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.clamp(0.0, 65504.0).to_bits();
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    if exponent <= 0 {
+        return 0;
+    }
+    ((exponent as u32) << 10 | (bits >> 13) & 0x3ff) as u16
+}
@@ -0,0 +1,232 @@
+/* RenderGraph: a frame is an ordered list of passes that declare which resources they read and
+ * write. Intermediate textures are declared once in TransientTextures, which owns their sizing,
+ * recreation on resize, and the bind groups used to sample them. */
+use crate::log_println;
+
+// A filterable 2D texture at binding 0 and its sampler at binding 1, as read by every pass.
+fn create_texture_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+        label: Some("transient_texture_bind_group_layout"),
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSize {
+    /// Matches the surface, and is recreated whenever it resizes.
+    Surface,
+    /// The surface size divided by this, rounded down; also follows resizes.
+    SurfaceFraction(u32),
+    /// A fixed size. These keep their contents across resizes, so they can carry data between
+    /// frames.
+    Fixed(u32, u32),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextureDesc {
+    pub label: &'static str,
+    pub format: wgpu::TextureFormat,
+    pub size: TextureSize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureId(usize);
+
+struct TransientTexture {
+    desc: TextureDesc,
+    view: wgpu::TextureView,
+    // For sampling this texture as the source of a later pass, with `sampled_layout`.
+    bind_group: wgpu::BindGroup,
+}
+
+pub struct TransientTextures {
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    textures: Vec<TransientTexture>,
+    width: u32,
+    height: u32,
+}
+impl TransientTextures {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        TransientTextures {
+            layout: create_texture_layout(device),
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Default::default()
+            }),
+            textures: Vec::new(),
+            width,
+            height,
+        }
+    }
+    pub fn declare(&mut self, device: &wgpu::Device, desc: TextureDesc) -> TextureId {
+        let texture = self.create(device, desc);
+        self.textures.push(texture);
+        TextureId(self.textures.len() - 1)
+    }
+    fn create(&self, device: &wgpu::Device, desc: TextureDesc) -> TransientTexture {
+        let (width, height) = match desc.size {
+            TextureSize::Surface => (self.width, self.height),
+            TextureSize::SurfaceFraction(divisor) => (self.width / divisor, self.height / divisor),
+            TextureSize::Fixed(width, height) => (width, height),
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(desc.label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} Bind Group", desc.label)),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        TransientTexture { desc, view, bind_group }
+    }
+    /// Recreates every surface-sized texture. Their previous contents are lost.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+        for i in 0..self.textures.len() {
+            let desc = self.textures[i].desc;
+            if !matches!(desc.size, TextureSize::Fixed(..)) {
+                self.textures[i] = self.create(device, desc);
+            }
+        }
+    }
+    /// Layout of the bind groups returned by `bind_group`, for building pipeline layouts.
+    pub fn sampled_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+    pub fn view(&self, id: TextureId) -> &wgpu::TextureView {
+        &self.textures[id.0].view
+    }
+    pub fn bind_group(&self, id: TextureId) -> &wgpu::BindGroup {
+        &self.textures[id.0].bind_group
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    Texture(TextureId),
+    /// Anything not managed by TransientTextures: the depth buffer, the swapchain image, buffers
+    /// written by compute passes.
+    External(&'static str),
+}
+
+struct PassNode<'a> {
+    label: &'static str,
+    reads: Vec<Resource>,
+    writes: Vec<Resource>,
+    record: Box<dyn FnOnce(&mut wgpu::CommandEncoder) + 'a>,
+}
+
+pub struct RenderGraph<'a> {
+    passes: Vec<PassNode<'a>>,
+    // Resources that already hold valid data when the frame starts.
+    imported: Vec<Resource>,
+}
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        RenderGraph { passes: Vec::new(), imported: Vec::new() }
+    }
+    /// Marks `resource` as valid before any pass runs, e.g. a texture kept from the previous
+    /// frame.
+    pub fn import(&mut self, resource: Resource) {
+        self.imported.push(resource);
+    }
+    /// Passes run in the order they are added. A pass that reads and writes the same resource
+    /// (e.g. blending onto the HDR target) should list it in both.
+    pub fn add_pass(
+        &mut self,
+        label: &'static str,
+        reads: &[Resource],
+        writes: &[Resource],
+        record: impl FnOnce(&mut wgpu::CommandEncoder) + 'a,
+    ) {
+        self.passes.push(PassNode {
+            label,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record: Box::new(record),
+        });
+    }
+    /// Records every pass that contributes to `output`, in order. Passes whose writes are never
+    /// read on the way to `output` are skipped.
+    pub fn execute(self, encoder: &mut wgpu::CommandEncoder, output: Resource) {
+        let mut needed = vec![output];
+        let mut live = vec![false; self.passes.len()];
+        for (i, pass) in self.passes.iter().enumerate().rev() {
+            if pass.writes.iter().any(|resource| needed.contains(resource)) {
+                live[i] = true;
+                for resource in &pass.reads {
+                    if !needed.contains(resource) {
+                        needed.push(*resource);
+                    }
+                }
+            }
+        }
+        let mut written = self.imported;
+        for (pass, live) in self.passes.into_iter().zip(live) {
+            if !live {
+                continue;
+            }
+            for resource in pass.reads.iter().filter(|resource| !written.contains(resource)) {
+                log_println!(
+                    "render graph: {} reads {:?} before anything writes it",
+                    pass.label,
+                    resource
+                );
+            }
+            written.extend(pass.writes.iter().copied());
+            encoder.push_debug_group(pass.label);
+            (pass.record)(encoder);
+            encoder.pop_debug_group();
+        }
+    }
+}
+impl<'a> Default for RenderGraph<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
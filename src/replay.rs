@@ -0,0 +1,288 @@
+/* Replay: a level's solution as a small binary file, for players to share and check in-engine. */
+use crate::{
+    game_state::{GameState, InputState},
+    ghost::BestRun,
+    headless::{self, InputScript, ScriptStep, HEADLESS_ASPECT_RATIO},
+    level::{Level, LevelStats},
+    movement::MovementMode,
+    tick_rate,
+};
+
+use anyhow::{bail, Context};
+use cgmath::Vector3;
+use std::{sync::Arc, time::Instant};
+
+pub const REPLAY_DIR: &str = "replays";
+pub const REPLAY_EXTENSION: &str = "replay";
+const MAGIC: [u8; 4] = *b"CUBR";
+// Bumped whenever the layout changes. Files of older versions are still read.
+pub const FORMAT_VERSION: u16 = 2;
+
+// Set in a step's buttons when the mouse or wheel moved on it, and their deltas follow.
+const MOVED_BIT: u16 = 1 << 15;
+
+/// A finished attempt at a level: its input, and what the game looked like after it.
+///
+/// Every replay starts from the same place: a fresh game on the level, with the camera turned the
+/// way it was when the attempt started. The simulation is deterministic, so running the input from
+/// there ends in exactly the state it did when recorded, which `checksum` confirms.
+///
+/// The file is little-endian: `CUBR`, the format version as a u16, the level id as a u64, the tick
+/// rate as a u32, the camera direction as three f32s, the stats as three u32s, the checksum as a
+/// u64, then the input. That is a step count, then for each step its tick count and a u16 of
+/// buttons. Bit 15 of the buttons says the step's mouse and wheel deltas follow. Counts and deltas
+/// are LEB128 varints, the deltas zigzag encoded.
+#[derive(Clone)]
+pub struct Replay {
+    // The `Level::id` of the level solved.
+    pub level_id: u64,
+    // Game ticks per second when recorded. Input is per tick, so a replay only plays back at the
+    // rate it was recorded at.
+    pub tick_rate: u32,
+    // Where the camera looked when the attempt started, which the movement keys go by.
+    pub camera_direction: [f32; 3],
+    pub stats: LevelStats,
+    // `GameState::simulation_checksum` after the last tick.
+    pub checksum: u64,
+    pub inputs: InputScript,
+}
+impl Replay {
+    /// Turns a finished attempt into a replay. The attempt is run again from where replays start,
+    /// to make sure it still solves the level from there. Replays start in physics movement, so
+    /// attempts in step mode can't be recorded.
+    pub fn record(run: &BestRun) -> anyhow::Result<Self> {
+        if run.start.get_movement_mode() != MovementMode::Physics {
+            bail!("replays only play back physics movement");
+        }
+        let level = run.start.get_level();
+        let mut replay = Replay {
+            level_id: level.id,
+            tick_rate: tick_rate::ticks_per_second(),
+            camera_direction: run.start.get_camera().direction.into(),
+            stats: LevelStats::default(),
+            checksum: 0,
+            inputs: run.inputs.clone(),
+        };
+        let end = replay.play(level);
+        replay.stats =
+            *end.get_completed_levels().first().context("the run doesn't solve the level")?;
+        replay.checksum = end.simulation_checksum();
+        Ok(replay)
+    }
+    /// The game a replay of `level` starts from.
+    pub fn start_state(&self, level: &Level) -> GameState {
+        let mut game_state = GameState::new(HEADLESS_ASPECT_RATIO);
+        game_state.play_pack(vec![level.clone()], 0);
+        game_state.set_camera_direction(Vector3::from(self.camera_direction));
+        game_state
+    }
+    /// The replay as a ghost to play alongside the game.
+    pub fn best_run(&self, level: &Level) -> BestRun {
+        BestRun {
+            start: Arc::new(self.start_state(level)),
+            inputs: self.inputs.clone(),
+            ticks: self.stats.ticks,
+        }
+    }
+    /// Runs the replay on `level`, checking that it solves it with the stats it claims and ends
+    /// where it did when recorded.
+    pub fn verify(&self, level: &Level) -> anyhow::Result<()> {
+        if level.id != self.level_id {
+            bail!("replay is of level {:016x}, not {:016x}", self.level_id, level.id);
+        }
+        let tick_rate = tick_rate::ticks_per_second();
+        if self.tick_rate != tick_rate {
+            bail!("replay runs at {} ticks per second, the game at {}", self.tick_rate, tick_rate);
+        }
+        let end = self.play(level);
+        match end.get_completed_levels().first() {
+            Some(stats) if *stats == self.stats => {}
+            Some(stats) => bail!("replay solves the level with {:?}, not {:?}", stats, self.stats),
+            None => bail!("replay doesn't solve the level"),
+        }
+        if end.simulation_checksum() != self.checksum {
+            bail!("replay ends somewhere other than where it was recorded");
+        }
+        Ok(())
+    }
+    fn play(&self, level: &Level) -> GameState {
+        let mut game_state = self.start_state(level);
+        headless::run(&mut game_state, &self.inputs, Instant::now());
+        game_state
+    }
+    /// Named after its level and how long it took, like `0123456789abcdef-1234.replay`.
+    pub fn file_name(&self) -> String {
+        format!("{:016x}-{}.{}", self.level_id, self.stats.ticks, REPLAY_EXTENSION)
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer { bytes: MAGIC.to_vec() };
+        writer.bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        writer.bytes.extend_from_slice(&self.level_id.to_le_bytes());
+        writer.bytes.extend_from_slice(&self.tick_rate.to_le_bytes());
+        for value in self.camera_direction {
+            writer.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [self.stats.moves, self.stats.pushes, self.stats.ticks] {
+            writer.bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        writer.bytes.extend_from_slice(&self.checksum.to_le_bytes());
+        writer.varint(self.inputs.steps.len() as u64);
+        for step in &self.inputs.steps {
+            writer.varint(step.ticks as u64);
+            writer.input(&step.input);
+        }
+        writer.bytes
+    }
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take::<4>()? != MAGIC {
+            bail!("not a replay");
+        }
+        let version = u16::from_le_bytes(reader.take()?);
+        if version == 0 || version > FORMAT_VERSION {
+            bail!("replay format version {} isn't one this game reads", version);
+        }
+        let level_id = u64::from_le_bytes(reader.take()?);
+        let tick_rate = u32::from_le_bytes(reader.take()?);
+        let mut camera_direction = [0.0; 3];
+        for value in &mut camera_direction {
+            *value = f32::from_le_bytes(reader.take()?);
+        }
+        let stats = LevelStats {
+            moves: u32::from_le_bytes(reader.take()?),
+            pushes: u32::from_le_bytes(reader.take()?),
+            ticks: u32::from_le_bytes(reader.take()?),
+        };
+        let checksum = u64::from_le_bytes(reader.take()?);
+        let step_count = reader.varint()?;
+        let mut inputs = InputScript::new();
+        for _ in 0..step_count {
+            let ticks = u32::try_from(reader.varint()?).context("step too long")?;
+            inputs.steps.push(ScriptStep { ticks, input: reader.input()? });
+        }
+        if !reader.bytes.is_empty() {
+            bail!("{} bytes left over after the replay", reader.bytes.len());
+        }
+        Ok(Replay { level_id, tick_rate, camera_direction, stats, checksum, inputs })
+    }
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("reading replay {}", path))?;
+        Self::from_bytes(&bytes).with_context(|| format!("parsing replay {}", path))
+    }
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_bytes()).with_context(|| format!("writing replay to {}", path))
+    }
+    /// Saves the replay in `REPLAY_DIR` under its `file_name`, returning the path.
+    pub fn export(&self) -> anyhow::Result<String> {
+        std::fs::create_dir_all(REPLAY_DIR).with_context(|| format!("creating {}", REPLAY_DIR))?;
+        let path = format!("{}/{}", REPLAY_DIR, self.file_name());
+        self.save(&path)?;
+        Ok(path)
+    }
+}
+
+/// The replay files in `REPLAY_DIR`, sorted. A missing directory means there are none.
+pub fn list_files() -> anyhow::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(REPLAY_DIR) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("listing {}", REPLAY_DIR)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == REPLAY_EXTENSION) {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+impl Writer {
+    fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+    fn signed(&mut self, value: i32) {
+        self.varint(((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+    // The buttons that play a part in the game as bits, in the order of the fields.
+    fn input(&mut self, input: &InputState) {
+        let buttons = [
+            input.forward,
+            input.backward,
+            input.left,
+            input.right,
+            input.jump,
+            input.undo,
+            input.restart,
+            input.interact,
+        ];
+        let moved = input.mouse_x != 0 || input.mouse_y != 0 || input.wheel != 0;
+        let bits = buttons.iter().enumerate().fold(0, |bits, (i, &down)| bits | (down as u16) << i);
+        let bits = if moved { bits | MOVED_BIT } else { bits };
+        self.bytes.extend_from_slice(&bits.to_le_bytes());
+        if moved {
+            self.signed(input.mouse_x);
+            self.signed(input.mouse_y);
+            self.signed(input.wheel);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        if self.bytes.len() < N {
+            bail!("replay ends early");
+        }
+        let (taken, rest) = self.bytes.split_at(N);
+        self.bytes = rest;
+        Ok(taken.try_into().unwrap())
+    }
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let [byte] = self.take()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("varint too long");
+    }
+    fn signed(&mut self) -> anyhow::Result<i32> {
+        let value = u32::try_from(self.varint()?).context("delta too big")?;
+        Ok((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+    fn input(&mut self) -> anyhow::Result<InputState> {
+        let bits = u16::from_le_bytes(self.take()?);
+        let down = |i: u32| bits & (1 << i) != 0;
+        let mut input = InputState {
+            forward: down(0),
+            backward: down(1),
+            left: down(2),
+            right: down(3),
+            jump: down(4),
+            undo: down(5),
+            restart: down(6),
+            interact: down(7),
+            ..InputState::new()
+        };
+        if bits & MOVED_BIT != 0 {
+            input.mouse_x = self.signed()?;
+            input.mouse_y = self.signed()?;
+            input.wheel = self.signed()?;
+        }
+        Ok(input)
+    }
+}
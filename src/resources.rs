@@ -1,19 +1,226 @@
 use crate::texture;
 
+use anyhow::Context;
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+    time::SystemTime,
+};
+
+/// Directories and .zip archives in here are mounted at startup, so level packs and texture mods
+/// can be dropped in without rebuilding.
+pub const MODS_DIR: &str = "mods";
+
+// A directory or archive mounted over the built-in assets.
+enum Mount {
+    Directory(PathBuf),
+    // ZipArchive needs to be mutable to read from.
+    Archive(Mutex<zip::ZipArchive<File>>),
+}
+impl Mount {
+    // The file's contents, or None if this mount doesn't have it.
+    fn read(&self, file_name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match self {
+            Mount::Directory(dir) => match std::fs::read(dir.join(file_name)) {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            },
+            Mount::Archive(archive) => {
+                let mut archive = archive.lock().unwrap();
+                let mut file = match archive.by_name(file_name) {
+                    Ok(file) => file,
+                    Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+        }
+    }
+    // Whether this mount has the file at all, and if it is on disk, when it was last modified.
+    fn modified(&self, file_name: &str) -> Option<Option<SystemTime>> {
+        match self {
+            Mount::Directory(dir) => {
+                let metadata = std::fs::metadata(dir.join(file_name)).ok()?;
+                Some(metadata.modified().ok())
+            }
+            // Archives are read once when mounted, so their files never change.
+            Mount::Archive(archive) => {
+                archive.lock().unwrap().file_names().any(|name| name == file_name).then_some(None)
+            }
+        }
+    }
+    // Names of the files directly in `dir`, in the form the load functions take.
+    fn list(&self, dir: &str) -> Vec<String> {
+        match self {
+            Mount::Directory(root) => std::fs::read_dir(root.join(dir))
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| Some(format!("{}/{}", dir, entry.file_name().to_str()?)))
+                .collect(),
+            Mount::Archive(archive) => {
+                let prefix = format!("{}/", dir);
+                let archive = archive.lock().unwrap();
+                archive
+                    .file_names()
+                    .filter(|name| {
+                        name.strip_prefix(&prefix).is_some_and(|rest| !rest.contains('/'))
+                    })
+                    .map(str::to_string)
+                    .collect()
+            }
+        }
+    }
+}
+
+// In the order they were mounted. Later mounts override earlier ones.
+static MOUNTS: RwLock<Vec<Mount>> = RwLock::new(Vec::new());
+
+/// Mounts a directory or .zip archive over the assets mounted so far, so its files override
+/// theirs. The built-in assets are always searched last.
+pub fn mount(path: &Path) -> anyhow::Result<()> {
+    let mount = if path.is_dir() {
+        Mount::Directory(path.to_path_buf())
+    } else {
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        let archive = zip::ZipArchive::new(file)
+            .with_context(|| format!("reading archive {}", path.display()))?;
+        Mount::Archive(Mutex::new(archive))
+    };
+    MOUNTS.write().unwrap().push(mount);
+
+    Ok(())
+}
+
+/// Mounts every directory and .zip archive in `dir`, in name order, so later names win. Returns
+/// how many were mounted; a missing `dir` mounts nothing.
+pub fn mount_all_in(dir: &Path) -> anyhow::Result<usize> {
+    let mut paths = match std::fs::read_dir(dir) {
+        Ok(entries) => {
+            entries.map(|entry| Ok(entry?.path())).collect::<anyhow::Result<Vec<_>>>()?
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    paths.retain(|path| path.is_dir() || path.extension().is_some_and(|e| e == "zip"));
+    paths.sort();
+    for path in &paths {
+        mount(path)?;
+    }
+
+    Ok(paths.len())
+}
+
+#[cfg(feature = "embed-assets")]
+mod embedded {
+    include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+}
+
+fn asset_path(file_name: &str) -> PathBuf {
+    Path::new(env!("OUT_DIR")).join("assets").join(file_name)
+}
+
+// The copy of an asset compiled in by the embed-assets feature.
+#[cfg(feature = "embed-assets")]
+fn embedded_asset(file_name: &str) -> Option<&'static [u8]> {
+    embedded::EMBEDDED_ASSETS.iter().find(|(name, _)| *name == file_name).map(|(_, data)| *data)
+}
+#[cfg(not(feature = "embed-assets"))]
+fn embedded_asset(_file_name: &str) -> Option<&'static [u8]> {
+    None
+}
+
+// Names of the embedded assets, in the form the load functions take.
+#[cfg(feature = "embed-assets")]
+fn embedded_names() -> impl Iterator<Item = &'static str> {
+    embedded::EMBEDDED_ASSETS.iter().map(|(name, _)| *name)
+}
+#[cfg(not(feature = "embed-assets"))]
+fn embedded_names() -> impl Iterator<Item = &'static str> {
+    std::iter::empty()
+}
+
+/// Assets are read from the mounts, most recent first, then the assets directory. With the
+/// embed-assets feature, an asset found nowhere on disk falls back to the copy compiled into the
+/// executable.
 pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
-    let path = std::path::Path::new(env!("OUT_DIR")).join("assets").join(file_name);
-    let data = std::fs::read(path)?;
+    for mount in MOUNTS.read().unwrap().iter().rev() {
+        if let Some(data) = mount.read(file_name)? {
+            return Ok(data);
+        }
+    }
+    let data = match std::fs::read(asset_path(file_name)) {
+        Ok(data) => data,
+        Err(e) => embedded_asset(file_name).ok_or(e)?.to_vec(),
+    };
 
     Ok(data)
 }
 
+/// Whether `load_binary` would find the asset anywhere.
+pub fn exists(file_name: &str) -> bool {
+    MOUNTS.read().unwrap().iter().any(|mount| mount.modified(file_name).is_some())
+        || asset_path(file_name).exists()
+        || embedded_asset(file_name).is_some()
+}
+
+/// When the copy of an asset that `load_binary` would read was last modified, for noticing
+/// changes. None for assets in archives, embedded assets, and missing files.
+pub fn modified_time(file_name: &str) -> Option<SystemTime> {
+    for mount in MOUNTS.read().unwrap().iter().rev() {
+        if let Some(modified) = mount.modified(file_name) {
+            return modified;
+        }
+    }
+    std::fs::metadata(asset_path(file_name)).and_then(|metadata| metadata.modified()).ok()
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    let path = std::path::Path::new(env!("OUT_DIR")).join("assets").join(file_name);
-    let txt = std::fs::read_to_string(path)?;
+    let txt = String::from_utf8(load_binary(file_name).await?)?;
 
     Ok(txt)
 }
 
+/// Names of the files in assets directory `dir` ending in `.extension`, sorted, in the form the
+/// load functions take. Mounted and embedded assets are included.
+pub fn list_files(dir: &str, extension: &str) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    match std::fs::read_dir(asset_path(dir)) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|e| e == extension) {
+                    if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                        names.push(format!("{}/{}", dir, name));
+                    }
+                }
+            }
+        }
+        // Without the directory on disk, there may still be mounted or embedded files to list.
+        Err(e) if embedded_names().next().is_none() && MOUNTS.read().unwrap().is_empty() => {
+            return Err(e.into())
+        }
+        Err(_) => {}
+    }
+    let prefix = format!("{}/", dir);
+    let in_dir = |name: &str| name.strip_prefix(&prefix).is_some_and(|rest| !rest.contains('/'));
+    names.extend(embedded_names().filter(|name| in_dir(name)).map(str::to_string));
+    for mount in MOUNTS.read().unwrap().iter() {
+        names.extend(mount.list(dir));
+    }
+    let suffix = format!(".{}", extension);
+    names.retain(|name| name.ends_with(&suffix));
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
@@ -0,0 +1,56 @@
+/* Rng: seeded random numbers, so anything random in the game plays out the same on every replay. */
+use crate::determinism::StateHasher;
+
+/// PCG32 (the XSH RR variant): a small, fast generator whose whole state is two numbers, so
+/// cloning a `GameState` clones where its random numbers are up to.
+///
+/// Everything random in the simulation draws from an `Rng` owned by the game, seeded from the
+/// level, never from the clock or the OS. Each user gets its own stream, so that, say, a new
+/// particle effect doesn't change what a level script rolls.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+    state: u64,
+    // Picks the stream. Always odd.
+    increment: u64,
+}
+impl Rng {
+    const MULTIPLIER: u64 = 6364136223846793005;
+
+    /// Numbers from different streams are unrelated, even from the same seed.
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Rng { state: 0, increment: (stream << 1) | 1 };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(Self::MULTIPLIER).wrapping_add(self.increment);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+    /// In [0, 1).
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+    /// In [min, max).
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+    /// In [0, n), each equally likely. `n` must not be 0.
+    pub fn below(&mut self, n: u32) -> u32 {
+        // Numbers under the threshold would make the low results a little more likely.
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let value = self.next_u32();
+            if value >= threshold {
+                return value % n;
+            }
+        }
+    }
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        hasher.write_u64(self.state);
+        hasher.write_u64(self.increment);
+    }
+}
@@ -0,0 +1,200 @@
+/* Rollback: runs another player's game from their input as it arrives over the network, guessing
+ * ahead when it is late and re-simulating once it comes in. */
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Instant,
+};
+
+use crate::{
+    game_state::{GameState, InputState},
+    log_println, tick_rate,
+};
+
+// The most ticks simulated on guessed input. Past this the game waits for the real input, so a
+// rewind never re-simulates more than this many ticks.
+pub const MAX_ROLLBACK_TICKS: usize = 30;
+// The most ticks of late input caught up on in one call to `advance`.
+const CATCH_UP_TICKS: usize = 8;
+
+// A simulated tick: the game before it, and the input it was simulated with.
+#[derive(Clone)]
+struct Tick {
+    before: GameState,
+    input: InputState,
+    // Whether `input` is the other player's, or a guess.
+    confirmed: bool,
+}
+
+/// Another player's game, simulated here from their input. The simulation is deterministic, so
+/// given the same start and the same input it plays out exactly as it did for them.
+///
+/// Their input for a tick can arrive late. Rather than wait, the tick is simulated on a guess:
+/// the keys they last held stay held. When the real input turns up and differs from the guess,
+/// the game is rewound to the snapshot taken before that tick and re-simulated from there.
+/// Snapshots are kept for the last `MAX_ROLLBACK_TICKS` ticks.
+///
+/// The other game also reports a checksum of its state after every tick. Once a tick can no
+/// longer be rewound, its checksum here is compared with theirs, to catch the two simulations
+/// drifting apart.
+pub struct Rollback {
+    state: GameState,
+    // Ticks simulated, counting from 1, guessed ones included.
+    tick: u32,
+    // When tick 0 happened, for the step times of the rest. Only the differences matter, and they
+    // are the same for every tick, so the simulation doesn't depend on when it runs.
+    start: Instant,
+    // The ticks that can still be rewound, oldest first, the last being `tick`. Guessed ticks
+    // always come after confirmed ones, since input arrives in order.
+    history: VecDeque<Tick>,
+    // The input of the last tick dropped from `history`, to guess from once all of it is.
+    last_input: InputState,
+    // Input that arrived for ticks not simulated yet, by tick.
+    pending: BTreeMap<u32, InputState>,
+    // The other game's checksums, by tick, until they are checked.
+    checksums: BTreeMap<u32, u64>,
+    // The earliest tick simulated on a guess that turned out wrong.
+    rewind_to: Option<u32>,
+    desynced: bool,
+}
+impl Rollback {
+    /// `start` must be the state the other game started from, before its tick 1.
+    pub fn new(start: GameState, now: Instant) -> Self {
+        Rollback {
+            state: start,
+            tick: 0,
+            start: now,
+            history: VecDeque::new(),
+            last_input: InputState::new(),
+            pending: BTreeMap::new(),
+            checksums: BTreeMap::new(),
+            rewind_to: None,
+            desynced: false,
+        }
+    }
+    /// The game as simulated so far, guesses included.
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
+    /// The game after the last tick simulated on real input.
+    pub fn confirmed_state(&self) -> &GameState {
+        self.history.iter().find(|tick| !tick.confirmed).map_or(&self.state, |tick| &tick.before)
+    }
+    /// How many ticks have been simulated on real input.
+    pub fn confirmed_tick(&self) -> u32 {
+        self.first_tick() - 1 + self.history.iter().filter(|tick| tick.confirmed).count() as u32
+    }
+    /// Whether a checksum has disagreed with the other game's.
+    pub fn desynced(&self) -> bool {
+        self.desynced
+    }
+    /// Takes the other game's input for `tick`, and its checksum after it.
+    pub fn receive(&mut self, tick: u32, input: InputState, checksum: u64) {
+        if tick < self.first_tick() {
+            return;
+        }
+        self.checksums.insert(tick, checksum);
+        if tick > self.tick {
+            self.pending.insert(tick, input);
+            return;
+        }
+        let index = (tick - self.first_tick()) as usize;
+        let entry = &mut self.history[index];
+        if entry.confirmed {
+            return;
+        }
+        entry.confirmed = true;
+        if entry.input != input {
+            entry.input = input;
+            self.rewind_to = Some(self.rewind_to.map_or(tick, |earliest| earliest.min(tick)));
+        }
+    }
+    /// Re-simulates from the earliest wrong guess, if there was one, then moves the game on:
+    /// through any input that has arrived for the ticks after it, or else one tick on a guess.
+    /// Waits instead once `MAX_ROLLBACK_TICKS` ticks are guesses.
+    pub fn advance(&mut self) {
+        if let Some(tick) = self.rewind_to.take() {
+            self.resimulate(tick);
+        }
+        let mut caught_up = 0;
+        while caught_up < CATCH_UP_TICKS {
+            let Some(input) = self.pending.remove(&(self.tick + 1)) else {
+                break;
+            };
+            self.simulate(input, true);
+            caught_up += 1;
+        }
+        let guessed = self.history.iter().filter(|tick| !tick.confirmed).count();
+        if caught_up == 0 && guessed < MAX_ROLLBACK_TICKS {
+            let input = self.guess(self.history.len());
+            self.simulate(input, false);
+        }
+        self.drop_old_ticks();
+    }
+    // The tick at the front of `history`.
+    fn first_tick(&self) -> u32 {
+        self.tick + 1 - self.history.len() as u32
+    }
+    fn step_time(&self, tick: u32) -> Instant {
+        self.start + tick_rate::time_per_tick() * tick
+    }
+    fn simulate(&mut self, input: InputState, confirmed: bool) {
+        self.history.push_back(Tick {
+            before: self.state.clone(),
+            input: input.clone(),
+            confirmed,
+        });
+        self.tick += 1;
+        self.state.update(&input, self.step_time(self.tick));
+    }
+    // Rewinds to before `from` and simulates up to the current tick again, guessing anew for the
+    // ticks still without real input.
+    fn resimulate(&mut self, from: u32) {
+        let first = (from - self.first_tick()) as usize;
+        self.state = self.history[first].before.clone();
+        for i in first..self.history.len() {
+            if !self.history[i].confirmed {
+                self.history[i].input = self.guess(i);
+            }
+            self.history[i].before = self.state.clone();
+            let tick = self.first_tick() + i as u32;
+            let input = self.history[i].input.clone();
+            self.state.update(&input, self.step_time(tick));
+        }
+    }
+    // The guess for the tick at `index` in `history`: the keys held on the last confirmed tick
+    // before it stay held. One-off input, like turning or undoing, isn't repeated.
+    fn guess(&self, index: usize) -> InputState {
+        let held = self
+            .history
+            .range(..index)
+            .rev()
+            .find(|tick| tick.confirmed)
+            .map_or(&self.last_input, |tick| &tick.input);
+        InputState {
+            forward: held.forward,
+            backward: held.backward,
+            left: held.left,
+            right: held.right,
+            jump: held.jump,
+            ..InputState::new()
+        }
+    }
+    // Drops the oldest confirmed ticks past `MAX_ROLLBACK_TICKS`, checking the state after each
+    // against the other game's checksum.
+    fn drop_old_ticks(&mut self) {
+        while self.history.len() > MAX_ROLLBACK_TICKS && self.history[0].confirmed {
+            let tick = self.first_tick();
+            let dropped = self.history.pop_front().unwrap();
+            self.last_input = dropped.input;
+            let after = self.history.front().map_or(&self.state, |next| &next.before);
+            if let Some(checksum) = self.checksums.remove(&tick) {
+                if checksum != after.simulation_checksum() && !self.desynced {
+                    log_println!("Rollback: the other game's state differs after tick {}", tick);
+                    self.desynced = true;
+                }
+            }
+        }
+        let first = self.first_tick();
+        self.checksums.retain(|&tick, _| tick >= first);
+    }
+}
@@ -1,35 +1,156 @@
-use cgmath::{num_traits::abs, InnerSpace, Quaternion, Rad, Rotation3, Vector3};
+/* Rotor: rotations as geometric-algebra rotors, a scalar plus the bivector of a plane. */
+use cgmath::{BaseFloat, InnerSpace, Matrix3, Quaternion, Rad, Vector3};
+use std::ops::Mul;
 
+/// Generic over the float type, like cgmath's types, so the simulation can try out f64 while
+/// rendering stays f32. A bare `Rotor` is an f32 one.
 #[repr(C)]
-#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct Rotor {
-    pub s: f32,
-    pub xy: f32,
-    pub xz: f32,
-    pub yz: f32,
+#[derive(Clone, Copy, Debug)]
+pub struct Rotor<S = f32> {
+    pub s: S,
+    pub xy: S,
+    pub xz: S,
+    pub yz: S,
 }
-impl Rotor {
+// Only the f32 rotor matches what the shaders read.
+unsafe impl bytemuck::Zeroable for Rotor<f32> {}
+unsafe impl bytemuck::Pod for Rotor<f32> {}
+
+/// `value` as an `S`. For the constants in generic float code, which can't be `S` literals.
+pub fn scalar<S: BaseFloat>(value: f64) -> S {
+    S::from(value).unwrap()
+}
+
+impl<S: BaseFloat> Rotor<S> {
     #[allow(unused)]
-    pub fn new(s: f32, xy: f32, xz: f32, yz: f32) -> Self {
+    pub fn new(s: S, xy: S, xz: S, yz: S) -> Self {
         Rotor { s, xy, xz, yz }
     }
     /// Assumes that the input vector has already been normalized. Will not work correctly otherwise.
-    pub fn from_axis_angle<A: Into<Rad<f32>>>(v: Vector3<f32>, angle: A) -> Rotor {
-        debug_assert!(abs(v.magnitude() - 1.0) < 0.000001);
-        // lol
-        Rotor::from_quat(cgmath::Quaternion::from_axis_angle(v, angle))
+    ///
+    /// Turning by `angle` about the axis `v` turns in the plane `v` is normal to: the bivector
+    /// `v.x yz + v.y zx + v.z xy`. The rotor is `cos(angle / 2) - sin(angle / 2)` times that plane,
+    /// and `zx` is `-xz`.
+    pub fn from_axis_angle<A: Into<Rad<S>>>(v: Vector3<S>, angle: A) -> Self {
+        debug_assert!((v.magnitude() - S::one()).abs() < scalar(0.000001));
+        let Rad(angle) = angle.into();
+        let (sin, cos) = (angle * scalar(0.5)).sin_cos();
+        Rotor { s: cos, xy: -(v.z * sin), xz: v.y * sin, yz: -(v.x * sin) }
+    }
+    /// The rotor that turns `from` onto `to` the shortest way. Both must be normalized.
+    ///
+    /// That is the geometric product `to from`, `to · from + to ∧ from`, with its angle halved by
+    /// adding 1 and normalizing. Opposite vectors have no shortest way, so they are turned half a
+    /// circle about some axis at right angles to both.
+    pub fn between(from: Vector3<S>, to: Vector3<S>) -> Self {
+        debug_assert!(
+            (from.magnitude() - S::one()).abs() < scalar(0.0001)
+                && (to.magnitude() - S::one()).abs() < scalar(0.0001)
+        );
+        let s = S::one() + from.dot(to);
+        let (xy, xz, yz) = (
+            to.x * from.y - to.y * from.x,
+            to.x * from.z - to.z * from.x,
+            to.y * from.z - to.z * from.y,
+        );
+        let norm = (s * s + xy * xy + xz * xz + yz * yz).sqrt();
+        if norm < scalar(0.000001) {
+            // Cross with whichever axis `from` is furthest from, so the result isn't tiny.
+            let other =
+                if from.x.abs() < scalar(0.9) { Vector3::unit_x() } else { Vector3::unit_y() };
+            let axis = from.cross(other).normalize();
+            return Rotor { s: S::zero(), xy: -axis.z, xz: axis.y, yz: -axis.x };
+        }
+        Rotor { s: s / norm, xy: xy / norm, xz: xz / norm, yz: yz / norm }
     }
-    pub fn from_quat(q: Quaternion<f32>) -> Rotor {
+    pub fn from_quat(q: Quaternion<S>) -> Self {
         Rotor { s: q.s, xy: -q.v.z, xz: q.v.y, yz: -q.v.x }
     }
-    pub fn identity() -> Rotor {
-        Rotor { s: 1.0, xy: 0.0, xz: 0.0, yz: 0.0 }
+    /// The quaternion turning the same way, for checking rotors against cgmath.
+    pub fn to_quat(&self) -> Quaternion<S> {
+        Quaternion::new(self.s, -self.yz, self.xz, -self.xy)
+    }
+    /// The rotation matrix: its columns are where the rotor takes the x, y and z axes.
+    pub fn to_matrix(&self) -> Matrix3<S> {
+        Matrix3::from_cols(
+            self.rotate_vector(Vector3::unit_x()),
+            self.rotate_vector(Vector3::unit_y()),
+            self.rotate_vector(Vector3::unit_z()),
+        )
+    }
+    /// The rotor of a rotation matrix, with a non-negative scalar part. `m` must be orthonormal
+    /// with a determinant of 1.
+    ///
+    /// Each component is read off whichever of the matrix's diagonal combinations is largest, so
+    /// nothing is divided by a number near zero.
+    pub fn from_matrix(m: Matrix3<S>) -> Self {
+        let (one, two, quarter) = (S::one(), scalar::<S>(2.0), scalar::<S>(0.25));
+        // Row r, column c.
+        let at = |r: usize, c: usize| m[c][r];
+        let trace = at(0, 0) + at(1, 1) + at(2, 2);
+        let rotor = if trace > S::zero() {
+            let k = two * (one + trace).sqrt();
+            Rotor {
+                s: quarter * k,
+                xy: (at(0, 1) - at(1, 0)) / k,
+                xz: (at(0, 2) - at(2, 0)) / k,
+                yz: (at(1, 2) - at(2, 1)) / k,
+            }
+        } else if at(0, 0) > at(1, 1) && at(0, 0) > at(2, 2) {
+            let k = two * (one + at(0, 0) - at(1, 1) - at(2, 2)).sqrt();
+            Rotor {
+                s: (at(2, 1) - at(1, 2)) / k,
+                xy: -(at(0, 2) + at(2, 0)) / k,
+                xz: (at(0, 1) + at(1, 0)) / k,
+                yz: -quarter * k,
+            }
+        } else if at(1, 1) > at(2, 2) {
+            let k = two * (one + at(1, 1) - at(0, 0) - at(2, 2)).sqrt();
+            Rotor {
+                s: (at(0, 2) - at(2, 0)) / k,
+                xy: -(at(1, 2) + at(2, 1)) / k,
+                xz: quarter * k,
+                yz: -(at(0, 1) + at(1, 0)) / k,
+            }
+        } else {
+            let k = two * (one + at(2, 2) - at(0, 0) - at(1, 1)).sqrt();
+            Rotor {
+                s: (at(1, 0) - at(0, 1)) / k,
+                xy: -quarter * k,
+                xz: (at(1, 2) + at(2, 1)) / k,
+                yz: -(at(0, 2) + at(2, 0)) / k,
+            }
+        };
+        // A rotor and its negation are the same rotation.
+        if rotor.s < S::zero() {
+            Rotor { s: -rotor.s, xy: -rotor.xy, xz: -rotor.xz, yz: -rotor.yz }
+        } else {
+            rotor
+        }
+    }
+    pub fn identity() -> Self {
+        Rotor { s: S::one(), xy: S::zero(), xz: S::zero(), yz: S::zero() }
     }
     #[allow(unused)]
-    pub fn inverse(&self) -> Rotor {
+    pub fn inverse(&self) -> Self {
         Rotor { s: self.s, xy: -self.xy, xz: -self.xz, yz: -self.yz }
     }
-    pub fn rotate_vector(&self, vec: Vector3<f32>) -> Vector3<f32> {
+    /// Normalized linear interpolation, along the shorter arc. Close to a slerp for the small
+    /// steps between animation keyframes, and much cheaper.
+    pub fn nlerp(&self, other: &Self, t: S) -> Self {
+        let dot = self.s * other.s + self.xy * other.xy + self.xz * other.xz + self.yz * other.yz;
+        let sign = if dot < S::zero() { -S::one() } else { S::one() };
+        let lerp = |a: S, b: S| a + (sign * b - a) * t;
+        let (s, xy, xz, yz) = (
+            lerp(self.s, other.s),
+            lerp(self.xy, other.xy),
+            lerp(self.xz, other.xz),
+            lerp(self.yz, other.yz),
+        );
+        let norm = (s * s + xy * xy + xz * xz + yz * yz).sqrt();
+        Rotor { s: s / norm, xy: xy / norm, xz: xz / norm, yz: yz / norm }
+    }
+    pub fn rotate_vector(&self, vec: Vector3<S>) -> Vector3<S> {
         // Calculate S = Rv
         let s_x = self.s * vec.x + self.xy * vec.y + self.xz * vec.z;
         let s_y = -self.xy * vec.x + self.s * vec.y + self.yz * vec.z;
@@ -44,8 +165,120 @@ impl Rotor {
         }
     }
 }
-impl Into<[f32; 4]> for Rotor {
-    fn into(self) -> [f32; 4] {
+/// `a * b` turns by `b`, then by `a`.
+impl<S: BaseFloat> Mul for Rotor<S> {
+    type Output = Self;
+    fn mul(self, b: Self) -> Self {
+        let a = self;
+        Rotor {
+            s: a.s * b.s - a.xy * b.xy - a.xz * b.xz - a.yz * b.yz,
+            xy: a.s * b.xy + a.xy * b.s + a.yz * b.xz - a.xz * b.yz,
+            xz: a.s * b.xz + a.xz * b.s + a.xy * b.yz - a.yz * b.xy,
+            yz: a.s * b.yz + a.yz * b.s + a.xz * b.xy - a.xy * b.xz,
+        }
+    }
+}
+impl<S> Into<[S; 4]> for Rotor<S> {
+    fn into(self) -> [S; 4] {
         [self.s, self.xy, self.xz, self.yz]
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use cgmath::{Rotation, Rotation3};
+    use std::f32::consts::PI;
+
+    const EPSILON: f32 = 0.0001;
+
+    fn assert_vectors_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < EPSILON, "{a:?} != {b:?}");
+    }
+    fn random_unit_vector(rng: &mut Rng) -> Vector3<f32> {
+        loop {
+            let v = Vector3::new(rng.range(-1.0, 1.0), rng.range(-1.0, 1.0), rng.range(-1.0, 1.0));
+            if v.magnitude() > 0.1 {
+                return v.normalize();
+            }
+        }
+    }
+    // Random axes and angles, with some of the angles right at or around half a turn, where the
+    // scalar part goes to zero.
+    fn axis_angles() -> Vec<(Vector3<f32>, f32)> {
+        let mut rng = Rng::new(7, 0);
+        let mut angles: Vec<f32> = (0..200).map(|_| rng.range(-2.0 * PI, 2.0 * PI)).collect();
+        angles.extend([PI, -PI, PI - 0.001, PI + 0.001, 0.0]);
+        angles.into_iter().map(|angle| (random_unit_vector(&mut rng), angle)).collect()
+    }
+
+    #[test]
+    fn rotate_vector_matches_quaternion() {
+        let mut rng = Rng::new(11, 0);
+        for (axis, angle) in axis_angles() {
+            let rotor = Rotor::from_axis_angle(axis, Rad(angle));
+            let quat = Quaternion::from_axis_angle(axis, Rad(angle));
+            let v = random_unit_vector(&mut rng) * 3.0;
+            assert_vectors_close(rotor.rotate_vector(v), quat.rotate_vector(v));
+        }
+    }
+
+    #[test]
+    fn to_matrix_matches_quaternion() {
+        for (axis, angle) in axis_angles() {
+            let matrix = Rotor::from_axis_angle(axis, Rad(angle)).to_matrix();
+            let expected = Matrix3::from(Quaternion::from_axis_angle(axis, Rad(angle)));
+            for column in 0..3 {
+                assert_vectors_close(matrix[column], expected[column]);
+            }
+        }
+    }
+
+    #[test]
+    fn from_matrix_round_trips_up_to_sign() {
+        for (axis, angle) in axis_angles() {
+            let rotor = Rotor::from_axis_angle(axis, Rad(angle));
+            let back = Rotor::from_matrix(rotor.to_matrix());
+            let (a, b): ([f32; 4], [f32; 4]) = (rotor.into(), back.into());
+            let sign = if a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3] < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            for (a, b) in a.iter().zip(b) {
+                assert!((a - sign * b).abs() < EPSILON, "{rotor:?} came back as {back:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn between_turns_from_onto_to() {
+        let mut rng = Rng::new(13, 0);
+        for _ in 0..200 {
+            let (from, to) = (random_unit_vector(&mut rng), random_unit_vector(&mut rng));
+            assert_vectors_close(Rotor::between(from, to).rotate_vector(from), to);
+        }
+    }
+
+    #[test]
+    fn between_parallel_vectors_is_identity() {
+        let mut rng = Rng::new(17, 0);
+        for _ in 0..50 {
+            let v = random_unit_vector(&mut rng);
+            let rotor = Rotor::between(v, v);
+            assert_vectors_close(rotor.to_matrix()[0], Vector3::unit_x());
+            assert_vectors_close(rotor.to_matrix()[1], Vector3::unit_y());
+        }
+    }
+
+    #[test]
+    fn between_antiparallel_vectors_turns_half_a_circle() {
+        let mut rng = Rng::new(19, 0);
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z(), -Vector3::unit_x()];
+        for from in axes.into_iter().chain((0..50).map(|_| random_unit_vector(&mut rng))) {
+            let rotor = Rotor::between(from, -from);
+            assert!(rotor.s.abs() < EPSILON, "{rotor:?} isn't half a turn");
+            assert_vectors_close(rotor.rotate_vector(from), -from);
+        }
+    }
+}
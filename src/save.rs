@@ -0,0 +1,86 @@
+/* Save: where the player left off, so the main menu can continue from there. */
+use anyhow::{anyhow, bail, Context};
+use cgmath::Point3;
+
+pub const SAVE_FILE: &str = "save.txt";
+
+/// The first line is the level's index within its pack, then the pack's file name. A second line,
+/// if there is one, is the checkpoint reached in that level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    // Relative to the assets directory, like the names passed to `Level::load_pack`.
+    pub pack: String,
+    pub level: usize,
+    pub checkpoint: Option<Checkpoint>,
+}
+impl SaveGame {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut lines = text.lines();
+        let line = lines.next().unwrap_or("");
+        let (level, pack) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("expected a level index and a pack, found {:?}", line))?;
+        let checkpoint = match lines.next().filter(|line| !line.trim().is_empty()) {
+            Some(line) => Some(Checkpoint::parse(line)?),
+            None => None,
+        };
+        Ok(SaveGame { pack: pack.trim().to_string(), level: level.parse()?, checkpoint })
+    }
+    /// A missing file means there is nothing to continue.
+    pub fn load(path: &str) -> anyhow::Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => {
+                Self::parse(&text).map(Some).with_context(|| format!("parsing save {}", path))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading save {}", path)),
+        }
+    }
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let mut text = format!("{} {}\n", self.level, self.pack);
+        if let Some(checkpoint) = &self.checkpoint {
+            text += &checkpoint.to_line();
+            text.push('\n');
+        }
+        std::fs::write(path, text).with_context(|| format!("writing save to {}", path))
+    }
+}
+
+/// What a checkpoint puts back: where the player stood, where each box was, and the level's move
+/// and push counts. Time keeps counting from wherever it had got to.
+///
+/// As a line: the position's x, y and z, the moves and pushes, then each box's column and row.
+/// Floats are written the shortest way that reads back exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub position: Point3<f32>,
+    pub boxes: Vec<(i32, i32)>,
+    pub moves: u32,
+    pub pushes: u32,
+}
+impl Checkpoint {
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.len() < 5 || (fields.len() - 5) % 2 != 0 {
+            bail!("expected a position, moves, pushes and box tiles, found {:?}", line);
+        }
+        let boxes = fields[5..]
+            .chunks(2)
+            .map(|tile| Ok((tile[0].parse()?, tile[1].parse()?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Checkpoint {
+            position: Point3::new(fields[0].parse()?, fields[1].parse()?, fields[2].parse()?),
+            boxes,
+            moves: fields[3].parse()?,
+            pushes: fields[4].parse()?,
+        })
+    }
+    pub fn to_line(&self) -> String {
+        let p = self.position;
+        let mut line = format!("{} {} {} {} {}", p.x, p.y, p.z, self.moves, self.pushes);
+        for (x, z) in &self.boxes {
+            line += &format!(" {} {}", x, z);
+        }
+        line
+    }
+}
@@ -0,0 +1,93 @@
+/* Scene: the uniform describing the world around the models, bound with the environment map as
+ * group 2 of the scene pipelines. */
+
+/// How the game wants the world lit this tick. Made on the game thread and uploaded by the render
+/// thread, which only writes the scene uniform when it changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Environment {
+    pub light_position: [f32; 3],
+    pub light_color: [f32; 3],
+    // None for the plain background color.
+    pub sky: Option<SkyColors>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkyColors {
+    pub zenith: [f32; 3],
+    // Also the fog color.
+    pub horizon: [f32; 3],
+}
+
+/// Exponential fog, thinning out with height. Far surfaces fade towards the fog color, which is
+/// kept the same as the clear color so the edge of the level melts into the background.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    // Extinction per world unit at `base_height`.
+    pub density: f32,
+    // How quickly the density falls off above `base_height`, per world unit.
+    pub height_falloff: f32,
+    pub base_height: f32,
+}
+impl Default for Fog {
+    fn default() -> Self {
+        // Thin enough that the middle of a level is barely touched, and based at the level's floor.
+        Fog { density: 0.02, height_falloff: 0.15, base_height: -5.5 }
+    }
+}
+
+/// The scene group's bindings after the uniform: the environment cubemap, then its sampler.
+pub(crate) fn environment_layout_entries() -> [wgpu::BindGroupLayoutEntry; 2] {
+    [
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ]
+}
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy, Debug)]
+pub(crate) struct SceneUniform {
+    pub(crate) light_position: [f32; 3],
+    // Due to uniforms requiring 16 byte (4 float) spacing, we need to use a padding field here
+    pub(crate) _padding: u32,
+    pub(crate) light_color: [f32; 3],
+    pub(crate) _padding2: u32,
+    pub(crate) fog_color: [f32; 3],
+    pub(crate) fog_density: f32,
+    pub(crate) fog_height_falloff: f32,
+    pub(crate) fog_base_height: f32,
+    // 1 to stripe goals, for the colorblind-safe palettes.
+    pub(crate) goal_patterns: u32,
+    pub(crate) _padding3: u32,
+}
+// The size of shaders.wgsl's Scene, whose fog_density packs into the end of fog_color.
+const _: () = assert!(std::mem::size_of::<SceneUniform>() == 64);
+impl SceneUniform {
+    pub(crate) fn new(light_position: [f32; 3], fog: Fog, fog_color: [f32; 3]) -> Self {
+        SceneUniform {
+            light_position,
+            _padding: 0,
+            light_color: [1.0, 1.0, 1.0],
+            _padding2: 0,
+            fog_color,
+            fog_density: fog.density,
+            fog_height_falloff: fog.height_falloff,
+            fog_base_height: fog.base_height,
+            goal_patterns: 0,
+            _padding3: 0,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+/* Scripting: per-level rhai scripts that react to gameplay events with game state changes. */
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use cgmath::Vector3;
+use rhai::{Dynamic, Engine, Scope, AST, FLOAT, INT};
+
+use crate::{log_println, resources, rng::Rng};
+
+// Keeps a runaway script (e.g. an infinite loop) from hanging the game thread.
+const MAX_OPERATIONS_PER_EVENT: u64 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScriptEvent {
+    BoxOnGoal { x: i32, z: i32 },
+    PlayerEnteredTile { x: i32, z: i32 },
+    TriggerEntered { trigger: u32 },
+    TriggerExited { trigger: u32 },
+}
+impl ScriptEvent {
+    // Name of the script function that handles this event.
+    fn handler(&self) -> &'static str {
+        match self {
+            ScriptEvent::BoxOnGoal { .. } => "on_box_on_goal",
+            ScriptEvent::PlayerEnteredTile { .. } => "on_player_entered_tile",
+            ScriptEvent::TriggerEntered { .. } => "on_trigger_entered",
+            ScriptEvent::TriggerExited { .. } => "on_trigger_exited",
+        }
+    }
+    fn args(&self) -> Vec<INT> {
+        match *self {
+            ScriptEvent::BoxOnGoal { x, z } | ScriptEvent::PlayerEnteredTile { x, z } => {
+                vec![x as INT, z as INT]
+            }
+            ScriptEvent::TriggerEntered { trigger } | ScriptEvent::TriggerExited { trigger } => {
+                vec![trigger as INT]
+            }
+        }
+    }
+}
+
+// Changes a script asks for. Scripts never touch the game state directly: commands are collected
+// while the handler runs and applied by GameState afterwards, on the same tick.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScriptCommand {
+    OpenDoor(u32),
+    SpawnInstance { entity: usize, position: Vector3<f32>, scale: f32 },
+    // Like SpawnInstance, `height` above the center of a tile's floor, wherever the level sits.
+    SpawnAtTile { entity: usize, tile: (i32, i32), height: f32, scale: f32 },
+    SetLightColor([f32; 3]),
+}
+
+/// A compiled level script.
+///
+/// Scripts define any of `on_box_on_goal(x, z)`, `on_player_entered_tile(x, z)`,
+/// `on_trigger_entered(id)` and `on_trigger_exited(id)`, and can call
+/// `open_door(id)`, `spawn_instance(entity, x, y, z, scale)`,
+/// `spawn_at_tile(entity, x, z, height, scale)` and `set_light_color(r, g, b)`.
+/// Tile coordinates are the level's (column, row); `spawn_instance` takes a world position, and
+/// `spawn_at_tile` a tile and a height above its floor, so it keeps working when the level moves.
+/// Positions, scales and colors are floats, so write `1.0` rather than `1`.
+///
+/// For randomness, `random()` gives a float in [0, 1) and `random_int(n)` an integer in [0, n).
+/// Both draw from the game's generator, so a replay rolls the same numbers.
+pub struct LevelScript {
+    engine: Engine,
+    ast: AST,
+    commands: Arc<Mutex<Vec<ScriptCommand>>>,
+    // The game's generator, lent to the script while a handler runs.
+    rng: Arc<Mutex<Rng>>,
+}
+impl LevelScript {
+    pub fn new(source: &str) -> anyhow::Result<Self> {
+        let commands = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS_PER_EVENT);
+        let queue = commands.clone();
+        engine.register_fn("open_door", move |id: INT| {
+            queue.lock().unwrap().push(ScriptCommand::OpenDoor(id as u32));
+        });
+        let queue = commands.clone();
+        engine.register_fn(
+            "spawn_instance",
+            move |entity: INT, x: FLOAT, y: FLOAT, z: FLOAT, scale: FLOAT| {
+                queue.lock().unwrap().push(ScriptCommand::SpawnInstance {
+                    entity: entity as usize,
+                    position: Vector3::new(x as f32, y as f32, z as f32),
+                    scale: scale as f32,
+                });
+            },
+        );
+        let queue = commands.clone();
+        engine.register_fn(
+            "spawn_at_tile",
+            move |entity: INT, x: INT, z: INT, height: FLOAT, scale: FLOAT| {
+                queue.lock().unwrap().push(ScriptCommand::SpawnAtTile {
+                    entity: entity as usize,
+                    tile: (x as i32, z as i32),
+                    height: height as f32,
+                    scale: scale as f32,
+                });
+            },
+        );
+        let queue = commands.clone();
+        engine.register_fn("set_light_color", move |r: FLOAT, g: FLOAT, b: FLOAT| {
+            queue
+                .lock()
+                .unwrap()
+                .push(ScriptCommand::SetLightColor([r as f32, g as f32, b as f32]));
+        });
+        let rng = Arc::new(Mutex::new(Rng::new(0, 0)));
+        let shared = rng.clone();
+        engine.register_fn("random", move || shared.lock().unwrap().next_f32() as FLOAT);
+        let shared = rng.clone();
+        engine.register_fn("random_int", move |n: INT| -> INT {
+            let n = n.clamp(1, u32::MAX as INT) as u32;
+            shared.lock().unwrap().below(n) as INT
+        });
+        let ast = engine.compile(source).map_err(|e| anyhow!("compiling level script: {}", e))?;
+        Ok(LevelScript { engine, ast, commands, rng })
+    }
+    pub fn load(file_name: &str) -> anyhow::Result<Self> {
+        let source = pollster::block_on(resources::load_string(file_name))?;
+        Self::new(&source)
+    }
+    /// Runs the script's handler for `event`, if it has one, and returns the commands it issued.
+    /// A script error abandons the handler but keeps whatever it queued before failing. The
+    /// handler's random numbers come from `rng`.
+    pub fn handle(&self, event: ScriptEvent, rng: &mut Rng) -> Vec<ScriptCommand> {
+        let handler = event.handler();
+        let args = event.args();
+        if !self.ast.iter_functions().any(|f| f.name == handler && f.params.len() == args.len()) {
+            return Vec::new();
+        }
+        *self.rng.lock().unwrap() = rng.clone();
+        let result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, handler, args);
+        *rng = self.rng.lock().unwrap().clone();
+        if let Err(e) = result {
+            log_println!("level script {} failed: {}", handler, e);
+        }
+        std::mem::take(&mut *self.commands.lock().unwrap())
+    }
+}
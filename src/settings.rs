@@ -0,0 +1,566 @@
+/* Settings: player-facing options, changed from the menus and kept on disk. */
+use crate::{
+    camera::FOVY_RANGE,
+    constants::DEFAULT_MAX_RENDER_FPS,
+    strings::{Language, StringTable},
+};
+
+use anyhow::{anyhow, Context};
+use std::time::Duration;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    VIRTUAL_KEY, VK_BACK, VK_CONTROL, VK_DOWN, VK_ESCAPE, VK_LEFT, VK_R, VK_RETURN, VK_RIGHT,
+    VK_SHIFT, VK_SPACE, VK_TAB, VK_UP, VK_X, VK_Z,
+};
+
+pub const SETTINGS_FILE: &str = "settings.txt";
+
+const MOUSE_SENSITIVITY_RANGE: (f32, f32) = (0.25, 4.0);
+const MOUSE_SENSITIVITY_STEP: f32 = 0.25;
+const MOUSE_SMOOTHING_RANGE: (f32, f32) = (0.0, 0.9);
+const MOUSE_SMOOTHING_STEP: f32 = 0.1;
+const MOUSE_ACCELERATION_RANGE: (f32, f32) = (0.0, 2.0);
+const MOUSE_ACCELERATION_STEP: f32 = 0.25;
+const FOV_STEP: f32 = 5.0;
+// Angular frequency of the camera's spring, per second.
+const CAMERA_STIFFNESS_RANGE: (f32, f32) = (5.0, 50.0);
+const CAMERA_STIFFNESS_STEP: f32 = 5.0;
+const VOLUME_STEP: f32 = 0.1;
+const FRAME_CAP_RANGE: (f32, f32) = (30.0, 300.0);
+const FRAME_CAP_STEP: f32 = 10.0;
+
+/// A game action that can be bound to a key.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Control {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Jump,
+    Undo,
+    Restart,
+    Interact,
+}
+impl Control {
+    pub const ALL: [Control; 8] = [
+        Control::Forward,
+        Control::Backward,
+        Control::Left,
+        Control::Right,
+        Control::Jump,
+        Control::Undo,
+        Control::Restart,
+        Control::Interact,
+    ];
+    fn key(self) -> &'static str {
+        match self {
+            Control::Forward => "key_forward",
+            Control::Backward => "key_backward",
+            Control::Left => "key_left",
+            Control::Right => "key_right",
+            Control::Jump => "key_jump",
+            Control::Undo => "key_undo",
+            Control::Restart => "key_restart",
+            Control::Interact => "key_interact",
+        }
+    }
+    pub fn label(self) -> &'static str {
+        match self {
+            Control::Forward => "Forward",
+            Control::Backward => "Backward",
+            Control::Left => "Left",
+            Control::Right => "Right",
+            Control::Jump => "Jump",
+            Control::Undo => "Undo",
+            Control::Restart => "Restart level",
+            Control::Interact => "Grab box",
+        }
+    }
+}
+
+/// The virtual key bound to each `Control`. Enter and Esc always confirm and go back in the menus,
+/// so they can't be bound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyBindings {
+    // Indexed like Control::ALL.
+    keys: [u16; Control::ALL.len()],
+}
+impl KeyBindings {
+    pub const RESERVED: [VIRTUAL_KEY; 2] = [VK_RETURN, VK_ESCAPE];
+    pub fn new() -> Self {
+        KeyBindings {
+            keys: [VK_UP, VK_DOWN, VK_LEFT, VK_RIGHT, VK_SPACE, VK_Z, VK_R, VK_X].map(|k| k.0),
+        }
+    }
+    fn index(control: Control) -> usize {
+        Control::ALL.iter().position(|&c| c == control).unwrap()
+    }
+    pub fn key(&self, control: Control) -> u16 {
+        self.keys[Self::index(control)]
+    }
+    /// The control bound to `key`, if any.
+    pub fn control(&self, key: u16) -> Option<Control> {
+        Control::ALL.into_iter().find(|&control| self.key(control) == key)
+    }
+    /// Binds `key` to `control`. A control already bound to `key` takes `control`'s old key, so
+    /// every control keeps a key.
+    pub fn bind(&mut self, control: Control, key: u16) {
+        if let Some(other) = self.control(key) {
+            self.keys[Self::index(other)] = self.key(control);
+        }
+        self.keys[Self::index(control)] = key;
+    }
+}
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How the render thread limits its frame rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameLimit {
+    Unlimited,
+    // One frame per refresh of the window's monitor.
+    Refresh,
+    // At most `Settings::frame_cap` frames a second.
+    Custom,
+}
+impl FrameLimit {
+    const ALL: [FrameLimit; 3] = [FrameLimit::Unlimited, FrameLimit::Refresh, FrameLimit::Custom];
+    // The limit's value in the settings file.
+    fn key(self) -> &'static str {
+        match self {
+            FrameLimit::Unlimited => "unlimited",
+            FrameLimit::Refresh => "refresh",
+            FrameLimit::Custom => "custom",
+        }
+    }
+    // The limit's English name in the menus.
+    fn label(self) -> &'static str {
+        match self {
+            FrameLimit::Unlimited => "unlimited",
+            FrameLimit::Refresh => "match display",
+            FrameLimit::Custom => "custom",
+        }
+    }
+}
+
+/// Colors remapped after tonemapping, so the colors that tell game states apart stay distinct for
+/// players with color vision deficiencies. Any palette but the standard one also stripes goals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Palette {
+    Standard,
+    // Red-green, missing the green-sensitive cones.
+    Deuteranopia,
+    // Red-green, missing the red-sensitive cones.
+    Protanopia,
+}
+impl Palette {
+    const ALL: [Palette; 3] = [Palette::Standard, Palette::Deuteranopia, Palette::Protanopia];
+    // The palette's value in the settings file.
+    fn key(self) -> &'static str {
+        match self {
+            Palette::Standard => "standard",
+            Palette::Deuteranopia => "deuteranopia",
+            Palette::Protanopia => "protanopia",
+        }
+    }
+    // The palette's English name in the menus.
+    fn label(self) -> &'static str {
+        match self {
+            Palette::Standard => "standard",
+            Palette::Deuteranopia => "deuteranopia (red-green)",
+            Palette::Protanopia => "protanopia (red-green)",
+        }
+    }
+}
+
+pub fn key_name(key: u16) -> String {
+    let name = match VIRTUAL_KEY(key) {
+        VK_UP => "Up",
+        VK_DOWN => "Down",
+        VK_LEFT => "Left",
+        VK_RIGHT => "Right",
+        VK_SPACE => "Space",
+        VK_RETURN => "Enter",
+        VK_ESCAPE => "Esc",
+        VK_TAB => "Tab",
+        VK_BACK => "Backspace",
+        VK_SHIFT => "Shift",
+        VK_CONTROL => "Ctrl",
+        // Digit and letter keys share their codes with ASCII.
+        _ if (0x30..=0x39).contains(&key) || (0x41..=0x5A).contains(&key) => {
+            return (key as u8 as char).to_string();
+        }
+        _ => return format!("Key {:02x}", key),
+    };
+    name.to_string()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Setting {
+    Language,
+    Ssao,
+    Fxaa,
+    AutoExposure,
+    DepthPrepass,
+    DayNight,
+    Palette,
+    Minimap,
+    Vsync,
+    FrameLimit,
+    FrameCap,
+    MouseSensitivity,
+    InvertY,
+    MouseSmoothing,
+    MouseAcceleration,
+    Fov,
+    FovKick,
+    CameraStiffness,
+    CameraShake,
+    Volume,
+    Key(Control),
+}
+impl Setting {
+    pub fn all() -> impl Iterator<Item = Setting> {
+        [
+            Setting::Language,
+            Setting::MouseSensitivity,
+            Setting::InvertY,
+            Setting::MouseSmoothing,
+            Setting::MouseAcceleration,
+            Setting::Fov,
+            Setting::FovKick,
+            Setting::CameraStiffness,
+            Setting::CameraShake,
+            Setting::Minimap,
+            Setting::Volume,
+            Setting::Vsync,
+            Setting::FrameLimit,
+            Setting::FrameCap,
+            Setting::Ssao,
+            Setting::Fxaa,
+            Setting::AutoExposure,
+            Setting::DepthPrepass,
+            Setting::DayNight,
+            Setting::Palette,
+        ]
+        .into_iter()
+        .chain(Control::ALL.map(Setting::Key))
+    }
+    /// The setting's key in the settings file, and in string tables as `setting.<key>`.
+    pub fn key(self) -> &'static str {
+        match self {
+            Setting::Language => "language",
+            Setting::Ssao => "ssao",
+            Setting::Fxaa => "fxaa",
+            Setting::AutoExposure => "auto_exposure",
+            Setting::DepthPrepass => "depth_prepass",
+            Setting::DayNight => "day_night",
+            Setting::Palette => "palette",
+            Setting::Minimap => "minimap",
+            Setting::Vsync => "vsync",
+            Setting::FrameLimit => "frame_limit",
+            Setting::FrameCap => "frame_cap",
+            Setting::MouseSensitivity => "mouse_sensitivity",
+            Setting::InvertY => "invert_y",
+            Setting::MouseSmoothing => "mouse_smoothing",
+            Setting::MouseAcceleration => "mouse_acceleration",
+            Setting::Fov => "fov",
+            Setting::FovKick => "fov_kick",
+            Setting::CameraStiffness => "camera_stiffness",
+            Setting::CameraShake => "camera_shake",
+            Setting::Volume => "volume",
+            Setting::Key(control) => control.key(),
+        }
+    }
+    /// The setting's English name in the menus.
+    pub fn label(self) -> &'static str {
+        match self {
+            Setting::Language => "Language",
+            Setting::Ssao => "Ambient occlusion",
+            Setting::Fxaa => "Anti-aliasing",
+            Setting::AutoExposure => "Auto exposure",
+            Setting::DepthPrepass => "Depth pre-pass",
+            Setting::DayNight => "Day/night cycle",
+            Setting::Palette => "Color palette",
+            Setting::Minimap => "Minimap",
+            Setting::Vsync => "Vsync",
+            Setting::FrameLimit => "Frame rate limit",
+            Setting::FrameCap => "Custom frame rate",
+            Setting::MouseSensitivity => "Mouse sensitivity",
+            Setting::InvertY => "Invert mouse Y",
+            Setting::MouseSmoothing => "Mouse smoothing",
+            Setting::MouseAcceleration => "Mouse acceleration",
+            Setting::Fov => "Field of view",
+            Setting::FovKick => "Widen view with speed",
+            Setting::CameraStiffness => "Camera stiffness",
+            Setting::CameraShake => "Camera shake",
+            Setting::Volume => "Volume",
+            Setting::Key(control) => control.label(),
+        }
+    }
+}
+
+/// The file format is one setting per line: its key, then its value. Switches are "on" or "off",
+/// the frame limit and palette are one of their enum's keys, the language is its code, and key
+/// bindings are virtual key codes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub language: Language,
+    pub ssao: bool,
+    pub fxaa: bool,
+    pub auto_exposure: bool,
+    // Draws opaque geometry's depth first, so expensive shading runs once per pixel.
+    pub depth_prepass: bool,
+    // Moves the light through the day and draws the sky behind the level.
+    pub day_night: bool,
+    pub palette: Palette,
+    // A top-down map of the level in the corner of the screen.
+    pub minimap: bool,
+    pub vsync: bool,
+    pub frame_limit: FrameLimit,
+    // Frames per second, for FrameLimit::Custom.
+    pub frame_cap: f32,
+    // Multiplies mouse movement before it turns the camera.
+    pub mouse_sensitivity: f32,
+    pub invert_y: bool,
+    // The fraction of the last tick's turn kept each tick, from 0 for no smoothing. Higher values
+    // turn the camera more smoothly, but later.
+    pub mouse_smoothing: f32,
+    // How much more fast mouse movements turn the camera than slow ones, from 0 for not at all.
+    pub mouse_acceleration: f32,
+    // Vertical field of view, in degrees.
+    pub fov: f32,
+    // Widens the field of view a little as the player speeds up.
+    pub fov_kick: bool,
+    // How quickly the camera catches up after the player is snapped into place.
+    pub camera_stiffness: f32,
+    pub camera_shake: bool,
+    // TODO: there is no audio yet, so this is only stored.
+    pub volume: f32,
+    pub bindings: KeyBindings,
+}
+impl Settings {
+    pub fn new() -> Self {
+        Settings {
+            language: Language::English,
+            ssao: true,
+            fxaa: true,
+            auto_exposure: false,
+            depth_prepass: true,
+            day_night: false,
+            palette: Palette::Standard,
+            minimap: true,
+            vsync: true,
+            frame_limit: FrameLimit::Refresh,
+            frame_cap: DEFAULT_MAX_RENDER_FPS,
+            mouse_sensitivity: 1.0,
+            invert_y: false,
+            mouse_smoothing: 0.0,
+            mouse_acceleration: 0.0,
+            fov: 45.0,
+            fov_kick: false,
+            camera_stiffness: 20.0,
+            camera_shake: true,
+            volume: 1.0,
+            bindings: KeyBindings::new(),
+        }
+    }
+    fn switch_mut(&mut self, setting: Setting) -> Option<&mut bool> {
+        match setting {
+            Setting::Ssao => Some(&mut self.ssao),
+            Setting::Fxaa => Some(&mut self.fxaa),
+            Setting::AutoExposure => Some(&mut self.auto_exposure),
+            Setting::DepthPrepass => Some(&mut self.depth_prepass),
+            Setting::DayNight => Some(&mut self.day_night),
+            Setting::Minimap => Some(&mut self.minimap),
+            Setting::Vsync => Some(&mut self.vsync),
+            Setting::InvertY => Some(&mut self.invert_y),
+            Setting::FovKick => Some(&mut self.fov_kick),
+            Setting::CameraShake => Some(&mut self.camera_shake),
+            _ => None,
+        }
+    }
+    // The setting's value, its step and its range, for settings that are numbers.
+    fn number_mut(&mut self, setting: Setting) -> Option<(&mut f32, f32, (f32, f32))> {
+        match setting {
+            Setting::MouseSensitivity => {
+                Some((&mut self.mouse_sensitivity, MOUSE_SENSITIVITY_STEP, MOUSE_SENSITIVITY_RANGE))
+            }
+            Setting::MouseSmoothing => {
+                Some((&mut self.mouse_smoothing, MOUSE_SMOOTHING_STEP, MOUSE_SMOOTHING_RANGE))
+            }
+            Setting::MouseAcceleration => Some((
+                &mut self.mouse_acceleration,
+                MOUSE_ACCELERATION_STEP,
+                MOUSE_ACCELERATION_RANGE,
+            )),
+            Setting::Fov => Some((&mut self.fov, FOV_STEP, FOVY_RANGE)),
+            Setting::CameraStiffness => {
+                Some((&mut self.camera_stiffness, CAMERA_STIFFNESS_STEP, CAMERA_STIFFNESS_RANGE))
+            }
+            Setting::Volume => Some((&mut self.volume, VOLUME_STEP, (0.0, 1.0))),
+            Setting::FrameCap => Some((&mut self.frame_cap, FRAME_CAP_STEP, FRAME_CAP_RANGE)),
+            _ => None,
+        }
+    }
+    /// Steps a number up or down by `steps` within its range, flips a switch, or cycles through
+    /// the languages, frame limits or palettes. Key bindings are changed with `KeyBindings::bind`
+    /// instead.
+    pub fn adjust(&mut self, setting: Setting, steps: i32) {
+        if setting == Setting::Language {
+            let index = Language::ALL.iter().position(|&language| language == self.language);
+            let index = (index.unwrap() as i32 + steps).rem_euclid(Language::ALL.len() as i32);
+            self.language = Language::ALL[index as usize];
+        } else if setting == Setting::FrameLimit {
+            let index = FrameLimit::ALL.iter().position(|&limit| limit == self.frame_limit);
+            let index = (index.unwrap() as i32 + steps).rem_euclid(FrameLimit::ALL.len() as i32);
+            self.frame_limit = FrameLimit::ALL[index as usize];
+        } else if setting == Setting::Palette {
+            let index = Palette::ALL.iter().position(|&palette| palette == self.palette);
+            let index = (index.unwrap() as i32 + steps).rem_euclid(Palette::ALL.len() as i32);
+            self.palette = Palette::ALL[index as usize];
+        } else if let Some(value) = self.switch_mut(setting) {
+            *value = !*value;
+        } else if let Some((value, step, (min, max))) = self.number_mut(setting) {
+            // Rounded to the step, so repeated steps don't accumulate float error.
+            let stepped = ((*value / step).round() + steps as f32) * step;
+            *value = stepped.clamp(min, max);
+        }
+    }
+    /// The setting's value as shown in the menus, in the language of `strings`. Languages are
+    /// always shown in their own.
+    pub fn value_text(&self, setting: Setting, strings: &StringTable) -> String {
+        let on_off = |on: bool| {
+            if on { strings.get("value.on", "on") } else { strings.get("value.off", "off") }
+                .to_string()
+        };
+        match setting {
+            Setting::Language => self.language.name().to_string(),
+            Setting::Ssao => on_off(self.ssao),
+            Setting::Fxaa => on_off(self.fxaa),
+            Setting::AutoExposure => on_off(self.auto_exposure),
+            Setting::DepthPrepass => on_off(self.depth_prepass),
+            Setting::DayNight => on_off(self.day_night),
+            Setting::Minimap => on_off(self.minimap),
+            Setting::Vsync => on_off(self.vsync),
+            Setting::FrameLimit => {
+                let key = format!("frame_limit.{}", self.frame_limit.key());
+                strings.get(&key, self.frame_limit.label()).to_string()
+            }
+            Setting::Palette => {
+                let key = format!("palette.{}", self.palette.key());
+                strings.get(&key, self.palette.label()).to_string()
+            }
+            Setting::FrameCap => format!("{:.0} fps", self.frame_cap),
+            Setting::InvertY => on_off(self.invert_y),
+            Setting::FovKick => on_off(self.fov_kick),
+            Setting::CameraShake => on_off(self.camera_shake),
+            Setting::MouseSensitivity => format!("{:.2}x", self.mouse_sensitivity),
+            Setting::MouseSmoothing if self.mouse_smoothing == 0.0 => on_off(false),
+            Setting::MouseSmoothing => format!("{:.0}%", self.mouse_smoothing * 100.0),
+            Setting::MouseAcceleration if self.mouse_acceleration == 0.0 => on_off(false),
+            Setting::MouseAcceleration => format!("{:.2}", self.mouse_acceleration),
+            Setting::Fov => format!("{:.0}", self.fov),
+            Setting::CameraStiffness => format!("{:.0}", self.camera_stiffness),
+            Setting::Volume => format!("{:.0}%", self.volume * 100.0),
+            Setting::Key(control) => key_name(self.bindings.key(control)),
+        }
+    }
+    /// The shortest time to leave between rendered frames, or None for no limit. `refresh_rate` is
+    /// the monitor's in hertz, if known; without it, matching the display falls back to the cap.
+    pub fn min_frame_time(&self, refresh_rate: Option<f32>) -> Option<Duration> {
+        let fps = match self.frame_limit {
+            FrameLimit::Unlimited => return None,
+            FrameLimit::Refresh => refresh_rate.unwrap_or(self.frame_cap),
+            FrameLimit::Custom => self.frame_cap,
+        };
+        Some(Duration::from_secs_f32(1.0 / fps))
+    }
+    /// Settings missing from `text` keep their defaults.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut settings = Settings::new();
+        for (line_number, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            settings
+                .parse_line(line)
+                .with_context(|| format!("settings line {}", line_number + 1))?;
+        }
+        Ok(settings)
+    }
+    fn parse_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        let [key, value] = fields[..] else {
+            return Err(anyhow!("expected 2 fields, found {}", fields.len()));
+        };
+        let setting = Setting::all()
+            .find(|setting| setting.key() == key)
+            .ok_or_else(|| anyhow!("unknown setting {}", key))?;
+        if let Setting::Key(control) = setting {
+            self.bindings.bind(control, value.parse()?);
+        } else if setting == Setting::Language {
+            self.language = Language::ALL
+                .into_iter()
+                .find(|language| language.code() == value)
+                .ok_or_else(|| anyhow!("unknown language {}", value))?;
+        } else if setting == Setting::FrameLimit {
+            self.frame_limit = FrameLimit::ALL
+                .into_iter()
+                .find(|limit| limit.key() == value)
+                .ok_or_else(|| anyhow!("unknown frame limit {}", value))?;
+        } else if setting == Setting::Palette {
+            self.palette = Palette::ALL
+                .into_iter()
+                .find(|palette| palette.key() == value)
+                .ok_or_else(|| anyhow!("unknown palette {}", value))?;
+        } else if let Some(switch) = self.switch_mut(setting) {
+            *switch = match value {
+                "on" => true,
+                "off" => false,
+                _ => return Err(anyhow!("expected on or off, found {}", value)),
+            };
+        } else if let Some((number, _, (min, max))) = self.number_mut(setting) {
+            *number = value.parse::<f32>()?.clamp(min, max);
+        }
+        Ok(())
+    }
+    /// A missing file means the defaults.
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).with_context(|| format!("parsing settings {}", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Settings::new()),
+            Err(e) => Err(e).with_context(|| format!("reading settings {}", path)),
+        }
+    }
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text: String = Setting::all()
+            .map(|setting| {
+                let value = match setting {
+                    Setting::Key(control) => self.bindings.key(control).to_string(),
+                    Setting::MouseSensitivity => self.mouse_sensitivity.to_string(),
+                    Setting::MouseSmoothing => self.mouse_smoothing.to_string(),
+                    Setting::MouseAcceleration => self.mouse_acceleration.to_string(),
+                    Setting::Fov => self.fov.to_string(),
+                    Setting::CameraStiffness => self.camera_stiffness.to_string(),
+                    Setting::Volume => self.volume.to_string(),
+                    Setting::FrameLimit => self.frame_limit.key().to_string(),
+                    Setting::Palette => self.palette.key().to_string(),
+                    Setting::Language => self.language.code().to_string(),
+                    Setting::FrameCap => self.frame_cap.to_string(),
+                    // Without a table the switches are "on" and "off", as the file has them.
+                    _ => self.value_text(setting, &StringTable::new()),
+                };
+                format!("{} {}\n", setting.key(), value)
+            })
+            .collect();
+        std::fs::write(path, text).with_context(|| format!("writing settings to {}", path))
+    }
+}
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -5,18 +5,36 @@ struct Camera {
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
+struct Scene {
+    light_position: vec3<f32>,
+    light_color: vec3<f32>,
+    fog_color: vec3<f32>,
+    fog_density: f32,
+    fog_height_falloff: f32,
+    fog_base_height: f32,
+    goal_patterns: u32,
 }
 @group(2) @binding(0)
-var<uniform> light: Light;
+var<uniform> scene: Scene;
+// The surroundings that reflective materials mirror, in HDR.
+@group(2) @binding(1)
+var t_environment: texture_cube<f32>;
+@group(2) @binding(2)
+var s_environment: sampler;
 
-struct Time {
+// Matches GlobalsUniform in globals.rs.
+struct Globals {
     secs: f32,
+    ticks: u32,
+    // In pixels, as is the cursor.
+    screen_size: vec2<f32>,
+    cursor: vec2<f32>,
 }
 @group(3) @binding(0)
-var<uniform> time: Time;
+var<uniform> globals: Globals;
+// Only bound for skinned models: one matrix per joint, taking bind pose to the current pose.
+@group(3) @binding(1)
+var<storage, read> bones: array<mat4x4<f32>>;
 
 // Vertex shader
 struct InstanceInput {
@@ -24,6 +42,8 @@ struct InstanceInput {
     @location(6) scale: f32,
     @location(7) rotation: vec4<f32>,
     @location(8) shader: u32,
+    @location(9) tint: vec4<f32>,
+    @location(10) layer: u32,
 };
 
 struct VertexInput {
@@ -37,7 +57,9 @@ fn apply_rotor_to_vector(
     vector: vec3<f32>,
 ) -> vec3<f32> {
     // Assumption: rotor comes from a quaternion representing a rotation, and is therefore a unit
-    // rotor.
+    // rotor. Its components are InstanceRaw::rot, (s, xy, xz, yz), and this is the same sandwich
+    // as Rotor::rotate_vector. The F6 rotation check draws cgmath's quaternion rotation of each
+    // turned instance next to it, to compare against.
     // Strategy: calculate RvR', where R' is "R-inverse" and is the conjugate of R.
     // Calculate S = Rv first:
     var s_x: f32 = rotor.x * vector.x + rotor.y * vector.y + rotor.z * vector.z;
@@ -78,18 +100,54 @@ fn vs_main(
     out.instance_world_position = instance.position;
     out.instance_scale = instance.scale;
     out.shader = instance.shader;
+    out.tint = instance.tint;
+    out.layer = instance.layer;
+    return out;
+}
+
+struct SkinnedVertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) joints: vec4<u32>,
+    @location(4) weights: vec4<f32>,
+}
+@vertex
+fn vs_skinned(
+    model: SkinnedVertexInput,
+    instance: InstanceInput,
+) -> FragmentInput {
+    let skin = model.weights.x * bones[model.joints.x]
+        + model.weights.y * bones[model.joints.y]
+        + model.weights.z * bones[model.joints.z]
+        + model.weights.w * bones[model.joints.w];
+    let position = (skin * vec4<f32>(model.position, 1.0)).xyz;
+    let normal = normalize((skin * vec4<f32>(model.normal, 0.0)).xyz);
+    var out: FragmentInput;
+    out.tex_coords = model.tex_coords;
+    out.world_normal = apply_rotor_to_vector(instance.rotation, normal);
+    out.world_position = calculate_world_position(instance.scale * position, instance);
+    out.clip_position = calculate_clip_position(out.world_position);
+    out.instance_world_position = instance.position;
+    out.instance_scale = instance.scale;
+    out.shader = instance.shader;
+    out.tint = instance.tint;
+    out.layer = instance.layer;
     return out;
 }
 
 // Fragment shader
 struct FragmentInput {
-    @builtin(position) clip_position: vec4<f32>,
+    // Invariant so each variant's pipeline computes exactly the depth the depth pre-pass wrote.
+    @builtin(position) @invariant clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
     @location(3) @interpolate(flat) instance_world_position: vec3<f32>,
     @location(4) @interpolate(flat) instance_scale: f32,
     @location(5) shader: u32,
+    @location(6) @interpolate(flat) tint: vec4<f32>,
+    @location(7) @interpolate(flat) layer: u32,
 };
 struct LightingOutput {
     ambient_color: vec3<f32>,
@@ -101,20 +159,64 @@ struct LightingOutput {
 var t_diffuse: texture_2d<f32>;
 @group(0) @binding(1)
 var s_diffuse: sampler;
+@group(0) @binding(2)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(3)
+var t_emissive: texture_2d<f32>;
+struct Material {
+    tint: vec4<f32>,
+    // Multiplies the emissive texture. Black for materials that don't glow.
+    emission: vec3<f32>,
+    // Replaces the instance's shader when that is Texture.
+    shader: u32,
+    has_normal_map: u32,
+    reflectivity: f32,
+}
+@group(0) @binding(4)
+var<uniform> material: Material;
+// Drawn from by Tiles, at the instance's layer. A single plain layer for most materials.
+@group(0) @binding(5)
+var t_layers: texture_2d_array<f32>;
+
+// The normal from a tangent-space normal map, with the tangent frame worked out from screen-space
+// derivatives, since vertices don't carry tangents.
+fn perturb_normal(in: FragmentInput, map_normal: vec3<f32>) -> vec3<f32> {
+    let normal = normalize(in.world_normal);
+    let dp1 = dpdx(in.world_position);
+    let dp2 = dpdy(in.world_position);
+    let duv1 = dpdx(in.tex_coords);
+    let duv2 = dpdy(in.tex_coords);
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    // Texture coordinates are loaded with v flipped, so the bitangent is too.
+    let bitangent = -(dp2_perp * duv1.y + dp1_perp * duv2.y);
+    let scale = inverseSqrt(max(max(dot(tangent, tangent), dot(bitangent, bitangent)), 1e-12));
+    let tbn = mat3x3<f32>(tangent * scale, bitangent * scale, normal);
+    return normalize(tbn * map_normal);
+}
+
+// The environment seen in the surface, as a mirror. The map has a single mip level, and sampling
+// it explicitly is allowed after shade's early returns have left control flow non-uniform.
+fn reflect_environment(in: FragmentInput) -> vec3<f32> {
+    let view_dir = normalize(in.world_position - camera.view_pos);
+    let direction = reflect(view_dir, normalize(in.world_normal));
+    return textureSampleLevel(t_environment, s_environment, direction, 0.0).rgb;
+}
 
 fn calculate_lighting(in: FragmentInput) -> LightingOutput {
     var out: LightingOutput;
     let ambient_strength = 0.2;
-    out.ambient_color = light.color * ambient_strength;
+    out.ambient_color = scene.light_color * ambient_strength;
 
-    let light_dir = normalize(light.position - in.world_position);
+    let light_dir = normalize(scene.light_position - in.world_position);
     let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    out.diffuse_color = light.color * diffuse_strength;
+    out.diffuse_color = scene.light_color * diffuse_strength;
 
     let view_dir = normalize(camera.view_pos - in.world_position);
     let half_dir = normalize(view_dir + light_dir);
     let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    out.specular_color = light.color * specular_strength;
+    out.specular_color = scene.light_color * specular_strength;
 
     return out;
 }
@@ -127,32 +229,120 @@ const Ripple = 3u;
 const ColorTween = 4u;
 const SimpleTransparency = 5u;
 const Aerogel = 6u;
+const Matte = 7u;
+const Portal = 8u;
+const Conveyor = 9u;
+const Ghost = 10u;
+const Tiles = 11u;
+const Goal = 12u;
+// How much brighter than the light itself the cube marking it glows, so it blooms.
+const LightMarkerGlow = 3.0;
+// The peak red glow of Pulse.
+const PulseGlow = 2.0;
+// Not a shader of its own: branch on the instance's shader id.
+const AnyVariant = 255u;
+// Each scene pipeline replaces this line with its own variant; see pipeline_cache.rs.
+const Variant = AnyVariant;
+// Blends `color` towards the fog color by how much fog lies between the camera and
+// `world_position`. The fog's density falls off exponentially with height, so its integral along
+// the view ray has a closed form.
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let ray = world_position - camera.view_pos;
+    let falloff = scene.fog_height_falloff;
+    let eye_density =
+        scene.fog_density * exp(-falloff * (camera.view_pos.y - scene.fog_base_height));
+    var optical_depth = eye_density * length(ray);
+    let climb = falloff * ray.y;
+    if abs(climb) > 1e-4 {
+        optical_depth *= (1.0 - exp(-climb)) / climb;
+    }
+    return mix(color, scene.fog_color, 1.0 - exp(-optical_depth));
+}
+
 @fragment
 fn fs_main(in: FragmentInput) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    return vec4<f32>(apply_fog(color.rgb, in.world_position), color.a);
+}
+fn shade(in: FragmentInput) -> vec4<f32> {
+    // Sampled up front: textureSample and derivatives need uniform control flow.
+    let diffuse = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    let emissive = textureSample(t_emissive, s_diffuse, in.tex_coords).rgb;
+    let tile = textureSample(t_layers, s_diffuse, in.tex_coords, in.layer);
+    let map_normal = textureSample(t_normal, s_diffuse, in.tex_coords).xyz * 2.0 - 1.0;
+    let mapped_normal = perturb_normal(in, map_normal);
+
+    var shader = Variant;
+    if shader == AnyVariant {
+        shader = in.shader;
+    }
+    if shader == Texture {
+        shader = material.shader;
+    }
+    var surface = in;
+    var glow = vec3<f32>(0.0);
+    var reflectivity = 0.0;
     var unlit: vec4<f32>;
-    switch in.shader {
-        case Texture: { unlit = textureSample(t_diffuse, s_diffuse, in.tex_coords); }
-        case NonMaterial { return vec4<f32>(light.color, 1.0); }
-        case Pulse: { unlit = fs_pulse(in); }
+    switch shader {
+        case Texture: {
+            unlit = diffuse * material.tint;
+            glow = emissive * material.emission;
+            reflectivity = material.reflectivity;
+            if material.has_normal_map != 0u {
+                surface.world_normal = mapped_normal;
+            }
+        }
+        case NonMaterial: {
+            unlit = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+            glow = scene.light_color * LightMarkerGlow;
+        }
+        case Pulse: {
+            unlit = vec4<f32>(0.03, 0.03, 0.03, 1.0);
+            glow = fs_pulse(in);
+        }
+        case Goal: {
+            unlit = vec4<f32>(0.03, 0.03, 0.03, 1.0);
+            glow = fs_pulse(in) * goal_pattern(in);
+        }
         case Ripple: { unlit = fs_ripple(in); }
         case ColorTween: { unlit = fs_color_tween(in); }
         case SimpleTransparency: { unlit = vec4<f32>(0.5); }
         case Aerogel: { unlit = fs_aerogel(in); }
+        case Matte: { unlit = vec4<f32>(0.8, 0.8, 0.8, 1.0); }
+        case Portal: { return fs_portal(in) * in.tint; }
+        case Conveyor: { unlit = fs_conveyor(in); }
+        case Ghost: { return fs_ghost(in) * in.tint; }
+        case Tiles: {
+            unlit = tile * material.tint;
+            reflectivity = material.reflectivity;
+        }
         default: { unlit = vec4<f32>(0.0, 0.0, 0.0, 1.0); }
     }
-    let light = calculate_lighting(in);
-    let result = (light.ambient_color + light.diffuse_color + light.specular_color) * unlit.xyz;
-    return vec4<f32>(result, unlit.a);
+    unlit *= in.tint;
+    glow *= in.tint.rgb;
+    let light = calculate_lighting(surface);
+    let lit = (light.ambient_color + light.diffuse_color + light.specular_color) * unlit.xyz;
+    let result = mix(lit, reflect_environment(surface) * unlit.xyz, reflectivity);
+    return vec4<f32>(result + glow, unlit.a);
+}
+// Diagonal stripes across goals, so they read by shape as well as by color. Plain without the
+// colorblind-safe palettes.
+const GoalStripes = 4.0;
+fn goal_pattern(in: FragmentInput) -> f32 {
+    if scene.goal_patterns == 0u {
+        return 1.0;
+    }
+    let stripe = fract((in.world_position.x + in.world_position.z) * GoalStripes);
+    return select(0.15, 1.0, stripe < 0.5);
 }
-fn fs_pulse(in: FragmentInput) -> vec4<f32> {
-    var object_color: vec4<f32> = vec4<f32>(0.03, 0.03, 0.03, 1.0);
-    object_color.x += 0.9 * (cos(time.secs * 2.0) + 1.0) / 2.0;
-    return object_color;
+// The glow of a pulsing surface, peaking bright enough to bloom.
+fn fs_pulse(in: FragmentInput) -> vec3<f32> {
+    return vec3<f32>(PulseGlow * (cos(globals.secs * 2.0) + 1.0) / 2.0, 0.0, 0.0);
 }
 fn fs_ripple(in: FragmentInput) -> vec4<f32> {
     let uv = in.tex_coords;
     let radius = length(uv);
-    let color_str = pow((cos(radius * 20.0 - 4.0 * time.secs) + 1.0) / 2.0, 2.0);
+    let color_str = pow((cos(radius * 20.0 - 4.0 * globals.secs) + 1.0) / 2.0, 2.0);
     return vec4<f32>(color_str, color_str, color_str, 1.0);
 }
 const NumTweenColors = 6;
@@ -166,7 +356,7 @@ fn fs_color_tween(in: FragmentInput) -> vec4<f32> {
         vec3<f32>(0.0, 0.0, 1.0), // blue
         vec3<f32>(1.0, 0.0, 1.0), // purple
     );
-    let split = modf(time.secs / TweenTimeSecs);
+    let split = modf(globals.secs / TweenTimeSecs);
     let prev_idx = i32(split.whole) % NumTweenColors;
     let next_idx = (prev_idx + 1) % NumTweenColors;
     return vec4<f32>(split.fract * TweenColors[next_idx] + (1.0 - split.fract) * TweenColors[prev_idx], 1.0);
@@ -188,6 +378,34 @@ fn fs_aerogel(in: FragmentInput) -> vec4<f32> {
     }
     return vec4<f32>(0.0, 1.0, 0.0, 1.0 - exp(-d));
 }
+// Unlit, so the swirl glows the same in any light. Values above 1.0 bloom.
+fn fs_portal(in: FragmentInput) -> vec4<f32> {
+    // From -1 to 1 across the quad, so the swirl turns about its middle.
+    let p = in.tex_coords * 2.0 - 1.0;
+    let radius = length(p);
+    let angle = atan2(p.y, p.x);
+    let swirl = (sin(3.0 * angle + 8.0 * radius - 4.0 * globals.secs) + 1.0) / 2.0;
+    let glow = swirl * (1.0 - min(radius, 1.0));
+    return vec4<f32>(0.2 + 0.8 * glow, 0.1 + 0.3 * glow, 0.5 + 2.5 * glow, 1.0);
+}
+// One tile per 0.5s, matching CONVEYOR_SECS_PER_TILE in game_state.rs. A cube face spans 2.0 in
+// texture coordinates, so that is 4.0 per second.
+const ConveyorScrollSpeed = 4.0;
+fn fs_conveyor(in: FragmentInput) -> vec4<f32> {
+    let uv = in.tex_coords;
+    // Chevrons pointing towards +u.
+    let stripe = fract(1.5 * (uv.x - abs(uv.y) * 0.5 - ConveyorScrollSpeed * globals.secs));
+    if stripe < 0.5 {
+        return vec4<f32>(0.9, 0.7, 0.1, 1.0);
+    }
+    return vec4<f32>(0.15, 0.15, 0.15, 1.0);
+}
+// Unlit pale blue, faint face-on and brighter at grazing angles, so overlapping ghosts stay legible.
+fn fs_ghost(in: FragmentInput) -> vec4<f32> {
+    let view = normalize(camera.view_pos - in.world_position);
+    let rim = 1.0 - abs(dot(normalize(in.world_normal), view));
+    return vec4<f32>(0.5, 0.7, 1.0, 0.15 + 0.5 * rim * rim);
+}
 // box = (a,b,c) should be all positive numbers that represent the box [-a,a]*[-b,b]*[-c,c].
 fn sdf_box(point: vec3<f32>, box: vec3<f32>) -> f32 {
     let q = abs(point) - box;
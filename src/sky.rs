@@ -0,0 +1,109 @@
+/* SkyRenderer: fills the HDR target with the sky gradient, for the scene to be drawn over. */
+use cgmath::SquareMatrix;
+use std::sync::Arc;
+
+use crate::{
+    camera::Camera,
+    gpu_state::{create_render_pipeline, BindGroupData},
+    post_process::HDR_FORMAT,
+    scene::SkyColors,
+    uploader::Uploader,
+};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    zenith: [f32; 3],
+    _padding: f32,
+    horizon: [f32; 3],
+    _padding2: f32,
+}
+
+pub struct SkyRenderer {
+    group: BindGroupData<SkyUniform>,
+    pipeline: Arc<wgpu::RenderPipeline>,
+}
+impl SkyRenderer {
+    pub fn new(device: &wgpu::Device, camera: &Camera) -> Self {
+        let mut uniform = SkyUniform {
+            inverse_view_proj: [[0.0; 4]; 4],
+            zenith: [0.0; 3],
+            _padding: 0.0,
+            horizon: [0.0; 3],
+            _padding2: 0.0,
+        };
+        set_camera(&mut uniform, camera);
+        let group = BindGroupData::<SkyUniform>::new(
+            uniform,
+            device,
+            "Sky",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::FRAGMENT,
+        );
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[&group.layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_render_pipeline(
+            "Sky",
+            device,
+            &layout,
+            HDR_FORMAT,
+            None,
+            Some(wgpu::BlendState::REPLACE),
+            &[],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Sky Shaders"),
+                source: wgpu::ShaderSource::Wgsl(
+                    concat!(include_str!("fullscreen.wgsl"), include_str!("sky.wgsl")).into(),
+                ),
+            },
+            "vs_fullscreen",
+            Some("fs_sky"),
+        );
+        SkyRenderer { group, pipeline }
+    }
+    pub fn update_camera(&mut self, uploader: &mut Uploader, camera: &Camera) {
+        set_camera(&mut self.group.uniform, camera);
+        self.write(uploader);
+    }
+    pub fn set_colors(&mut self, uploader: &mut Uploader, colors: SkyColors) {
+        let uniform = &mut self.group.uniform;
+        if (colors.zenith, colors.horizon) == (uniform.zenith, uniform.horizon) {
+            return;
+        }
+        uniform.zenith = colors.zenith;
+        uniform.horizon = colors.horizon;
+        self.write(uploader);
+    }
+    fn write(&self, uploader: &mut Uploader) {
+        uploader.upload(&self.group.buffer, 0, &[self.group.uniform]);
+    }
+    /// Overwrites all of `hdr_view` with the sky.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, hdr_view: &wgpu::TextureView) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Sky Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: hdr_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.group.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+fn set_camera(uniform: &mut SkyUniform, camera: &Camera) {
+    let inverse = camera.build_view_projection_matrix().invert();
+    uniform.inverse_view_proj = inverse.unwrap_or(cgmath::Matrix4::identity()).into();
+}
@@ -0,0 +1,20 @@
+// Sky gradient behind the scene, from the horizon color up to the zenith color by the height of
+// each pixel's view ray.
+struct Sky {
+    inverse_view_proj: mat4x4<f32>,
+    zenith: vec3<f32>,
+    horizon: vec3<f32>,
+}
+@group(0) @binding(0)
+var<uniform> sky: Sky;
+
+@fragment
+fn fs_sky(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let ndc = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    let near = sky.inverse_view_proj * vec4<f32>(ndc, 0.0, 1.0);
+    let far = sky.inverse_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    let ray = normalize(far.xyz / far.w - near.xyz / near.w);
+    // Below the horizon stays the horizon color, which is also the fog's.
+    let height = sqrt(clamp(ray.y, 0.0, 1.0));
+    return vec4<f32>(mix(sky.horizon, sky.zenith, height), 1.0);
+}
@@ -0,0 +1,251 @@
+/* Ssao: screen-space ambient occlusion from the depth buffer, multiplied into the HDR target. */
+use crate::{
+    camera::Camera,
+    gpu_state::{create_render_pipeline, BindGroupData},
+    post_process::HDR_FORMAT,
+    render_graph::{RenderGraph, Resource, TextureDesc, TextureId, TextureSize, TransientTextures},
+    uploader::Uploader,
+};
+
+use cgmath::SquareMatrix;
+use std::sync::Arc;
+
+const AO_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SsaoUniform {
+    pub projection: [[f32; 4]; 4],
+    pub inverse_projection: [[f32; 4]; 4],
+    // View-space radius of the sampled hemisphere.
+    pub radius: f32,
+    // Depth difference below which a sample doesn't count as occluded, to avoid self-shadowing.
+    pub bias: f32,
+    // Exponent applied to the final AO term.
+    pub intensity: f32,
+    _padding: f32,
+}
+impl SsaoUniform {
+    pub fn new() -> Self {
+        let identity: [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
+        SsaoUniform {
+            projection: identity,
+            inverse_projection: identity,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.5,
+            _padding: 0.0,
+        }
+    }
+}
+impl Default for SsaoUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Ssao {
+    pub enabled: bool,
+    depth_layout: wgpu::BindGroupLayout,
+    params: BindGroupData<SsaoUniform>,
+    depth_bind_group: wgpu::BindGroup,
+    // Occlusion is computed at half resolution; the apply pass upsamples it bilinearly.
+    ao: TextureId,
+    ssao_pipeline: Arc<wgpu::RenderPipeline>,
+    apply_pipeline: Arc<wgpu::RenderPipeline>,
+}
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        textures: &mut TransientTextures,
+        depth_view: &wgpu::TextureView,
+    ) -> Self {
+        let depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Depth,
+                },
+                count: None,
+            }],
+            label: Some("ssao_depth_bind_group_layout"),
+        });
+        let params = BindGroupData::<SsaoUniform>::new(
+            SsaoUniform::new(),
+            device,
+            "SSAO",
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::FRAGMENT,
+        );
+        // The AO pass can't have its own target bound for sampling, so only the apply pass gets
+        // group 2.
+        let ssao_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSAO Pipeline Layout"),
+            bind_group_layouts: &[&depth_layout, &params.layout],
+            push_constant_ranges: &[],
+        });
+        let apply_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Apply SSAO Pipeline Layout"),
+            bind_group_layouts: &[&depth_layout, &params.layout, textures.sampled_layout()],
+            push_constant_ranges: &[],
+        });
+        let shader = || wgpu::ShaderModuleDescriptor {
+            label: Some("SSAO Shaders"),
+            source: wgpu::ShaderSource::Wgsl(
+                concat!(include_str!("fullscreen.wgsl"), include_str!("ssao.wgsl")).into(),
+            ),
+        };
+        let ssao_pipeline = create_render_pipeline(
+            "SSAO",
+            device,
+            &ssao_layout,
+            AO_FORMAT,
+            None,
+            Some(wgpu::BlendState::REPLACE),
+            &[],
+            shader(),
+            "vs_fullscreen",
+            Some("fs_ssao"),
+        );
+        // dst = dst * src, i.e. darken the lit scene by the occlusion term.
+        let multiply = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::Src,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        };
+        let apply_pipeline = create_render_pipeline(
+            "Apply SSAO",
+            device,
+            &apply_layout,
+            HDR_FORMAT,
+            None,
+            Some(multiply),
+            &[],
+            shader(),
+            "vs_fullscreen",
+            Some("fs_apply_ssao"),
+        );
+        let depth_bind_group = Self::create_depth_bind_group(device, &depth_layout, depth_view);
+        let ao = textures.declare(
+            device,
+            TextureDesc {
+                label: "AO Target",
+                format: AO_FORMAT,
+                size: TextureSize::SurfaceFraction(2),
+            },
+        );
+        Ssao {
+            enabled: true,
+            depth_layout,
+            params,
+            depth_bind_group,
+            ao,
+            ssao_pipeline,
+            apply_pipeline,
+        }
+    }
+    fn create_depth_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        depth_view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SSAO Depth Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(depth_view),
+            }],
+        })
+    }
+    /// Must be called whenever the depth texture is recreated.
+    pub fn resize(&mut self, device: &wgpu::Device, depth_view: &wgpu::TextureView) {
+        self.depth_bind_group =
+            Self::create_depth_bind_group(device, &self.depth_layout, depth_view);
+    }
+    pub fn update_projection(&mut self, uploader: &mut Uploader, camera: &Camera) {
+        let projection = camera.build_projection_matrix();
+        self.params.uniform.projection = projection.into();
+        self.params.uniform.inverse_projection =
+            projection.invert().unwrap_or(cgmath::Matrix4::identity()).into();
+        uploader.upload(&self.params.buffer, 0, &[self.params.uniform]);
+    }
+    /// Adds passes that compute occlusion from `depth` and multiply it into `hdr`.
+    pub fn add_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        textures: &'a TransientTextures,
+        hdr: TextureId,
+        depth: Resource,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        graph.add_pass("SSAO Pass", &[depth], &[Resource::Texture(self.ao)], move |encoder| {
+            self.encode_pass(
+                encoder,
+                "SSAO Pass",
+                &self.ssao_pipeline,
+                textures.view(self.ao),
+                None,
+            )
+        });
+        graph.add_pass(
+            "Apply SSAO Pass",
+            &[depth, Resource::Texture(self.ao), Resource::Texture(hdr)],
+            &[Resource::Texture(hdr)],
+            move |encoder| {
+                self.encode_pass(
+                    encoder,
+                    "Apply SSAO Pass",
+                    &self.apply_pipeline,
+                    textures.view(hdr),
+                    Some(textures.bind_group(self.ao)),
+                )
+            },
+        );
+    }
+    // Without `ao` this is the occlusion pass, which overwrites its target. With it, this is the
+    // apply pass, which blends onto the target's existing contents.
+    fn encode_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        target: &wgpu::TextureView,
+        ao: Option<&wgpu::BindGroup>,
+    ) {
+        let load = match ao {
+            Some(_) => wgpu::LoadOp::Load,
+            None => wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+        };
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &self.depth_bind_group, &[]);
+        pass.set_bind_group(1, &self.params.bind_group, &[]);
+        if let Some(ao) = ao {
+            pass.set_bind_group(2, ao, &[]);
+        }
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,83 @@
+struct SsaoParams {
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+}
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+@group(1) @binding(0)
+var<uniform> params: SsaoParams;
+// Only bound for the apply pass.
+@group(2) @binding(0)
+var t_ao: texture_2d<f32>;
+@group(2) @binding(1)
+var s_ao: sampler;
+
+const NumSamples = 16;
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+fn view_position(uv: vec2<f32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let coord = clamp(vec2<i32>(uv * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    let depth = textureLoad(t_depth, coord, 0);
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let view = params.inverse_projection * ndc;
+    return view.xyz / view.w;
+}
+
+@fragment
+fn fs_ssao(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let position = view_position(in.uv);
+    // There's no G-buffer, so the normal comes from the depth buffer's screen-space derivatives.
+    // These have to be taken before any non-uniform control flow.
+    let normal = normalize(cross(dpdy(position), dpdx(position)));
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let depth = textureLoad(t_depth, vec2<i32>(in.uv * size), 0);
+    if depth >= 1.0 {
+        // Nothing was drawn here.
+        return vec4<f32>(1.0);
+    }
+
+    // Rotate the sample kernel randomly per pixel, trading banding for noise.
+    let random = vec3<f32>(hash(in.clip_position.xy), hash(in.clip_position.yx + 17.0), 0.0) * 2.0 - 1.0;
+    let tangent = normalize(random - normal * dot(random, normal));
+    let bitangent = cross(normal, tangent);
+
+    var occlusion = 0.0;
+    for (var i = 0; i < NumSamples; i += 1) {
+        let fi = f32(i);
+        let phi = 6.2831853 * hash(vec2<f32>(fi, 1.3));
+        let cos_theta = hash(vec2<f32>(fi, 7.1));
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        // Concentrate samples near the center of the hemisphere.
+        let t = fi / f32(NumSamples);
+        let scale = mix(0.1, 1.0, t * t);
+        let hemisphere = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta) * scale;
+        let offset = tangent * hemisphere.x + bitangent * hemisphere.y + normal * hemisphere.z;
+        let sample_position = position + offset * params.radius;
+
+        let clip = params.projection * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let sample_uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        let scene_position = view_position(sample_uv);
+        // Ignore occluders far outside the sampling radius, e.g. foreground edges.
+        let range = smoothstep(0.0, 1.0, params.radius / abs(position.z - scene_position.z));
+        if scene_position.z >= sample_position.z + params.bias {
+            occlusion += range;
+        }
+    }
+    let ao = pow(1.0 - occlusion / f32(NumSamples), params.intensity);
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
+
+// Drawn over the HDR target with multiplicative blending.
+@fragment
+fn fs_apply_ssao(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let ao = textureSample(t_ao, s_ao, in.uv).r;
+    return vec4<f32>(ao, ao, ao, 1.0);
+}
@@ -0,0 +1,57 @@
+/* Static batching: a level's static cubes merged into one pre-transformed mesh per shader, so
+ * the renderer draws a single instance for all of them. */
+use crate::{
+    game_state::{Instance, Shader, StaticGeometry},
+    model::{self, Model, ModelVertex},
+    transform::Transform,
+};
+
+use cgmath::Vector3;
+
+// What static cubes have to share to be baked into one model: shader, tint and tile layer.
+type GroupKey = (Shader, [f32; 4], u32);
+
+// Each baked model is drawn as this one instance, which leaves its vertices where they are.
+fn baked_instance(shader: Shader, tint: [f32; 4], layer: u32) -> Instance {
+    Instance { transform: Transform::identity(), shader, tint, layer }
+}
+
+/// One model per shader, tint and tile layer, holding every static cube drawn with them, already
+/// moved, turned and scaled into place. Returned with the instance to draw each with.
+pub fn bake(device: &wgpu::Device, geometry: &StaticGeometry) -> Vec<(Model, Instance)> {
+    // Shaders, tints and layers in order of first appearance, with the instances that use them.
+    let mut groups: Vec<(GroupKey, Vec<&Instance>)> = Vec::new();
+    for instance in &geometry.instances {
+        let key = (instance.shader, instance.tint, instance.layer);
+        match groups.iter_mut().find(|(group_key, _)| *group_key == key) {
+            Some((_, instances)) => instances.push(instance),
+            None => groups.push((key, vec![instance])),
+        }
+    }
+    let cube = model::cube_mesh(device, false);
+    groups
+        .into_iter()
+        .map(|((shader, tint, layer), instances)| {
+            let mut vertices = Vec::with_capacity(instances.len() * cube.raw_vertices.len());
+            let mut indices = Vec::with_capacity(instances.len() * cube.raw_indices.len());
+            for instance in instances {
+                let base = vertices.len() as u32;
+                let transform = instance.transform;
+                vertices.extend(cube.raw_vertices.iter().map(|vertex| ModelVertex {
+                    position: transform.transform_point(Vector3::from(vertex.position)).into(),
+                    tex_coords: vertex.tex_coords,
+                    normal: transform.transform_normal(vertex.normal.into()).into(),
+                }));
+                indices.extend(cube.raw_indices.iter().map(|index| base + index));
+            }
+            let name = format!("Static {:?}", shader);
+            let model = Model {
+                meshes: vec![model::create_mesh(device, &name, vertices, indices)],
+                materials: vec![],
+                sources: vec![],
+                lods: vec![],
+            };
+            (model, baked_instance(shader, tint, layer))
+        })
+        .collect()
+}
@@ -0,0 +1,212 @@
+/* StatsHud: render and game timing, counted on the render thread and drawn in a corner of the
+ * screen when toggled with F3. */
+use std::time::{Duration, Instant};
+
+use crate::{
+    frame_times::{FrameTimes, Histogram, GRAPH_FRAMES},
+    gpu_errors,
+    gpu_memory::{self, Category},
+    strings::{Language, StringTable},
+    tick_rate,
+    ui::UiLayer,
+};
+
+// The counts are turned into rates once per this long, so the numbers are readable.
+const WINDOW: Duration = Duration::from_secs(1);
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 0.3, 1.0];
+const OVER_BUDGET_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+const BACKDROP_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const GRAPH_COLOR: [f32; 4] = [0.3, 0.9, 0.3, 1.0];
+const HITCH_COLOR: [f32; 4] = [1.0, 0.3, 0.3, 1.0];
+// The frame time graph's height, in lines of text. Its top is twice the hitch time, so the hitch
+// line runs across the middle.
+const GRAPH_LINES: f32 = 4.0;
+
+/// Counts frames, game ticks and snapshots, and shows their rates over the last whole second.
+///
+/// The game tick rate is of the simulation itself, so it reads 0 in the menus. The interpolation
+/// alpha is how far into the next tick each frame is drawn, averaged: what a renderer
+/// interpolating between snapshots would blend by. Dropped snapshots are those the game thread
+/// sent that were replaced by a newer one before they could be drawn, counted since startup. GPU
+/// memory is what the engine's buffers and textures take up, in total and by category, with the
+/// total in red while it is over budget.
+///
+/// Above the stats, a graph of the latest frame times, with a line at the time past which a frame
+/// is a hitch. Frame and tick times are also shown as the buckets of their histograms the median
+/// and the 99th percentile fall in.
+pub struct StatsHud {
+    visible: bool,
+    window_start: Instant,
+    frames: u32,
+    alpha_sum: f32,
+    // The game's tick at the start of the window, and the latest one seen.
+    start_tick: Option<isize>,
+    tick: Option<isize>,
+    fps: f32,
+    tick_rate: f32,
+    alpha: f32,
+    dropped: u64,
+    strings: StringTable,
+}
+impl StatsHud {
+    pub fn new(now: Instant) -> Self {
+        StatsHud {
+            visible: false,
+            window_start: now,
+            frames: 0,
+            alpha_sum: 0.0,
+            start_tick: None,
+            tick: None,
+            fps: 0.0,
+            tick_rate: 0.0,
+            alpha: 0.0,
+            dropped: 0,
+            strings: StringTable::new(),
+        }
+    }
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+    pub fn set_language(&mut self, language: Language) {
+        self.strings = StringTable::load_language(language);
+    }
+    /// Counts the snapshots taken off the channel for one frame, the newest of which is at game
+    /// tick `tick`.
+    pub fn record_snapshots(&mut self, received: usize, tick: isize) {
+        self.dropped += received.saturating_sub(1) as u64;
+        self.start_tick.get_or_insert(tick);
+        self.tick = Some(tick);
+    }
+    /// Counts a frame drawn at `now` from the snapshot made at `made_at`, while the game's time
+    /// passed at `time_scale` times real time.
+    pub fn record_frame(&mut self, now: Instant, made_at: Option<Instant>, time_scale: f32) {
+        self.frames += 1;
+        if let Some(made_at) = made_at {
+            let since = now.saturating_duration_since(made_at).as_secs_f32() * time_scale;
+            self.alpha_sum += (since / tick_rate::tick_secs()).min(1.0);
+        }
+        let elapsed = now - self.window_start;
+        if elapsed < WINDOW {
+            return;
+        }
+        let seconds = elapsed.as_secs_f32();
+        self.fps = self.frames as f32 / seconds;
+        self.alpha = self.alpha_sum / self.frames as f32;
+        self.tick_rate = match (self.start_tick, self.tick) {
+            (Some(start), Some(tick)) => (tick - start) as f32 / seconds,
+            _ => 0.0,
+        };
+        self.window_start = now;
+        self.frames = 0;
+        self.alpha_sum = 0.0;
+        self.start_tick = self.tick;
+    }
+    /// The stats on a backdrop in the bottom-left corner of a window `height` pixels tall, with
+    /// the frame time graph above them.
+    pub fn draw(&self, height: f32, frame_times: &FrameTimes) -> UiLayer {
+        let mut ui = UiLayer::new();
+        let scale = UiLayer::scale_for(height);
+        let mut lines = vec![
+            format!("{} {:.0}", self.strings.get("hud.fps", "FPS"), self.fps),
+            format!("{} {:.0}", self.strings.get("hud.tick_rate", "Tick rate"), self.tick_rate),
+            format!("{} {:.2}", self.strings.get("hud.alpha", "Alpha"), self.alpha),
+            format!("{} {}", self.strings.get("hud.dropped", "Dropped"), self.dropped),
+        ];
+        let percentiles = |histogram: &Histogram| {
+            let percentile = |fraction| histogram.percentile(fraction).unwrap_or_default();
+            format!("p50 {} p99 {}", percentile(0.5), percentile(0.99))
+        };
+        lines.push(format!(
+            "{} {}",
+            self.strings.get("hud.frame_time", "Frame time"),
+            percentiles(&frame_times.frames)
+        ));
+        lines.push(format!(
+            "{} {}",
+            self.strings.get("hud.tick_time", "Tick time"),
+            percentiles(&frame_times.ticks)
+        ));
+        let hitches_line = lines.len();
+        lines.push(format!(
+            "{} {}",
+            self.strings.get("hud.hitches", "Hitches"),
+            frame_times.hitches()
+        ));
+        let memory_line = lines.len();
+        lines.push(format!(
+            "{} {} MB",
+            self.strings.get("hud.gpu_memory", "GPU memory"),
+            gpu_memory::megabytes(gpu_memory::total())
+        ));
+        for category in Category::ALL {
+            let label =
+                self.strings.get(&format!("hud.memory.{}", category.key()), category.label());
+            let megabytes = gpu_memory::megabytes(gpu_memory::allocated(category));
+            lines.push(format!("  {} {} MB", label, megabytes));
+        }
+        let line_height = (UiLayer::text_height(scale) * 1.5).round();
+        let margin = UiLayer::text_height(scale);
+        let padding = margin / 2.0;
+        let text_width =
+            lines.iter().map(|line| UiLayer::text_width(line, scale)).fold(0.0, f32::max);
+        let text_height = line_height * (lines.len() - 1) as f32 + UiLayer::text_height(scale);
+        let top = height - margin - text_height - 2.0 * padding;
+        ui.rect(
+            margin,
+            top,
+            text_width + 2.0 * padding,
+            text_height + 2.0 * padding,
+            BACKDROP_COLOR,
+        );
+        for (i, line) in lines.iter().enumerate() {
+            let y = top + padding + i as f32 * line_height;
+            let color = if i == memory_line && gpu_memory::is_over_budget() {
+                OVER_BUDGET_COLOR
+            } else if i == hitches_line && frame_times.hitches() > 0 {
+                HITCH_COLOR
+            } else {
+                TEXT_COLOR
+            };
+            ui.text(margin + padding, y, scale, color, line);
+        }
+        let graph_height = (line_height * GRAPH_LINES).round();
+        draw_graph(&mut ui, margin, top - padding, graph_height, scale as f32, frame_times);
+        ui
+    }
+    /// The latest GPU error, while it is recent, on a banner across the top of a window `width`
+    /// by `height` pixels. Shown whether or not the stats are.
+    pub fn draw_gpu_error(&self, ui: &mut UiLayer, now: Instant, width: f32, height: f32) {
+        let label = self.strings.get("hud.gpu_error", "GPU error");
+        gpu_errors::draw_banner(ui, now, width, UiLayer::scale_for(height), label);
+    }
+}
+
+// The frame time graph on a backdrop with its bottom-left corner at (`left`, `bottom`), a bar
+// `bar_width` pixels wide for each of the latest frames, red for hitches.
+fn draw_graph(
+    ui: &mut UiLayer,
+    left: f32,
+    bottom: f32,
+    height: f32,
+    bar_width: f32,
+    frame_times: &FrameTimes,
+) {
+    let padding = bar_width * 2.0;
+    let width = GRAPH_FRAMES as f32 * bar_width;
+    let (outer_width, outer_height) = (width + 2.0 * padding, height + 2.0 * padding);
+    ui.rect(left, bottom - outer_height, outer_width, outer_height, BACKDROP_COLOR);
+    let (left, bottom) = (left + padding, bottom - padding);
+    let hitch_time = frame_times.frame_hitch_time().as_secs_f32();
+    for (i, time) in frame_times.recent_frames().enumerate() {
+        let time = time.as_secs_f32();
+        let bar_height = (time / (2.0 * hitch_time)).min(1.0) * height;
+        let color = if time > hitch_time { HITCH_COLOR } else { GRAPH_COLOR };
+        let x = left + i as f32 * bar_width;
+        ui.rect(x, bottom - bar_height, bar_width, bar_height, color);
+    }
+    let [r, g, b, _] = HITCH_COLOR;
+    ui.rect(left, bottom - (height / 2.0).round(), width, bar_width, [r, g, b, 0.5]);
+}
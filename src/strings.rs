@@ -0,0 +1,103 @@
+/* Strings: the text shown to the player, looked up by key in the chosen language's string table
+ * with the English text as the fallback. */
+use crate::{log_println, resources};
+
+use anyhow::{anyhow, Context};
+use std::{collections::HashMap, fmt::Display};
+
+pub const LANG_DIR: &str = "lang";
+pub const LANG_EXTENSION: &str = "lang";
+
+/// A language the game can be shown in.
+///
+/// English is written into the code, next to where each string is used, so it needs no table.
+/// The built-in font has no accented letters, so translations spell them out as plain ASCII, like
+/// "ae" for "ä".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Language {
+    English,
+    German,
+}
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::German];
+    /// The language's code, which names its tables: `lang/de.lang`, and `levels/<pack>.de.lang`
+    /// for a level pack's.
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::German => "de",
+        }
+    }
+    /// The language's name for itself, so players can find theirs whatever is showing.
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "Deutsch",
+        }
+    }
+}
+
+/// Strings by key. Text may have `{0}`, `{1}` and so on in it, for `format` to fill in, in
+/// whatever order the language needs.
+///
+/// The file format is one string per line: its key, `=`, then the text to the end of the line.
+/// Lines starting with `#` are comments.
+#[derive(Clone, Debug, Default)]
+pub struct StringTable {
+    strings: HashMap<String, String>,
+}
+impl StringTable {
+    pub fn new() -> Self {
+        StringTable::default()
+    }
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut table = StringTable::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, text) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected key = text"))
+                .with_context(|| format!("strings line {}", line_number + 1))?;
+            table.strings.insert(key.trim().to_string(), text.trim().to_string());
+        }
+        Ok(table)
+    }
+    /// A missing file means an empty table.
+    pub fn load(file_name: &str) -> anyhow::Result<Self> {
+        if !resources::exists(file_name) {
+            return Ok(StringTable::new());
+        }
+        let text = pollster::block_on(resources::load_string(file_name))?;
+        Self::parse(&text).with_context(|| format!("parsing strings {}", file_name))
+    }
+    /// The game's strings in `language`, or an empty table if they fail to load.
+    pub fn load_language(language: Language) -> Self {
+        if language == Language::English {
+            return StringTable::new();
+        }
+        let file_name = format!("{}/{}.{}", LANG_DIR, language.code(), LANG_EXTENSION);
+        Self::load(&file_name).unwrap_or_else(|e| {
+            log_println!("Falling back to English: {:?}", e);
+            StringTable::new()
+        })
+    }
+    /// Adds `other`'s strings, replacing any with the same key.
+    pub fn extend(&mut self, other: StringTable) {
+        self.strings.extend(other.strings);
+    }
+    /// The text for `key`, or `english` if the table doesn't have it.
+    pub fn get<'a>(&'a self, key: &str, english: &'a str) -> &'a str {
+        self.strings.get(key).map_or(english, String::as_str)
+    }
+    /// The text for `key`, or `english`, with `{i}` replaced by `args[i]`.
+    pub fn format(&self, key: &str, english: &str, args: &[&dyn Display]) -> String {
+        let mut text = self.get(key, english).to_string();
+        for (i, arg) in args.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", i), &arg.to_string());
+        }
+        text
+    }
+}
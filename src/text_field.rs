@@ -0,0 +1,62 @@
+/* Text field: a line of text typed in a character at a time, as WM_CHAR and the IME deliver
+ * them, for things like naming a level. */
+
+/// The keys that edit a text field rather than type into it. These come as key presses, as
+/// their characters could arrive after the press that focused the field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextKey {
+    Backspace,
+    // Keeps the text and stops typing into the field.
+    Enter,
+    // Puts back the text from before the field was focused, and stops typing into it.
+    Escape,
+}
+
+/// A single line of text and whether it's being typed into. While it is, the keys type rather
+/// than play.
+#[derive(Default)]
+pub struct TextField {
+    text: String,
+    // The text from before it was focused, to go back to if editing is cancelled. None while
+    // it isn't focused.
+    original: Option<String>,
+    // The most characters it takes.
+    max_chars: usize,
+}
+impl TextField {
+    pub fn new(text: &str, max_chars: usize) -> Self {
+        TextField { text: text.to_string(), original: None, max_chars }
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    pub fn is_focused(&self) -> bool {
+        self.original.is_some()
+    }
+    /// Starts typing into the field.
+    pub fn focus(&mut self) {
+        if self.original.is_none() {
+            self.original = Some(self.text.clone());
+        }
+    }
+    /// Adds a typed character to the end of the text. Control characters, which Enter,
+    /// Backspace and Ctrl+letter type, are ignored: the editing keys come through `press`.
+    pub fn type_char(&mut self, c: char) {
+        if self.is_focused() && !c.is_control() && self.text.chars().count() < self.max_chars {
+            self.text.push(c);
+        }
+    }
+    pub fn press(&mut self, key: TextKey) {
+        let Some(original) = self.original.take() else {
+            return;
+        };
+        match key {
+            TextKey::Backspace => {
+                self.text.pop();
+                self.original = Some(original);
+            }
+            TextKey::Enter => {}
+            TextKey::Escape => self.text = original,
+        }
+    }
+}
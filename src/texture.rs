@@ -1,9 +1,13 @@
 use anyhow::Result;
 use image::GenericImageView;
+
+use crate::gpu_memory::Allocation;
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
+    pub memory: Allocation,
 }
 impl Texture {
     pub fn from_bytes(
@@ -21,22 +25,35 @@ impl Texture {
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
         label: Option<&str>,
+    ) -> Result<Self> {
+        Self::from_image_with_format(device, queue, img, label, wgpu::TextureFormat::Rgba8UnormSrgb)
+    }
+
+    /// Normal maps and other data that isn't color should be Rgba8Unorm, so sampling doesn't
+    /// convert from sRGB.
+    pub fn from_image_with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
         let size =
             wgpu::Extent3d { width: dimensions.0, height: dimensions.1, depth_or_array_layers: 1 };
-        let texture = device.create_texture(&wgpu::TextureDescriptor {
+        let desc = wgpu::TextureDescriptor {
             label,
             size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
             view_formats: &[],
-        });
+        };
+        let texture = device.create_texture(&desc);
 
         queue.write_texture(
             wgpu::ImageCopyTexture {
@@ -55,20 +72,86 @@ impl Texture {
         );
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+        let sampler = create_sampler(device);
+
+        Ok(Self { texture, view, sampler, memory: Allocation::texture(&desc) })
+    }
+
+    /// One layer per image, viewed as a 2D array. Images are scaled to the size of the first.
+    pub fn array_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        label: Option<&str>,
+        format: wgpu::TextureFormat,
+    ) -> Result<Self> {
+        let Some(first) = images.first() else {
+            anyhow::bail!("a texture array needs at least one layer");
+        };
+        let (width, height) = first.dimensions();
+        let size = wgpu::Extent3d { width, height, depth_or_array_layers: images.len() as u32 };
+        let desc = wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        for (layer, img) in images.iter().enumerate() {
+            let rgba = if img.dimensions() == (width, height) {
+                img.to_rgba8()
+            } else {
+                image::imageops::resize(
+                    &img.to_rgba8(),
+                    width,
+                    height,
+                    image::imageops::FilterType::Triangle,
+                )
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+                },
+                &rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            );
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
             ..Default::default()
         });
+        let sampler = create_sampler(device);
 
-        Ok(Self { texture, view, sampler })
+        Ok(Self { texture, view, sampler, memory: Allocation::texture(&desc) })
     }
 }
 
+fn create_sampler(device: &wgpu::Device) -> wgpu::Sampler {
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    })
+}
+
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 pub fn create_depth_texture(
     device: &wgpu::Device,
@@ -88,7 +171,9 @@ pub fn create_depth_texture(
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: DEPTH_FORMAT,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+            | wgpu::TextureUsages::TEXTURE_BINDING
+            | wgpu::TextureUsages::COPY_SRC,
         view_formats: &[DEPTH_FORMAT],
     };
     let texture = device.create_texture(&desc);
@@ -110,5 +195,5 @@ pub fn create_depth_texture(
         lod_max_clamp: 100.0,
         ..Default::default()
     });
-    Texture { texture, view, sampler }
+    Texture { texture, view, sampler, memory: Allocation::texture(&desc) }
 }
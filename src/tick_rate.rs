@@ -0,0 +1,52 @@
+/* Tick rate: how many times a second the game simulates, picked at startup, and how long things
+ * timed in seconds last in ticks at that rate. */
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use anyhow::bail;
+
+use crate::constants::DEFAULT_GAME_TICKS_PER_SECOND;
+
+// Below this, fast movement skips through walls between ticks. Above it, a tick's work may not fit
+// in its time.
+const MIN_TICKS_PER_SECOND: u32 = 20;
+const MAX_TICKS_PER_SECOND: u32 = 1000;
+
+static TICKS_PER_SECOND: AtomicU32 = AtomicU32::new(DEFAULT_GAME_TICKS_PER_SECOND);
+
+pub fn ticks_per_second() -> u32 {
+    TICKS_PER_SECOND.load(Ordering::Relaxed)
+}
+
+/// Runs the simulation at `rate` ticks per second. Set it before any game starts: games step by a
+/// tick's time, so ones at different rates end up in different places from the same input, and
+/// replays and network sessions only agree at the same rate.
+pub fn set_ticks_per_second(rate: u32) -> anyhow::Result<()> {
+    if !(MIN_TICKS_PER_SECOND..=MAX_TICKS_PER_SECOND).contains(&rate) {
+        bail!(
+            "{} ticks per second is outside {}..={}",
+            rate,
+            MIN_TICKS_PER_SECOND,
+            MAX_TICKS_PER_SECOND
+        );
+    }
+    TICKS_PER_SECOND.store(rate, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The game time each tick moves the simulation on by.
+pub fn time_per_tick() -> Duration {
+    Duration::from_secs_f32(1.0 / ticks_per_second() as f32)
+}
+
+/// `time_per_tick` in seconds, the delta_t of every update.
+pub fn tick_secs() -> f32 {
+    time_per_tick().as_secs_f32()
+}
+
+/// The number of ticks closest to `secs`, and at least one, for things counted in ticks.
+pub fn ticks(secs: f32) -> u32 {
+    ((secs * ticks_per_second() as f32).round() as u32).max(1)
+}
@@ -0,0 +1,67 @@
+/* Toasts: short messages, like "Progress saved", shown at the top of the screen for a few
+ * seconds. */
+use std::collections::VecDeque;
+
+use crate::ui::UiLayer;
+
+// How long a toast stays up, and how much of that it spends fading out, in seconds.
+const TOAST_SECS: f32 = 3.0;
+const FADE_SECS: f32 = 0.5;
+// Past this many, the oldest toast makes way for a new one.
+const MAX_TOASTS: usize = 4;
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const BACKDROP_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.7];
+
+struct Toast {
+    text: String,
+    // Seconds left before it's gone.
+    remaining: f32,
+}
+
+/// The toasts on screen, oldest first. Advanced once per tick, in the menus as well as in play, by
+/// real time rather than the game's, so slow motion doesn't keep them up longer.
+#[derive(Default)]
+pub struct Toasts {
+    toasts: VecDeque<Toast>,
+}
+impl Toasts {
+    pub fn new() -> Self {
+        Toasts::default()
+    }
+    pub fn push(&mut self, text: impl Into<String>) {
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast { text: text.into(), remaining: TOAST_SECS });
+    }
+    /// Counts `delta_t` seconds off every toast.
+    pub fn update(&mut self, delta_t: f32) {
+        for toast in &mut self.toasts {
+            toast.remaining -= delta_t;
+        }
+        self.toasts.retain(|toast| toast.remaining > 0.0);
+    }
+    /// Stacks the toasts down from the top center of a window `width` pixels wide, each on a
+    /// backdrop, fading out as they expire.
+    pub fn draw(&self, ui: &mut UiLayer, width: f32, scale: u32) {
+        let text_height = UiLayer::text_height(scale);
+        let padding = (text_height / 2.0).round();
+        let mut y = text_height * 2.0;
+        for toast in &self.toasts {
+            let opacity = (toast.remaining / FADE_SECS).min(1.0);
+            let fade = |[r, g, b, a]: [f32; 4]| [r, g, b, a * opacity];
+            let text_width = UiLayer::text_width(&toast.text, scale);
+            let x = ((width - text_width) / 2.0).round();
+            let backdrop_height = text_height + 2.0 * padding;
+            ui.rect(
+                x - padding,
+                y,
+                text_width + 2.0 * padding,
+                backdrop_height,
+                fade(BACKDROP_COLOR),
+            );
+            ui.text(x, y + padding, scale, fade(TEXT_COLOR), &toast.text);
+            y += backdrop_height + padding;
+        }
+    }
+}
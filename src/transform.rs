@@ -0,0 +1,129 @@
+/* Transform: where something is, how it is turned and how big it is, as one value. */
+use cgmath::{BaseFloat, Matrix4, Vector3, VectorSpace, Zero};
+use std::ops::Mul;
+
+use crate::{motor::Motor, rotor::Rotor};
+
+/// Scales, then rotates, then moves, as the vertex shader places an instance's vertices. The
+/// scale is the same along every axis, so composing two transforms gives another one. Generic over
+/// the float type, like `Rotor`.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform<S = f32> {
+    pub position: Vector3<S>,
+    pub rotation: Rotor<S>,
+    pub scale: S,
+}
+impl<S: BaseFloat> Transform<S> {
+    pub fn new(position: Vector3<S>, rotation: Rotor<S>, scale: S) -> Self {
+        Transform { position, rotation, scale }
+    }
+    pub fn identity() -> Self {
+        Transform::new(Vector3::zero(), Rotor::identity(), S::one())
+    }
+    pub fn from_position(position: Vector3<S>) -> Self {
+        Transform { position, ..Transform::identity() }
+    }
+    /// Scales by `scale`, then moves as `motor` does.
+    pub fn from_motor(motor: Motor<S>, scale: S) -> Self {
+        Transform::new(motor.translation(), motor.rotation(), scale)
+    }
+    /// The turn and move, without the scale.
+    pub fn motor(&self) -> Motor<S> {
+        Motor::new(self.rotation, self.position)
+    }
+    pub fn transform_point(&self, point: Vector3<S>) -> Vector3<S> {
+        self.rotation.rotate_vector(point * self.scale) + self.position
+    }
+    /// Scales and rotates `vector`, without moving it.
+    pub fn transform_vector(&self, vector: Vector3<S>) -> Vector3<S> {
+        self.rotation.rotate_vector(vector * self.scale)
+    }
+    /// Only rotates `normal`: the scale is uniform, so it doesn't change a normal's direction.
+    pub fn transform_normal(&self, normal: Vector3<S>) -> Vector3<S> {
+        self.rotation.rotate_vector(normal)
+    }
+    /// Undoes the transform. The scale must not be 0.
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.inverse();
+        let scale = S::one() / self.scale;
+        Transform { position: -(rotation.rotate_vector(self.position) * scale), rotation, scale }
+    }
+    /// Partway from `self` at 0 to `other` at 1, each part blended on its own.
+    pub fn lerp(&self, other: &Self, t: S) -> Self {
+        Transform {
+            position: self.position.lerp(other.position, t),
+            rotation: self.rotation.nlerp(&other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+    pub fn to_matrix(&self) -> Matrix4<S> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation.to_matrix())
+            * Matrix4::from_scale(self.scale)
+    }
+}
+/// `parent * child` places something in `parent`'s space that `child` places in its own.
+impl<S: BaseFloat> Mul for Transform<S> {
+    type Output = Self;
+    fn mul(self, child: Self) -> Self {
+        // Scaling is uniform, so the parent's can be moved past the child's motion by scaling how
+        // far the child moves, leaving the motors to compose.
+        let child_motor = Motor::new(child.rotation, child.position * self.scale);
+        Transform::from_motor(self.motor() * child_motor, self.scale * child.scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, InnerSpace};
+
+    fn assert_vectors_close(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 0.0001, "{a:?} != {b:?}");
+    }
+    fn transforms() -> [Transform; 3] {
+        let axis = Vector3::new(1.0, 2.0, -2.0).normalize();
+        [
+            Transform::new(
+                Vector3::new(1.0, -2.0, 3.0),
+                Rotor::from_axis_angle(axis, Deg(70.0)),
+                2.0,
+            ),
+            Transform::new(
+                Vector3::unit_x(),
+                Rotor::from_axis_angle(Vector3::unit_y(), Deg(-135.0)),
+                0.5,
+            ),
+            Transform::from_position(Vector3::new(-4.0, 0.5, 0.0)),
+        ]
+    }
+
+    #[test]
+    fn composing_places_the_child_in_the_parent() {
+        let p = Vector3::new(0.5, -1.0, 2.0);
+        for parent in transforms() {
+            for child in transforms() {
+                let composed = parent * child;
+                assert_vectors_close(
+                    composed.transform_point(p),
+                    parent.transform_point(child.transform_point(p)),
+                );
+                assert_vectors_close(
+                    composed.transform_vector(p),
+                    parent.transform_vector(child.transform_vector(p)),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let p = Vector3::new(0.5, -1.0, 2.0);
+        for transform in transforms() {
+            let inverse = transform.inverse();
+            assert_vectors_close(inverse.transform_point(transform.transform_point(p)), p);
+            assert_vectors_close((transform * inverse).transform_point(p), p);
+            assert_vectors_close((inverse * transform).transform_point(p), p);
+        }
+    }
+}
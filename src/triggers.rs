@@ -0,0 +1,70 @@
+/* Triggers: sensor volumes that report bodies entering and leaving them, without colliding. */
+use crate::{
+    determinism::StateHasher,
+    events::{Body, EventBus, GameEvent},
+    physics::Aabb,
+};
+
+#[derive(Clone)]
+pub struct TriggerVolume {
+    pub id: u32,
+    pub bounds: Aabb,
+    // Bodies overlapping the volume as of the last update.
+    occupants: Vec<Body>,
+}
+impl TriggerVolume {
+    pub fn new(id: u32, bounds: Aabb) -> Self {
+        TriggerVolume { id, bounds, occupants: Vec::new() }
+    }
+    pub fn occupants(&self) -> &[Body] {
+        &self.occupants
+    }
+}
+
+#[derive(Clone)]
+pub struct Triggers {
+    pub volumes: Vec<TriggerVolume>,
+}
+impl Triggers {
+    pub fn new() -> Self {
+        Triggers { volumes: Vec::new() }
+    }
+    pub fn add(&mut self, volume: TriggerVolume) {
+        self.volumes.push(volume);
+    }
+    pub fn remove(&mut self, id: u32) {
+        self.volumes.retain(|volume| volume.id != id);
+    }
+    pub fn get(&self, id: u32) -> Option<&TriggerVolume> {
+        self.volumes.iter().find(|volume| volume.id == id)
+    }
+    /// Overlaps every volume with `bodies` and publishes an event for each body that started or
+    /// stopped overlapping one. Exits are published before enters, volume by volume.
+    pub fn update(&mut self, bodies: &[(Body, Aabb)], events: &mut EventBus) {
+        for volume in &mut self.volumes {
+            let inside = bodies
+                .iter()
+                .filter(|(_, bounds)| volume.bounds.intersects(bounds))
+                .map(|(body, _)| *body)
+                .collect::<Vec<_>>();
+            for body in volume.occupants.iter().filter(|body| !inside.contains(body)) {
+                events.publish(GameEvent::TriggerExited { trigger: volume.id, body: *body });
+            }
+            for body in inside.iter().filter(|body| !volume.occupants.contains(body)) {
+                events.publish(GameEvent::TriggerEntered { trigger: volume.id, body: *body });
+            }
+            volume.occupants = inside;
+        }
+    }
+    pub fn hash_into(&self, hasher: &mut StateHasher) {
+        for volume in &self.volumes {
+            hasher.write_u64(volume.id as u64);
+            hasher.write_u64(volume.occupants.len() as u64);
+        }
+    }
+}
+impl Default for Triggers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
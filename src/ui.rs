@@ -0,0 +1,196 @@
+/* Ui: screen-space rectangles and text, built each tick and drawn over the finished frame. */
+
+// Glyphs are drawn from a built-in 5x7 pixel font. Each row is 5 bits, leftmost pixel highest.
+pub const GLYPH_WIDTH: u32 = 5;
+pub const GLYPH_HEIGHT: u32 = 7;
+// Blank pixels between neighbouring glyphs, in font pixels.
+const GLYPH_SPACING: u32 = 1;
+// Atlas cells are one pixel larger than a glyph on each axis, so sampling never bleeds.
+const CELL_WIDTH: u32 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u32 = GLYPH_HEIGHT + 1;
+// Text is scaled so the screen is roughly this many font pixels tall.
+const UI_PIXELS_PER_SCREEN: f32 = 240.0;
+
+// Lowercase letters are drawn as uppercase. Anything missing is drawn as '?'.
+#[rustfmt::skip]
+const GLYPHS: &[(char, [u8; GLYPH_HEIGHT as usize])] = &[
+    (' ', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000]),
+    ('A', [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('B', [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110]),
+    ('C', [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110]),
+    ('D', [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100]),
+    ('E', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111]),
+    ('F', [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('G', [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111]),
+    ('H', [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001]),
+    ('I', [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('J', [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100]),
+    ('K', [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001]),
+    ('L', [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111]),
+    ('M', [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001]),
+    ('N', [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001]),
+    ('O', [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('P', [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000]),
+    ('Q', [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101]),
+    ('R', [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001]),
+    ('S', [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110]),
+    ('T', [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100]),
+    ('U', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110]),
+    ('V', [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100]),
+    ('W', [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010]),
+    ('X', [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001]),
+    ('Y', [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100]),
+    ('Z', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111]),
+    ('0', [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110]),
+    ('1', [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110]),
+    ('2', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111]),
+    ('3', [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110]),
+    ('4', [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010]),
+    ('5', [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110]),
+    ('6', [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110]),
+    ('7', [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000]),
+    ('8', [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110]),
+    ('9', [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100]),
+    ('.', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100]),
+    (',', [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000]),
+    (':', [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000]),
+    ('!', [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100]),
+    ('?', [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100]),
+    ('-', [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000]),
+    ('+', [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000]),
+    ('/', [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000]),
+    ('(', [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010]),
+    (')', [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000]),
+    ('%', [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011]),
+    ('\'', [0b01100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000]),
+    ('"', [0b01010, 0b01010, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000]),
+    ('<', [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010]),
+    ('>', [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000]),
+    ('_', [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111]),
+    ('=', [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000]),
+    ('[', [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110]),
+    (']', [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110]),
+    ('#', [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010]),
+    ('*', [0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000]),
+];
+
+/// The font as a single-channel texture: one cell per glyph in a row, after a solid cell that
+/// plain rectangles sample from.
+pub struct FontAtlas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+impl FontAtlas {
+    pub fn new() -> Self {
+        let width = (GLYPHS.len() as u32 + 1) * CELL_WIDTH;
+        let mut pixels = vec![0; (width * CELL_HEIGHT) as usize];
+        let mut set = |cell: u32, x: u32, y: u32| {
+            pixels[(y * width + cell * CELL_WIDTH + x) as usize] = 255;
+        };
+        for y in 0..GLYPH_HEIGHT {
+            for x in 0..GLYPH_WIDTH {
+                set(0, x, y);
+            }
+        }
+        for (i, (_, rows)) in GLYPHS.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                for x in (0..GLYPH_WIDTH).filter(|x| row & (1 << (GLYPH_WIDTH - 1 - x)) != 0) {
+                    set(i as u32 + 1, x, y as u32);
+                }
+            }
+        }
+        FontAtlas { width, height: CELL_HEIGHT, pixels }
+    }
+}
+impl Default for FontAtlas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Texture coordinates of atlas cell `cell`'s glyph area.
+fn cell_uv(cell: u32) -> ([f32; 2], [f32; 2]) {
+    let atlas_width = ((GLYPHS.len() as u32 + 1) * CELL_WIDTH) as f32;
+    let left = (cell * CELL_WIDTH) as f32 / atlas_width;
+    let right = (cell * CELL_WIDTH + GLYPH_WIDTH) as f32 / atlas_width;
+    ([left, 0.0], [right, GLYPH_HEIGHT as f32 / CELL_HEIGHT as f32])
+}
+
+fn glyph_cell(c: char) -> u32 {
+    let c = c.to_ascii_uppercase();
+    let index = GLYPHS.iter().position(|&(glyph, _)| glyph == c);
+    let index = index.or_else(|| GLYPHS.iter().position(|&(glyph, _)| glyph == '?'));
+    index.unwrap() as u32 + 1
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct UiQuadRaw {
+    // Top-left corner and size, in pixels from the top left of the window.
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub color: [f32; 4],
+}
+
+/// Everything to draw over one frame, back to front.
+///
+/// Text is drawn at a whole number of screen pixels per font pixel, `scale`, so it stays crisp.
+#[derive(Clone)]
+pub struct UiLayer {
+    quads: Vec<UiQuadRaw>,
+}
+impl UiLayer {
+    pub fn new() -> Self {
+        UiLayer { quads: Vec::new() }
+    }
+    pub fn quads(&self) -> &[UiQuadRaw] {
+        &self.quads
+    }
+    pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        let (uv_min, uv_max) = cell_uv(0);
+        self.quads.push(UiQuadRaw {
+            position: [x, y],
+            size: [width, height],
+            uv_min,
+            uv_max,
+            color,
+        });
+    }
+    /// Draws `text` on one line with its top-left corner at (`x`, `y`). Returns its width.
+    pub fn text(&mut self, x: f32, y: f32, scale: u32, color: [f32; 4], text: &str) -> f32 {
+        let advance = ((GLYPH_WIDTH + GLYPH_SPACING) * scale) as f32;
+        for (i, c) in text.chars().enumerate().filter(|(_, c)| *c != ' ') {
+            let (uv_min, uv_max) = cell_uv(glyph_cell(c));
+            self.quads.push(UiQuadRaw {
+                position: [x + i as f32 * advance, y],
+                size: [(GLYPH_WIDTH * scale) as f32, (GLYPH_HEIGHT * scale) as f32],
+                uv_min,
+                uv_max,
+                color,
+            });
+        }
+        Self::text_width(text, scale)
+    }
+    pub fn text_width(text: &str, scale: u32) -> f32 {
+        let count = text.chars().count() as u32;
+        if count == 0 {
+            return 0.0;
+        }
+        ((count * (GLYPH_WIDTH + GLYPH_SPACING) - GLYPH_SPACING) * scale) as f32
+    }
+    pub fn text_height(scale: u32) -> f32 {
+        (GLYPH_HEIGHT * scale) as f32
+    }
+    /// The text scale for a window `height` pixels tall.
+    pub fn scale_for(height: f32) -> u32 {
+        (height / UI_PIXELS_PER_SCREEN).max(1.0) as u32
+    }
+}
+impl Default for UiLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
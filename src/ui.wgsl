@@ -0,0 +1,63 @@
+// Screen-space UI quads, positioned in pixels from the top left of the window. Rectangles sample
+// the font atlas's solid cell and text samples its glyphs, so both go through the same pipeline.
+// Images, like the minimap, bind their own texture in place of the atlas and use fs_ui_image.
+struct Screen {
+    size: vec2<f32>,
+    _padding: vec2<f32>,
+}
+@group(0) @binding(0)
+var<uniform> screen: Screen;
+@group(1) @binding(0)
+var t_font: texture_2d<f32>;
+@group(1) @binding(1)
+var s_font: sampler;
+
+struct QuadInput {
+    @location(0) position: vec2<f32>,
+    @location(1) size: vec2<f32>,
+    @location(2) uv_min: vec2<f32>,
+    @location(3) uv_max: vec2<f32>,
+    @location(4) color: vec4<f32>,
+}
+
+struct QuadOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@vertex
+fn vs_ui(@builtin(vertex_index) index: u32, quad: QuadInput) -> QuadOutput {
+    // Counter-clockwise once y is flipped into clip space.
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+    );
+    let corner = corners[index];
+    let pixel = quad.position + corner * quad.size;
+    var out: QuadOutput;
+    out.clip_position = vec4<f32>(
+        pixel.x / screen.size.x * 2.0 - 1.0,
+        1.0 - pixel.y / screen.size.y * 2.0,
+        0.0,
+        1.0,
+    );
+    out.uv = mix(quad.uv_min, quad.uv_max, corner);
+    out.color = quad.color;
+    return out;
+}
+
+@fragment
+fn fs_ui(in: QuadOutput) -> @location(0) vec4<f32> {
+    let coverage = textureSample(t_font, s_font, in.uv).r;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
+
+@fragment
+fn fs_ui_image(in: QuadOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_font, s_font, in.uv) * in.color;
+}
@@ -0,0 +1,419 @@
+/* UiRenderer: draws a UiLayer's quads, the minimap and the stats HUD over the tonemapped
+ * frame. */
+use std::{cell::Cell, mem, sync::Arc};
+
+use crate::{
+    gpu_state::{create_render_pipeline, BindGroupData},
+    minimap::MinimapGrid,
+    ui::{FontAtlas, UiLayer, UiQuadRaw},
+};
+
+// Between the minimap and the top-right corner of the screen, in pixels.
+const MINIMAP_MARGIN: f32 = 16.0;
+// Multiplies the minimap's texture, letting a little of the scene show through.
+const MINIMAP_TINT: [f32; 4] = [1.0, 1.0, 1.0, 0.85];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ScreenUniform {
+    size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+fn describe_ui_quad_vb() -> wgpu::VertexBufferLayout<'static> {
+    wgpu::VertexBufferLayout {
+        array_stride: mem::size_of::<UiQuadRaw>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: &[
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32x2,
+            },
+            wgpu::VertexAttribute {
+                offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                shader_location: 4,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+        ],
+    }
+}
+
+// Quads in a vertex buffer that grows as needed, positioned in pixels of a target whose size is
+// in the uniform.
+struct QuadBatch {
+    label: &'static str,
+    screen_group: BindGroupData<ScreenUniform>,
+    buffer: wgpu::Buffer,
+    // Capacity of `buffer` in quads.
+    capacity: usize,
+    count: u32,
+}
+impl QuadBatch {
+    fn new(device: &wgpu::Device, label: &'static str, width: u32, height: u32) -> Self {
+        let screen_group = BindGroupData::<ScreenUniform>::new(
+            ScreenUniform { size: [width as f32, height as f32], _padding: [0.0; 2] },
+            device,
+            &format!("{} Screen", label),
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            wgpu::ShaderStages::VERTEX,
+        );
+        const INITIAL_CAPACITY: usize = 256;
+        let buffer = Self::create_buffer(device, label, INITIAL_CAPACITY);
+        QuadBatch { label, screen_group, buffer, capacity: INITIAL_CAPACITY, count: 0 }
+    }
+    fn create_buffer(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} Quad Buffer", label)),
+            size: (capacity * mem::size_of::<UiQuadRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+    fn size(&self) -> [f32; 2] {
+        self.screen_group.uniform.size
+    }
+    fn resize(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.screen_group.uniform.size = [width as f32, height as f32];
+        queue.write_buffer(
+            &self.screen_group.buffer,
+            0,
+            bytemuck::cast_slice(&[self.screen_group.uniform]),
+        );
+    }
+    fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, quads: &[UiQuadRaw]) {
+        if quads.len() > self.capacity {
+            self.capacity = quads.len().next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.label, self.capacity);
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(quads));
+        self.count = quads.len() as u32;
+    }
+    // Draws the quads with whatever pipeline and group 1 the pass already has set.
+    fn draw<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_bind_group(0, &self.screen_group.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.draw(0..6, 0..self.count);
+    }
+}
+
+// The minimap's texture, with the grid it was drawn from and the quad that shows it on screen.
+struct MinimapTexture {
+    grid: MinimapGrid,
+    view: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+    // The grid's rectangles, in texels of the texture.
+    cells: QuadBatch,
+    // Where the texture goes, in pixels of the screen.
+    overlay: QuadBatch,
+    // Whether `grid` has changed since the texture was last drawn.
+    stale: Cell<bool>,
+}
+impl MinimapTexture {
+    fn new(
+        device: &wgpu::Device,
+        texture_layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        grid: &MinimapGrid,
+        screen_size: [f32; 2],
+    ) -> Self {
+        let (width, height) = grid.texture_size();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Minimap"),
+            size: wgpu::Extent3d { width, height, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Shown at one texel per pixel, so nearest keeps the tiles' edges sharp.
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Minimap Bind Group"),
+            layout: texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+        let screen_width = screen_size[0] as u32;
+        let screen_height = screen_size[1] as u32;
+        MinimapTexture {
+            grid: grid.clone(),
+            view,
+            bind_group,
+            cells: QuadBatch::new(device, "Minimap Cells", width, height),
+            overlay: QuadBatch::new(device, "Minimap Overlay", screen_width, screen_height),
+            stale: Cell::new(true),
+        }
+    }
+    // Places the texture in the top-right corner of the screen.
+    fn update_overlay(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let (width, height) = self.grid.texture_size();
+        let (width, height) = (width as f32, height as f32);
+        let quad = UiQuadRaw {
+            position: [self.overlay.size()[0] - MINIMAP_MARGIN - width, MINIMAP_MARGIN],
+            size: [width, height],
+            uv_min: [0.0, 0.0],
+            uv_max: [1.0, 1.0],
+            color: MINIMAP_TINT,
+        };
+        self.overlay.update(device, queue, &[quad]);
+    }
+}
+
+pub struct UiRenderer {
+    font_bind_group: wgpu::BindGroup,
+    pipeline: Arc<wgpu::RenderPipeline>,
+    // Same as `pipeline`, but draws the color of a texture instead of the font's coverage.
+    image_pipeline: Arc<wgpu::RenderPipeline>,
+    output_format: wgpu::TextureFormat,
+    quads: QuadBatch,
+    // Drawn over everything else, and empty while the HUD is hidden.
+    hud: QuadBatch,
+    // Kept while hidden, so showing the same grid again doesn't redraw it.
+    minimap: Option<MinimapTexture>,
+    minimap_visible: bool,
+}
+impl UiRenderer {
+    /// `texture_layout` is a filterable texture and sampler, like TransientTextures' sampled
+    /// layout.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_layout: &wgpu::BindGroupLayout,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let quads = QuadBatch::new(device, "Ui", width, height);
+        let hud = QuadBatch::new(device, "Hud", width, height);
+        let atlas = FontAtlas::new();
+        let size =
+            wgpu::Extent3d { width: atlas.width, height: atlas.height, depth_or_array_layers: 1 };
+        let font_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Font Atlas"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &font_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            &atlas.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(atlas.width),
+                rows_per_image: Some(atlas.height),
+            },
+            size,
+        );
+        let font_view = font_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Nearest, so scaled-up glyphs keep their hard pixel edges.
+        let font_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let font_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Font Atlas Bind Group"),
+            layout: texture_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&font_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&font_sampler),
+                },
+            ],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Ui Pipeline Layout"),
+            bind_group_layouts: &[&quads.screen_group.layout, texture_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_render_pipeline(
+            "Ui",
+            device,
+            &layout,
+            output_format,
+            None,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+            &[describe_ui_quad_vb()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Ui Shaders"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("ui.wgsl").into()),
+            },
+            "vs_ui",
+            Some("fs_ui"),
+        );
+        let image_pipeline = create_render_pipeline(
+            "Ui Image",
+            device,
+            &layout,
+            output_format,
+            None,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+            &[describe_ui_quad_vb()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Ui Shaders"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("ui.wgsl").into()),
+            },
+            "vs_ui",
+            Some("fs_ui_image"),
+        );
+        UiRenderer {
+            font_bind_group,
+            pipeline,
+            image_pipeline,
+            output_format,
+            quads,
+            hud,
+            minimap: None,
+            minimap_visible: false,
+        }
+    }
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.quads.resize(queue, width, height);
+        self.hud.resize(queue, width, height);
+        if let Some(minimap) = &mut self.minimap {
+            minimap.overlay.resize(queue, width, height);
+            minimap.update_overlay(device, queue);
+        }
+    }
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layer: &UiLayer) {
+        self.quads.update(device, queue, layer.quads());
+    }
+    /// Shows `layer` over the rest of the UI, or nothing if it's None.
+    pub fn update_hud(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layer: Option<&UiLayer>,
+    ) {
+        self.hud.update(device, queue, layer.map_or(&[], |layer| layer.quads()));
+    }
+    /// Shows `grid` in the corner from now on, or hides the minimap if it's None. The texture is
+    /// only redrawn when the grid changes.
+    pub fn update_minimap(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        texture_layout: &wgpu::BindGroupLayout,
+        grid: Option<&MinimapGrid>,
+    ) {
+        self.minimap_visible = grid.is_some();
+        let Some(grid) = grid else {
+            return;
+        };
+        if self.minimap.as_ref().is_some_and(|minimap| minimap.grid == *grid) {
+            return;
+        }
+        let same_size =
+            |minimap: &MinimapTexture| minimap.grid.texture_size() == grid.texture_size();
+        if !self.minimap.as_ref().is_some_and(same_size) {
+            let screen_size = self.quads.size();
+            self.minimap = Some(MinimapTexture::new(
+                device,
+                texture_layout,
+                self.output_format,
+                grid,
+                screen_size,
+            ));
+        }
+        let minimap = self.minimap.as_mut().unwrap();
+        minimap.grid = grid.clone();
+        minimap.cells.update(device, queue, grid.draw().quads());
+        minimap.update_overlay(device, queue);
+        minimap.stale.set(true);
+    }
+    /// Draws the minimap, the quads and the HUD over `output_view`, keeping what is already there.
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, output_view: &wgpu::TextureView) {
+        let minimap = self.minimap.as_ref().filter(|_| self.minimap_visible);
+        if let Some(minimap) = minimap.filter(|minimap| minimap.stale.get()) {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Minimap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &minimap.view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(1, &self.font_bind_group, &[]);
+            minimap.cells.draw(&mut pass);
+            minimap.stale.set(false);
+        }
+        if self.quads.count == 0 && self.hud.count == 0 && minimap.is_none() {
+            return;
+        }
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Ui Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output_view,
+                resolve_target: None,
+                ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        if let Some(minimap) = minimap {
+            pass.set_pipeline(&self.image_pipeline);
+            pass.set_bind_group(1, &minimap.bind_group, &[]);
+            minimap.overlay.draw(&mut pass);
+        }
+        if self.quads.count > 0 || self.hud.count > 0 {
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(1, &self.font_bind_group, &[]);
+            self.quads.draw(&mut pass);
+            self.hud.draw(&mut pass);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+/* Uploader: the small writes to GPU buffers made every frame, gathered through a staging belt
+ * into one command buffer rather than a queue write each. */
+use std::sync::Arc;
+
+use wgpu::util::StagingBelt;
+
+// Bytes in each of the belt's staging buffers. A frame's uniforms fit in one.
+const CHUNK_SIZE: wgpu::BufferAddress = 64 * 1024;
+
+/// Gathers uploads, like each frame's uniforms, to be submitted together ahead of the work that
+/// reads them.
+///
+/// A buffer should only ever be written through one uploader or only through the queue. Queue
+/// writes land before every command buffer in the same submission, so mixing the two can apply
+/// them out of order.
+pub struct Uploader {
+    device: Arc<wgpu::Device>,
+    belt: StagingBelt,
+    // Started by the first upload since the last `finish`.
+    encoder: Option<wgpu::CommandEncoder>,
+}
+impl Uploader {
+    pub fn new(device: Arc<wgpu::Device>) -> Self {
+        Uploader { device, belt: StagingBelt::new(CHUNK_SIZE), encoder: None }
+    }
+    /// Copies `data` into `target`, `offset` bytes in. `target` needs BufferUsages::COPY_DST, and
+    /// both the offset and the size of `data` must be multiples of wgpu::COPY_BUFFER_ALIGNMENT.
+    pub fn upload<T: bytemuck::Pod>(
+        &mut self,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[T],
+    ) {
+        let bytes: &[u8] = bytemuck::cast_slice(data);
+        let Some(size) = wgpu::BufferSize::new(bytes.len() as u64) else {
+            return;
+        };
+        let encoder = self.encoder.get_or_insert_with(|| {
+            self.device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Uploads") })
+        });
+        self.belt.write_buffer(encoder, target, offset, size, &self.device).copy_from_slice(bytes);
+    }
+    /// The uploads since the last call, to submit before anything reading them, or None if there
+    /// were none. Call `recall` once they are submitted.
+    pub fn finish(&mut self) -> Option<wgpu::CommandBuffer> {
+        let encoder = self.encoder.take()?;
+        self.belt.finish();
+        Some(encoder.finish())
+    }
+    /// Lets the staging buffers of submitted uploads be reused, once the GPU is done with them.
+    pub fn recall(&mut self) {
+        self.belt.recall();
+    }
+    /// Submits the uploads on their own, for when no frame is going to be submitted with them.
+    pub fn flush(&mut self, queue: &wgpu::Queue) {
+        if let Some(uploads) = self.finish() {
+            queue.submit(std::iter::once(uploads));
+            self.recall();
+        }
+    }
+}